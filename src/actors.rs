@@ -0,0 +1,1102 @@
+use crate::debug_draw_b::{
+    du_debug_draw_polyline_fade, DebugDraw, GizmoDebugDraw, DU_DRAW_LINES, DU_DRAW_POINTS,
+};
+use crate::layers;
+use crate::net::{ActorMessage, Vector3};
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use bevy_egui::{egui, EguiContexts};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+// How long a Move takes to play out, so actors glide instead of teleporting
+pub const ACTOR_MOVE_DURATION: f32 = 0.25;
+
+const ACTOR_RADIUS: f32 = 25.0;
+
+pub const MAX_TRAIL_LENGTH: usize = 1000;
+
+// Picking tests against a sphere that covers every actor shape
+const PICK_RADIUS: f32 = ACTOR_RADIUS * 1.75;
+const SELECTION_COLOR: Color = Color::YELLOW;
+
+// Positions listed in the inspector, newest first
+const INSPECTOR_HISTORY_LENGTH: usize = 10;
+
+// Labels are drawn at full size and opacity up to this camera distance, then
+// shrink and fade until they disappear past the second one
+const LABEL_NEAR_DISTANCE: f32 = 500.0;
+const LABEL_FAR_DISTANCE: f32 = 5000.0;
+const LABEL_FONT_SIZE: f32 = 14.0;
+const MIN_LABEL_FONT_SIZE: f32 = 8.0;
+
+// Slack around the mesh bounds, as a fraction of its size, before a position
+// counts as outside
+const BOUNDS_TOLERANCE: f32 = 0.1;
+
+const OUT_OF_BOUNDS_COLOR: Vec4 = Vec4::new(1.0, 0.4, 0.0, 1.0);
+
+// Ground snapping looks for a floor this far above the actor first, so actors
+// on lower floors aren't lifted onto the roof
+const GROUND_SNAP_STEP: f32 = ACTOR_RADIUS * 4.0;
+
+// Actor received over the network, `from` -> `to` is the move in progress
+#[derive(Component)]
+pub struct Actor {
+    pub id: String,
+    pub actor_type: String,
+    from: Vec3,
+    to: Vec3,
+    elapsed: f32,
+    trail: VecDeque<Vec3>, // Recent positions from the network, oldest first
+}
+
+impl Actor {
+    fn new(id: String, actor_type: String, position: Vec3) -> Self {
+        Self {
+            id,
+            actor_type,
+            from: position,
+            to: position,
+            elapsed: ACTOR_MOVE_DURATION,
+            trail: VecDeque::from([position]),
+        }
+    }
+
+    fn start_move(&mut self, from: Vec3, to: Vec3) {
+        self.from = from;
+        self.to = to;
+        self.elapsed = 0.0;
+
+        if self.trail.back() != Some(&from) {
+            self.trail.push_back(from);
+        }
+        self.trail.push_back(to);
+        while self.trail.len() > MAX_TRAIL_LENGTH {
+            self.trail.pop_front();
+        }
+    }
+
+    // Step the move forward, returns the new position
+    fn advance(&mut self, delta_seconds: f32) -> Vec3 {
+        self.elapsed = (self.elapsed + delta_seconds).min(ACTOR_MOVE_DURATION);
+        self.position()
+    }
+
+    // Position from the network, part way through the current move
+    fn position(&self) -> Vec3 {
+        self.from.lerp(self.to, self.elapsed / ACTOR_MOVE_DURATION)
+    }
+}
+
+// Number of trail points drawn per actor
+#[derive(Resource)]
+pub struct TrailSettings {
+    pub length: usize,
+}
+
+impl Default for TrailSettings {
+    fn default() -> Self {
+        Self { length: 100 }
+    }
+}
+
+#[derive(Resource)]
+pub struct ActorLabels {
+    pub show: bool,
+}
+
+impl Default for ActorLabels {
+    fn default() -> Self {
+        Self { show: true }
+    }
+}
+
+// Actors last seen outside the mesh bounds, by id, with the position from the
+// network and where it is clamped to the bounds
+#[derive(Resource)]
+pub struct OutOfBounds {
+    pub show_markers: bool,
+    pub actors: HashMap<String, (Vec3, Vec3)>,
+}
+
+impl Default for OutOfBounds {
+    fn default() -> Self {
+        Self {
+            show_markers: true,
+            actors: HashMap::default(),
+        }
+    }
+}
+
+// Draw actors on the mesh surface below them instead of at the Y the server sent
+#[derive(Resource, Default)]
+pub struct GroundSnap {
+    pub enabled: bool,
+}
+
+// Actor shown in the inspector, picked with a left click
+#[derive(Resource, Default)]
+pub struct SelectedActor(pub Option<Entity>);
+
+// Network id -> spawned entity
+#[derive(Resource, Default)]
+pub struct ActorMap(pub HashMap<String, Entity>);
+
+// Decoded messages waiting to be applied, oldest first
+#[derive(Resource, Default)]
+pub struct ActorInbox(pub Vec<ActorMessage>);
+
+// Shared sphere mesh and material for every actor
+#[derive(Resource)]
+pub struct ActorAssets {
+    pub mesh: Handle<Mesh>,
+    pub material: Handle<StandardMaterial>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default, Serialize, Deserialize)]
+pub enum ActorShape {
+    #[default]
+    Sphere,
+    Cube,
+    Capsule,
+}
+
+impl ActorShape {
+    pub const ALL: [ActorShape; 3] = [ActorShape::Sphere, ActorShape::Cube, ActorShape::Capsule];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ActorShape::Sphere => "Sphere",
+            ActorShape::Cube => "Cube",
+            ActorShape::Capsule => "Capsule",
+        }
+    }
+
+    fn mesh(self) -> Mesh {
+        match self {
+            ActorShape::Sphere => Mesh::from(shape::UVSphere {
+                radius: ACTOR_RADIUS,
+                sectors: 16,
+                stacks: 8,
+            }),
+            ActorShape::Cube => Mesh::from(shape::Cube {
+                size: ACTOR_RADIUS * 2.0,
+            }),
+            ActorShape::Capsule => Mesh::from(shape::Capsule {
+                radius: ACTOR_RADIUS * 0.6,
+                depth: ACTOR_RADIUS * 0.8,
+                ..default()
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ActorStyle {
+    pub color: [f32; 3],
+    pub shape: ActorShape,
+}
+
+impl Default for ActorStyle {
+    fn default() -> Self {
+        Self {
+            color: [0.9, 0.2, 0.2],
+            shape: ActorShape::Sphere,
+        }
+    }
+}
+
+// How each actor type from `ActorSpawn` is drawn, types without an entry
+// use the fallback
+#[derive(Resource, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ActorStyles {
+    pub types: BTreeMap<String, ActorStyle>,
+    pub fallback: ActorStyle,
+}
+
+impl ActorStyles {
+    pub fn style(&self, actor_type: &str) -> ActorStyle {
+        self.types.get(actor_type).copied().unwrap_or(self.fallback)
+    }
+}
+
+// A mesh per shape and a material per actor type, filled by `style_actors`
+#[derive(Resource, Default)]
+pub struct ActorStyleAssets {
+    shapes: HashMap<ActorShape, Handle<Mesh>>,
+    materials: HashMap<String, Handle<StandardMaterial>>,
+}
+
+impl From<Vector3> for Vec3 {
+    fn from(v: Vector3) -> Self {
+        Vec3::new(v.x, v.y, v.z)
+    }
+}
+
+pub fn setup_actor_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(ActorAssets {
+        mesh: meshes.add(Mesh::from(shape::UVSphere {
+            radius: ACTOR_RADIUS,
+            sectors: 16,
+            stacks: 8,
+        })),
+        material: materials.add(StandardMaterial {
+            base_color: Color::rgb(0.9, 0.2, 0.2),
+            unlit: true,
+            ..default()
+        }),
+    });
+
+    let shapes = ActorShape::ALL
+        .iter()
+        .map(|&shape| (shape, meshes.add(shape.mesh())))
+        .collect();
+    commands.insert_resource(ActorStyleAssets {
+        shapes,
+        materials: HashMap::default(),
+    });
+}
+
+// Give new actors the mesh and color of their type, and restyle every actor
+// when the styles are edited
+pub fn style_actors(
+    styles: Res<ActorStyles>,
+    mut style_assets: ResMut<ActorStyleAssets>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut actors: Query<(Ref<Actor>, &mut Handle<Mesh>, &mut Handle<StandardMaterial>)>,
+) {
+    let style_assets = &mut *style_assets;
+    if styles.is_changed() {
+        for (actor_type, handle) in &style_assets.materials {
+            if let Some(material) = materials.get_mut(handle) {
+                material.base_color = Color::from(styles.style(actor_type).color);
+            }
+        }
+    }
+
+    for (actor, mut mesh, mut material) in actors.iter_mut() {
+        if !styles.is_changed() && !actor.is_added() {
+            continue;
+        }
+        let style = styles.style(&actor.actor_type);
+        if let Some(shape) = style_assets.shapes.get(&style.shape) {
+            *mesh = shape.clone();
+        }
+        *material = style_assets
+            .materials
+            .entry(actor.actor_type.clone())
+            .or_insert_with(|| {
+                materials.add(StandardMaterial {
+                    base_color: Color::from(style.color),
+                    unlit: true,
+                    ..default()
+                })
+            })
+            .clone();
+    }
+}
+
+// The point clamped into the bounds, grown by `BOUNDS_TOLERANCE`, or None when
+// it is already inside
+pub fn clamp_to_bounds(bounds: (Vec3, Vec3), point: Vec3) -> Option<Vec3> {
+    let (min, max) = bounds;
+    if min.cmpgt(max).any() {
+        return None;
+    }
+    let margin = (max - min) * BOUNDS_TOLERANCE;
+    let clamped = point.clamp(min - margin, max + margin);
+    (clamped != point).then_some(clamped)
+}
+
+// Surface height of the primary mesh under `position`, preferring the floor
+// just below it, None when there is no mesh there. Actors are in world space,
+// so the mesh counts where its layer transform puts it
+pub fn ground_height(
+    mesh_data: &crate::MeshData,
+    picking_grid: Option<&crate::world::TriangleGrid>,
+    layers: &layers::MeshLayers,
+    position: Vec3,
+) -> Option<f32> {
+    let transform = layers.transform(layers::PRIMARY_LAYER);
+    let top = crate::transformed_bounds(mesh_data.bounds, &transform).1.y + 1.0;
+    let near = position.y + GROUND_SNAP_STEP;
+    [near.min(top), top]
+        .into_iter()
+        .find_map(|y| {
+            let origin = Vec3::new(position.x, y, position.z);
+            layers.raycast_layer(
+                layers::PRIMARY_LAYER,
+                mesh_data,
+                picking_grid,
+                origin,
+                Vec3::NEG_Y,
+            )
+        })
+        .map(|(hit, ..)| hit.y)
+}
+
+// Put actors on the ground when snapping is on, and back at their network
+// height when it is turned off
+pub fn snap_actors_to_ground(
+    snap: Res<GroundSnap>,
+    mesh_data: Option<Res<crate::MeshData>>,
+    picking_grid: Option<Res<crate::world::TriangleGrid>>,
+    layers: Res<layers::MeshLayers>,
+    mut actors: Query<(Ref<Actor>, &mut Transform)>,
+) {
+    let mesh_changed = layers.is_changed()
+        || mesh_data
+            .as_ref()
+            .is_some_and(|mesh_data| mesh_data.is_changed());
+    for (actor, mut transform) in actors.iter_mut() {
+        if !actor.is_changed() && !snap.is_changed() && !mesh_changed {
+            continue;
+        }
+        let mut position = actor.position();
+        if let (true, Some(mesh_data)) = (snap.enabled, &mesh_data) {
+            // Actor meshes are centered, so they sit one radius above the hit
+            let grid = picking_grid.as_deref();
+            if let Some(ground) = ground_height(mesh_data, grid, &layers, position) {
+                position.y = ground + ACTOR_RADIUS;
+            }
+        }
+        transform.translation = position;
+    }
+}
+
+// Warn when a spawn or move lands outside the loaded mesh, which usually
+// means the server and the viewer disagree on coordinates
+pub fn check_actor_bounds(
+    inbox: Res<ActorInbox>,
+    mesh_data: Option<Res<crate::MeshData>>,
+    mut out_of_bounds: ResMut<OutOfBounds>,
+) {
+    let Some(mesh_data) = mesh_data else {
+        return;
+    };
+    if !inbox.is_changed() {
+        return;
+    }
+    for message in &inbox.0 {
+        let position = match message {
+            ActorMessage::Spawn(msg) => msg.position.clone(),
+            ActorMessage::Move(msg) => msg.dest.clone(),
+            ActorMessage::Position(msg) => msg.pos.clone(),
+            ActorMessage::Despawn(msg) => {
+                out_of_bounds.actors.remove(&msg.id);
+                continue;
+            }
+        };
+        let position = Vec3::from(position);
+        match clamp_to_bounds(mesh_data.bounds, position) {
+            Some(clamped) => {
+                // Only the first position outside is logged, not every move
+                let previous = out_of_bounds
+                    .actors
+                    .insert(message.id().to_string(), (position, clamped));
+                if previous.is_none() {
+                    warn!(
+                        "Actor {} at {} is outside the mesh bounds {} - {}",
+                        message.id(),
+                        position,
+                        mesh_data.bounds.0,
+                        mesh_data.bounds.1
+                    );
+                }
+            }
+            None => {
+                out_of_bounds.actors.remove(message.id());
+            }
+        }
+    }
+}
+
+// A cross on the edge of the bounds, with a line pointing towards the actor
+pub fn draw_out_of_bounds(mut gizmos: Gizmos, out_of_bounds: Res<OutOfBounds>) {
+    if !out_of_bounds.show_markers {
+        return;
+    }
+    let mut dd = GizmoDebugDraw::new(&mut gizmos);
+    dd.begin(DU_DRAW_POINTS, ACTOR_RADIUS * 2.0);
+    for (_, clamped) in out_of_bounds.actors.values() {
+        dd.vertex(*clamped, OUT_OF_BOUNDS_COLOR);
+    }
+    dd.end();
+
+    dd.begin(DU_DRAW_LINES, 2.0);
+    for (position, clamped) in out_of_bounds.actors.values() {
+        let towards = (*position - *clamped).normalize_or_zero();
+        dd.vertex(*clamped, OUT_OF_BOUNDS_COLOR);
+        dd.vertex(*clamped + towards * ACTOR_RADIUS * 4.0, OUT_OF_BOUNDS_COLOR);
+    }
+    dd.end();
+}
+
+// Font size and opacity of a label this far from the camera, None once it is
+// too far to draw
+pub fn label_appearance(distance: f32) -> Option<(f32, f32)> {
+    if distance >= LABEL_FAR_DISTANCE {
+        return None;
+    }
+    let fade = ((distance - LABEL_NEAR_DISTANCE) / (LABEL_FAR_DISTANCE - LABEL_NEAR_DISTANCE))
+        .clamp(0.0, 1.0);
+    let size = LABEL_FONT_SIZE + (MIN_LABEL_FONT_SIZE - LABEL_FONT_SIZE) * fade;
+    Some((size, 1.0 - fade))
+}
+
+// Id and type of each actor as egui text just above its mesh
+pub fn draw_actor_labels(
+    mut contexts: EguiContexts,
+    labels: Res<ActorLabels>,
+    screenshot: Res<crate::ScreenshotState>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<crate::MainCamera>>,
+    actors: Query<(&Actor, &GlobalTransform)>,
+) {
+    // Labels are UI, left out of screenshots without it
+    if !labels.show || screenshot.hide_ui {
+        return;
+    }
+    let Ok((camera, camera_transform)) = camera_query.get_single() else {
+        return;
+    };
+
+    let painter = contexts
+        .ctx_mut()
+        .layer_painter(egui::LayerId::background());
+    for (actor, transform) in actors.iter() {
+        let anchor = transform.translation() + Vec3::Y * ACTOR_RADIUS * 1.5;
+        let distance = anchor.distance(camera_transform.translation());
+        let (Some((size, alpha)), Some(screen)) = (
+            label_appearance(distance),
+            camera.world_to_viewport(camera_transform, anchor),
+        ) else {
+            continue;
+        };
+        painter.text(
+            egui::pos2(screen.x, screen.y),
+            egui::Align2::CENTER_BOTTOM,
+            format!("{} ({})", actor.id, actor.actor_type),
+            egui::FontId::proportional(size),
+            egui::Color32::WHITE.gamma_multiply(alpha),
+        );
+    }
+}
+
+// Nearest actor whose pick sphere the ray goes through
+pub fn pick_actor(
+    origin: Vec3,
+    direction: Vec3,
+    actors: impl IntoIterator<Item = (Entity, Vec3)>,
+) -> Option<Entity> {
+    actors
+        .into_iter()
+        .filter_map(|(entity, center)| {
+            crate::world::ray_sphere_intersection(origin, direction, center, PICK_RADIUS)
+                .map(|distance| (distance, entity))
+        })
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, entity)| entity)
+}
+
+// Left click selects the actor under the cursor, or clears the selection when
+// there is none. Left to the measure tool while it is on
+#[allow(clippy::too_many_arguments)]
+pub fn select_actor(
+    mut selected: ResMut<SelectedActor>,
+    mut contexts: EguiContexts,
+    measure: Res<crate::measure::MeasureTool>,
+    keyboard: Res<Input<KeyCode>>,
+    mouse_button: Res<Input<MouseButton>>,
+    windows: Query<&Window, With<bevy::window::PrimaryWindow>>,
+    camera_query: Query<(&Transform, &Camera), With<crate::MainCamera>>,
+    actors: Query<(Entity, &GlobalTransform), With<Actor>>,
+) {
+    let ctrl_pressed =
+        keyboard.pressed(KeyCode::ControlLeft) || keyboard.pressed(KeyCode::ControlRight);
+    if measure.enabled
+        || ctrl_pressed
+        || !mouse_button.just_pressed(MouseButton::Left)
+        || contexts.ctx_mut().wants_pointer_input()
+    {
+        return;
+    }
+    let (Ok(window), Ok((transform, camera))) = (windows.get_single(), camera_query.get_single())
+    else {
+        return;
+    };
+    let Some(cursor_pos) = window.cursor_position() else {
+        return;
+    };
+
+    let (origin, direction) = crate::world::screen_to_ray(window, camera, transform, cursor_pos);
+    selected.0 = pick_actor(
+        origin,
+        direction,
+        actors
+            .iter()
+            .map(|(entity, transform)| (entity, transform.translation())),
+    );
+}
+
+pub fn draw_selected_actor(
+    mut gizmos: Gizmos,
+    selected: Res<SelectedActor>,
+    actors: Query<&GlobalTransform, With<Actor>>,
+) {
+    let Some(transform) = selected.0.and_then(|entity| actors.get(entity).ok()) else {
+        return;
+    };
+    gizmos
+        .sphere(
+            transform.translation(),
+            Quat::IDENTITY,
+            PICK_RADIUS,
+            SELECTION_COLOR,
+        )
+        .circle_segments(24);
+}
+
+// Details of the selected actor. Closing the window, or the actor despawning,
+// clears the selection
+pub fn actor_inspector_window(
+    mut contexts: EguiContexts,
+    mut selected: ResMut<SelectedActor>,
+    screenshot: Res<crate::ScreenshotState>,
+    actors: Query<&Actor>,
+) {
+    let Some(entity) = selected.0 else {
+        return;
+    };
+    let Ok(actor) = actors.get(entity) else {
+        selected.0 = None;
+        return;
+    };
+    if screenshot.hide_ui {
+        return;
+    }
+
+    let mut open = true;
+    egui::Window::new("Actor")
+        .open(&mut open)
+        .show(contexts.ctx_mut(), |ui| {
+            egui::Grid::new("actor_details").show(ui, |ui| {
+                ui.label("Id");
+                ui.label(&actor.id);
+                ui.end_row();
+                ui.label("Type");
+                ui.label(&actor.actor_type);
+                ui.end_row();
+                ui.label("Position");
+                ui.label(format!(
+                    "{:.1}, {:.1}, {:.1}",
+                    actor.to.x, actor.to.y, actor.to.z
+                ));
+                ui.end_row();
+            });
+
+            ui.separator();
+            ui.label(format!("Recent positions ({} kept)", actor.trail.len()));
+            egui::Grid::new("actor_history")
+                .striped(true)
+                .show(ui, |ui| {
+                    for (age, position) in actor
+                        .trail
+                        .iter()
+                        .rev()
+                        .take(INSPECTOR_HISTORY_LENGTH)
+                        .enumerate()
+                    {
+                        ui.label(format!("-{age}"));
+                        ui.label(format!(
+                            "{:.1}, {:.1}, {:.1}",
+                            position.x, position.y, position.z
+                        ));
+                        ui.end_row();
+                    }
+                });
+        });
+    if !open {
+        selected.0 = None;
+    }
+}
+
+// Table of actor types, the configured ones and any spawned so far
+pub fn actor_styles_window(
+    mut contexts: EguiContexts,
+    mut styles: ResMut<ActorStyles>,
+    mut labels: ResMut<ActorLabels>,
+    mut out_of_bounds: ResMut<OutOfBounds>,
+    mut ground_snap: ResMut<GroundSnap>,
+    screenshot: Res<crate::ScreenshotState>,
+    actors: Query<&Actor>,
+) {
+    if screenshot.hide_ui {
+        return;
+    }
+
+    let mut types: BTreeSet<String> = styles.types.keys().cloned().collect();
+    types.extend(actors.iter().map(|actor| actor.actor_type.clone()));
+
+    egui::Window::new("Actor Types")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.checkbox(&mut labels.show, "Show labels");
+            ui.checkbox(&mut ground_snap.enabled, "Snap to ground");
+            ui.checkbox(
+                &mut out_of_bounds.show_markers,
+                "Flag actors outside the mesh",
+            );
+            if !out_of_bounds.actors.is_empty() {
+                ui.colored_label(
+                    egui::Color32::from_rgb(255, 102, 0),
+                    format!(
+                        "{} actors outside the mesh bounds",
+                        out_of_bounds.actors.len()
+                    ),
+                );
+            }
+            egui::Grid::new("actor_styles")
+                .striped(true)
+                .show(ui, |ui| {
+                    let mut fallback = styles.fallback;
+                    if style_row(ui, "(other)", &mut fallback) {
+                        styles.fallback = fallback;
+                    }
+                    for actor_type in types {
+                        let mut style = styles.style(&actor_type);
+                        if style_row(ui, &actor_type, &mut style) {
+                            styles.types.insert(actor_type, style);
+                        }
+                    }
+                });
+        });
+}
+
+// One grid row, true when the style was edited
+fn style_row(ui: &mut egui::Ui, label: &str, style: &mut ActorStyle) -> bool {
+    ui.label(label);
+    let mut changed = ui.color_edit_button_rgb(&mut style.color).changed();
+    egui::ComboBox::from_id_source(("actor_shape", label))
+        .selected_text(style.shape.label())
+        .show_ui(ui, |ui| {
+            for shape in ActorShape::ALL {
+                changed |= ui
+                    .selectable_value(&mut style.shape, shape, shape.label())
+                    .changed();
+            }
+        });
+    ui.end_row();
+    changed
+}
+
+// Apply one decoded message to the scene
+pub fn apply_actor_message(
+    message: ActorMessage,
+    commands: &mut Commands,
+    assets: &ActorAssets,
+    actor_map: &mut ActorMap,
+    actors: &mut Query<&mut Actor>,
+) {
+    match message {
+        ActorMessage::Spawn(msg) => {
+            let position = Vec3::from(msg.position);
+            let entity = commands
+                .spawn((
+                    PbrBundle {
+                        mesh: assets.mesh.clone(),
+                        material: assets.material.clone(),
+                        transform: Transform::from_translation(position),
+                        ..default()
+                    },
+                    Actor::new(msg.id.clone(), msg.actor_type, position),
+                ))
+                .id();
+
+            // A repeated spawn replaces the old entity
+            if let Some(old) = actor_map.0.insert(msg.id, entity) {
+                commands.entity(old).despawn_recursive();
+            }
+        }
+        ActorMessage::Move(msg) => {
+            let Some(mut actor) = actor_map
+                .0
+                .get(&msg.id)
+                .and_then(|&entity| actors.get_mut(entity).ok())
+            else {
+                warn!("Move for unknown actor {}", msg.id);
+                return;
+            };
+            actor.start_move(msg.orig.into(), msg.dest.into());
+        }
+        ActorMessage::Position(msg) => {
+            // Positions of actors that were never spawned only feed the camera
+            if let Some(mut actor) = actor_map
+                .0
+                .get(&msg.id)
+                .and_then(|&entity| actors.get_mut(entity).ok())
+            {
+                let from = actor.to;
+                actor.start_move(from, msg.pos.into());
+            }
+        }
+        ActorMessage::Despawn(msg) => match actor_map.0.remove(&msg.id) {
+            Some(entity) => commands.entity(entity).despawn_recursive(),
+            None => warn!("Despawn for unknown actor {}", msg.id),
+        },
+    }
+}
+
+// Apply the queued messages in order. An update for an actor spawned earlier
+// in the batch waits for the next frame, with every later message for that
+// actor, as its entity only exists once the spawn's commands have run
+pub fn apply_actor_messages(
+    mut inbox: ResMut<ActorInbox>,
+    mut commands: Commands,
+    assets: Res<ActorAssets>,
+    mut actor_map: ResMut<ActorMap>,
+    mut actors: Query<&mut Actor>,
+) {
+    let mut waiting = HashSet::new();
+    for message in std::mem::take(&mut inbox.0) {
+        if waiting.contains(message.id()) || awaits_spawn(&message, &actor_map, &actors) {
+            waiting.insert(message.id().to_string());
+            inbox.0.push(message);
+            continue;
+        }
+        apply_actor_message(message, &mut commands, &assets, &mut actor_map, &mut actors);
+    }
+}
+
+fn awaits_spawn(message: &ActorMessage, actor_map: &ActorMap, actors: &Query<&mut Actor>) -> bool {
+    if !matches!(message, ActorMessage::Move(_) | ActorMessage::Position(_)) {
+        return false;
+    }
+    actor_map
+        .0
+        .get(message.id())
+        .is_some_and(|&entity| actors.get(entity).is_err())
+}
+
+// Moves play out at the replay's speed while one is running
+pub fn animate_actors(
+    time: Res<Time>,
+    replay: Res<crate::replay::ReplayController>,
+    mut actors: Query<(&mut Actor, &mut Transform)>,
+) {
+    let delta_seconds = time.delta_seconds() * replay.time_scale();
+    for (mut actor, mut transform) in actors.iter_mut() {
+        if actor.elapsed < ACTOR_MOVE_DURATION {
+            transform.translation = actor.advance(delta_seconds);
+        }
+    }
+}
+
+pub fn draw_actor_trails(mut gizmos: Gizmos, settings: Res<TrailSettings>, actors: Query<&Actor>) {
+    let mut dd = GizmoDebugDraw::new(&mut gizmos);
+    for actor in actors.iter() {
+        let skip = actor.trail.len().saturating_sub(settings.length);
+        du_debug_draw_polyline_fade(
+            &mut dd,
+            actor.trail.iter().skip(skip).copied(),
+            Vec4::new(1.0, 0.8, 0.2, 1.0),
+            1.0,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{ActorDespawn, ActorMove, ActorSpawn};
+    use bevy::ecs::system::RunSystemOnce;
+
+    fn apply(world: &mut World, message: ActorMessage) {
+        world.run_system_once(
+            move |mut commands: Commands,
+                  assets: Res<ActorAssets>,
+                  mut actor_map: ResMut<ActorMap>,
+                  mut actors: Query<&mut Actor>| {
+                apply_actor_message(
+                    message.clone(),
+                    &mut commands,
+                    &assets,
+                    &mut actor_map,
+                    &mut actors,
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn test_spawn_move_despawn() {
+        let mut world = World::new();
+        world.insert_resource(ActorMap::default());
+        world.insert_resource(ActorAssets {
+            mesh: Handle::default(),
+            material: Handle::default(),
+        });
+
+        apply(
+            &mut world,
+            ActorMessage::Spawn(ActorSpawn::new(
+                "enemy1".to_string(),
+                "goblin".to_string(),
+                Vector3::new(5.0, 0.0, 5.0),
+            )),
+        );
+        let entity = world.resource::<ActorMap>().0["enemy1"];
+        assert_eq!(
+            world.get::<Transform>(entity).unwrap().translation,
+            Vec3::new(5.0, 0.0, 5.0)
+        );
+
+        apply(
+            &mut world,
+            ActorMessage::Move(ActorMove::new(
+                "enemy1".to_string(),
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(10.0, 0.0, 10.0),
+            )),
+        );
+
+        // Half way through the move, then clamped at the destination
+        let mut actor = world.get_mut::<Actor>(entity).unwrap();
+        assert_eq!(
+            actor.trail,
+            [
+                Vec3::new(5.0, 0.0, 5.0),
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(10.0, 0.0, 10.0)
+            ]
+        );
+        assert_eq!(
+            actor.advance(ACTOR_MOVE_DURATION / 2.0),
+            Vec3::new(5.0, 0.0, 5.0)
+        );
+        assert_eq!(actor.advance(1.0), Vec3::new(10.0, 0.0, 10.0));
+
+        apply(
+            &mut world,
+            ActorMessage::Despawn(ActorDespawn::new("enemy1".to_string())),
+        );
+        assert!(world.resource::<ActorMap>().0.is_empty());
+        assert!(world.get_entity(entity).is_none());
+    }
+
+    #[test]
+    fn test_pick_actor_nearest() {
+        let near = Entity::from_raw(1);
+        let far = Entity::from_raw(2);
+        let actors = [
+            (far, Vec3::new(0.0, 0.0, -500.0)),
+            (near, Vec3::new(10.0, 0.0, -200.0)),
+        ];
+        assert_eq!(pick_actor(Vec3::ZERO, Vec3::NEG_Z, actors), Some(near));
+
+        // Clicking beside every actor picks nothing
+        let beside = Vec3::new(PICK_RADIUS * 2.0, 0.0, 0.0);
+        assert_eq!(pick_actor(beside, Vec3::NEG_Z, actors), None);
+    }
+
+    #[test]
+    fn test_actor_styles() {
+        let mut world = World::new();
+        world.init_resource::<Assets<StandardMaterial>>();
+        let mut styles = ActorStyles::default();
+        styles.types.insert(
+            "goblin".to_string(),
+            ActorStyle {
+                color: [0.1, 0.8, 0.1],
+                shape: ActorShape::Cube,
+            },
+        );
+        world.insert_resource(styles);
+        let cube = Handle::weak_from_u128(1);
+        world.insert_resource(ActorStyleAssets {
+            shapes: HashMap::from_iter([(ActorShape::Cube, cube.clone())]),
+            materials: HashMap::default(),
+        });
+
+        let spawn = |world: &mut World, actor_type: &str| {
+            world
+                .spawn((
+                    Actor::new("a".to_string(), actor_type.to_string(), Vec3::ZERO),
+                    Handle::<Mesh>::default(),
+                    Handle::<StandardMaterial>::default(),
+                ))
+                .id()
+        };
+        let goblin = spawn(&mut world, "goblin");
+        let unknown = spawn(&mut world, "dragon");
+        world.run_system_once(style_actors);
+
+        let color = |world: &World, entity: Entity| {
+            let handle = world.get::<Handle<StandardMaterial>>(entity).unwrap();
+            let materials = world.resource::<Assets<StandardMaterial>>();
+            materials.get(handle).unwrap().base_color
+        };
+        assert_eq!(world.get::<Handle<Mesh>>(goblin), Some(&cube));
+        assert_eq!(color(&world, goblin), Color::rgb(0.1, 0.8, 0.1));
+        assert_eq!(color(&world, unknown), Color::rgb(0.9, 0.2, 0.2));
+
+        // Recoloring a type updates the actors already spawned
+        world.resource_mut::<ActorStyles>().fallback.color = [0.0, 0.0, 1.0];
+        world.run_system_once(style_actors);
+        assert_eq!(color(&world, unknown), Color::rgb(0.0, 0.0, 1.0));
+        assert_eq!(color(&world, goblin), Color::rgb(0.1, 0.8, 0.1));
+    }
+
+    #[test]
+    fn test_out_of_bounds_positions() {
+        let bounds = (Vec3::ZERO, Vec3::new(100.0, 10.0, 100.0));
+        assert_eq!(clamp_to_bounds(bounds, Vec3::new(50.0, 5.0, 50.0)), None);
+        // Inside the tolerance
+        assert_eq!(clamp_to_bounds(bounds, Vec3::new(105.0, 0.0, -5.0)), None);
+        assert_eq!(
+            clamp_to_bounds(bounds, Vec3::new(5000.0, 5.0, 50.0)),
+            Some(Vec3::new(110.0, 5.0, 50.0))
+        );
+        // No mesh loaded
+        let empty = (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN));
+        assert_eq!(clamp_to_bounds(empty, Vec3::splat(1e6)), None);
+
+        let mut world = World::new();
+        world.init_resource::<OutOfBounds>();
+        world.insert_resource(crate::MeshData {
+            vertices: vec![bounds.0, bounds.1],
+            indices: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            tile_size: 988.0,
+            bounds,
+            material_groups: Vec::new(),
+            file_vertices: Vec::new(),
+        });
+        world.insert_resource(ActorInbox(vec![
+            ActorMessage::Spawn(ActorSpawn::new(
+                "enemy1".to_string(),
+                "goblin".to_string(),
+                Vector3::new(0.0, 0.0, -500.0),
+            )),
+            ActorMessage::Spawn(ActorSpawn::new(
+                "enemy2".to_string(),
+                "goblin".to_string(),
+                Vector3::new(0.0, 0.0, 0.0),
+            )),
+        ]));
+        world.run_system_once(check_actor_bounds);
+        let out_of_bounds = world.resource::<OutOfBounds>();
+        assert_eq!(out_of_bounds.actors.len(), 1);
+        assert_eq!(out_of_bounds.actors["enemy1"].1, Vec3::new(0.0, 0.0, -10.0));
+
+        // Moving back in clears the flag
+        world.insert_resource(ActorInbox(vec![ActorMessage::Move(ActorMove::new(
+            "enemy1".to_string(),
+            Vector3::new(0.0, 0.0, -500.0),
+            Vector3::new(10.0, 0.0, 10.0),
+        ))]));
+        world.run_system_once(check_actor_bounds);
+        assert!(world.resource::<OutOfBounds>().actors.is_empty());
+    }
+
+    #[test]
+    fn test_ground_height() {
+        // Two floors, at 0 and 500
+        let vertices = vec![
+            Vec3::new(-100.0, 0.0, -100.0),
+            Vec3::new(-100.0, 0.0, 100.0),
+            Vec3::new(100.0, 0.0, 0.0),
+            Vec3::new(-100.0, 500.0, -100.0),
+            Vec3::new(-100.0, 500.0, 100.0),
+            Vec3::new(100.0, 500.0, 0.0),
+        ];
+        let mesh_data = crate::MeshData {
+            bounds: crate::point_bounds(&vertices),
+            normals: vec![Vec3::Y; vertices.len()],
+            vertices,
+            indices: vec![0, 1, 2, 3, 4, 5],
+            uvs: Vec::new(),
+            tile_size: 988.0,
+            material_groups: Vec::new(),
+            file_vertices: Vec::new(),
+        };
+
+        // Floating and buried actors land on the floor they're nearest to
+        let mut layers = layers::MeshLayers::default();
+        let ground = |layers: &layers::MeshLayers, position| {
+            ground_height(&mesh_data, None, layers, position)
+        };
+        assert_eq!(ground(&layers, Vec3::new(0.0, 40.0, 0.0)), Some(0.0));
+        assert_eq!(ground(&layers, Vec3::new(0.0, 480.0, 0.0)), Some(500.0));
+        assert_eq!(ground(&layers, Vec3::new(0.0, 900.0, 0.0)), Some(500.0));
+        // Buried too deep for the step, the top floor is all there is
+        assert_eq!(ground(&layers, Vec3::new(0.0, -300.0, 0.0)), Some(500.0));
+        assert_eq!(ground(&layers, Vec3::new(1000.0, 0.0, 0.0)), None);
+
+        // A raised mesh raises the floors with it
+        layers.primary_transform.translation = Vec3::new(0.0, 100.0, 0.0);
+        assert_eq!(ground(&layers, Vec3::new(0.0, 140.0, 0.0)), Some(100.0));
+        assert_eq!(ground(&layers, Vec3::new(0.0, 900.0, 0.0)), Some(600.0));
+    }
+
+    #[test]
+    fn test_label_fades_with_distance() {
+        assert_eq!(label_appearance(0.0), Some((LABEL_FONT_SIZE, 1.0)));
+        assert_eq!(
+            label_appearance(LABEL_NEAR_DISTANCE),
+            Some((LABEL_FONT_SIZE, 1.0))
+        );
+
+        let (size, alpha) = label_appearance(2750.0).unwrap();
+        assert_eq!(size, (LABEL_FONT_SIZE + MIN_LABEL_FONT_SIZE) / 2.0);
+        assert_eq!(alpha, 0.5);
+
+        assert_eq!(label_appearance(LABEL_FAR_DISTANCE), None);
+    }
+
+    #[test]
+    fn test_move_waits_for_spawn_in_same_batch() {
+        let mut world = World::new();
+        world.insert_resource(ActorMap::default());
+        world.insert_resource(ActorAssets {
+            mesh: Handle::default(),
+            material: Handle::default(),
+        });
+        world.insert_resource(ActorInbox(vec![
+            ActorMessage::Spawn(ActorSpawn::new(
+                "enemy1".to_string(),
+                "goblin".to_string(),
+                Vector3::new(5.0, 0.0, 5.0),
+            )),
+            ActorMessage::Move(ActorMove::new(
+                "enemy1".to_string(),
+                Vector3::new(5.0, 0.0, 5.0),
+                Vector3::new(10.0, 0.0, 10.0),
+            )),
+            ActorMessage::Spawn(ActorSpawn::new(
+                "enemy2".to_string(),
+                "goblin".to_string(),
+                Vector3::new(0.0, 0.0, 0.0),
+            )),
+            ActorMessage::Despawn(ActorDespawn::new("enemy1".to_string())),
+        ]));
+
+        // Only the moved actor's messages wait
+        world.run_system_once(apply_actor_messages);
+        assert_eq!(world.resource::<ActorInbox>().0.len(), 2);
+        assert!(world.resource::<ActorMap>().0.contains_key("enemy2"));
+        let entity = world.resource::<ActorMap>().0["enemy1"];
+        assert_eq!(world.get::<Actor>(entity).unwrap().trail.len(), 1);
+
+        world.run_system_once(apply_actor_messages);
+        assert!(world.resource::<ActorInbox>().0.is_empty());
+        assert!(world.get_entity(entity).is_none());
+        assert!(!world.resource::<ActorMap>().0.contains_key("enemy1"));
+    }
+}
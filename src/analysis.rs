@@ -0,0 +1,207 @@
+use crate::stats::{walkable_components, MeshStats, SlopeHistogram};
+use crate::{face_normal, is_walkable, point_bounds, triangle_tiles, MIN_TILE_SIZE};
+use glam::Vec3;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// What the stats panel knows about a mesh, per tile too, as JSON so CI can
+// check a mesh without opening the viewer
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct MeshAnalysis {
+    pub vertex_count: usize,
+    pub triangle_count: usize,
+    pub bounds: Bounds,
+    pub tile_size: f32,
+    pub walkable_slope_angle: f32,
+    pub walkable_triangles: usize,
+    pub walkable_ratio: f32,
+    pub boundary_edges: usize,
+    pub non_manifold_edges: usize,
+    pub degenerate_triangles: usize,
+    pub components: ComponentAnalysis,
+    pub slope_histogram: SlopeHistogram,
+    pub tiles: Vec<TileAnalysis>, // Sorted by tile X, then Z
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+// Islands of walkable triangles, see `stats::walkable_components`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ComponentAnalysis {
+    pub count: usize,
+    pub largest: usize,
+    pub sizes: Vec<usize>, // Triangles in each, in order of their first triangle
+}
+
+// Triangles of one tile, those crossing tile edges count in every tile they
+// touch like when the mesh is split
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TileAnalysis {
+    pub tile_x: i32,
+    pub tile_z: i32,
+    pub triangles: usize,
+    pub walkable_triangles: usize,
+    pub walkable_ratio: f32,
+    pub components: usize, // Walkable islands with a triangle here
+    pub bounds: Bounds,    // Of the whole triangles, which can stick out of the tile
+}
+
+impl MeshAnalysis {
+    pub fn new(
+        vertices: &[Vec3],
+        indices: &[u32],
+        tile_size: f32,
+        walkable_slope_angle: f32,
+    ) -> Self {
+        let stats = MeshStats::new(vertices, indices, tile_size, walkable_slope_angle);
+        let components = walkable_components(vertices, indices, walkable_slope_angle);
+
+        let tile_size = tile_size.max(MIN_TILE_SIZE);
+        let mut tiles: BTreeMap<(i32, i32), Vec<usize>> = BTreeMap::new();
+        for triangle in 0..indices.len() / 3 {
+            for tile in triangle_tiles(vertices, indices, triangle, tile_size) {
+                tiles.entry(tile).or_default().push(triangle);
+            }
+        }
+        let tiles = tiles
+            .into_iter()
+            .map(|((tile_x, tile_z), triangles)| {
+                let walkable_triangles = triangles
+                    .iter()
+                    .filter(|&&triangle| {
+                        is_walkable(
+                            face_normal(vertices, indices, triangle),
+                            walkable_slope_angle,
+                        )
+                    })
+                    .count();
+                let islands: HashSet<usize> = triangles
+                    .iter()
+                    .filter_map(|&triangle| components.triangle_component[triangle])
+                    .collect();
+                let corners: Vec<Vec3> = triangles
+                    .iter()
+                    .flat_map(|&triangle| &indices[triangle * 3..triangle * 3 + 3])
+                    .map(|&index| vertices[index as usize])
+                    .collect();
+                TileAnalysis {
+                    tile_x,
+                    tile_z,
+                    triangles: triangles.len(),
+                    walkable_triangles,
+                    walkable_ratio: walkable_triangles as f32 / triangles.len() as f32,
+                    components: islands.len(),
+                    bounds: point_bounds(&corners).into(),
+                }
+            })
+            .collect();
+
+        Self {
+            vertex_count: stats.vertex_count,
+            triangle_count: stats.triangle_count,
+            bounds: stats.bounds.into(),
+            tile_size,
+            walkable_slope_angle,
+            walkable_triangles: stats.walkable_triangles,
+            walkable_ratio: stats.walkable_ratio(),
+            boundary_edges: stats.boundary_edges,
+            non_manifold_edges: stats.non_manifold_edges,
+            degenerate_triangles: stats.degenerate_triangles,
+            components: ComponentAnalysis {
+                count: stats.walkable_components,
+                largest: stats.largest_component,
+                sizes: components.sizes,
+            },
+            slope_histogram: stats.slope_histogram,
+            tiles,
+        }
+    }
+
+    pub fn write(&self, mut out: impl Write) -> io::Result<()> {
+        serde_json::to_writer_pretty(&mut out, self)?;
+        writeln!(out)?;
+        out.flush()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        self.write(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl From<(Vec3, Vec3)> for Bounds {
+    fn from((min, max): (Vec3, Vec3)) -> Self {
+        Self { min, max }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analysis_per_tile() {
+        // A floor quad over two tiles and a wall in the second one
+        let vertices = vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.5, 0.0, 0.0),
+            Vec3::new(0.5, 0.0, 0.5),
+            Vec3::new(0.0, 0.0, 0.5),
+            Vec3::new(1.5, 0.0, 0.0),
+            Vec3::new(1.5, 0.0, 0.5),
+            Vec3::new(1.5, 1.0, 0.0),
+        ];
+        let indices = vec![
+            0, 2, 1, 0, 3, 2, // First tile
+            4, 1, 2, 4, 2, 5, // Crossing into the second
+            4, 5, 6, // Wall
+        ];
+
+        let analysis = MeshAnalysis::new(&vertices, &indices, 1.0, 45.0);
+        assert_eq!(analysis.triangle_count, 5);
+        assert_eq!(analysis.walkable_triangles, 4);
+        assert_eq!(analysis.components.sizes, vec![4]);
+        assert_eq!(analysis.slope_histogram.bins[0], 4);
+
+        let tiles: Vec<_> = analysis
+            .tiles
+            .iter()
+            .map(|tile| {
+                (
+                    (tile.tile_x, tile.tile_z),
+                    tile.triangles,
+                    tile.walkable_triangles,
+                    tile.components,
+                )
+            })
+            .collect();
+        assert_eq!(tiles, vec![((0, 0), 4, 4, 1), ((1, 0), 3, 2, 1)]);
+        assert_eq!(analysis.tiles[1].walkable_ratio, 2.0 / 3.0);
+        assert_eq!(
+            analysis.tiles[1].bounds,
+            Bounds {
+                min: Vec3::new(0.5, 0.0, 0.0),
+                max: Vec3::new(1.5, 1.0, 0.5),
+            }
+        );
+
+        // Fields CI would read come out under these names
+        let mut json = Vec::new();
+        analysis.write(&mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["walkable_ratio"], 0.8);
+        assert_eq!(json["components"]["count"], 1);
+        assert_eq!(json["tiles"][1]["triangles"], 3);
+        assert_eq!(json["bounds"]["max"], serde_json::json!([1.5, 1.0, 0.5]));
+        assert_eq!(
+            json["slope_histogram"]["bins"].as_array().unwrap().len(),
+            90
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+// Saved camera views: name, position, yaw and pitch
+#[derive(Resource, Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct CameraBookmarks(pub Vec<(String, Vec3, f32, f32)>);
+
+#[derive(Debug)]
+pub enum BookmarkError {
+    IoError(io::Error),
+    ParseError(serde_json::Error),
+}
+
+impl From<io::Error> for BookmarkError {
+    fn from(error: io::Error) -> Self {
+        BookmarkError::IoError(error)
+    }
+}
+
+impl From<serde_json::Error> for BookmarkError {
+    fn from(error: serde_json::Error) -> Self {
+        BookmarkError::ParseError(error)
+    }
+}
+
+impl std::fmt::Display for BookmarkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BookmarkError::IoError(e) => write!(f, "{}", e),
+            BookmarkError::ParseError(e) => write!(f, "invalid bookmarks file: {}", e),
+        }
+    }
+}
+
+// bookmarks.json lives next to the executable
+pub fn bookmarks_path() -> PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+        .unwrap_or_default()
+        .join("bookmarks.json")
+}
+
+impl CameraBookmarks {
+    pub fn load(path: &Path) -> Result<Self, BookmarkError> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), BookmarkError> {
+        let file = File::create(path)?;
+        Ok(serde_json::to_writer_pretty(BufWriter::new(file), self)?)
+    }
+
+    // Missing file means no bookmarks yet, anything else is worth a warning
+    pub fn load_or_default(path: &Path) -> Self {
+        match Self::load(path) {
+            Ok(bookmarks) => bookmarks,
+            Err(BookmarkError::IoError(e)) if e.kind() == io::ErrorKind::NotFound => {
+                Self::default()
+            }
+            Err(e) => {
+                warn!("Failed to load {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bookmarks_round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("mesh_viewer_{}_bookmarks.json", std::process::id()));
+
+        let bookmarks = CameraBookmarks(vec![
+            ("spawn".to_string(), Vec3::new(1.0, 2.0, 3.0), 0.5, -0.25),
+            ("bridge".to_string(), Vec3::new(-10.0, 0.0, 42.0), -1.5, 0.0),
+        ]);
+        bookmarks.save(&path).unwrap();
+
+        assert_eq!(CameraBookmarks::load(&path).unwrap(), bookmarks);
+    }
+
+    #[test]
+    fn test_missing_bookmarks_file() {
+        let path = std::env::temp_dir().join("mesh_viewer_no_such_bookmarks.json");
+        assert_eq!(
+            CameraBookmarks::load_or_default(&path),
+            CameraBookmarks::default()
+        );
+    }
+}
@@ -0,0 +1,407 @@
+use crate::obj_loader::ObjData;
+use crate::point_bounds;
+use bevy::prelude::*;
+use bevy_egui::egui;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_BUDGET_MB: f32 = 2048.0;
+pub const MIN_BUDGET_MB: f32 = 64.0;
+
+// Rough bytes a vertex costs once the mesh is built: position, normal and
+// texture coordinates in `MeshData`, then the tile meshes with their colors,
+// kept on the CPU and uploaded to the GPU
+const BYTES_PER_VERTEX: usize = 32 + 2 * 48;
+
+// Indices and file vertex numbers in `MeshData`, the tile indices twice and
+// the picking grid
+const BYTES_PER_TRIANGLE: usize = 12 + 24 + 2 * 12 + 8;
+
+// Clustering cells start this much finer than the average triangle, then
+// double until few enough triangles are left
+const START_CELL_FRACTION: f32 = 0.125;
+const MAX_GROW_STEPS: usize = 32;
+
+// Halvings between the last two cell sizes, to get close to the target
+const REFINE_STEPS: usize = 6;
+
+// Size of a mesh before it is built
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MeshEstimate {
+    pub vertices: usize,
+    pub triangles: usize,
+}
+
+impl MeshEstimate {
+    pub fn of(obj: &ObjData) -> Self {
+        Self {
+            vertices: obj.vertices.len(),
+            triangles: obj
+                .faces
+                .iter()
+                .map(|face| face.len().saturating_sub(2))
+                .sum(),
+        }
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.vertices * BYTES_PER_VERTEX + self.triangles * BYTES_PER_TRIANGLE
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BudgetDecision {
+    Full,     // Build the mesh as it is
+    Decimate, // Cluster vertices until it fits the budget
+}
+
+// A loaded file over the budget, waiting for the user
+#[derive(Clone, PartialEq, Debug)]
+pub struct PendingMesh {
+    pub path: Option<PathBuf>,
+    pub estimate: MeshEstimate,
+}
+
+// Limit on the estimated memory of the primary mesh. Files over it are held
+// in `update_mesh` until the user picks what to do, and the choice sticks
+// while the same file is reloaded
+#[derive(Resource, Debug)]
+pub struct MeshBudget {
+    pub limit_mb: f32,
+    pub pending: Option<PendingMesh>,
+    decision: Option<(Option<PathBuf>, BudgetDecision)>,
+}
+
+impl Default for MeshBudget {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUDGET_MB)
+    }
+}
+
+impl MeshBudget {
+    pub fn new(limit_mb: f32) -> Self {
+        Self {
+            limit_mb: limit_mb.max(MIN_BUDGET_MB),
+            pending: None,
+            decision: None,
+        }
+    }
+
+    pub fn limit_bytes(&self) -> usize {
+        (self.limit_mb as f64 * 1024.0 * 1024.0) as usize
+    }
+
+    // Triangles that fit the budget, counting half a vertex for each like a
+    // typical closed mesh has
+    pub fn triangle_budget(&self) -> usize {
+        self.limit_bytes() / (BYTES_PER_TRIANGLE + BYTES_PER_VERTEX / 2)
+    }
+
+    // How to build `obj` loaded from `path`, None while it waits for the user
+    pub fn decide(&mut self, path: Option<&Path>, obj: &ObjData) -> Option<BudgetDecision> {
+        let estimate = MeshEstimate::of(obj);
+        let decision = match &self.decision {
+            _ if estimate.bytes() <= self.limit_bytes() => Some(BudgetDecision::Full),
+            Some((decided, decision)) if decided.as_deref() == path => Some(*decision),
+            _ => None,
+        };
+        self.pending = match decision {
+            Some(_) => None,
+            None => Some(PendingMesh {
+                path: path.map(Path::to_path_buf),
+                estimate,
+            }),
+        };
+        decision
+    }
+
+    // Answer for the pending file
+    pub fn choose(&mut self, decision: BudgetDecision) {
+        if let Some(pending) = self.pending.take() {
+            self.decision = Some((pending.path, decision));
+        }
+    }
+}
+
+// Result of `decimate`. `indices` point into `positions`, and `triangles` is
+// the original triangle behind each new one
+#[derive(Debug, PartialEq)]
+pub struct Decimated {
+    pub positions: Vec<Vec3>, // Mean of the vertices merged into each
+    pub indices: Vec<u32>,
+    pub triangles: Vec<usize>,
+    vertices: Vec<usize>, // First original vertex merged into each
+    merged: Vec<u32>,     // New vertex of each original one
+}
+
+impl Decimated {
+    // Per vertex values, taken from the first vertex merged into each new
+    // one. Empty stays empty
+    pub fn vertex_values<T: Copy>(&self, values: &[T]) -> Vec<T> {
+        if values.is_empty() {
+            return Vec::new();
+        }
+        self.vertices.iter().map(|&vertex| values[vertex]).collect()
+    }
+
+    // Per triangle values of the kept triangles, empty stays empty
+    pub fn triangle_values<T: Copy>(&self, values: &[T]) -> Vec<T> {
+        if values.is_empty() {
+            return Vec::new();
+        }
+        self.triangles
+            .iter()
+            .map(|&triangle| values[triangle])
+            .collect()
+    }
+
+    // Another list of triangles over the same vertices, like a material
+    // group, with the ones that collapsed left out
+    pub fn remap(&self, indices: &[u32]) -> Vec<u32> {
+        remap(&self.merged, indices).0
+    }
+}
+
+// Vertex clustering: vertices in the same grid cell merge into one at their
+// mean and triangles left without an area are dropped. The cells are the
+// smallest found that leave at most `target` triangles
+pub fn decimate(vertices: &[Vec3], indices: &[u32], target: usize) -> Decimated {
+    let (min, max) = point_bounds(vertices);
+    let extent = (max - min).max_element().max(f32::EPSILON);
+    let average = extent / ((indices.len() / 3) as f32).sqrt().max(1.0);
+
+    let mut fine = 0.0;
+    let mut cell = average * START_CELL_FRACTION;
+    let mut decimated = cluster(vertices, indices, min, cell);
+    for _ in 0..MAX_GROW_STEPS {
+        if decimated.triangles.len() <= target {
+            break;
+        }
+        fine = cell;
+        cell *= 2.0;
+        decimated = cluster(vertices, indices, min, cell);
+    }
+    if fine == 0.0 {
+        return decimated;
+    }
+    for _ in 0..REFINE_STEPS {
+        let middle = (fine + cell) * 0.5;
+        let candidate = cluster(vertices, indices, min, middle);
+        if candidate.triangles.len() <= target {
+            cell = middle;
+            decimated = candidate;
+        } else {
+            fine = middle;
+        }
+    }
+    decimated
+}
+
+fn cluster(vertices: &[Vec3], indices: &[u32], min: Vec3, cell: f32) -> Decimated {
+    let mut cells: HashMap<IVec3, u32> = HashMap::new();
+    let mut kept = Vec::new();
+    let mut sums: Vec<(Vec3, f32)> = Vec::new();
+    let merged: Vec<u32> = vertices
+        .iter()
+        .enumerate()
+        .map(|(vertex, &position)| {
+            let key = ((position - min) / cell).floor().as_ivec3();
+            let merged = *cells.entry(key).or_insert_with(|| {
+                kept.push(vertex);
+                sums.push((Vec3::ZERO, 0.0));
+                (kept.len() - 1) as u32
+            });
+            let (sum, count) = &mut sums[merged as usize];
+            *sum += position;
+            *count += 1.0;
+            merged
+        })
+        .collect();
+
+    let (indices, triangles) = remap(&merged, indices);
+    Decimated {
+        positions: sums.iter().map(|&(sum, count)| sum / count).collect(),
+        indices,
+        triangles,
+        vertices: kept,
+        merged,
+    }
+}
+
+fn remap(merged: &[u32], indices: &[u32]) -> (Vec<u32>, Vec<usize>) {
+    let mut new_indices = Vec::new();
+    let mut triangles = Vec::new();
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        let [a, b, c] = [0, 1, 2].map(|corner| merged[corners[corner] as usize]);
+        if a != b && b != c && a != c {
+            new_indices.extend([a, b, c]);
+            triangles.push(triangle);
+        }
+    }
+    (new_indices, triangles)
+}
+
+// Asks what to do with a file over the budget. True once the user has
+// answered or changed the limit, so the held file gets built
+pub fn budget_prompt(ctx: &egui::Context, budget: &mut MeshBudget) -> bool {
+    let Some(pending) = budget.pending.clone() else {
+        return false;
+    };
+    let mut answered = false;
+    egui::Window::new("Large Mesh")
+        .collapsible(false)
+        .show(ctx, |ui| {
+            let name = pending
+                .path
+                .as_deref()
+                .and_then(Path::file_name)
+                .map_or("The file".into(), |name| name.to_string_lossy());
+            ui.label(format!(
+                "{} needs about {} MB for {} vertices and {} triangles, over the {:.0} MB budget.",
+                name,
+                pending.estimate.bytes() / (1024 * 1024),
+                pending.estimate.vertices,
+                pending.estimate.triangles,
+                budget.limit_mb,
+            ));
+            ui.horizontal(|ui| {
+                if ui.button("Load Anyway").clicked() {
+                    budget.choose(BudgetDecision::Full);
+                    answered = true;
+                }
+                if ui
+                    .button(format!(
+                        "Decimate to {} Triangles",
+                        budget.triangle_budget()
+                    ))
+                    .clicked()
+                {
+                    budget.choose(BudgetDecision::Decimate);
+                    answered = true;
+                }
+            });
+            answered |= budget_limit(ui, budget);
+        });
+    answered
+}
+
+// Slider for the limit, true when it changed
+pub fn budget_limit(ui: &mut egui::Ui, budget: &mut MeshBudget) -> bool {
+    ui.add(
+        egui::DragValue::new(&mut budget.limit_mb)
+            .speed(16.0)
+            .clamp_range(MIN_BUDGET_MB..=f32::MAX)
+            .suffix(" MB memory budget"),
+    )
+    .changed()
+}
+
+// A 20 by 20 grid of quads on the floor, and a wall along its far edge. Shared
+// by the decimation tests
+#[cfg(test)]
+pub fn floor_and_wall() -> (Vec<Vec3>, Vec<u32>) {
+    let size = 21;
+    let mut vertices: Vec<Vec3> = (0..size * size)
+        .map(|i| Vec3::new((i % size) as f32, 0.0, (i / size) as f32))
+        .collect();
+    vertices.extend((0..size).map(|x| Vec3::new(x as f32, 10.0, 20.0)));
+    let mut indices = Vec::new();
+    let size = size as u32;
+    for z in 0..size - 1 {
+        for x in 0..size - 1 {
+            let corner = z * size + x;
+            indices.extend([corner, corner + size, corner + 1]);
+            indices.extend([corner + 1, corner + size, corner + size + 1]);
+        }
+    }
+    for x in 0..size - 1 {
+        let (bottom, top) = ((size - 1) * size + x, size * size + x);
+        indices.extend([bottom, top, bottom + 1]);
+        indices.extend([bottom + 1, top, top + 1]);
+    }
+    (vertices, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{face_normal, is_walkable};
+
+    #[test]
+    fn test_decimate_grid_to_budget() {
+        let (vertices, indices) = floor_and_wall();
+        let decimated = decimate(&vertices, &indices, 200);
+        let kept = decimated.triangles.len();
+        assert!(kept <= 200);
+        assert!(kept > 100, "Refining should get close, kept {}", kept);
+        assert_eq!(decimated.indices.len(), kept * 3);
+        assert!(decimated.vertices.len() < vertices.len());
+        assert_eq!(decimated.positions.len(), decimated.vertices.len());
+
+        // Kept triangles point at kept vertices and keep their order
+        assert!(decimated
+            .indices
+            .iter()
+            .all(|&index| (index as usize) < decimated.vertices.len()));
+        assert!(decimated.triangles.windows(2).all(|pair| pair[0] < pair[1]));
+        let positions = decimated.vertex_values(&vertices);
+        assert_eq!(positions[0], vertices[decimated.vertices[0]]);
+        assert!(decimated.vertex_values::<Vec2>(&[]).is_empty());
+
+        // Floor triangles stay walkable and wall ones don't
+        let walkable = |vertices: &[Vec3], indices: &[u32], triangle| {
+            is_walkable(face_normal(vertices, indices, triangle), 45.0)
+        };
+        for (new, &old) in decimated.triangles.iter().enumerate() {
+            assert_eq!(
+                walkable(&decimated.positions, &decimated.indices, new),
+                walkable(&vertices, &indices, old)
+            );
+        }
+
+        // A budget it already fits keeps every triangle
+        let all = decimate(&vertices, &indices, indices.len() / 3);
+        assert_eq!(all.triangles.len(), indices.len() / 3);
+        assert_eq!(all.positions, vertices);
+    }
+
+    #[test]
+    fn test_budget_holds_large_files_until_answered() {
+        // Two quads, four triangles
+        let path = std::env::temp_dir().join(format!("budget_{}.obj", std::process::id()));
+        std::fs::write(
+            &path,
+            "v 0 0 0\nv 1 0 0\nv 1 0 1\nv 0 0 1\nf 1 2 3 4\nf 4 3 2 1\n",
+        )
+        .unwrap();
+        let obj = crate::obj_loader::load_obj(&path).unwrap();
+        let path = path.as_path();
+        let estimate = MeshEstimate::of(&obj);
+        assert_eq!(
+            estimate,
+            MeshEstimate {
+                vertices: 4,
+                triangles: 4
+            }
+        );
+
+        let mut budget = MeshBudget::new(1.0);
+        assert_eq!(budget.limit_mb, MIN_BUDGET_MB);
+        assert_eq!(budget.decide(Some(path), &obj), Some(BudgetDecision::Full));
+        assert_eq!(budget.pending, None);
+
+        // Over a budget too small for it, it waits
+        budget.limit_mb = estimate.bytes() as f32 / (2.0 * 1024.0 * 1024.0);
+        assert_eq!(budget.decide(Some(path), &obj), None);
+        assert_eq!(budget.pending.as_ref().unwrap().estimate, estimate);
+
+        // The answer sticks for reloads of that file only
+        budget.choose(BudgetDecision::Decimate);
+        assert_eq!(budget.pending, None);
+        assert_eq!(
+            budget.decide(Some(path), &obj),
+            Some(BudgetDecision::Decimate)
+        );
+        assert_eq!(budget.decide(Some(Path::new("other.obj")), &obj), None);
+    }
+}
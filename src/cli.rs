@@ -0,0 +1,93 @@
+use std::path::PathBuf;
+
+pub const USAGE: &str =
+    "usage: mesh_viewer [file.obj] [--headless <file.obj>] [--tile-size <size>] [--slope <degrees>] [--analysis <out.json>]";
+
+// Options given on the command line
+#[derive(Debug, PartialEq)]
+pub struct CliArgs {
+    pub obj_path: Option<PathBuf>, // Loaded on startup
+    pub headless: Option<PathBuf>, // Print stats for this OBJ instead of opening a window
+    pub tile_size: Option<f32>,
+    pub slope: Option<f32>,        // Walkable slope angle in degrees
+    pub analysis: Option<PathBuf>, // JSON analysis written in headless mode
+}
+
+// Parse the arguments after the program name
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<CliArgs, String> {
+    let mut parsed = CliArgs {
+        obj_path: None,
+        headless: None,
+        tile_size: None,
+        slope: None,
+        analysis: None,
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+        match arg.as_str() {
+            "--headless" => parsed.headless = Some(PathBuf::from(value("--headless")?)),
+            "--tile-size" => {
+                parsed.tile_size = Some(parse_number("--tile-size", value("--tile-size")?)?)
+            }
+            "--slope" => parsed.slope = Some(parse_number("--slope", value("--slope")?)?),
+            "--analysis" => parsed.analysis = Some(PathBuf::from(value("--analysis")?)),
+            _ if arg.starts_with("--") => return Err(format!("unknown argument: {}", arg)),
+            _ if parsed.obj_path.is_some() => {
+                return Err(format!("only one file can be opened, got {}", arg))
+            }
+            _ => parsed.obj_path = Some(PathBuf::from(arg)),
+        }
+    }
+    if parsed.analysis.is_some() && parsed.headless.is_none() {
+        return Err("--analysis needs --headless".to_string());
+    }
+    Ok(parsed)
+}
+
+fn parse_number(name: &str, value: String) -> Result<f32, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} expects a number, got {}", name, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CliArgs, String> {
+        parse_args(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse(&["--headless", "level.obj", "--slope", "30"]).unwrap(),
+            CliArgs {
+                obj_path: None,
+                headless: Some(PathBuf::from("level.obj")),
+                tile_size: None,
+                slope: Some(30.0),
+                analysis: None,
+            }
+        );
+        assert_eq!(
+            parse(&["--headless", "level.obj", "--analysis", "level.json"])
+                .unwrap()
+                .analysis,
+            Some(PathBuf::from("level.json"))
+        );
+        assert!(parse(&["--analysis", "level.json"]).is_err());
+        assert!(parse(&["--headless"]).is_err());
+        assert!(parse(&["--tile-size", "big"]).is_err());
+        assert!(parse(&["--verbose"]).is_err());
+
+        // A plain path is the file to open
+        assert_eq!(
+            parse(&["level.obj"]).unwrap().obj_path,
+            Some(PathBuf::from("level.obj"))
+        );
+        assert!(parse(&["a.obj", "b.obj"]).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+use crate::net::Vector3;
+use bevy::prelude::*;
+
+// Up axis and handedness of the game's coordinates
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum CoordinatePreset {
+    #[default]
+    YUp, // Same as Bevy, right-handed
+    ZUp, // Right-handed, Z up
+    YUpLeftHanded,
+    ZUpLeftHanded,
+}
+
+impl CoordinatePreset {
+    pub const ALL: [CoordinatePreset; 4] = [
+        CoordinatePreset::YUp,
+        CoordinatePreset::ZUp,
+        CoordinatePreset::YUpLeftHanded,
+        CoordinatePreset::ZUpLeftHanded,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            CoordinatePreset::YUp => "Y-up",
+            CoordinatePreset::ZUp => "Z-up",
+            CoordinatePreset::YUpLeftHanded => "Y-up (left-handed)",
+            CoordinatePreset::ZUpLeftHanded => "Z-up (left-handed)",
+        }
+    }
+
+    // Game component and sign for each of Bevy's x, y and z
+    fn axes(self) -> [(usize, f32); 3] {
+        match self {
+            CoordinatePreset::YUp => [(0, 1.0), (1, 1.0), (2, 1.0)],
+            CoordinatePreset::ZUp => [(0, 1.0), (2, 1.0), (1, -1.0)],
+            CoordinatePreset::YUpLeftHanded => [(0, 1.0), (1, 1.0), (2, -1.0)],
+            CoordinatePreset::ZUpLeftHanded => [(0, 1.0), (2, 1.0), (1, 1.0)],
+        }
+    }
+}
+
+// Maps game coordinates, from the network and from OBJ files, into Bevy's
+// Y-up right-handed space
+#[derive(Resource, Clone, Copy, PartialEq, Debug)]
+pub struct CoordinateTransform {
+    pub preset: CoordinatePreset,
+    pub scale: f32, // Game units to viewer units, applied after the remap
+}
+
+impl Default for CoordinateTransform {
+    fn default() -> Self {
+        Self {
+            preset: CoordinatePreset::YUp,
+            scale: 1.0,
+        }
+    }
+}
+
+impl CoordinateTransform {
+    // Remap a direction, without scaling
+    pub fn direction(&self, v: [f32; 3]) -> Vec3 {
+        let [x, y, z] = self.preset.axes().map(|(axis, sign)| v[axis] * sign);
+        Vec3::new(x, y, z)
+    }
+
+    pub fn point(&self, v: [f32; 3]) -> Vec3 {
+        self.direction(v) * self.scale
+    }
+
+    pub fn vector3(&self, v: Vector3) -> Vector3 {
+        let p = self.point([v.x, v.y, v.z]);
+        Vector3::new(p.x, p.y, p.z)
+    }
+
+    // A mirroring remap turns triangles inside out
+    pub fn flips_winding(&self) -> bool {
+        let m = Mat3::from_cols(
+            self.direction([1.0, 0.0, 0.0]),
+            self.direction([0.0, 1.0, 0.0]),
+            self.direction([0.0, 0.0, 1.0]),
+        );
+        m.determinant() < 0.0
+    }
+
+    // Transform a loaded mesh in place, keeping its faces pointing outwards
+    pub fn apply_to_mesh(&self, vertices: &mut [Vec3], indices: &mut [u32], normals: &mut [Vec3]) {
+        if *self == Self::default() {
+            return;
+        }
+        for v in vertices.iter_mut() {
+            *v = self.point(v.to_array());
+        }
+        for n in normals.iter_mut() {
+            *n = self.direction(n.to_array());
+        }
+        if self.flips_winding() {
+            for triangle in indices.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::face_normal;
+
+    #[test]
+    fn test_z_up_to_y_up() {
+        let transform = CoordinateTransform {
+            preset: CoordinatePreset::ZUp,
+            scale: 1.0,
+        };
+        // Game up is Bevy up, game +Y (forward) goes into the screen
+        assert_eq!(transform.point([0.0, 0.0, 1.0]), Vec3::Y);
+        assert_eq!(transform.point([1.0, 2.0, 3.0]), Vec3::new(1.0, 3.0, -2.0));
+        assert!(!transform.flips_winding());
+
+        let transform = CoordinateTransform {
+            preset: CoordinatePreset::ZUpLeftHanded,
+            scale: 0.01,
+        };
+        assert_eq!(
+            transform.point([100.0, 200.0, 300.0]),
+            Vec3::new(1.0, 3.0, 2.0)
+        );
+        assert!(transform.flips_winding());
+    }
+
+    #[test]
+    fn test_mirrored_mesh_keeps_facing_up() {
+        // A floor in left-handed Z-up space, wound so its cross product
+        // points up there
+        let mut vertices = vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ];
+        let mut indices = vec![0, 1, 2];
+        let mut normals = vec![Vec3::Z; 3];
+
+        let transform = CoordinateTransform {
+            preset: CoordinatePreset::ZUpLeftHanded,
+            scale: 1.0,
+        };
+        transform.apply_to_mesh(&mut vertices, &mut indices, &mut normals);
+
+        assert_eq!(normals, vec![Vec3::Y; 3]);
+        assert_eq!(face_normal(&vertices, &indices, 0), Vec3::Y);
+    }
+}
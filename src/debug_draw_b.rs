@@ -1,3 +1,5 @@
+use bevy::gizmos::gizmos::Gizmos;
+use bevy::render::color::Color;
 use glam::Vec2;
 use glam::{Vec3, Vec4};
 use std::f32::consts::PI;
@@ -85,6 +87,123 @@ pub fn du_debug_draw_tri_mesh_slope(
     dd.texture(false);
 }
 
+// Polyline through `points`, oldest first. Older segments fade towards
+// transparent, the newest segment is drawn with `color`
+pub fn du_debug_draw_polyline_fade(
+    dd: &mut impl DebugDraw,
+    points: impl ExactSizeIterator<Item = Vec3>,
+    color: Vec4,
+    line_width: f32,
+) {
+    let count = points.len();
+    if count < 2 {
+        return;
+    }
+
+    dd.begin(DU_DRAW_LINES, line_width);
+
+    let mut points = points.enumerate();
+    let (_, mut prev) = points.next().unwrap();
+    for (i, point) in points {
+        let alpha = i as f32 / (count - 1) as f32;
+        let prev_alpha = (i - 1) as f32 / (count - 1) as f32;
+        dd.vertex(
+            prev,
+            Vec4::new(color.x, color.y, color.z, color.w * prev_alpha),
+        );
+        dd.vertex(point, Vec4::new(color.x, color.y, color.z, color.w * alpha));
+        prev = point;
+    }
+
+    dd.end();
+}
+
+// Grid of `w` x `h` cells of `size` on the XZ plane, starting at `origin`
+pub fn du_debug_draw_grid_xz(
+    dd: &mut impl DebugDraw,
+    origin: Vec3,
+    w: i32,
+    h: i32,
+    size: f32,
+    color: Vec4,
+    line_width: f32,
+) {
+    dd.begin(DU_DRAW_LINES, line_width);
+    for i in 0..=h {
+        dd.vertex(origin + Vec3::new(0.0, 0.0, i as f32 * size), color);
+        dd.vertex(
+            origin + Vec3::new(w as f32 * size, 0.0, i as f32 * size),
+            color,
+        );
+    }
+    for i in 0..=w {
+        dd.vertex(origin + Vec3::new(i as f32 * size, 0.0, 0.0), color);
+        dd.vertex(
+            origin + Vec3::new(i as f32 * size, 0.0, h as f32 * size),
+            color,
+        );
+    }
+    dd.end();
+}
+
+// X, Y and Z axis lines of `length` from `origin` in red, green and blue
+pub fn du_debug_draw_axes(dd: &mut impl DebugDraw, origin: Vec3, length: f32, line_width: f32) {
+    dd.begin(DU_DRAW_LINES, line_width);
+    for (axis, color) in [
+        (Vec3::X, Vec4::new(1.0, 0.0, 0.0, 1.0)),
+        (Vec3::Y, Vec4::new(0.0, 1.0, 0.0, 1.0)),
+        (Vec3::Z, Vec4::new(0.0, 0.0, 1.0, 1.0)),
+    ] {
+        dd.vertex(origin, color);
+        dd.vertex(origin + axis * length, color);
+    }
+    dd.end();
+}
+
+// Line through the measured points with an upright tick of `tick` at each
+pub fn du_debug_draw_measure(
+    dd: &mut impl DebugDraw,
+    points: &[Vec3],
+    tick: f32,
+    color: Vec4,
+    line_width: f32,
+) {
+    if points.is_empty() {
+        return;
+    }
+
+    dd.begin(DU_DRAW_LINES, line_width);
+    for pair in points.windows(2) {
+        dd.vertex(pair[0], color);
+        dd.vertex(pair[1], color);
+    }
+    for &point in points {
+        dd.vertex(point, color);
+        dd.vertex(point + Vec3::Y * tick, color);
+    }
+    dd.end();
+}
+
+// Edges given as pairs of indices into `verts`, all in one color
+pub fn du_debug_draw_edges(
+    dd: &mut impl DebugDraw,
+    verts: &[Vec3],
+    edges: &[(u32, u32)],
+    color: Vec4,
+    line_width: f32,
+) {
+    if edges.is_empty() {
+        return;
+    }
+
+    dd.begin(DU_DRAW_LINES, line_width);
+    for &(a, b) in edges {
+        dd.vertex(verts[a as usize], color);
+        dd.vertex(verts[b as usize], color);
+    }
+    dd.end();
+}
+
 fn tex_coord(v: &Vec3, ax: usize, ay: usize, scale: f32) -> Vec2 {
     Vec2::new(v[ax] * scale, v[ay] * scale)
 }
@@ -103,4 +222,215 @@ pub trait DebugDraw {
 }
 
 // Constants
+pub const DU_DRAW_POINTS: i32 = 0;
+pub const DU_DRAW_LINES: i32 = 1;
 pub const DU_DRAW_TRIS: i32 = 2;
+
+// DebugDraw on top of Bevy gizmos. Gizmos only draw lines, so points become
+// small crosses and triangles become their outlines
+pub struct GizmoDebugDraw<'a, 's> {
+    gizmos: &'a mut Gizmos<'s>,
+    prim: i32,
+    size: f32,
+    vertices: Vec<(Vec3, Vec4)>,
+}
+
+impl<'a, 's> GizmoDebugDraw<'a, 's> {
+    pub fn new(gizmos: &'a mut Gizmos<'s>) -> Self {
+        Self {
+            gizmos,
+            prim: DU_DRAW_LINES,
+            size: 1.0,
+            vertices: Vec::new(),
+        }
+    }
+}
+
+fn to_color(c: Vec4) -> Color {
+    Color::rgba(c.x, c.y, c.z, c.w)
+}
+
+impl DebugDraw for GizmoDebugDraw<'_, '_> {
+    fn begin(&mut self, prim: i32, size: f32) {
+        self.prim = prim;
+        self.size = size;
+        self.vertices.clear();
+    }
+
+    fn end(&mut self) {
+        let gizmos = &mut *self.gizmos;
+        match self.prim {
+            DU_DRAW_POINTS => {
+                for (pos, color) in &self.vertices {
+                    let half = self.size * 0.5;
+                    gizmos.line(
+                        *pos - Vec3::X * half,
+                        *pos + Vec3::X * half,
+                        to_color(*color),
+                    );
+                    gizmos.line(
+                        *pos - Vec3::Z * half,
+                        *pos + Vec3::Z * half,
+                        to_color(*color),
+                    );
+                }
+            }
+            DU_DRAW_LINES => {
+                for pair in self.vertices.chunks_exact(2) {
+                    let (a, ca) = pair[0];
+                    let (b, cb) = pair[1];
+                    gizmos.line_gradient(a, b, to_color(ca), to_color(cb));
+                }
+            }
+            _ => {
+                for tri in self.vertices.chunks_exact(3) {
+                    for (i, j) in [(0, 1), (1, 2), (2, 0)] {
+                        let (a, ca) = tri[i];
+                        let (b, cb) = tri[j];
+                        gizmos.line_gradient(a, b, to_color(ca), to_color(cb));
+                    }
+                }
+            }
+        }
+        self.vertices.clear();
+    }
+
+    fn vertex(&mut self, pos: Vec3, color: Vec4) {
+        self.vertices.push((pos, color));
+    }
+
+    fn vertex_uv(&mut self, pos: Vec3, color: Vec4, _uv: Vec2) {
+        self.vertices.push((pos, color));
+    }
+
+    // Gizmo lines are never textured
+    fn texture(&mut self, _state: bool) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Records what was drawn
+    #[derive(Default)]
+    struct RecordingDraw {
+        prims: Vec<i32>,
+        vertices: Vec<(Vec3, Vec4)>,
+    }
+
+    impl DebugDraw for RecordingDraw {
+        fn begin(&mut self, prim: i32, _size: f32) {
+            self.prims.push(prim);
+        }
+        fn end(&mut self) {}
+        fn vertex(&mut self, pos: Vec3, color: Vec4) {
+            self.vertices.push((pos, color));
+        }
+        fn vertex_uv(&mut self, pos: Vec3, color: Vec4, _uv: Vec2) {
+            self.vertices.push((pos, color));
+        }
+        fn texture(&mut self, _state: bool) {}
+    }
+
+    #[test]
+    fn test_grid_xz() {
+        let mut dd = RecordingDraw::default();
+        du_debug_draw_grid_xz(
+            &mut dd,
+            Vec3::new(10.0, 5.0, 20.0),
+            3,
+            2,
+            4.0,
+            Vec4::ONE,
+            1.0,
+        );
+
+        // h + 1 lines along X and w + 1 lines along Z
+        assert_eq!(dd.prims, vec![DU_DRAW_LINES]);
+        assert_eq!(dd.vertices.len(), 2 * ((2 + 1) + (3 + 1)));
+        assert_eq!(dd.vertices[0].0, Vec3::new(10.0, 5.0, 20.0));
+        assert_eq!(dd.vertices[5].0, Vec3::new(22.0, 5.0, 28.0));
+        assert!(dd.vertices.iter().all(|(v, _)| v.y == 5.0));
+    }
+
+    #[test]
+    fn test_axes() {
+        let mut dd = RecordingDraw::default();
+        du_debug_draw_axes(&mut dd, Vec3::ONE, 2.0, 1.0);
+
+        assert_eq!(dd.prims, vec![DU_DRAW_LINES]);
+        let ends: Vec<Vec3> = dd
+            .vertices
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .map(|(v, _)| *v)
+            .collect();
+        assert_eq!(
+            ends,
+            vec![
+                Vec3::new(3.0, 1.0, 1.0),
+                Vec3::new(1.0, 3.0, 1.0),
+                Vec3::new(1.0, 1.0, 3.0)
+            ]
+        );
+        assert_eq!(dd.vertices[1].1, Vec4::new(1.0, 0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_polyline_fade() {
+        let points = [Vec3::ZERO, Vec3::X, Vec3::new(1.0, 0.0, 1.0)];
+        let mut dd = RecordingDraw::default();
+        du_debug_draw_polyline_fade(&mut dd, points.iter().copied(), Vec4::ONE, 2.0);
+
+        assert_eq!(dd.prims, vec![DU_DRAW_LINES]);
+        assert_eq!(dd.vertices.len(), 4); // Two segments
+
+        // Oldest end is transparent, newest is fully opaque
+        let alphas: Vec<f32> = dd.vertices.iter().map(|(_, c)| c.w).collect();
+        assert_eq!(alphas, vec![0.0, 0.5, 0.5, 1.0]);
+        assert_eq!(dd.vertices[3].0, points[2]);
+
+        // A single point has nothing to connect
+        let mut dd = RecordingDraw::default();
+        du_debug_draw_polyline_fade(&mut dd, [Vec3::ZERO].into_iter(), Vec4::ONE, 2.0);
+        assert!(dd.prims.is_empty());
+    }
+
+    #[test]
+    fn test_edges() {
+        let verts = [Vec3::ZERO, Vec3::X, Vec3::Z];
+        let mut dd = RecordingDraw::default();
+        du_debug_draw_edges(&mut dd, &verts, &[], Vec4::ONE, 1.0);
+        assert!(dd.prims.is_empty());
+
+        du_debug_draw_edges(&mut dd, &verts, &[(0, 2), (1, 2)], Vec4::ONE, 1.0);
+        assert_eq!(dd.prims, vec![DU_DRAW_LINES]);
+        let points: Vec<Vec3> = dd.vertices.iter().map(|(v, _)| *v).collect();
+        assert_eq!(points, vec![Vec3::ZERO, Vec3::Z, Vec3::X, Vec3::Z]);
+    }
+
+    #[test]
+    fn test_measure() {
+        let mut dd = RecordingDraw::default();
+        du_debug_draw_measure(&mut dd, &[], 1.0, Vec4::ONE, 1.0);
+        assert!(dd.prims.is_empty());
+
+        // The segment, then a tick at each end
+        let (a, b) = (Vec3::ZERO, Vec3::new(3.0, 1.0, 4.0));
+        du_debug_draw_measure(&mut dd, &[a, b], 0.5, Vec4::ONE, 1.0);
+        assert_eq!(dd.prims, vec![DU_DRAW_LINES]);
+        let points: Vec<Vec3> = dd.vertices.iter().map(|(v, _)| *v).collect();
+        assert_eq!(
+            points,
+            vec![
+                a,
+                b,
+                a,
+                Vec3::new(0.0, 0.5, 0.0),
+                b,
+                Vec3::new(3.0, 1.5, 4.0)
+            ]
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use crate::budget::decimate;
+use crate::{point_bounds, world, MeshData, MeshViewer};
+use bevy::prelude::*;
+use bevy_egui::egui;
+
+pub const MAX_REDUCTION: f32 = 99.0;
+
+// Lighter copy of the primary mesh for overviews of dense files. While it is
+// on `MeshData` holds the decimated mesh and `original` the full one, so
+// turning it off brings the detail back without reloading
+#[derive(Resource)]
+pub struct Decimation {
+    pub enabled: bool,
+    pub reduction: f32, // Percent of the triangles to take away
+    slider: f32,        // Reduction being dragged to, applied once let go
+    original: Option<MeshData>,
+}
+
+impl Default for Decimation {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            reduction: 50.0,
+            slider: 50.0,
+            original: None,
+        }
+    }
+}
+
+impl Decimation {
+    // The mesh to show for the full mesh `full`, which is kept while
+    // decimation is on
+    pub fn apply(&mut self, full: MeshData) -> MeshData {
+        if !self.enabled {
+            self.original = None;
+            return full;
+        }
+        let shown = decimate_mesh(&full, self.reduction);
+        self.original = Some(full);
+        shown
+    }
+
+    pub fn original(&self) -> Option<&MeshData> {
+        self.original.as_ref()
+    }
+}
+
+// `mesh` with `reduction` percent of its triangles taken away, near enough
+pub fn decimate_mesh(mesh: &MeshData, reduction: f32) -> MeshData {
+    let triangles = mesh.indices.len() / 3;
+    let keep = 1.0 - reduction.clamp(0.0, MAX_REDUCTION) / 100.0;
+    let decimated = decimate(
+        &mesh.vertices,
+        &mesh.indices,
+        (triangles as f32 * keep).round() as usize,
+    );
+    MeshData {
+        bounds: point_bounds(&decimated.positions),
+        normals: decimated.vertex_values(&mesh.normals),
+        uvs: decimated.vertex_values(&mesh.uvs),
+        tile_size: mesh.tile_size,
+        material_groups: mesh
+            .material_groups
+            .iter()
+            .map(|(material, indices)| (material.clone(), decimated.remap(indices)))
+            .collect(),
+        file_vertices: decimated.triangle_values(&mesh.file_vertices),
+        vertices: decimated.positions,
+        indices: decimated.indices,
+    }
+}
+
+pub fn decimation_controls(ui: &mut egui::Ui, decimation: &mut Decimation, shown: usize) {
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut decimation.enabled, "Decimate");
+        let slider = ui.add_enabled(
+            decimation.enabled,
+            egui::Slider::new(&mut decimation.slider, 0.0..=MAX_REDUCTION)
+                .suffix("%")
+                .text("Reduction"),
+        );
+        // Every new reduction decimates the whole mesh again, so a drag only
+        // counts once it ends. Typed or clicked values apply right away
+        if slider.drag_released() || (slider.changed() && !slider.dragged()) {
+            decimation.reduction = decimation.slider;
+        }
+    });
+    if let Some(original) = decimation.original() {
+        ui.label(format!(
+            "Showing {} of {} triangles",
+            shown,
+            original.indices.len() / 3
+        ));
+    }
+}
+
+// Swap the shown mesh when decimation is turned on or off or the reduction
+// changes, always starting from the full mesh
+pub fn update_decimation(
+    mut commands: Commands,
+    mut decimation: ResMut<Decimation>,
+    mesh_data: Option<ResMut<MeshData>>,
+    mut mesh_viewer: Query<&mut MeshViewer>,
+    mut last: Local<Option<(bool, f32)>>,
+) {
+    let current = (decimation.enabled, decimation.reduction);
+    let Some(previous) = last.replace(current) else {
+        return;
+    };
+    let Some(mut mesh_data) = mesh_data.filter(|_| previous != current) else {
+        return;
+    };
+    // Changing only the reduction of a mesh shown in full changes nothing
+    if !previous.0 && !current.0 {
+        return;
+    }
+    let full = match decimation.original.take() {
+        Some(full) => full,
+        None => mesh_data.clone(),
+    };
+    *mesh_data = decimation.apply(full);
+    commands.insert_resource(world::TriangleGrid::build(
+        &mesh_data.vertices,
+        &mesh_data.indices,
+    ));
+    for mut viewer in mesh_viewer.iter_mut() {
+        viewer.needs_rebuild = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::budget::floor_and_wall;
+
+    #[test]
+    fn test_decimation_keeps_the_full_mesh() {
+        let (vertices, indices) = floor_and_wall();
+        let triangles = indices.len() / 3;
+        let full = MeshData {
+            bounds: point_bounds(&vertices),
+            normals: vec![Vec3::Y; vertices.len()],
+            uvs: Vec::new(),
+            tile_size: 10.0,
+            material_groups: vec![(Handle::default(), indices.clone())],
+            file_vertices: vec![[1, 2, 3]; triangles],
+            vertices,
+            indices,
+        };
+
+        let mut decimation = Decimation {
+            enabled: true,
+            reduction: 75.0,
+            ..default()
+        };
+        let shown = decimation.apply(full);
+        let kept = shown.indices.len() / 3;
+        assert!(kept <= triangles / 4);
+        assert_eq!(shown.material_groups[0].1, shown.indices);
+        assert_eq!(shown.file_vertices.len(), kept);
+        assert_eq!(shown.normals.len(), shown.vertices.len());
+        assert_eq!(decimation.original().unwrap().indices.len() / 3, triangles);
+
+        // Turned off, the full mesh comes back as it was
+        decimation.enabled = false;
+        let full = decimation.original.take().unwrap();
+        let shown = decimation.apply(full);
+        assert_eq!(shown.indices.len() / 3, triangles);
+        assert!(decimation.original().is_none());
+    }
+}
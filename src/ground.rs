@@ -0,0 +1,173 @@
+use crate::{layers, transformed_bounds, MeshData};
+use bevy::prelude::*;
+
+// Room around the mesh bounds, as a fraction of their larger side
+const GROUND_MARGIN: f32 = 0.25;
+
+// About this many minor lines span the grid
+const GRID_LINES: f32 = 50.0;
+
+// Every this many minor lines is drawn brighter
+const MAJOR_LINE_EVERY: i64 = 10;
+
+const MINOR_LINE_COLOR: Color = Color::rgba(0.5, 0.5, 0.55, 0.35);
+const MAJOR_LINE_COLOR: Color = Color::rgba(0.75, 0.75, 0.8, 0.7);
+
+// Reference grid under the mesh for depth cues, sized to the mesh bounds
+#[derive(Resource)]
+pub struct GroundPlane {
+    pub show: bool,
+    pub height: f32,
+    pub auto: bool, // Follow the bottom of the loaded mesh
+}
+
+impl Default for GroundPlane {
+    fn default() -> Self {
+        Self {
+            show: false,
+            height: 0.0,
+            auto: true,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct GroundMesh;
+
+// Area of the XZ plane the grid covers: the bounds grown by `GROUND_MARGIN`,
+// as (min, max) in world X and Z
+pub fn ground_extent((min, max): (Vec3, Vec3)) -> (Vec2, Vec2) {
+    let (min, max) = (Vec2::new(min.x, min.z), Vec2::new(max.x, max.z));
+    let margin = ((max - min).max_element() * GROUND_MARGIN).max(1.0);
+    (min - margin, max + margin)
+}
+
+// Power of ten between grid lines, so about `GRID_LINES` cover `size`
+pub fn grid_step(size: f32) -> f32 {
+    10.0_f32.powf((size.max(1.0) / GRID_LINES).log10().round())
+}
+
+pub fn setup_ground(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(shape::Plane::from_size(1.0).into()),
+            material: materials.add(StandardMaterial {
+                base_color: Color::rgba(0.12, 0.12, 0.14, 0.6),
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                cull_mode: None,
+                ..default()
+            }),
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        GroundMesh,
+    ));
+}
+
+// Keep the plane under the primary mesh where it is drawn, and its height
+// at the mesh bottom while `auto` is on
+pub fn update_ground_plane(
+    mut ground: ResMut<GroundPlane>,
+    mesh_data: Option<Res<MeshData>>,
+    layers: Res<layers::MeshLayers>,
+    mut planes: Query<(&mut Transform, &mut Visibility), With<GroundMesh>>,
+) {
+    let bounds = mesh_data.as_ref().map(|mesh_data| {
+        transformed_bounds(mesh_data.bounds, &layers.transform(layers::PRIMARY_LAYER))
+    });
+    if let (true, Some((min, _))) = (ground.auto, bounds) {
+        if ground.height != min.y {
+            ground.height = min.y;
+        }
+    }
+
+    let Ok((mut transform, mut visibility)) = planes.get_single_mut() else {
+        return;
+    };
+    let wanted = match bounds {
+        Some(_) if ground.show => Visibility::Inherited,
+        _ => Visibility::Hidden,
+    };
+    if *visibility != wanted {
+        *visibility = wanted;
+    }
+    let Some(bounds) = bounds else {
+        return;
+    };
+
+    // Just below the grid lines so they don't fight over depth
+    let (min, max) = ground_extent(bounds);
+    let step = grid_step((max - min).max_element());
+    let center = (min + max) * 0.5;
+    let size = max - min;
+    let wanted = Transform::from_xyz(center.x, ground.height - step * 0.01, center.y)
+        .with_scale(Vec3::new(size.x, 1.0, size.y));
+    if *transform != wanted {
+        *transform = wanted;
+    }
+}
+
+pub fn draw_ground_grid(
+    mut gizmos: Gizmos,
+    ground: Res<GroundPlane>,
+    mesh_data: Option<Res<MeshData>>,
+    layers: Res<layers::MeshLayers>,
+) {
+    let Some(mesh_data) = mesh_data.filter(|_| ground.show) else {
+        return;
+    };
+    let bounds = transformed_bounds(mesh_data.bounds, &layers.transform(layers::PRIMARY_LAYER));
+    let (min, max) = ground_extent(bounds);
+    let step = grid_step((max - min).max_element());
+
+    // Lines sit on multiples of the step, so they read like a ruler
+    let (first, last) = ((min / step).ceil(), (max / step).floor());
+    let y = ground.height;
+    for i in first.x as i64..=last.x as i64 {
+        let x = i as f32 * step;
+        gizmos.line(
+            Vec3::new(x, y, min.y),
+            Vec3::new(x, y, max.y),
+            line_color(i),
+        );
+    }
+    for i in first.y as i64..=last.y as i64 {
+        let z = i as f32 * step;
+        gizmos.line(
+            Vec3::new(min.x, y, z),
+            Vec3::new(max.x, y, z),
+            line_color(i),
+        );
+    }
+}
+
+fn line_color(index: i64) -> Color {
+    if index % MAJOR_LINE_EVERY == 0 {
+        MAJOR_LINE_COLOR
+    } else {
+        MINOR_LINE_COLOR
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ground_extent_and_step() {
+        let bounds = (Vec3::new(0.0, -5.0, 0.0), Vec3::new(1000.0, 20.0, 400.0));
+        let (min, max) = ground_extent(bounds);
+        assert_eq!(min, Vec2::new(-250.0, -250.0));
+        assert_eq!(max, Vec2::new(1250.0, 650.0));
+
+        // 1500 wide over about 50 lines
+        assert_eq!(grid_step(1500.0), 10.0);
+        assert_eq!(grid_step(50_000.0), 1000.0);
+        assert_eq!(grid_step(0.0), 0.01);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::stats::walkable_components;
+use crate::{face_normal, insert_vertex_attributes, layers, MeshData, MeshViewer, RenderMode};
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+
+// Pulls the overlay in front of the mesh it is drawn over
+const OVERLAY_DEPTH_BIAS: f32 = 50.0;
+
+// Walkable triangles of the primary mesh colored by island, shown in the
+// walkable islands render mode
+#[derive(Component)]
+pub struct IslandOverlay;
+
+// Distinct colors for neighbouring component numbers, stepping the hue by the
+// golden angle
+pub fn component_color(component: usize) -> [f32; 3] {
+    let hue = (component as f32 * 137.508) % 360.0;
+    let [r, g, b, _] = Color::hsl(hue, 0.75, 0.55).as_rgba_f32();
+    [r, g, b]
+}
+
+// One triangle per walkable face with corners of its own, shaded like
+// `SlopeColors::shade` so the overlay keeps the mesh's relief
+fn island_mesh(mesh_data: &MeshData, walkable_slope_angle: f32) -> Mesh {
+    let (vertices, indices) = (&mesh_data.vertices, &mesh_data.indices);
+    let components = walkable_components(vertices, indices, walkable_slope_angle);
+
+    let (mut positions, mut normals, mut colors) = (Vec::new(), Vec::new(), Vec::new());
+    for (triangle, component) in components.triangle_component.iter().enumerate() {
+        let Some(component) = component else {
+            continue;
+        };
+        let normal = face_normal(vertices, indices, triangle);
+        let brightness = (2.0 + normal.x + normal.y) / 4.0;
+        let [r, g, b] = component_color(*component).map(|c| c * brightness);
+        for &index in &indices[triangle * 3..triangle * 3 + 3] {
+            positions.push(vertices[index as usize]);
+            normals.push(normal);
+            colors.push([r, g, b, 1.0]);
+        }
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    let indices = (0..positions.len() as u32).collect();
+    insert_vertex_attributes(&mut mesh, positions, normals, colors, Vec::new());
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+// Rebuild the overlay when the mode is entered, the mesh changes or the
+// walkable angle moves, and drop it in the other modes
+#[allow(clippy::too_many_arguments)]
+pub fn update_island_overlay(
+    mut commands: Commands,
+    mode: Res<RenderMode>,
+    mesh_data: Option<Res<MeshData>>,
+    mesh_viewer: Query<&MeshViewer>,
+    overlays: Query<Entity, With<IslandOverlay>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut built_at: Local<Option<f32>>, // Walkable angle of the overlay shown
+) {
+    let Ok(viewer) = mesh_viewer.get_single() else {
+        return;
+    };
+    let angle = viewer.walkable_slope_angle;
+    let mesh_data = mesh_data.filter(|_| *mode == RenderMode::WalkableIslands);
+    let stale = match &mesh_data {
+        Some(mesh_data) => mesh_data.is_changed() || *built_at != Some(angle),
+        None => built_at.is_some(),
+    };
+    if !stale {
+        return;
+    }
+
+    for entity in overlays.iter() {
+        commands.entity(entity).despawn();
+    }
+    *built_at = None;
+    let Some(mesh_data) = mesh_data else {
+        return;
+    };
+
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(island_mesh(&mesh_data, angle)),
+            material: materials.add(StandardMaterial {
+                base_color: Color::WHITE,
+                unlit: true,
+                depth_bias: OVERLAY_DEPTH_BIAS,
+                ..default()
+            }),
+            ..default()
+        },
+        IslandOverlay,
+        layers::MeshLayerId(layers::PRIMARY_LAYER),
+    ));
+    *built_at = Some(angle);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::point_bounds;
+
+    #[test]
+    fn test_island_mesh_colors_each_component() {
+        // Two floor triangles apart and a wall
+        let vertices = vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(5.0, 0.0, 0.0),
+            Vec3::new(5.0, 0.0, 1.0),
+            Vec3::new(6.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ];
+        let mesh_data = MeshData {
+            bounds: point_bounds(&vertices),
+            normals: vec![Vec3::Y; vertices.len()],
+            vertices,
+            indices: vec![0, 1, 2, 3, 4, 5, 0, 6, 1],
+            uvs: Vec::new(),
+            tile_size: 988.0,
+            material_groups: Vec::new(),
+            file_vertices: Vec::new(),
+        };
+
+        let mesh = island_mesh(&mesh_data, 45.0);
+        assert_eq!(mesh.count_vertices(), 6);
+        let Some(bevy::render::mesh::VertexAttributeValues::Float32x4(colors)) =
+            mesh.attribute(Mesh::ATTRIBUTE_COLOR)
+        else {
+            panic!("island mesh should have vertex colors");
+        };
+        assert_eq!(colors[0], colors[2]);
+        assert_ne!(colors[0], colors[3]);
+        assert_ne!(component_color(0), component_color(1));
+    }
+}
@@ -0,0 +1,547 @@
+use crate::world::TriangleGrid;
+use crate::{
+    convert_obj_to_mesh_data, coords, face_normal, obj_loader, point_bounds, raycast_mesh_data,
+    section, spawn_mesh_entities, tiling, ElevationColors, ImportOptions, MeshData,
+    NormalWeighting, RenderMode, RenderSettings, ScreenshotState, SlopeColors, TileSettings,
+    MIN_TILE_SIZE,
+};
+use bevy::math::Vec3A;
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use std::path::PathBuf;
+
+// Layer of the mesh opened from the main controls, extra layers count up from 1
+pub const PRIMARY_LAYER: u32 = 0;
+
+// Tints given to new layers in turn
+const LAYER_TINTS: [[f32; 3]; 4] = [
+    [0.3, 0.8, 1.0],
+    [1.0, 0.5, 0.8],
+    [0.6, 1.0, 0.4],
+    [1.0, 0.8, 0.3],
+];
+
+// Layer a spawned mesh entity belongs to
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MeshLayerId(pub u32);
+
+// Offset a layer is drawn with, to line it up with another mesh or a game's
+// frame. Applied to the spawned entities so it doesn't need a re-tile
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct LayerTransform {
+    pub translation: Vec3,
+    pub rotation: Vec3, // XYZ Euler angles in degrees
+    pub scale: f32,
+}
+
+impl Default for LayerTransform {
+    fn default() -> Self {
+        Self {
+            translation: Vec3::ZERO,
+            rotation: Vec3::ZERO,
+            scale: 1.0,
+        }
+    }
+}
+
+impl LayerTransform {
+    pub fn to_transform(self) -> Transform {
+        let rotation = Quat::from_euler(
+            EulerRot::XYZ,
+            self.rotation.x.to_radians(),
+            self.rotation.y.to_radians(),
+            self.rotation.z.to_radians(),
+        );
+        Transform {
+            translation: self.translation,
+            rotation,
+            scale: Vec3::splat(self.scale),
+        }
+    }
+}
+
+// An extra OBJ shown over the primary mesh, to compare versions
+pub struct MeshLayer {
+    pub id: u32,
+    pub path: PathBuf,
+    pub tint: [f32; 3],
+    pub visible: bool,
+    pub transform: LayerTransform,
+    material: Handle<StandardMaterial>, // Unlit `tint`, shared by the layer's entities
+    loading: Option<obj_loader::ObjLoadTask>,
+    pub error: Option<String>,
+    pub data: Option<(MeshData, TriangleGrid)>, // Once loaded
+}
+
+impl MeshLayer {
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.path.display().to_string())
+    }
+}
+
+#[derive(Resource)]
+pub struct MeshLayers {
+    pub primary_visible: bool,
+    pub primary_transform: LayerTransform, // Kept when another primary mesh is opened
+    pub layers: Vec<MeshLayer>,
+    pub needs_rebuild: bool, // Respawn every layer, set when the primary is re-tiled
+    next_id: u32,
+}
+
+impl Default for MeshLayers {
+    fn default() -> Self {
+        Self {
+            primary_visible: true,
+            primary_transform: LayerTransform::default(),
+            layers: Vec::new(),
+            needs_rebuild: false,
+            next_id: PRIMARY_LAYER + 1,
+        }
+    }
+}
+
+impl MeshLayers {
+    pub fn add(&mut self, path: PathBuf, materials: &mut Assets<StandardMaterial>) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        let tint = LAYER_TINTS[(id as usize - 1) % LAYER_TINTS.len()];
+        self.layers.push(MeshLayer {
+            id,
+            loading: Some(obj_loader::load_obj_streaming(&path)),
+            path,
+            tint,
+            visible: true,
+            transform: LayerTransform::default(),
+            material: materials.add(layer_material(tint)),
+            error: None,
+            data: None,
+        });
+        id
+    }
+
+    pub fn is_visible(&self, id: u32) -> bool {
+        if id == PRIMARY_LAYER {
+            return self.primary_visible;
+        }
+        self.layers
+            .iter()
+            .find(|layer| layer.id == id)
+            .is_none_or(|layer| layer.visible)
+    }
+
+    pub fn transform(&self, id: u32) -> Transform {
+        if id == PRIMARY_LAYER {
+            return self.primary_transform.to_transform();
+        }
+        self.layers
+            .iter()
+            .find(|layer| layer.id == id)
+            .map_or(Transform::IDENTITY, |layer| layer.transform.to_transform())
+    }
+
+    // Geometry of a loaded layer other than the primary one
+    pub fn mesh_data(&self, id: u32) -> Option<&MeshData> {
+        let layer = self.layers.iter().find(|layer| layer.id == id)?;
+        layer.data.as_ref().map(|(mesh_data, _)| mesh_data)
+    }
+
+    pub fn label(&self, id: u32) -> String {
+        if id == PRIMARY_LAYER {
+            return "Primary".to_string();
+        }
+        self.layers
+            .iter()
+            .find(|layer| layer.id == id)
+            .map_or_else(|| format!("Layer {}", id), MeshLayer::name)
+    }
+
+    // Nearest hit over the primary mesh and every visible loaded layer: the
+    // layer, the point, the triangle and its normal. The ray is taken into each
+    // layer's own space, the hit comes back in world space
+    pub fn raycast(
+        &self,
+        primary: Option<(&MeshData, Option<&TriangleGrid>)>,
+        origin: Vec3,
+        direction: Vec3,
+    ) -> Option<(u32, Vec3, usize, Vec3)> {
+        let primary = primary
+            .filter(|_| self.primary_visible)
+            .map(|(mesh_data, grid)| (PRIMARY_LAYER, mesh_data, grid));
+        let layers = self
+            .layers
+            .iter()
+            .filter(|layer| layer.visible)
+            .filter_map(|layer| {
+                let (mesh_data, grid) = layer.data.as_ref()?;
+                Some((layer.id, mesh_data, Some(grid)))
+            });
+        primary
+            .into_iter()
+            .chain(layers)
+            .filter_map(|(id, mesh_data, grid)| {
+                let (point, triangle, normal) =
+                    self.raycast_layer(id, mesh_data, grid, origin, direction)?;
+                Some((id, point, triangle, normal))
+            })
+            .min_by(|a, b| {
+                let distance = |point: Vec3| point.distance_squared(origin);
+                distance(a.1).total_cmp(&distance(b.1))
+            })
+    }
+
+    // Hit on the geometry of layer `id`, visible or not, with the ray taken
+    // into its space and the point and normal brought back to world space
+    pub fn raycast_layer(
+        &self,
+        id: u32,
+        mesh_data: &MeshData,
+        grid: Option<&TriangleGrid>,
+        origin: Vec3,
+        direction: Vec3,
+    ) -> Option<(Vec3, usize, Vec3)> {
+        let affine = self.transform(id).compute_affine();
+        let inverse = affine.inverse();
+        let (point, triangle) = raycast_mesh_data(
+            mesh_data,
+            grid,
+            inverse.transform_point3(origin),
+            inverse.transform_vector3(direction).normalize(),
+        )?;
+        let normal = face_normal(&mesh_data.vertices, &mesh_data.indices, triangle);
+        let normal = Vec3::from(inverse.matrix3.transpose() * Vec3A::from(normal));
+        Some((affine.transform_point3(point), triangle, normal.normalize()))
+    }
+}
+
+fn layer_material(tint: [f32; 3]) -> StandardMaterial {
+    StandardMaterial {
+        base_color: Color::from(tint),
+        unlit: true,
+        ..default()
+    }
+}
+
+// Geometry of a loaded layer, tiled like the primary mesh but drawn with the
+// layer's tint instead of its .mtl materials
+fn layer_mesh_data(
+    obj_data: &obj_loader::ObjData,
+    coords: &coords::CoordinateTransform,
+    import: ImportOptions,
+    material: Handle<StandardMaterial>,
+    tile_size: f32,
+) -> MeshData {
+    let (mut vertices, mut indices, mut normals, uvs) =
+        convert_obj_to_mesh_data(obj_data, NormalWeighting::default(), import);
+    coords.apply_to_mesh(&mut vertices, &mut indices, &mut normals);
+    MeshData {
+        bounds: point_bounds(&vertices),
+        material_groups: vec![(material, indices.clone())],
+        file_vertices: obj_data.triangulate_vertex_numbers(),
+        vertices,
+        indices,
+        normals,
+        uvs,
+        tile_size,
+    }
+}
+
+// Finish loading layers and respawn them when the primary mesh is re-tiled
+#[allow(clippy::too_many_arguments)]
+pub fn update_layers(
+    mut commands: Commands,
+    mut layers: ResMut<MeshLayers>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    entities: Query<(Entity, &MeshLayerId)>,
+    tile_settings: Res<TileSettings>,
+    render_settings: Res<RenderSettings>,
+    slope_colors: Res<SlopeColors>,
+    render_mode: Res<RenderMode>,
+    elevation_colors: Res<ElevationColors>,
+    coords: Res<coords::CoordinateTransform>,
+    import: Res<ImportOptions>,
+    section: Res<section::SectionPlane>,
+    mut tiler: ResMut<tiling::IncrementalTiler>,
+) {
+    let slope_colors = render_mode.colors(&slope_colors, &elevation_colors);
+    let tile_size = tile_settings.tile_size.max(MIN_TILE_SIZE);
+    let rebuild = std::mem::take(&mut layers.needs_rebuild);
+
+    for layer in &mut layers.layers {
+        let mut spawn = false;
+        if let Some(loading) = &layer.loading {
+            for message in loading.poll() {
+                if let obj_loader::LoadProgress::Done(result) = message {
+                    match *result {
+                        Ok(obj_data) => {
+                            let mesh_data = layer_mesh_data(
+                                &obj_data,
+                                &coords,
+                                *import,
+                                layer.material.clone(),
+                                tile_size,
+                            );
+                            let grid = TriangleGrid::build(&mesh_data.vertices, &mesh_data.indices);
+                            layer.data = Some((mesh_data, grid));
+                            layer.error = None;
+                            spawn = true;
+                        }
+                        Err(e) => layer.error = Some(e.to_string()),
+                    }
+                    layer.loading = None;
+                }
+            }
+        }
+
+        let Some((mesh_data, _)) = &mut layer.data else {
+            continue;
+        };
+        if rebuild {
+            spawn = true;
+            mesh_data.tile_size = tile_size;
+            for (entity, id) in entities.iter() {
+                if id.0 == layer.id {
+                    commands.entity(entity).despawn();
+                }
+            }
+        }
+        if spawn {
+            spawn_mesh_entities(
+                &mut commands,
+                &mut meshes,
+                &mut tiler,
+                mesh_data,
+                &tile_settings,
+                &render_settings,
+                &slope_colors,
+                layer.id,
+                section.local_plane(&layer.transform.to_transform()),
+            );
+        }
+    }
+}
+
+type UnculledFilter = Or<(With<crate::MergedMesh>, With<crate::islands::IslandOverlay>)>;
+
+// Merged meshes and overlays aren't culled, so their layer's visibility is
+// applied here. `cull_tiles` does it for tiles
+pub fn apply_layer_visibility(
+    layers: Res<MeshLayers>,
+    mut merged: Query<(&MeshLayerId, &mut Visibility), UnculledFilter>,
+) {
+    for (id, mut visibility) in merged.iter_mut() {
+        let wanted = if layers.is_visible(id.0) {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        if *visibility != wanted {
+            *visibility = wanted;
+        }
+    }
+}
+
+pub fn apply_layer_transforms(
+    layers: Res<MeshLayers>,
+    mut entities: Query<(&MeshLayerId, &mut Transform)>,
+) {
+    for (id, mut transform) in entities.iter_mut() {
+        let wanted = layers.transform(id.0);
+        if *transform != wanted {
+            *transform = wanted;
+        }
+    }
+}
+
+// Position, rotation and scale editors, true when any of them changed
+fn transform_controls(ui: &mut egui::Ui, transform: &mut LayerTransform) -> bool {
+    let mut changed = false;
+    let mut row = |ui: &mut egui::Ui, label: &str, value: &mut Vec3, speed: f64, suffix: &str| {
+        ui.horizontal(|ui| {
+            ui.label(label);
+            for axis in 0..3 {
+                changed |= ui
+                    .add(
+                        egui::DragValue::new(&mut value[axis])
+                            .speed(speed)
+                            .suffix(suffix),
+                    )
+                    .changed();
+            }
+        });
+    };
+    row(ui, "Position:", &mut transform.translation, 1.0, "");
+    row(ui, "Rotation:", &mut transform.rotation, 0.5, "°");
+    ui.horizontal(|ui| {
+        ui.label("Scale:");
+        changed |= ui
+            .add(
+                egui::DragValue::new(&mut transform.scale)
+                    .speed(0.01)
+                    .clamp_range(0.001..=1000.0),
+            )
+            .changed();
+        if ui.button("Reset").clicked() {
+            *transform = LayerTransform::default();
+            changed = true;
+        }
+    });
+    changed
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn layers_window(
+    mut contexts: EguiContexts,
+    mut commands: Commands,
+    mut layers: ResMut<MeshLayers>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mesh_data: Option<Res<MeshData>>,
+    entities: Query<(Entity, &MeshLayerId)>,
+    mut tiler: ResMut<tiling::IncrementalTiler>,
+    screenshot: Res<ScreenshotState>,
+) {
+    if screenshot.hide_ui {
+        return;
+    }
+
+    egui::Window::new("Layers")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            if ui.button("Add layer").clicked() {
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("OBJ files", &["obj", "gz"])
+                    .pick_file()
+                {
+                    layers.add(path, &mut materials);
+                }
+            }
+
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut layers.primary_visible, "Primary");
+                if let Some(mesh_data) = &mesh_data {
+                    ui.label(format!("{} triangles", mesh_data.indices.len() / 3));
+                }
+            });
+            ui.collapsing("Primary transform", |ui| {
+                transform_controls(ui, &mut layers.primary_transform);
+            });
+
+            let mut removed = None;
+            for layer in &mut layers.layers {
+                ui.horizontal(|ui| {
+                    let name = layer.name();
+                    ui.checkbox(&mut layer.visible, name);
+                    if ui.color_edit_button_rgb(&mut layer.tint).changed() {
+                        if let Some(material) = materials.get_mut(&layer.material) {
+                            material.base_color = Color::from(layer.tint);
+                        }
+                    }
+                    match (&layer.data, &layer.error) {
+                        (Some((mesh_data, _)), _) => {
+                            ui.label(format!("{} triangles", mesh_data.indices.len() / 3));
+                        }
+                        (None, Some(error)) => {
+                            ui.colored_label(egui::Color32::RED, error);
+                        }
+                        (None, None) => {
+                            ui.spinner();
+                        }
+                    }
+                    if ui.small_button("x").clicked() {
+                        removed = Some(layer.id);
+                    }
+                });
+                ui.push_id(layer.id, |ui| {
+                    ui.collapsing("Transform", |ui| {
+                        transform_controls(ui, &mut layer.transform);
+                    });
+                });
+            }
+
+            if let Some(removed) = removed {
+                layers.layers.retain(|layer| layer.id != removed);
+                tiler.cancel(removed);
+                for (entity, id) in entities.iter() {
+                    if id.0 == removed {
+                        commands.entity(entity).despawn();
+                    }
+                }
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn floor(height: f32) -> MeshData {
+        let vertices = vec![
+            Vec3::new(-10.0, height, -10.0),
+            Vec3::new(-10.0, height, 10.0),
+            Vec3::new(10.0, height, 0.0),
+        ];
+        MeshData {
+            bounds: point_bounds(&vertices),
+            normals: vec![Vec3::Y; 3],
+            vertices,
+            indices: vec![0, 1, 2],
+            uvs: Vec::new(),
+            tile_size: 988.0,
+            material_groups: Vec::new(),
+            file_vertices: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_raycast_picks_nearest_layer() {
+        let mut layers = MeshLayers::default();
+        let upper = floor(5.0);
+        let grid = TriangleGrid::build(&upper.vertices, &upper.indices);
+        layers.layers.push(MeshLayer {
+            id: 1,
+            path: PathBuf::from("meshes/v2.obj"),
+            tint: LAYER_TINTS[0],
+            visible: true,
+            transform: LayerTransform::default(),
+            material: Handle::default(),
+            loading: None,
+            error: None,
+            data: Some((upper, grid)),
+        });
+
+        let primary = floor(0.0);
+        let origin = Vec3::new(0.0, 100.0, 0.0);
+        let hit = layers.raycast(Some((&primary, None)), origin, Vec3::NEG_Y);
+        assert_eq!(hit, Some((1, Vec3::new(0.0, 5.0, 0.0), 0, Vec3::Y)));
+        assert_eq!(layers.label(1), "v2.obj");
+
+        // Hidden layers are looked through
+        layers.layers[0].visible = false;
+        assert!(!layers.is_visible(1));
+        let hit = layers.raycast(Some((&primary, None)), origin, Vec3::NEG_Y);
+        assert_eq!(hit.map(|(id, ..)| id), Some(PRIMARY_LAYER));
+
+        // Hits on a moved layer land where it is drawn
+        layers.layers[0].visible = true;
+        layers.layers[0].transform = LayerTransform {
+            translation: Vec3::new(0.0, 20.0, 0.0),
+            rotation: Vec3::new(180.0, 0.0, 0.0),
+            scale: 2.0,
+        };
+        let (id, point, _, normal) = layers
+            .raycast(Some((&primary, None)), origin, Vec3::NEG_Y)
+            .unwrap();
+        assert_eq!(id, 1);
+        assert!(point.distance(Vec3::new(0.0, 10.0, 0.0)) < 1e-3);
+        assert!(normal.distance(Vec3::NEG_Y) < 1e-3);
+        layers.layers[0].visible = false;
+
+        layers.primary_visible = false;
+        assert_eq!(
+            layers.raycast(Some((&primary, None)), origin, Vec3::NEG_Y),
+            None
+        );
+    }
+}
@@ -0,0 +1,6246 @@
+mod actors;
+mod analysis;
+mod bookmarks;
+mod budget;
+mod cli;
+mod coords;
+mod debug_draw_b;
+mod decimation;
+mod ground;
+mod islands;
+mod layers;
+mod measure;
+mod message_log;
+mod minimap;
+mod net;
+mod obj_export;
+mod obj_loader;
+mod perf;
+mod plugin;
+mod replay;
+mod section;
+mod selections;
+mod settings;
+mod stats;
+mod tiling;
+mod world;
+
+pub use plugin::{NavViewerConfig, NavViewerPlugin};
+use settings::ViewerSettings;
+
+//use crate::obj_loader::load_obj;
+use bevy::app::AppExit;
+use bevy::ecs::system::SystemParam;
+use bevy::input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel};
+use bevy::pbr::wireframe::{Wireframe, WireframeColor};
+use bevy::prelude::*;
+use bevy::render::camera::{CameraProjection, ScalingMode};
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use bevy::render::primitives::{Aabb, Frustum};
+use bevy::render::render_resource::{Face, TextureFormat};
+use bevy::render::renderer::RenderAdapter;
+use bevy::render::settings::{WgpuFeatures, WgpuSettings};
+use bevy::render::texture::BevyDefault;
+use bevy::render::view::screenshot::ScreenshotManager;
+use bevy::render::RenderPlugin;
+use bevy::tasks::{ComputeTaskPool, ParallelSlice, TaskPool};
+use bevy::window::PrimaryWindow;
+use bevy_egui::{egui, EguiContexts};
+use glam::{Vec2, Vec3, Vec4};
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+// Import the debug draw implementation and obj loader
+use bookmarks::CameraBookmarks;
+use obj_loader::ObjData;
+use serde::{Deserialize, Serialize};
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Components
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum CameraMode {
+    Fly,
+    Orbit,  // Rotate around `MainCamera::focus` at `orbit_radius`
+    Follow, // Orbit with the focus easing towards the watched actor
+}
+
+#[derive(Component)]
+struct MainCamera {
+    yaw: f32,
+    pitch: f32,
+    mode: CameraMode,
+    focus: Vec3,
+    orbit_radius: f32,
+    move_speed: f32,      // Units per second, scaled by the mouse wheel in fly mode
+    perspective_fov: f32, // Restored when leaving the orthographic projection
+}
+
+impl Default for MainCamera {
+    fn default() -> Self {
+        Self {
+            yaw: -90.0_f32.to_radians(),
+            pitch: 0.0,
+            mode: CameraMode::Fly,
+            focus: Vec3::ZERO,
+            orbit_radius: 988.0,
+            move_speed: CameraSpeed::default().base,
+            perspective_fov: main_camera_projection().fov,
+        }
+    }
+}
+
+// How fast the movement keys go, remembered across runs. Shift and Ctrl
+// multiply the speed while held
+#[derive(Resource, Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct CameraSpeed {
+    base: f32,   // Units per second, what the mouse wheel scales from
+    sprint: f32, // Multiplier with Shift
+    crawl: f32,  // Multiplier with Ctrl
+}
+
+impl Default for CameraSpeed {
+    fn default() -> Self {
+        Self {
+            base: 988.0,
+            sprint: 4.0,
+            crawl: 0.25,
+        }
+    }
+}
+
+impl CameraSpeed {
+    // Both modifiers together multiply each other
+    fn multiplier(&self, sprint: bool, crawl: bool) -> f32 {
+        let sprint = if sprint { self.sprint } else { 1.0 };
+        let crawl = if crawl { self.crawl } else { 1.0 };
+        sprint * crawl
+    }
+
+    // Hand edited values the sliders can't reach
+    fn validated(self) -> Self {
+        Self {
+            base: self.base.clamp(MIN_MOVE_SPEED, MAX_MOVE_SPEED),
+            sprint: self.sprint.clamp(1.0, MAX_SPEED_MULTIPLIER),
+            crawl: self.crawl.clamp(1.0 / MAX_SPEED_MULTIPLIER, 1.0),
+        }
+    }
+}
+
+const MIN_ORBIT_RADIUS: f32 = 1.0;
+const MAX_ORBIT_RADIUS: f32 = 100000.0;
+// How quickly the follow focus catches up, per second
+const FOLLOW_SMOOTHING: f32 = 5.0;
+const MIN_MOVE_SPEED: f32 = 10.0;
+const MAX_MOVE_SPEED: f32 = 100000.0;
+const MAX_SPEED_MULTIPLIER: f32 = 20.0;
+// Half the height of the orthographic view in world units
+const MIN_ORTHO_SCALE: f32 = 0.5;
+const MAX_ORTHO_SCALE: f32 = 100000.0;
+const MIN_FOV_DEGREES: f32 = 20.0;
+const MAX_FOV_DEGREES: f32 = 90.0;
+const LEVEL_VIEW_KEY: KeyCode = KeyCode::H;
+const MIN_NEAR_CLIP: f32 = 0.001;
+const MAX_NEAR_CLIP: f32 = 1.0;
+const MAX_FAR_CLIP: f32 = 1e6;
+
+// With Ctrl, copies the file vertices of the triangle under the cursor
+const COPY_INDICES_KEY: KeyCode = KeyCode::C;
+
+// Left clicks at most this far apart in time and pixels make a double click
+const DOUBLE_CLICK_SECONDS: f64 = 0.4;
+const DOUBLE_CLICK_PIXELS: f32 = 6.0;
+
+// A double click orbits the point from this fraction of the mesh diagonal
+const DOUBLE_CLICK_DISTANCE: f32 = 0.1;
+
+// Yaw snapped to the nearest quarter turn, for levelling the view
+fn snap_yaw(yaw: f32) -> f32 {
+    (yaw / std::f32::consts::FRAC_PI_2).round() * std::f32::consts::FRAC_PI_2
+}
+
+// How far from the center a camera with this vertical field of view has to be
+// for a sphere of `radius` to fit the narrower of the two view angles
+fn fit_distance(radius: f32, fov: f32, aspect: f32) -> f32 {
+    let horizontal_fov = 2.0 * ((fov * 0.5).tan() * aspect).atan();
+    let half_angle = fov.min(horizontal_fov) * 0.5;
+    radius.max(MIN_ORBIT_RADIUS) / half_angle.sin()
+}
+
+// Orthographic scale that fits a sphere of `radius` in the narrower side of
+// the view
+fn ortho_fit_scale(radius: f32, aspect: f32) -> f32 {
+    (radius / aspect.min(1.0)).clamp(MIN_ORTHO_SCALE, MAX_ORTHO_SCALE)
+}
+
+// Switch between perspective and orthographic keeping the look direction.
+// The orthographic view is as big as the perspective one at the orbit
+// radius, and going back picks the orbit radius of the same size
+fn toggle_orthographic(
+    projection: &mut Projection,
+    camera: &mut MainCamera,
+    clip_planes: &ClipPlanes,
+) {
+    *projection = match &*projection {
+        Projection::Perspective(perspective) => {
+            camera.perspective_fov = perspective.fov;
+            let scale = camera.orbit_radius * (perspective.fov * 0.5).tan();
+            Projection::Orthographic(OrthographicProjection {
+                far: clip_planes.far,
+                scaling_mode: ScalingMode::FixedVertical(2.0),
+                scale: scale.clamp(MIN_ORTHO_SCALE, MAX_ORTHO_SCALE),
+                ..default()
+            })
+        }
+        Projection::Orthographic(ortho) => {
+            let fov = camera.perspective_fov;
+            camera.orbit_radius =
+                (ortho.scale / (fov * 0.5).tan()).clamp(MIN_ORBIT_RADIUS, MAX_ORBIT_RADIUS);
+            Projection::Perspective(PerspectiveProjection {
+                fov,
+                near: clip_planes.near,
+                far: clip_planes.far,
+                ..default()
+            })
+        }
+    };
+}
+
+// Orbit `point` from `distance` away, keeping the look direction. Returns
+// the camera position
+fn orbit_point(camera: &mut MainCamera, point: Vec3, distance: f32) -> Vec3 {
+    camera.mode = CameraMode::Orbit;
+    camera.focus = point;
+    camera.orbit_radius = distance.clamp(MIN_ORBIT_RADIUS, MAX_ORBIT_RADIUS);
+    point - camera_forward(camera.yaw, camera.pitch) * camera.orbit_radius
+}
+
+// Times and places of left clicks, to tell double clicks
+#[derive(Default)]
+struct ClickTracker {
+    last: Option<(f64, Vec2)>,
+}
+
+impl ClickTracker {
+    // True when this click makes a double click with the one before. A third
+    // click starts over rather than making another
+    fn click(&mut self, seconds: f64, position: Vec2) -> bool {
+        let double = matches!(self.last, Some((last_seconds, last_position))
+            if seconds - last_seconds <= DOUBLE_CLICK_SECONDS
+                && last_position.distance(position) <= DOUBLE_CLICK_PIXELS);
+        self.last = (!double).then_some((seconds, position));
+        double
+    }
+}
+
+// Orbit `center` from `distance` away, looking down at 45 degrees facing
+// forward. Returns the camera position
+fn frame_view(camera: &mut MainCamera, center: Vec3, distance: f32) -> Vec3 {
+    camera.pitch = -45.0_f32.to_radians();
+    camera.yaw = -90.0_f32.to_radians();
+    camera.focus = center;
+    camera.orbit_radius = distance.clamp(MIN_ORBIT_RADIUS, MAX_ORBIT_RADIUS);
+    center - camera_forward(camera.yaw, camera.pitch) * distance
+}
+
+// Frame the world box `bounds` like `frame_view`, backing off until it fits.
+// Returns the camera position
+fn fit_view(
+    camera: &mut MainCamera,
+    projection: &mut Projection,
+    (min, max): (Vec3, Vec3),
+    aspect: f32,
+) -> Vec3 {
+    let radius = (max - min).length() * 0.5;
+    // Orthographic still backs off, so the box is in front of the camera
+    let distance = match projection {
+        Projection::Perspective(perspective) => fit_distance(radius, perspective.fov, aspect),
+        Projection::Orthographic(ortho) => {
+            ortho.scale = ortho_fit_scale(radius, aspect);
+            fit_distance(radius, camera.perspective_fov, aspect)
+        }
+    };
+    frame_view(camera, (min + max) * 0.5, distance)
+}
+
+// A position pasted from a log, like "1.5, -2, 30" or "(1.5 -2 30)"
+fn parse_coordinates(text: &str) -> Option<Vec3> {
+    let text = text.trim().trim_matches(|c| "()[]{}".contains(c));
+    let values: Vec<f32> = text
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    match values[..] {
+        [x, y, z] => Some(Vec3::new(x, y, z)),
+        _ => None,
+    }
+}
+
+// Look direction for the camera angles
+fn camera_forward(yaw: f32, pitch: f32) -> Vec3 {
+    Vec3::new(
+        yaw.cos() * pitch.cos(),
+        pitch.sin(),
+        yaw.sin() * pitch.cos(),
+    )
+    .normalize()
+}
+
+#[derive(Component, Default)]
+struct CameraMouseState {
+    initial_position: Option<Vec2>,
+    last_position: Option<Vec2>,
+}
+
+struct MitmInfo {
+    socket: Option<std::net::TcpStream>,
+    watched: BTreeSet<String>, // Actor ids asked for, everything when empty
+    watch_entry: String,       // Id being typed in the UI
+    positions: HashMap<String, Vec3>, // Latest position of each actor
+    latest: Option<String>,    // Actor whose position arrived last
+    follow: Option<String>,    // Followed actor, the latest one when unset
+    protocol_version: Option<u32>,
+    error: Option<String>,
+    reader: net::FrameReader, // Partial frames from `socket`
+    address: String,
+    format: net::WireFormat, // Asked for on the next connect
+    state: net::ConnectionState,
+    pending: Option<net::PendingConnect>,
+    reconnect: bool, // Set once connected, a dropped stream is then retried
+    backoff: net::Backoff,
+    retry_at: Option<Instant>,
+    paused: bool,                 // Hold incoming messages back, to study a moment
+    held: Vec<net::ActorMessage>, // Read while paused, oldest first, see `hold`
+}
+
+impl MitmInfo {
+    fn new(address: &str, max_frame_len: u32) -> Self {
+        Self {
+            socket: None,
+            watched: BTreeSet::new(),
+            watch_entry: String::new(),
+            positions: HashMap::new(),
+            latest: None,
+            follow: None,
+            protocol_version: None,
+            error: None,
+            reader: net::FrameReader::new(max_frame_len),
+            address: address.to_string(),
+            format: net::WireFormat::default(),
+            state: net::ConnectionState::Disconnected,
+            pending: None,
+            reconnect: false,
+            backoff: net::Backoff::default(),
+            retry_at: None,
+            paused: false,
+            held: Vec::new(),
+        }
+    }
+
+    // Keep this frame's messages back while paused, the socket is still read
+    // so nothing piles up there. Once unpaused the held ones go out first
+    fn hold_messages(&mut self, messages: &mut Vec<net::ActorMessage>) {
+        if self.paused {
+            for message in messages.drain(..) {
+                self.hold(message);
+            }
+        } else if !self.held.is_empty() {
+            messages.splice(0..0, self.held.drain(..));
+        }
+    }
+
+    // Only an actor's newest move or position is worth keeping, so a long
+    // pause holds about one message per actor. Spawns and despawns stay in
+    // order with the moves around them
+    fn hold(&mut self, message: net::ActorMessage) {
+        let moves = |message: &net::ActorMessage| {
+            matches!(
+                message,
+                net::ActorMessage::Move(_) | net::ActorMessage::Position(_)
+            )
+        };
+        let latest = self.held.iter().rposition(|held| held.id() == message.id());
+        match latest {
+            Some(index) if moves(&message) && moves(&self.held[index]) => {
+                self.held[index] = message
+            }
+            _ => self.held.push(message),
+        }
+    }
+
+    fn start_connect(&mut self) {
+        self.state = net::ConnectionState::Connecting;
+        self.retry_at = None;
+        self.pending = Some(net::connect_in_background(
+            self.address.clone(),
+            self.format,
+            self.watched.iter().cloned().collect(),
+        ));
+    }
+
+    // Send a command on the open socket, a failed write drops the connection
+    fn send_command(&mut self, payload: &[u8]) {
+        let Some(socket) = &mut self.socket else {
+            return;
+        };
+        if let Err(e) = net::write_frame(socket, payload) {
+            self.disconnect(e.to_string());
+        }
+    }
+
+    // No watched ids means every actor is watched, so the first id replaces
+    // the watch of everything and removing the last one brings it back
+    fn watch(&mut self, id: String) {
+        if self.watched.insert(id.clone()) {
+            if self.watched.len() == 1 {
+                self.send_command(&[net::CMD_STOP_WATCH]);
+            }
+            let command = net::watch_actor_command(self.format, &id);
+            self.send_command(&command);
+        }
+    }
+
+    fn unwatch(&mut self, id: &str) {
+        if self.watched.remove(id) {
+            self.send_command(&net::stop_watch_command(id));
+            if self.watched.is_empty() {
+                self.send_command(&[self.format.watch_command()]);
+            }
+            self.positions.remove(id);
+            if self.follow.as_deref() == Some(id) {
+                self.follow = None;
+            }
+            if self.latest.as_deref() == Some(id) {
+                self.latest = None;
+            }
+        }
+    }
+
+    fn update_position(&mut self, id: &str, pos: Vec3) {
+        self.positions.insert(id.to_string(), pos);
+        self.latest = Some(id.to_string());
+    }
+
+    // Where the follow camera should look
+    fn followed_position(&self) -> Option<Vec3> {
+        let id = self.follow.as_ref().or(self.latest.as_ref())?;
+        self.positions.get(id).copied()
+    }
+
+    // Leave the server for good when the viewer closes: stop the watches,
+    // close the socket and drop any connect in progress
+    fn shutdown(&mut self) {
+        self.pending = None;
+        self.reconnect = false;
+        self.retry_at = None;
+        self.state = net::ConnectionState::Disconnected;
+        if let Some(socket) = self.socket.take() {
+            let watched: Vec<String> = self.watched.iter().cloned().collect();
+            if let Err(e) = net::close_connection(socket, &watched) {
+                warn!("Failed to close the MITM connection cleanly: {}", e);
+            }
+        }
+    }
+
+    // Drop the socket, and retry later if we were connected before
+    fn disconnect(&mut self, error: String) {
+        self.socket = None;
+        self.protocol_version = None;
+        self.error = Some(error);
+        self.held.clear();
+        self.state = net::ConnectionState::Disconnected;
+        if self.reconnect {
+            self.retry_at = Some(Instant::now() + self.backoff.next_delay());
+        }
+    }
+}
+
+// How face normals are weighted when a vertex normal is computed from them
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+enum NormalWeighting {
+    #[default]
+    Area, // Larger faces count for more
+    Equal, // Every face counts the same, for comparison
+}
+
+// How an OBJ file is read before it becomes a mesh, which axis is up comes
+// from the preset of `coords::CoordinateTransform`
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct ImportOptions {
+    flip_winding: bool, // For files whose faces come out inside out
+}
+
+#[derive(Component)]
+struct MeshViewer {
+    obj_path: Option<PathBuf>,
+    walkable_slope_angle: f32,
+    needs_update: bool,  // Add this field to track when updates are needed
+    needs_rebuild: bool, // Respawn the mesh entities from `MeshData` without reloading
+    load_error: Option<String>,
+    loading: Option<obj_loader::ObjLoadTask>, // Background load in progress
+    load_progress: f32,
+    export_message: Option<Result<String, String>>, // Saved path or why it failed
+    normal_weighting: NormalWeighting,
+    mitm_info: Arc<Mutex<MitmInfo>>, // Shared with the network systems
+}
+
+#[allow(clippy::too_many_arguments)]
+fn ui_system(
+    mut contexts: EguiContexts,
+    mut mesh_viewer: Query<&mut MeshViewer>,
+    mut camera_query: Query<(&mut Transform, &mut MainCamera, &Camera, &mut Projection)>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut bookmarks: ResMut<CameraBookmarks>,
+    mut bookmark_name: Local<String>,
+    mut go_to: Local<GoToInput>,
+    mesh_data: Option<Res<MeshData>>,
+    picking_grid: Option<Res<world::TriangleGrid>>,
+    mut settings: ResMut<ViewerSettings>,
+    mut trail_settings: ResMut<actors::TrailSettings>,
+    mut tiles: TileUi,
+    mut render: RenderUi,
+    mut measure: ResMut<measure::MeasureTool>,
+    mut mesh_ui: MeshUi,
+) {
+    // No UI in the frame a screenshot without it is taken
+    if render.screenshot.hide_ui {
+        return;
+    }
+
+    let mut viewer = mesh_viewer.single_mut();
+
+    egui::Window::new("Mitm").show(contexts.ctx_mut(), |ui| {
+        let mut mitm = viewer.mitm_info.lock().unwrap();
+        let connecting = mitm.state == net::ConnectionState::Connecting;
+        ui.horizontal(|ui| {
+            ui.label("Address:");
+            ui.add_enabled(
+                !connecting,
+                egui::TextEdit::singleline(&mut mitm.address).desired_width(140.0),
+            );
+        });
+        // Takes effect on the next connect
+        let mut binary = mitm.format == net::WireFormat::Binary;
+        if ui
+            .add_enabled(
+                !connecting,
+                egui::Checkbox::new(&mut binary, "Binary messages"),
+            )
+            .changed()
+        {
+            mitm.format = if binary {
+                net::WireFormat::Binary
+            } else {
+                net::WireFormat::Json
+            };
+        }
+        if ui
+            .add_enabled(!connecting, egui::Button::new("connect"))
+            .clicked()
+        {
+            let address = mitm.address.trim().to_string();
+            match address.parse::<std::net::SocketAddr>() {
+                Ok(_) => {
+                    mitm.address = address;
+                    mitm.reconnect = false;
+                    mitm.backoff.reset();
+                    mitm.start_connect();
+
+                    if settings.mitm_address != mitm.address {
+                        settings.mitm_address = mitm.address.clone();
+                    }
+                }
+                Err(e) => {
+                    mitm.error = Some(format!("Invalid address {:?}: {}", address, e));
+                }
+            }
+        }
+
+        // Actors stay as they are, what arrives meanwhile is applied on resume
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut mitm.paused, "Pause updates");
+            if mitm.paused {
+                ui.label(format!("{} held", mitm.held.len()));
+            }
+        });
+
+        // Raw frames to a file, and back in place of the socket
+        let mut recording = mitm.reader.recorder.is_some();
+        if ui.checkbox(&mut recording, "Record frames").changed() {
+            mitm.reader.recorder = None;
+            if recording {
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis();
+                let path = std::env::current_dir()
+                    .unwrap_or_default()
+                    .join(format!("frames_{}.bin", timestamp));
+                match net::FrameRecorder::create(&path) {
+                    Ok(recorder) => mitm.reader.recorder = Some(recorder),
+                    Err(e) => {
+                        mitm.error = Some(format!("Failed to record to {}: {}", path.display(), e))
+                    }
+                }
+            }
+        }
+        if let Some(recorder) = &mitm.reader.recorder {
+            ui.label(format!("Recording to {}", recorder.path.display()));
+        }
+
+        // Watched actors, commands for changes go out right away when connected
+        ui.horizontal(|ui| {
+            ui.label("Watch actor:");
+            let response =
+                ui.add(egui::TextEdit::singleline(&mut mitm.watch_entry).desired_width(100.0));
+            let submitted = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if ui.button("Watch").clicked() || submitted {
+                let id = mitm.watch_entry.trim().to_string();
+                if !id.is_empty() {
+                    mitm.watch(id);
+                    mitm.watch_entry.clear();
+                }
+            }
+        });
+        if mitm.watched.is_empty() {
+            ui.label("Watching all actors");
+        }
+        let mut unwatched = None;
+        for id in &mitm.watched {
+            ui.horizontal(|ui| {
+                if ui.small_button("x").clicked() {
+                    unwatched = Some(id.clone());
+                }
+                match mitm.positions.get(id) {
+                    Some(pos) => {
+                        ui.label(format!("{} ({:.1}, {:.1}, {:.1})", id, pos.x, pos.y, pos.z))
+                    }
+                    None => ui.label(id),
+                };
+            });
+        }
+        if let Some(id) = unwatched {
+            mitm.unwatch(&id);
+        }
+
+        let mut follow_ids: Vec<String> = mitm.positions.keys().cloned().collect();
+        follow_ids.sort();
+        let selected = mitm.follow.clone().unwrap_or_else(|| "Latest".to_string());
+        egui::ComboBox::from_label("Follow")
+            .selected_text(selected)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut mitm.follow, None, "Latest");
+                for id in follow_ids {
+                    ui.selectable_value(&mut mitm.follow, Some(id.clone()), id);
+                }
+            });
+
+        ui.label(format!("State: {:?}", mitm.state));
+        if let Some(retry_at) = mitm.retry_at {
+            let remaining = retry_at.saturating_duration_since(Instant::now());
+            ui.label(format!("Reconnecting in {:.1}s", remaining.as_secs_f32()));
+        }
+        if let Some(version) = mitm.protocol_version {
+            ui.label(format!("Protocol version: {}", version));
+        }
+        if let Some(error) = &mitm.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+
+        ui.add(
+            egui::Slider::new(&mut trail_settings.length, 0..=actors::MAX_TRAIL_LENGTH)
+                .text("Trail Length"),
+        );
+    });
+
+    egui::Window::new("Mesh Viewer Controls").show(contexts.ctx_mut(), |ui| {
+        // File loading button
+        ui.horizontal(|ui| {
+            if ui.button("Load OBJ").clicked() {
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("OBJ files", &["obj", "gz"])
+                    .pick_file()
+                {
+                    viewer.obj_path = Some(path);
+                    viewer.needs_update = true; // Set flag when new file is loaded
+                }
+            }
+
+            // Load the same file again, the camera and slope settings stay
+            if ui
+                .add_enabled(viewer.obj_path.is_some(), egui::Button::new("Reload"))
+                .clicked()
+            {
+                viewer.needs_update = true;
+            }
+            ui.checkbox(&mut mesh_ui.watcher.enabled, "Reload on change");
+        });
+
+        // Smoothing of computed normals, reloads the mesh
+        let mut equal_weight = viewer.normal_weighting == NormalWeighting::Equal;
+        if ui
+            .checkbox(&mut equal_weight, "Equal-weight normals")
+            .changed()
+        {
+            viewer.normal_weighting = if equal_weight {
+                NormalWeighting::Equal
+            } else {
+                NormalWeighting::Area
+            };
+            viewer.needs_update = viewer.obj_path.is_some();
+        }
+
+        // How the file itself is read, reloads the mesh
+        let prev_import = *mesh_ui.import;
+        ui.checkbox(&mut mesh_ui.import.flip_winding, "Flip winding");
+        if *mesh_ui.import != prev_import && viewer.obj_path.is_some() {
+            viewer.needs_update = true;
+        }
+
+        // Checked when a file is loaded, larger ones ask first
+        budget::budget_limit(ui, &mut mesh_ui.budget);
+        let shown = mesh_data
+            .as_ref()
+            .map_or(0, |mesh_data| mesh_data.indices.len() / 3);
+        decimation::decimation_controls(ui, &mut mesh_ui.decimation, shown);
+
+        // How the game's coordinates map to the viewer's, reloads the mesh
+        let prev_transform = *mesh_ui.coords;
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("Coordinates")
+                .selected_text(mesh_ui.coords.preset.label())
+                .show_ui(ui, |ui| {
+                    for preset in coords::CoordinatePreset::ALL {
+                        ui.selectable_value(&mut mesh_ui.coords.preset, preset, preset.label());
+                    }
+                });
+            ui.add(
+                egui::DragValue::new(&mut mesh_ui.coords.scale)
+                    .speed(0.01)
+                    .clamp_range(0.0001..=10000.0)
+                    .prefix("Scale: "),
+            );
+        });
+        if *mesh_ui.coords != prev_transform && viewer.obj_path.is_some() {
+            viewer.needs_update = true;
+        }
+
+        // Save the merged mesh with its current slope colors
+        if ui.button("Export OBJ").clicked() {
+            match mesh_data.as_deref().filter(|m| !m.indices.is_empty()) {
+                None => viewer.export_message = Some(Err("No mesh to export".to_string())),
+                Some(mesh) => {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("OBJ files", &["obj", "gz"])
+                        .set_file_name("export.obj")
+                        .save_file()
+                    {
+                        let colors = calculate_colors(
+                            &mesh.vertices,
+                            &mesh.indices,
+                            &mesh.normals,
+                            &render.slope_colors,
+                        );
+                        viewer.export_message = Some(
+                            obj_export::save_obj(
+                                &path,
+                                &mesh.vertices,
+                                &mesh.normals,
+                                &mesh.uvs,
+                                &mesh.indices,
+                                &colors,
+                            )
+                            .map(|()| format!("Exported {}", path.display()))
+                            .map_err(|e| format!("Failed to export {}: {}", path.display(), e)),
+                        );
+                    }
+                }
+            }
+        }
+
+        // Stats per tile as JSON, the same as `--headless --analysis` writes
+        if ui.button("Export Analysis").clicked() {
+            match mesh_data.as_deref().filter(|m| !m.indices.is_empty()) {
+                None => viewer.export_message = Some(Err("No mesh to analyze".to_string())),
+                Some(mesh) => {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("JSON files", &["json"])
+                        .set_file_name("analysis.json")
+                        .save_file()
+                    {
+                        let analysis = analysis::MeshAnalysis::new(
+                            &mesh.vertices,
+                            &mesh.indices,
+                            mesh.tile_size,
+                            viewer.walkable_slope_angle,
+                        );
+                        viewer.export_message = Some(
+                            analysis
+                                .save(&path)
+                                .map(|()| format!("Exported {}", path.display()))
+                                .map_err(|e| format!("Failed to export {}: {}", path.display(), e)),
+                        );
+                    }
+                }
+            }
+        }
+        match &viewer.export_message {
+            Some(Ok(message)) => {
+                ui.label(message);
+            }
+            Some(Err(message)) => {
+                ui.colored_label(egui::Color32::RED, message);
+            }
+            None => {}
+        }
+
+        // Display loaded file path, or why it failed to load
+        if viewer.loading.is_some() {
+            ui.add(egui::ProgressBar::new(viewer.load_progress).show_percentage());
+        } else if let Some(progress) = tiles.tiler.progress() {
+            ui.add(
+                egui::ProgressBar::new(progress)
+                    .show_percentage()
+                    .text("Tiling"),
+            );
+        } else if let Some(path) = &viewer.obj_path {
+            match &viewer.load_error {
+                Some(error) => {
+                    ui.colored_label(
+                        egui::Color32::RED,
+                        format!("Failed to load {}: {}", path.display(), error),
+                    );
+                }
+                None => {
+                    ui.label(format!("Loaded: {}", path.display()));
+                }
+            }
+        }
+
+        // Objects and groups of the file, hiding one rebuilds the mesh without it
+        let groups = &mut *mesh_ui.groups;
+        if let Some(obj_data) = &groups.obj_data {
+            ui.collapsing(format!("Groups ({})", obj_data.group_names.len()), |ui| {
+                for (i, name) in obj_data.group_names.iter().enumerate() {
+                    let label = format!("{} ({} faces)", name, groups.face_counts[i]);
+                    groups.changed |= ui.checkbox(&mut groups.visible[i], label).changed();
+                }
+            });
+        }
+
+        // Slope colors, edited on a copy so the meshes are only repainted
+        // when something actually changed
+        let mut colors = render.slope_colors.clone();
+
+        // Walkable slope angle slider, also the upper edge of the first band
+        let prev_angle = viewer.walkable_slope_angle;
+        ui.add(
+            egui::Slider::new(&mut viewer.walkable_slope_angle, 0.0..=90.0)
+                .text("Walkable Slope Angle"),
+        );
+        if (viewer.walkable_slope_angle - prev_angle).abs() > f32::EPSILON {
+            viewer.walkable_slope_angle =
+                colors.set_walkable_slope_angle(viewer.walkable_slope_angle);
+        }
+
+        ui.collapsing("Slope Bands", |ui| {
+            ui.horizontal(|ui| {
+                ui.color_edit_button_rgb(&mut colors.base);
+                ui.label("Base");
+            });
+            let can_remove = colors.bands.len() > 1;
+            let mut remove = None;
+            for (i, band) in colors.bands.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.color_edit_button_rgb(&mut band.color);
+                    ui.label("Up to");
+                    ui.add(
+                        egui::DragValue::new(&mut band.max_angle)
+                            .clamp_range(0.0..=90.0)
+                            .suffix("°"),
+                    );
+                    ui.add(egui::Slider::new(&mut band.blend, 0.0..=1.0).text("Blend"));
+                    if ui
+                        .add_enabled(can_remove, egui::Button::new("Remove"))
+                        .clicked()
+                    {
+                        remove = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = remove {
+                colors.bands.remove(i);
+            }
+            if ui.button("Add Band").clicked() {
+                colors.bands.push(SlopeBand {
+                    max_angle: 90.0,
+                    color: [0.8, 0.1, 0.1],
+                    blend: 0.5,
+                });
+            }
+        });
+
+        let mut show_flipped = colors.flipped.is_some();
+        if ui
+            .checkbox(&mut show_flipped, "Show flipped faces")
+            .changed()
+        {
+            colors.flipped = show_flipped.then_some(FLIPPED_COLOR);
+        }
+
+        if colors != *render.slope_colors {
+            colors.sort_bands();
+            // The first band is what the slope slider shows
+            viewer.walkable_slope_angle = colors.bands[0].max_angle;
+            *render.slope_colors = colors;
+        }
+
+        // Tile size, re-tiles the loaded mesh
+        let prev_tile_size = tiles.settings.tile_size;
+        ui.add(
+            egui::Slider::new(&mut tiles.settings.tile_size, MIN_TILE_SIZE..=MAX_TILE_SIZE)
+                .logarithmic(true)
+                .text("Tile Size"),
+        );
+        tiles.settings.tile_size = tiles.settings.tile_size.max(MIN_TILE_SIZE);
+        if (tiles.settings.tile_size - prev_tile_size).abs() > f32::EPSILON {
+            viewer.needs_rebuild = true;
+        }
+        if ui
+            .checkbox(&mut tiles.settings.single_mesh, "Single Mesh")
+            .changed()
+        {
+            viewer.needs_rebuild = true;
+        }
+        egui::ComboBox::from_label("Render Mode (Tab)")
+            .selected_text(render.mode.label())
+            .show_ui(ui, |ui| {
+                for mode in RenderMode::ALL {
+                    if ui
+                        .selectable_label(*render.mode == mode, mode.label())
+                        .clicked()
+                    {
+                        *render.mode = mode;
+                    }
+                }
+            });
+        if ui
+            .checkbox(&mut render.settings.flat_shading, "Flat Shading")
+            .on_hover_text("Color each triangle by its own slope instead of smoothed normals")
+            .changed()
+        {
+            viewer.needs_rebuild = true;
+        }
+        ui.checkbox(
+            &mut render.settings.double_sided,
+            "Double-sided (no backface culling)",
+        );
+        ui.checkbox(&mut render.settings.lit, "Lit Shading")
+            .on_hover_text("Light the mesh with the sun, the slope colors still tint it");
+        ui.checkbox(&mut render.settings.show_axes, "Show Axes");
+        let mut anti_aliasing = *render.anti_aliasing;
+        egui::ComboBox::from_label("Anti-aliasing")
+            .selected_text(anti_aliasing.label())
+            .show_ui(ui, |ui| {
+                for level in AntiAliasing::ALL {
+                    ui.selectable_value(&mut anti_aliasing, level, level.label());
+                }
+            });
+        if anti_aliasing != *render.anti_aliasing {
+            *render.anti_aliasing = anti_aliasing;
+        }
+        if render.anti_aliasing.msaa() != *render.msaa {
+            ui.label(format!(
+                "Not supported here, using {}x",
+                render.msaa.samples()
+            ));
+        }
+        ui.checkbox(
+            &mut render.settings.show_open_edges,
+            "Show Boundary / Non-manifold Edges",
+        );
+        ui.checkbox(&mut render.minimap.open, "Show Minimap (M)");
+        ui.horizontal(|ui| {
+            let ground = &mut *render.ground;
+            ui.checkbox(&mut ground.show, "Ground Grid at Y:");
+            ui.add_enabled(
+                !ground.auto,
+                egui::DragValue::new(&mut ground.height).speed(0.5),
+            );
+            ui.checkbox(&mut ground.auto, "Mesh bottom");
+        });
+        ui.checkbox(&mut render.perf.open, "Show Performance (F3)");
+
+        // A near plane too close for the mesh size shows as z-fighting
+        let mut clip_planes = *render.clip_planes;
+        ui.add(
+            egui::Slider::new(&mut clip_planes.near, MIN_NEAR_CLIP..=MAX_NEAR_CLIP)
+                .logarithmic(true)
+                .text("Near Clip"),
+        );
+        ui.add(
+            egui::Slider::new(&mut clip_planes.far, clip_planes.near * 2.0..=MAX_FAR_CLIP)
+                .logarithmic(true)
+                .text("Far Clip"),
+        );
+        let clip_planes = clip_planes.validated();
+        if clip_planes != *render.clip_planes {
+            *render.clip_planes = clip_planes;
+        }
+
+        // Cut away what hides the floors below, `rebuild_on_section_change`
+        // re-tiles when it moves
+        if let Some(mesh_data) = &mesh_data {
+            let bounds = transformed_bounds(
+                mesh_data.bounds,
+                &mesh_ui.layers.transform(layers::PRIMARY_LAYER),
+            );
+            let mut section = *render.section;
+            section::section_controls(ui, &mut section, bounds);
+            if section != *render.section {
+                *render.section = section;
+            }
+        }
+        ui.horizontal(|ui| {
+            if ui.button("Screenshot (F12)").clicked() {
+                render.screenshot.requested = true;
+            }
+            ui.checkbox(&mut render.screenshot.include_ui, "Include UI");
+        });
+        match &render.screenshot.message {
+            Some(Ok(message)) => {
+                ui.label(message);
+            }
+            Some(Err(message)) => {
+                ui.colored_label(egui::Color32::RED, message);
+            }
+            None => {}
+        }
+        ui.checkbox(&mut tiles.settings.show_grid, "Show Tile Grid");
+        ui.label(format!(
+            "Visible Tiles: {} / {}",
+            tiles.cull_stats.visible, tiles.cull_stats.total
+        ));
+
+        // Find a tile by its grid coordinates
+        ui.horizontal(|ui| {
+            ui.label("Tile X:");
+            ui.add(egui::DragValue::new(&mut tiles.highlight.tile_x));
+            ui.label("Y:");
+            ui.add(egui::DragValue::new(&mut tiles.highlight.tile_y));
+            if ui.button("Highlight").clicked() {
+                tiles.highlight.requested = true;
+            }
+        });
+        ui.checkbox(&mut tiles.highlight.fly_to, "Fly to highlighted tile");
+        if let Some(message) = &tiles.highlight.message {
+            ui.colored_label(egui::Color32::YELLOW, message);
+        }
+
+        // Distance between two clicked points
+        ui.separator();
+        ui.checkbox(&mut measure.enabled, "Measure (click two points)");
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut measure.snap, "Snap to grid");
+            ui.add(
+                egui::DragValue::new(&mut measure.grid_step)
+                    .speed(0.1)
+                    .clamp_range(0.01..=10000.0)
+                    .prefix("Step: "),
+            );
+        });
+        if let Some((distance, horizontal)) = measure.distances() {
+            ui.label(format!("Distance: {:.2}", distance));
+            ui.label(format!("Horizontal: {:.2}", horizontal));
+        }
+        if ui.button("Clear (Delete)").clicked() {
+            measure.points.clear();
+        }
+
+        // Controls help
+        ui.separator();
+        ui.label("Controls:");
+        ui.label("WASD - Move");
+        ui.label("Q/E - Up/Down");
+        ui.label("Hold Shift - Move faster, Ctrl - Move slower");
+        ui.label("Ctrl + Left Drag - Look (also slows movement)");
+        ui.label("Middle Click - Set orbit focus");
+        ui.label("Double Click - Orbit the point on the mesh");
+        ui.label("Scroll - Move speed / Orbit radius, zoom in orthographic");
+        ui.label("Alt + Scroll - Field of view");
+        ui.label("Tab - Cycle render mode");
+        ui.label("F - Fit view to mesh, H - Level view");
+        ui.label("M - Minimap, click it to move there");
+        ui.label("F3 - Performance overlay");
+        ui.label("Left Click - Select actor, or measure point with the tool on");
+        ui.label("Delete - Clear measurement");
+        ui.label("Ctrl + C - Copy the hovered triangle's file vertices");
+    });
+
+    let walkable_slope_angle = viewer.walkable_slope_angle;
+
+    // The held file is built once the user answers
+    if budget::budget_prompt(contexts.ctx_mut(), &mut mesh_ui.budget) {
+        mesh_ui.groups.changed = true;
+    }
+
+    if let Some(stats) = &mesh_ui.stats_panel.stats {
+        egui::Window::new("Mesh Stats").show(contexts.ctx_mut(), |ui| {
+            let size = stats.bounds.1 - stats.bounds.0;
+            ui.label(format!("Triangles: {}", stats.triangle_count));
+            ui.label(format!("Vertices: {}", stats.vertex_count));
+            ui.label(format!(
+                "Size: {:.2} x {:.2} x {:.2}",
+                size.x, size.y, size.z
+            ));
+            ui.label(format!("Tiles: {}", stats.tile_count));
+            ui.label(format!(
+                "Walkable: {:.1}% at {:.1}°",
+                stats.walkable_ratio() * 100.0,
+                mesh_ui.stats_panel.walkable_slope_angle
+            ));
+            ui.label(format!("Boundary Edges: {}", stats.boundary_edges));
+            ui.label(format!("Non-manifold Edges: {}", stats.non_manifold_edges));
+            ui.label(format!(
+                "Degenerate Triangles: {}",
+                stats.degenerate_triangles
+            ));
+            ui.label(format!(
+                "Walkable islands: {} (largest {:.1}%)",
+                stats.walkable_components,
+                stats.largest_component_share() * 100.0
+            ));
+        });
+
+        egui::Window::new("Slope Histogram")
+            .default_open(false)
+            .show(contexts.ctx_mut(), |ui| {
+                let mut angle = viewer.walkable_slope_angle;
+                if slope_histogram_chart(ui, &stats.slope_histogram, &mut angle) {
+                    viewer.walkable_slope_angle =
+                        render.slope_colors.set_walkable_slope_angle(angle);
+                }
+                ui.label(format!(
+                    "Up to {:.1}°: {} triangles, steeper: {}",
+                    mesh_ui.stats_panel.walkable_slope_angle,
+                    stats.walkable_triangles,
+                    stats.triangle_count - stats.walkable_triangles
+                ));
+                if stats.slope_histogram.facing_down > 0 {
+                    ui.label(format!(
+                        "Facing down: {} triangles",
+                        stats.slope_histogram.facing_down
+                    ));
+                }
+            });
+    }
+
+    // Slope range of each band, shown as a flat face would be colored. In
+    // the elevation mode the gradient's colors with the heights they stand for
+    egui::Window::new("Legend")
+        .anchor(egui::Align2::LEFT_BOTTOM, [10.0, -10.0])
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            if *render.mode == RenderMode::Elevation {
+                let (min, max) = mesh_data
+                    .as_deref()
+                    .map_or((0.0, 0.0), |mesh| (mesh.bounds.0.y, mesh.bounds.1.y));
+                let mut elevation = *render.elevation;
+                for (color, height) in [
+                    (&mut elevation.high, max),
+                    (&mut elevation.mid, (min + max) * 0.5),
+                    (&mut elevation.low, min),
+                ] {
+                    ui.horizontal(|ui| {
+                        ui.color_edit_button_rgb(color);
+                        ui.label(format!("Height {:.2}", height));
+                    });
+                }
+                if elevation != *render.elevation {
+                    *render.elevation = elevation;
+                }
+                return;
+            }
+            let mut min_angle = 0.0;
+            for band in &render.slope_colors.bands {
+                let flat = SlopeColors {
+                    bands: vec![band.clone()],
+                    ..render.slope_colors.clone()
+                };
+                let [r, g, b, _] = flat.shade(Vec3::Y);
+                ui.horizontal(|ui| {
+                    egui::color_picker::show_color(
+                        ui,
+                        egui::Rgba::from_rgb(r, g, b),
+                        egui::vec2(16.0, 16.0),
+                    );
+                    ui.label(format!("Slope {:.1}° - {:.1}°", min_angle, band.max_angle));
+                });
+                min_angle = band.max_angle;
+            }
+            if let Some([r, g, b]) = render.slope_colors.flipped {
+                ui.horizontal(|ui| {
+                    egui::color_picker::show_color(
+                        ui,
+                        egui::Rgba::from_rgb(r, g, b),
+                        egui::vec2(16.0, 16.0),
+                    );
+                    ui.label("Flipped (facing down)");
+                });
+            }
+        });
+
+    egui::Window::new("Coordinates").show(contexts.ctx_mut(), |ui| {
+        if let Ok((mut transform, mut camera, camera_comp, mut projection)) =
+            camera_query.get_single_mut()
+        {
+            let pos = transform.translation;
+            ui.label(format!(
+                "Camera Position: {:.2}, {:.2}, {:.2}",
+                pos.x, pos.y, pos.z
+            ));
+
+            // Convert radians to degrees for more readable output
+            let yaw_degrees = camera.yaw.to_degrees();
+            let pitch_degrees = camera.pitch.to_degrees();
+            ui.label(format!(
+                "Camera Angles: Yaw {:.1}°, Pitch {:.1}°",
+                yaw_degrees, pitch_degrees
+            ));
+            ui.label(format!(
+                "Move Speed: {:.0} ({:.2}x)",
+                camera.move_speed,
+                camera.move_speed / render.camera_speed.base
+            ));
+
+            // A new base speed replaces what the mouse wheel made of the old one
+            ui.collapsing("Camera Speed", |ui| {
+                let mut speed = *render.camera_speed;
+                ui.add(
+                    egui::Slider::new(&mut speed.base, MIN_MOVE_SPEED..=MAX_MOVE_SPEED)
+                        .logarithmic(true)
+                        .text("Base Speed"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut speed.sprint, 1.0..=MAX_SPEED_MULTIPLIER)
+                        .logarithmic(true)
+                        .text("Shift Multiplier"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut speed.crawl, 1.0 / MAX_SPEED_MULTIPLIER..=1.0)
+                        .logarithmic(true)
+                        .text("Ctrl Multiplier"),
+                );
+                if speed.base != render.camera_speed.base {
+                    camera.move_speed = speed.base;
+                }
+                if speed != *render.camera_speed {
+                    *render.camera_speed = speed;
+                }
+            });
+
+            // Camera mode, leaving fly mode keeps the view by putting the focus
+            // straight ahead
+            let mut mode = camera.mode;
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut mode, CameraMode::Fly, "Fly");
+                ui.radio_value(&mut mode, CameraMode::Orbit, "Orbit");
+                ui.radio_value(&mut mode, CameraMode::Follow, "Follow");
+            });
+            if mode != camera.mode {
+                if camera.mode == CameraMode::Fly {
+                    camera.focus = transform.translation
+                        + camera_forward(camera.yaw, camera.pitch) * camera.orbit_radius;
+                }
+                camera.mode = mode;
+            }
+
+            let mut orthographic = matches!(*projection, Projection::Orthographic(_));
+            if ui.checkbox(&mut orthographic, "Orthographic").changed() {
+                toggle_orthographic(&mut projection, &mut camera, &render.clip_planes);
+            }
+
+            if camera.mode != CameraMode::Fly {
+                ui.add(
+                    egui::Slider::new(
+                        &mut camera.orbit_radius,
+                        MIN_ORBIT_RADIUS..=MAX_ORBIT_RADIUS,
+                    )
+                    .logarithmic(true)
+                    .text("Orbit Radius"),
+                );
+                ui.label(format!(
+                    "Orbit Focus: {:.2}, {:.2}, {:.2}",
+                    camera.focus.x, camera.focus.y, camera.focus.z
+                ));
+            }
+
+            if let Ok(window) = windows.get_single() {
+                if let Some(cursor_pos) = window.cursor_position() {
+                    ui.label(format!(
+                        "Screen Position: {:.2}, {:.2}",
+                        cursor_pos.x, cursor_pos.y
+                    ));
+
+                    // Pick the mesh surface, fall back to the ground plane
+                    let (origin, direction) =
+                        world::screen_to_ray(window, camera_comp, &transform, cursor_pos);
+                    let primary = mesh_data
+                        .as_deref()
+                        .map(|mesh_data| (mesh_data, picking_grid.as_deref()));
+                    let hit = render
+                        .section
+                        .clip_ray(origin, direction)
+                        .and_then(|origin| mesh_ui.layers.raycast(primary, origin, direction));
+
+                    if let Some((layer, world_pos, triangle, normal)) = hit {
+                        ui.label(format!(
+                            "World Position: {:.2}, {:.2}, {:.2}",
+                            world_pos.x, world_pos.y, world_pos.z
+                        ));
+                        ui.label(format!(
+                            "Triangle: {} ({})",
+                            triangle,
+                            mesh_ui.layers.label(layer)
+                        ));
+
+                        // Corners as the OBJ numbers them, to find the face in the file
+                        let hit_mesh = if layer == layers::PRIMARY_LAYER {
+                            mesh_data.as_deref()
+                        } else {
+                            mesh_ui.layers.mesh_data(layer)
+                        };
+                        if let Some(&[a, b, c]) =
+                            hit_mesh.and_then(|mesh| mesh.file_vertices.get(triangle))
+                        {
+                            ui.label(format!("File vertices: {} {} {}", a, b, c));
+                            let ctrl = mesh_ui
+                                .keyboard
+                                .any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+                            if ctrl
+                                && mesh_ui.keyboard.just_pressed(COPY_INDICES_KEY)
+                                && !ui.ctx().wants_keyboard_input()
+                            {
+                                ui.output_mut(|output| {
+                                    output.copied_text =
+                                        format!("triangle {}: v {} {} {}", triangle, a, b, c)
+                                });
+                            }
+                        }
+
+                        ui.label(format!(
+                            "Slope: {:.1}°",
+                            normal.y.clamp(-1.0, 1.0).acos().to_degrees()
+                        ));
+                        ui.label(format!(
+                            "Walkable: {}",
+                            if is_walkable(normal, walkable_slope_angle) {
+                                "yes"
+                            } else {
+                                "no"
+                            }
+                        ));
+                    } else if let Some(world_pos) =
+                        world::ray_plane_intersection(origin, direction, mesh_ui.pick_plane.height)
+                    {
+                        ui.label(format!(
+                            "World Position (Y={:.2} plane): {:.2}, {:.2}, {:.2}",
+                            mesh_ui.pick_plane.height, world_pos.x, world_pos.y, world_pos.z
+                        ));
+                    }
+                }
+            }
+
+            ui.horizontal(|ui| {
+                let pick_plane = &mut *mesh_ui.pick_plane;
+                ui.label("Fallback Plane Y:");
+                ui.add_enabled(
+                    !pick_plane.auto,
+                    egui::DragValue::new(&mut pick_plane.height).speed(0.5),
+                );
+                ui.checkbox(&mut pick_plane.auto, "Mesh average");
+            });
+
+            // Jump to a position in game coordinates, typed or pasted
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("Go to:");
+                let pasted = ui.add(
+                    egui::TextEdit::singleline(&mut go_to.text)
+                        .hint_text("x, y, z")
+                        .desired_width(140.0),
+                );
+                if pasted.changed() {
+                    if let Some(position) = parse_coordinates(&go_to.text) {
+                        go_to.position = position;
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                for (label, value) in ["X", "Y", "Z"].into_iter().zip(go_to.position.as_mut()) {
+                    ui.label(label);
+                    ui.add(egui::DragValue::new(value).speed(1.0));
+                }
+                if ui.button("Go").clicked() {
+                    let target = mesh_ui.coords.point(go_to.position.to_array());
+                    let aspect = windows
+                        .get_single()
+                        .map_or(1.0, |window| window.width() / window.height().max(1.0));
+                    let fov = match &mut *projection {
+                        Projection::Perspective(perspective) => perspective.fov,
+                        Projection::Orthographic(ortho) => {
+                            ortho.scale = ortho_fit_scale(GO_TO_VIEW_RADIUS, aspect);
+                            camera.perspective_fov
+                        }
+                    };
+                    let distance = fit_distance(GO_TO_VIEW_RADIUS, fov, aspect);
+                    transform.translation = frame_view(&mut camera, target, distance);
+                    transform.look_to(camera_forward(camera.yaw, camera.pitch), Vec3::Y);
+                }
+            });
+        }
+    });
+
+    egui::Window::new("Bookmarks").show(contexts.ctx_mut(), |ui| {
+        let Ok((mut transform, mut camera, _, _)) = camera_query.get_single_mut() else {
+            return;
+        };
+        let mut changed = false;
+
+        // Save the current view under the typed name
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut *bookmark_name);
+            if ui.button("Save").clicked() && !bookmark_name.trim().is_empty() {
+                bookmarks.0.push((
+                    bookmark_name.trim().to_string(),
+                    transform.translation,
+                    camera.yaw,
+                    camera.pitch,
+                ));
+                bookmark_name.clear();
+                changed = true;
+            }
+        });
+
+        let mut remove = None;
+        for (index, (name, position, yaw, pitch)) in bookmarks.0.iter().enumerate() {
+            ui.horizontal(|ui| {
+                if ui.button(name).clicked() {
+                    camera.yaw = *yaw;
+                    camera.pitch = *pitch;
+                    let forward = camera_forward(*yaw, *pitch);
+                    transform.translation = *position;
+                    transform.look_to(forward, Vec3::Y);
+
+                    // Keep orbit mode from pulling the camera back to the old focus
+                    camera.focus = *position + forward * camera.orbit_radius;
+                }
+                if ui.small_button("x").clicked() {
+                    remove = Some(index);
+                }
+            });
+        }
+        if let Some(index) = remove {
+            bookmarks.0.remove(index);
+            changed = true;
+        }
+
+        if changed {
+            let path = bookmarks::bookmarks_path();
+            if let Err(e) = bookmarks.save(&path) {
+                warn!("Failed to save {}: {}", path.display(), e);
+            }
+        }
+    });
+
+    let tile_size = tiles.settings.tile_size;
+    selections::selection_window(
+        contexts.ctx_mut(),
+        &mut tiles.selection,
+        &mut tiles.selection_sets,
+        tile_size,
+        &mesh_ui.layers,
+    );
+}
+
+#[derive(Component)]
+struct DebugMesh;
+
+// All tiles of one material in a single entity, see `TileSettings::single_mesh`
+#[derive(Component)]
+struct MergedMesh;
+
+#[derive(Component)]
+struct TileMesh {
+    tile_x: i32,
+    tile_y: i32,
+    aabb: Aabb, // Bounds in the space of the tile's layer, before its transform
+}
+
+// Tile picked in the UI, `highlight_tiles` swaps in a bright material for it
+#[derive(Resource, Default)]
+struct TileHighlight {
+    tile_x: i32,
+    tile_y: i32,
+    fly_to: bool,
+    requested: bool, // Set by the UI, handled by `highlight_tiles`
+    selected: Option<(i32, i32)>,
+    message: Option<String>,
+}
+
+// Material a highlighted tile had before, restored when the highlight moves
+#[derive(Component)]
+struct HighlightedTile {
+    original: Handle<StandardMaterial>,
+}
+
+// Rendering state the UI reads and edits
+#[derive(SystemParam)]
+struct RenderUi<'w> {
+    settings: ResMut<'w, RenderSettings>,
+    slope_colors: ResMut<'w, SlopeColors>,
+    elevation: ResMut<'w, ElevationColors>,
+    mode: ResMut<'w, RenderMode>,
+    screenshot: ResMut<'w, ScreenshotState>,
+    minimap: ResMut<'w, minimap::Minimap>,
+    clip_planes: ResMut<'w, ClipPlanes>,
+    perf: ResMut<'w, perf::PerfOverlay>,
+    anti_aliasing: ResMut<'w, AntiAliasing>,
+    msaa: Res<'w, Msaa>,
+    ground: ResMut<'w, ground::GroundPlane>,
+    section: ResMut<'w, section::SectionPlane>,
+    camera_speed: ResMut<'w, CameraSpeed>,
+}
+
+// Loaded mesh state the UI reads and edits
+#[derive(SystemParam)]
+struct MeshUi<'w> {
+    watcher: ResMut<'w, ObjWatcher>,
+    stats_panel: Res<'w, MeshStatsPanel>,
+    coords: ResMut<'w, coords::CoordinateTransform>,
+    import: ResMut<'w, ImportOptions>,
+    layers: Res<'w, layers::MeshLayers>,
+    keyboard: Res<'w, Input<KeyCode>>,
+    pick_plane: ResMut<'w, world::PickPlane>,
+    groups: ResMut<'w, ObjGroups>,
+    budget: ResMut<'w, budget::MeshBudget>,
+    decimation: ResMut<'w, decimation::Decimation>,
+}
+
+// How loaded geometry is read, placed, cut and kept in budget before it is
+// tiled
+#[derive(SystemParam)]
+struct MeshPlacement<'w> {
+    coords: Res<'w, coords::CoordinateTransform>,
+    import: Res<'w, ImportOptions>,
+    section: Res<'w, section::SectionPlane>,
+    budget: ResMut<'w, budget::MeshBudget>,
+    decimation: ResMut<'w, decimation::Decimation>,
+}
+
+// Tile related state the UI reads and edits
+#[derive(SystemParam)]
+struct TileUi<'w> {
+    settings: ResMut<'w, TileSettings>,
+    cull_stats: Res<'w, TileCullStats>,
+    highlight: ResMut<'w, TileHighlight>,
+    tiler: Res<'w, tiling::IncrementalTiler>,
+    selection: ResMut<'w, selections::TileSelection>,
+    selection_sets: ResMut<'w, selections::SelectionSets>,
+}
+
+// Size of the area around a "go to" point that fills the view
+const GO_TO_VIEW_RADIUS: f32 = 500.0;
+
+// The "go to" fields of the coordinates window
+#[derive(Default)]
+struct GoToInput {
+    position: Vec3,
+    text: String, // Pasted coordinates, copied into `position` once they parse
+}
+
+// Tiles left visible by `cull_tiles` in the last frame
+#[derive(Resource, Default)]
+struct TileCullStats {
+    visible: usize,
+    total: usize,
+}
+
+#[derive(Resource, Clone)]
+struct MeshData {
+    vertices: Vec<Vec3>,
+    indices: Vec<u32>,
+    normals: Vec<Vec3>,
+    uvs: Vec<Vec2>, // Empty when the mesh has no texture coordinates
+    tile_size: f32,
+    bounds: (Vec3, Vec3), // Min and max corner of `vertices`
+    // Triangles of a loaded OBJ by material, what its entities are built from
+    material_groups: Vec<(Handle<StandardMaterial>, Vec<u32>)>,
+    // One-based `v` numbers of each triangle's corners in the OBJ, empty when
+    // the mesh isn't from a file
+    file_vertices: Vec<[usize; 3]>,
+}
+
+// Object and group names of the loaded OBJ and which of them are shown. The
+// file is kept so hiding a group only rebuilds the mesh
+#[derive(Resource, Default)]
+struct ObjGroups {
+    obj_data: Option<ObjData>,
+    visible: Vec<bool>,      // Per entry of `group_names`
+    face_counts: Vec<usize>, // Per entry of `group_names`
+    changed: bool,           // Set by the UI, the mesh is rebuilt by `update_mesh`
+}
+
+impl ObjGroups {
+    // Groups hidden before stay hidden when the file is reloaded
+    fn set_obj_data(&mut self, obj_data: ObjData) {
+        let hidden: Vec<&String> = self
+            .obj_data
+            .iter()
+            .flat_map(|old| old.group_names.iter().zip(&self.visible))
+            .filter_map(|(name, visible)| (!visible).then_some(name))
+            .collect();
+        let visible = obj_data
+            .group_names
+            .iter()
+            .map(|name| !hidden.contains(&name))
+            .collect();
+        self.visible = visible;
+        self.face_counts = obj_data.group_face_counts();
+        self.obj_data = Some(obj_data);
+    }
+}
+
+// Axis aligned bounds of the points, inverted (MAX, MIN) when empty
+fn point_bounds(points: &[Vec3]) -> (Vec3, Vec3) {
+    points.iter().fold(
+        (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+        |(min, max), v| (min.min(*v), max.max(*v)),
+    )
+}
+
+// Bounds of the box `(min, max)` once moved by `transform`
+fn transformed_bounds((min, max): (Vec3, Vec3), transform: &Transform) -> (Vec3, Vec3) {
+    let corners: Vec<Vec3> = (0..8)
+        .map(|i| {
+            let corner = Vec3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            );
+            transform.transform_point(corner)
+        })
+        .collect();
+    point_bounds(&corners)
+}
+
+// The viewer's command line, `args` without the program name. Either opens
+// the window or, with `--headless`, prints the mesh stats
+pub fn run(args: impl IntoIterator<Item = String>) -> ExitCode {
+    let args = match cli::parse_args(args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, cli::USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    // The command line overrides the saved settings for this run
+    let config = plugin::NavViewerConfig {
+        obj_path: args.obj_path,
+        tile_size: args.tile_size,
+        walkable_slope_angle: args.slope,
+        mitm_address: None,
+        max_frame_len: None,
+    };
+    if let Some(path) = &args.headless {
+        let settings = ViewerSettings::load_or_default(&settings::settings_path());
+        return stats::run_headless(
+            path,
+            config.tile_size(&settings),
+            config.slope_colors(&settings).bands[0].max_angle,
+            settings.import_options,
+            args.analysis.as_deref(),
+        );
+    }
+
+    App::new()
+        // Wireframes need line polygon mode from the GPU
+        .add_plugins((
+            DefaultPlugins.set(RenderPlugin {
+                render_creation: WgpuSettings {
+                    features: WgpuFeatures::POLYGON_MODE_LINE,
+                    ..default()
+                }
+                .into(),
+            }),
+            plugin::NavViewerPlugin { config },
+        ))
+        .insert_resource(ClearColor(Color::rgb(0.1, 0.1, 0.1)))
+        .run();
+    ExitCode::SUCCESS
+}
+
+// How often `watch_obj_file` looks at the file, and how long it has to stay
+// unchanged before reloading, so a file still being written isn't loaded
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(250);
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(500);
+
+// Watches the loaded OBJ by polling its modification time
+#[derive(Resource, Default)]
+struct ObjWatcher {
+    enabled: bool,
+    path: Option<PathBuf>, // File `modified` belongs to
+    modified: Option<SystemTime>,
+    changed_at: Option<Instant>, // Last change that hasn't been reloaded yet
+    last_poll: Option<Instant>,
+}
+
+impl ObjWatcher {
+    // Start over with the file as it is now
+    fn watch(&mut self, path: PathBuf, modified: Option<SystemTime>) {
+        self.path = Some(path);
+        self.modified = modified;
+        self.changed_at = None;
+    }
+
+    // Feed the current modification time, true once the file changed and then
+    // stayed the same for `RELOAD_DEBOUNCE`
+    fn update(&mut self, modified: Option<SystemTime>, now: Instant) -> bool {
+        if modified != self.modified {
+            self.modified = modified;
+            self.changed_at = Some(now);
+        }
+        match self.changed_at {
+            Some(changed_at) if now.duration_since(changed_at) >= RELOAD_DEBOUNCE => {
+                self.changed_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+fn watch_obj_file(mut watcher: ResMut<ObjWatcher>, mut mesh_viewer: Query<&mut MeshViewer>) {
+    let mut viewer = mesh_viewer.single_mut();
+    let path = match (&viewer.obj_path, watcher.enabled) {
+        (Some(path), true) => path.clone(),
+        // Changes made while not watching don't count
+        _ => {
+            watcher.path = None;
+            return;
+        }
+    };
+
+    let now = Instant::now();
+    if watcher
+        .last_poll
+        .is_some_and(|last_poll| now.duration_since(last_poll) < WATCH_POLL_INTERVAL)
+    {
+        return;
+    }
+    watcher.last_poll = Some(now);
+
+    let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+    if watcher.path.as_ref() != Some(&path) {
+        watcher.watch(path, modified);
+    } else if watcher.update(modified, now) {
+        viewer.needs_update = true;
+    }
+}
+
+// Stats of the current `MeshData`, kept up to date by `update_mesh_stats`
+#[derive(Resource, Default)]
+struct MeshStatsPanel {
+    stats: Option<stats::MeshStats>,
+    walkable_slope_angle: f32, // Angle the walkable triangles were counted at
+}
+
+// Keep the fallback pick plane at the mesh's average height while it's on auto
+fn follow_mesh_height(mut pick_plane: ResMut<world::PickPlane>, mesh_data: Option<Res<MeshData>>) {
+    let Some(mesh_data) = mesh_data else {
+        return;
+    };
+    if !pick_plane.auto || !(mesh_data.is_changed() || pick_plane.is_changed()) {
+        return;
+    }
+    if let Some(height) = world::average_height(&mesh_data.vertices) {
+        if pick_plane.height != height {
+            pick_plane.height = height;
+        }
+    }
+}
+
+// Recompute the stats for a new mesh, only the walkable count for a new angle
+fn update_mesh_stats(
+    mesh_data: Option<Res<MeshData>>,
+    mesh_viewer: Query<&MeshViewer>,
+    mut panel: ResMut<MeshStatsPanel>,
+) {
+    let Some(mesh_data) = mesh_data else {
+        return;
+    };
+    let angle = mesh_viewer.single().walkable_slope_angle;
+    let MeshStatsPanel {
+        stats,
+        walkable_slope_angle,
+    } = &mut *panel;
+
+    match stats {
+        Some(stats) if !mesh_data.is_changed() => {
+            if angle != *walkable_slope_angle {
+                stats.set_walkable_slope_angle(&mesh_data.vertices, &mesh_data.indices, angle);
+            }
+        }
+        _ => {
+            *stats = Some(stats::MeshStats::new(
+                &mesh_data.vertices,
+                &mesh_data.indices,
+                mesh_data.tile_size,
+                angle,
+            ))
+        }
+    }
+    *walkable_slope_angle = angle;
+}
+
+// Geometry of a single tile, cut out of the full mesh
+struct TileGeometry {
+    tile_x: i32,
+    tile_z: i32,
+    vertices: Vec<Vec3>,
+    indices: Vec<u32>,
+    normals: Vec<Vec3>,
+    uvs: Vec<Vec2>, // Empty when the mesh has no texture coordinates
+}
+
+impl TileGeometry {
+    fn new(tile_x: i32, tile_z: i32) -> Self {
+        Self {
+            tile_x,
+            tile_z,
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+        }
+    }
+}
+
+// Side length of the square tiles the mesh is split into
+#[derive(Resource)]
+struct TileSettings {
+    tile_size: f32,
+    show_grid: bool,   // Tile boundaries and coordinates drawn over the mesh
+    single_mesh: bool, // One entity per material instead of one per tile
+}
+
+impl Default for TileSettings {
+    fn default() -> Self {
+        Self {
+            tile_size: 988.0,
+            show_grid: false,
+            single_mesh: false,
+        }
+    }
+}
+
+// How the mesh is drawn
+#[derive(Resource)]
+struct RenderSettings {
+    show_axes: bool,       // Axes at the world origin and in the corner of the screen
+    double_sided: bool,    // Back faces drawn too, so flipped triangles don't vanish
+    flat_shading: bool,    // Each triangle shaded by its own face normal
+    show_open_edges: bool, // Boundary and non-manifold edges drawn over the mesh
+    lit: bool,             // Shaded by the directional light instead of only the vertex colors
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            show_axes: true,
+            double_sided: false,
+            flat_shading: false,
+            show_open_edges: false,
+            lit: false,
+        }
+    }
+}
+
+// MSAA level picked in the UI. 4x is the one level every GPU has to support;
+// 2x and 8x depend on the adapter (WebGL2 and many GLES or mobile drivers lack
+// them), so `apply_anti_aliasing` falls back to the nearest lower level the
+// adapter can do
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+enum AntiAliasing {
+    Off,
+    X2,
+    #[default]
+    X4,
+    X8,
+}
+
+impl AntiAliasing {
+    const ALL: [AntiAliasing; 4] = [
+        AntiAliasing::Off,
+        AntiAliasing::X2,
+        AntiAliasing::X4,
+        AntiAliasing::X8,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            AntiAliasing::Off => "Off",
+            AntiAliasing::X2 => "2x MSAA",
+            AntiAliasing::X4 => "4x MSAA",
+            AntiAliasing::X8 => "8x MSAA",
+        }
+    }
+
+    fn msaa(self) -> Msaa {
+        match self {
+            AntiAliasing::Off => Msaa::Off,
+            AntiAliasing::X2 => Msaa::Sample2,
+            AntiAliasing::X4 => Msaa::Sample4,
+            AntiAliasing::X8 => Msaa::Sample8,
+        }
+    }
+
+    // This level, or the highest one below it that `supported` takes the
+    // sample count of
+    fn fallback(self, supported: impl Fn(u32) -> bool) -> Self {
+        Self::ALL
+            .into_iter()
+            .rev()
+            .skip_while(|&level| level != self)
+            .find(|level| *level == AntiAliasing::Off || supported(level.msaa().samples()))
+            .unwrap_or(AntiAliasing::Off)
+    }
+}
+
+// Set `Msaa` to the chosen level, as far as the adapter supports it for the
+// color and depth targets of the views
+fn apply_anti_aliasing(
+    anti_aliasing: Res<AntiAliasing>,
+    adapter: Option<Res<RenderAdapter>>,
+    mut msaa: ResMut<Msaa>,
+) {
+    if !anti_aliasing.is_changed() {
+        return;
+    }
+    let level = anti_aliasing.fallback(|samples| {
+        adapter.as_ref().is_none_or(|adapter| {
+            [TextureFormat::bevy_default(), TextureFormat::Depth32Float]
+                .into_iter()
+                .all(|format| {
+                    adapter
+                        .get_texture_format_features(format)
+                        .flags
+                        .sample_count_supported(samples)
+                })
+        })
+    });
+    if level != *anti_aliasing {
+        warn!(
+            "{} isn't supported by this GPU, using {}",
+            anti_aliasing.label(),
+            level.label()
+        );
+    }
+    if *msaa != level.msaa() {
+        *msaa = level.msaa();
+    }
+}
+
+// What the mesh surfaces show, cycled with Tab
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+enum RenderMode {
+    Solid, // Shaded base color only
+    #[default]
+    SlopeColor,
+    Wireframe, // Triangle edges over the solid mesh
+    SlopeColorPlusWireframe,
+    WalkableIslands, // Each connected walkable area in its own color, see `islands`
+    Elevation,       // Height within the mesh on the `ElevationColors` gradient
+}
+
+impl RenderMode {
+    const ALL: [RenderMode; 6] = [
+        RenderMode::Solid,
+        RenderMode::SlopeColor,
+        RenderMode::Wireframe,
+        RenderMode::SlopeColorPlusWireframe,
+        RenderMode::WalkableIslands,
+        RenderMode::Elevation,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            RenderMode::Solid => "Solid",
+            RenderMode::SlopeColor => "Slope Color",
+            RenderMode::Wireframe => "Wireframe",
+            RenderMode::SlopeColorPlusWireframe => "Slope Color + Wireframe",
+            RenderMode::WalkableIslands => "Walkable Islands",
+            RenderMode::Elevation => "Elevation",
+        }
+    }
+
+    fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&mode| mode == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    fn wireframe(self) -> bool {
+        matches!(
+            self,
+            RenderMode::Wireframe | RenderMode::SlopeColorPlusWireframe
+        )
+    }
+
+    // Colors the surfaces get, every slope in the base color without slope
+    // colors. The elevation gradient spans no heights until `with_heights`
+    fn colors<'a>(
+        self,
+        slope_colors: &'a SlopeColors,
+        elevation: &ElevationColors,
+    ) -> Cow<'a, SlopeColors> {
+        let plain = || SlopeColors {
+            base: slope_colors.base,
+            flipped: slope_colors.flipped,
+            bands: vec![SlopeBand {
+                max_angle: 90.0,
+                color: slope_colors.base,
+                blend: 0.0,
+            }],
+            elevation: None,
+        };
+        match self {
+            RenderMode::SlopeColor | RenderMode::SlopeColorPlusWireframe => {
+                Cow::Borrowed(slope_colors)
+            }
+            RenderMode::Solid | RenderMode::Wireframe | RenderMode::WalkableIslands => {
+                Cow::Owned(plain())
+            }
+            RenderMode::Elevation => Cow::Owned(SlopeColors {
+                elevation: Some(ElevationRamp {
+                    colors: *elevation,
+                    min: 0.0,
+                    max: 0.0,
+                }),
+                ..plain()
+            }),
+        }
+    }
+}
+
+const HISTOGRAM_SIZE: egui::Vec2 = egui::vec2(360.0, 120.0);
+
+// Bars of triangles per degree of slope, walkable ones in the first band's
+// color, with a marker at `angle` that can be dragged. True when it was moved
+fn slope_histogram_chart(
+    ui: &mut egui::Ui,
+    histogram: &stats::SlopeHistogram,
+    angle: &mut f32,
+) -> bool {
+    let (rect, response) = ui.allocate_exact_size(HISTOGRAM_SIZE, egui::Sense::click_and_drag());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+
+    let bins = histogram.bins.len().max(1);
+    let bar_width = rect.width() / bins as f32;
+    let x_at = |degrees: f32| rect.left() + degrees / bins as f32 * rect.width();
+    let tallest = histogram.bins.iter().copied().max().unwrap_or(0).max(1);
+    for (bin, &count) in histogram.bins.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        let height = count as f32 / tallest as f32 * rect.height();
+        let color = if (bin as f32) < *angle {
+            egui::Color32::from_rgb(90, 200, 90)
+        } else {
+            egui::Color32::GRAY
+        };
+        painter.rect_filled(
+            egui::Rect::from_min_max(
+                egui::pos2(x_at(bin as f32), rect.bottom() - height),
+                egui::pos2(x_at(bin as f32) + bar_width, rect.bottom()),
+            ),
+            0.0,
+            color,
+        );
+    }
+
+    let mut changed = false;
+    if let Some(pointer) = response.interact_pointer_pos() {
+        let degrees = ((pointer.x - rect.left()) / rect.width() * bins as f32).clamp(0.0, 90.0);
+        if (degrees - *angle).abs() > f32::EPSILON {
+            *angle = degrees;
+            changed = true;
+        }
+    }
+    painter.vline(
+        x_at(*angle),
+        rect.y_range(),
+        egui::Stroke::new(2.0, egui::Color32::RED),
+    );
+
+    if let Some(pointer) = response.hover_pos() {
+        let bin = (((pointer.x - rect.left()) / bar_width) as usize).min(bins - 1);
+        response.on_hover_text_at_pointer(format!(
+            "{}° - {}°: {} triangles",
+            bin,
+            bin + 1,
+            histogram.bins.get(bin).copied().unwrap_or(0)
+        ));
+    }
+    for (degrees, align) in [
+        (0.0, egui::Align2::LEFT_TOP),
+        (90.0, egui::Align2::RIGHT_TOP),
+    ] {
+        painter.text(
+            egui::pos2(x_at(degrees), rect.top()),
+            align,
+            format!("{}°", degrees),
+            egui::FontId::default(),
+            ui.visuals().text_color(),
+        );
+    }
+    changed
+}
+
+// Tab switches to the next render mode, unless a text field has the keyboard
+fn cycle_render_mode(
+    keyboard: Res<Input<KeyCode>>,
+    mut contexts: EguiContexts,
+    mut mode: ResMut<RenderMode>,
+) {
+    if keyboard.just_pressed(KeyCode::Tab) && !contexts.ctx_mut().wants_keyboard_input() {
+        *mode = mode.next();
+    }
+}
+
+// Size of the corner axis gizmo in points, and its distance from the corner
+const AXIS_GIZMO_RADIUS: f32 = 40.0;
+const AXIS_GIZMO_MARGIN: f32 = 20.0;
+
+// Screenshot asked for with F12 or the UI, taken by `take_screenshot`
+#[derive(Resource)]
+struct ScreenshotState {
+    include_ui: bool,
+    requested: bool,
+    hide_ui: bool, // Set for the frame being captured without UI
+    message: Option<Result<String, String>>, // Saved path or why it failed
+    saved: Arc<Mutex<Option<Result<String, String>>>>, // Filled once the file is written
+}
+
+impl Default for ScreenshotState {
+    fn default() -> Self {
+        Self {
+            include_ui: true,
+            requested: false,
+            hide_ui: false,
+            message: None,
+            saved: Arc::default(),
+        }
+    }
+}
+
+// Dark enough to read against both walkable and unwalkable colors
+const WIREFRAME_COLOR: Color = Color::rgb(0.05, 0.05, 0.05);
+
+// Grid labels are skipped when more tiles than this would be labelled
+const MAX_TILE_LABELS: i64 = 4096;
+
+// Smaller (or non-positive) tile sizes are clamped to this
+const MIN_TILE_SIZE: f32 = 1.0;
+const MAX_TILE_SIZE: f32 = 100000.0;
+
+// Vertices closer than this (and with equally close normal and uv) are merged
+// into one when building tiles, as long as their triangles are colored alike
+const VERTEX_WELD_EPSILON: f32 = 1e-4;
+
+// Twice the area a triangle needs, relative to its longest edge squared, to
+// not count as degenerate
+const DEGENERATE_AREA_EPSILON: f32 = 1e-6;
+
+// Position, normal, uv and the normal `calculate_colors` colors the corner's
+// triangle by. Corners of triangles colored from different normals stay
+// apart, so one triangle's slope color doesn't spill into the next
+type VertexKey = ([i64; 3], [i64; 3], [i64; 2], [i64; 3]);
+
+fn quantize(value: f32) -> i64 {
+    (value / VERTEX_WELD_EPSILON).round() as i64
+}
+
+fn quantize_vec3(value: Vec3) -> [i64; 3] {
+    [quantize(value.x), quantize(value.y), quantize(value.z)]
+}
+
+fn vertex_key(position: Vec3, normal: Vec3, uv: Vec2, shading_normal: Vec3) -> VertexKey {
+    (
+        quantize_vec3(position),
+        quantize_vec3(normal),
+        [quantize(uv.x), quantize(uv.y)],
+        quantize_vec3(shading_normal),
+    )
+}
+
+fn split_mesh_into_tiles(
+    vertices: &[Vec3],
+    indices: &[u32],
+    normals: &[Vec3],
+    uvs: &[Vec2],
+    tile_size: f32,
+) -> Vec<TileGeometry> {
+    let pool = ComputeTaskPool::get_or_init(TaskPool::default);
+    split_mesh_into_tiles_on(pool, vertices, indices, normals, uvs, tile_size)
+}
+
+// Tiles come back sorted by (tile_x, tile_z) with their triangles in the
+// original order, so the result does not depend on how the work was split
+fn split_mesh_into_tiles_on(
+    pool: &TaskPool,
+    vertices: &[Vec3],
+    indices: &[u32],
+    normals: &[Vec3],
+    uvs: &[Vec2],
+    tile_size: f32,
+) -> Vec<TileGeometry> {
+    let mut tile_triangles = std::collections::BTreeMap::new();
+    bin_triangles(
+        pool,
+        vertices,
+        indices,
+        0..(indices.len() / 3) as u32,
+        tile_size,
+        &mut tile_triangles,
+    );
+    let tile_triangles: Vec<_> = tile_triangles.into_iter().collect();
+    build_tiles(pool, &tile_triangles, vertices, indices, normals, uvs)
+}
+
+// Triangles of each tile, in the order they were binned
+type TileTriangles = std::collections::BTreeMap<(i32, i32), Vec<u32>>;
+
+// Add the triangles in `triangles` to the tile(s) they belong to, worked out
+// in parallel
+fn bin_triangles(
+    pool: &TaskPool,
+    vertices: &[Vec3],
+    indices: &[u32],
+    triangles: std::ops::Range<u32>,
+    tile_size: f32,
+    tile_triangles: &mut TileTriangles,
+) {
+    let tile_size = tile_size.max(MIN_TILE_SIZE);
+    let triangles: Vec<u32> = triangles.collect();
+    let binned = triangles.par_splat_map(pool, None, |chunk| {
+        let mut binned = Vec::with_capacity(chunk.len());
+        for &triangle in chunk {
+            binned.extend(
+                triangle_tiles(vertices, indices, triangle as usize, tile_size)
+                    .map(|tile| (tile, triangle)),
+            );
+        }
+        binned
+    });
+
+    // Chunks come back in order, so each tile lists its triangles in order
+    for (tile, triangle) in binned.into_iter().flatten() {
+        tile_triangles.entry(tile).or_default().push(triangle);
+    }
+}
+
+// Build the tiles in parallel, in the order given
+fn build_tiles(
+    pool: &TaskPool,
+    tile_triangles: &[((i32, i32), Vec<u32>)],
+    vertices: &[Vec3],
+    indices: &[u32],
+    normals: &[Vec3],
+    uvs: &[Vec2],
+) -> Vec<TileGeometry> {
+    tile_triangles
+        .par_splat_map(pool, None, |chunk| {
+            chunk
+                .iter()
+                .map(|((tile_x, tile_z), triangles)| {
+                    build_tile(*tile_x, *tile_z, triangles, vertices, indices, normals, uvs)
+                })
+                .collect::<Vec<_>>()
+        })
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+// Tiles a triangle might touch, every tile its XZ bounds overlap
+fn triangle_tiles(
+    vertices: &[Vec3],
+    indices: &[u32],
+    triangle: usize,
+    tile_size: f32,
+) -> impl Iterator<Item = (i32, i32)> {
+    let tile_of = |corner: usize| {
+        let v = vertices[indices[triangle * 3 + corner] as usize];
+        (
+            (v.x / tile_size).floor() as i32,
+            (v.z / tile_size).floor() as i32,
+        )
+    };
+    let (tile_x0, tile_z0) = tile_of(0);
+    let (tile_x1, tile_z1) = tile_of(1);
+    let (tile_x2, tile_z2) = tile_of(2);
+
+    let min_tile_x = tile_x0.min(tile_x1).min(tile_x2);
+    let max_tile_x = tile_x0.max(tile_x1).max(tile_x2);
+    let min_tile_z = tile_z0.min(tile_z1).min(tile_z2);
+    let max_tile_z = tile_z0.max(tile_z1).max(tile_z2);
+    (min_tile_x..=max_tile_x)
+        .flat_map(move |tile_x| (min_tile_z..=max_tile_z).map(move |tile_z| (tile_x, tile_z)))
+}
+
+// Copy the triangles of one tile, reusing vertices the tile already has so
+// shared corners keep a single index
+fn build_tile(
+    tile_x: i32,
+    tile_z: i32,
+    triangles: &[u32],
+    vertices: &[Vec3],
+    indices: &[u32],
+    normals: &[Vec3],
+    uvs: &[Vec2],
+) -> TileGeometry {
+    let mut tile = TileGeometry::new(tile_x, tile_z);
+    let mut vertex_map: std::collections::HashMap<VertexKey, u32> =
+        std::collections::HashMap::new();
+
+    for &triangle in triangles {
+        let corners = &indices[triangle as usize * 3..triangle as usize * 3 + 3];
+        let shading_normal = normals[corners[0] as usize];
+        for &index in corners {
+            let position = vertices[index as usize];
+            let normal = normals[index as usize];
+            let uv = uvs.get(index as usize).copied().unwrap_or(Vec2::ZERO);
+
+            let tile_index = *vertex_map
+                .entry(vertex_key(position, normal, uv, shading_normal))
+                .or_insert_with(|| {
+                    tile.vertices.push(position);
+                    tile.normals.push(normal);
+                    if !uvs.is_empty() {
+                        tile.uvs.push(uv);
+                    }
+                    tile.vertices.len() as u32 - 1
+                });
+            tile.indices.push(tile_index);
+        }
+    }
+    tile
+}
+
+// Steepest slope angle a band can have, a vertical wall
+const MAX_SLOPE_ANGLE: f32 = 90.0;
+
+// Slope angles only mean something from flat to vertical, others are clamped
+// with a warning
+fn clamp_slope_angle(angle: f32) -> f32 {
+    let clamped = if angle.is_nan() {
+        0.0
+    } else {
+        angle.clamp(0.0, MAX_SLOPE_ANGLE)
+    };
+    if clamped != angle {
+        warn!(
+            "Slope angle {}° is outside 0-{}°, using {}°",
+            angle, MAX_SLOPE_ANGLE, clamped
+        );
+    }
+    clamped
+}
+
+// Walkable when the slope is not steeper than the threshold
+fn is_walkable(normal: Vec3, walkable_slope_angle: f32) -> bool {
+    normal.y >= (walkable_slope_angle.to_radians()).cos()
+}
+
+// The slope test `calculate_colors` bands by, per normal
+pub fn classify_walkable(normals: &[Vec3], walkable_slope_angle: f32) -> Vec<bool> {
+    normals
+        .iter()
+        .map(|&normal| is_walkable(normal, walkable_slope_angle))
+        .collect()
+}
+
+// Only the triangles `calculate_colors` would shade as walkable, i.e. those
+// whose first corner's normal passes. Vertices no kept triangle uses are
+// dropped, the rest keep their order
+pub fn extract_walkable_mesh(
+    vertices: &[Vec3],
+    indices: &[u32],
+    normals: &[Vec3],
+    walkable_slope_angle: f32,
+) -> (Vec<Vec3>, Vec<u32>) {
+    let walkable = classify_walkable(normals, walkable_slope_angle);
+    let kept: Vec<&[u32]> = indices
+        .chunks_exact(3)
+        .filter(|triangle| walkable[triangle[0] as usize])
+        .collect();
+
+    let mut used = vec![false; vertices.len()];
+    for &index in kept.iter().flat_map(|triangle| triangle.iter()) {
+        used[index as usize] = true;
+    }
+    let mut remap = vec![0; vertices.len()];
+    let mut walkable_vertices = Vec::new();
+    for (index, vertex) in vertices.iter().enumerate().filter(|&(i, _)| used[i]) {
+        remap[index] = walkable_vertices.len() as u32;
+        walkable_vertices.push(*vertex);
+    }
+    let walkable_indices = kept
+        .iter()
+        .flat_map(|triangle| triangle.iter().map(|&index| remap[index as usize]))
+        .collect();
+    (walkable_vertices, walkable_indices)
+}
+
+// Zero-area triangles, including slivers whose area is lost in rounding next
+// to the size of their edges. Their normal is meaningless
+fn is_degenerate(v0: Vec3, v1: Vec3, v2: Vec3) -> bool {
+    let longest = (v1 - v0)
+        .length_squared()
+        .max((v2 - v1).length_squared())
+        .max((v0 - v2).length_squared());
+    (v1 - v0).cross(v2 - v0).length() <= DEGENERATE_AREA_EPSILON * longest
+}
+
+// Unit normal of a triangle, `triangle` indexes `indices` in steps of 3
+fn face_normal(vertices: &[Vec3], indices: &[u32], triangle: usize) -> Vec3 {
+    let v0 = vertices[indices[triangle * 3] as usize];
+    let v1 = vertices[indices[triangle * 3 + 1] as usize];
+    let v2 = vertices[indices[triangle * 3 + 2] as usize];
+    (v1 - v0).cross(v2 - v0).normalize_or_zero()
+}
+
+// Slopes up to `max_angle` degrees that are not in an earlier band
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+struct SlopeBand {
+    max_angle: f32,
+    color: [f32; 3],
+    blend: f32, // How much of `color` is mixed over the shaded base color
+}
+
+// Colors used by `calculate_colors`, edited in the UI and saved in the settings
+#[derive(Resource, Clone, PartialEq, Debug, Serialize, Deserialize)]
+struct SlopeColors {
+    base: [f32; 3],        // Scaled by the brightness of the face
+    bands: Vec<SlopeBand>, // Sorted by `max_angle`, never empty
+    // Faces pointing down get this color instead, to spot flipped winding
+    #[serde(skip)]
+    flipped: Option<[f32; 3]>,
+    // Vertices are colored by height instead of the bands, see `RenderMode::Elevation`
+    #[serde(skip)]
+    elevation: Option<ElevationRamp>,
+}
+
+impl Default for SlopeColors {
+    // Walkable faces in plain grey, steeper ones tinted orange
+    fn default() -> Self {
+        Self {
+            base: [220.0 / 255.0; 3],
+            bands: vec![
+                SlopeBand {
+                    max_angle: 45.0,
+                    color: [220.0 / 255.0; 3],
+                    blend: 0.0,
+                },
+                SlopeBand {
+                    max_angle: 90.0,
+                    color: [192.0 / 255.0, 128.0 / 255.0, 0.0],
+                    blend: 64.0 / 255.0,
+                },
+            ],
+            flipped: None,
+            elevation: None,
+        }
+    }
+}
+
+// Gradient of the elevation render mode, from the lowest point of a mesh
+// through the middle to the highest. Edited in the legend and saved
+#[derive(Resource, Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct ElevationColors {
+    low: [f32; 3],
+    mid: [f32; 3],
+    high: [f32; 3],
+}
+
+impl Default for ElevationColors {
+    fn default() -> Self {
+        Self {
+            low: [0.1, 0.3, 0.9],
+            mid: [0.1, 0.8, 0.2],
+            high: [0.9, 0.2, 0.1],
+        }
+    }
+}
+
+impl ElevationColors {
+    // Color at `t` from 0 at the bottom to 1 at the top
+    fn at(&self, t: f32) -> [f32; 3] {
+        let t = t.clamp(0.0, 1.0) * 2.0;
+        let (from, to, t) = if t <= 1.0 {
+            (self.low, self.mid, t)
+        } else {
+            (self.mid, self.high, t - 1.0)
+        };
+        [0, 1, 2].map(|c| from[c] * (1.0 - t) + to[c] * t)
+    }
+}
+
+// The gradient over the heights of one mesh
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct ElevationRamp {
+    colors: ElevationColors,
+    min: f32,
+    max: f32,
+}
+
+impl ElevationRamp {
+    fn color(&self, height: f32) -> [f32; 3] {
+        let range = self.max - self.min;
+        let t = if range > f32::EPSILON {
+            (height - self.min) / range
+        } else {
+            0.0
+        };
+        self.colors.at(t)
+    }
+}
+
+// Color of downward faces when flipped faces are shown
+const FLIPPED_COLOR: [f32; 3] = [1.0, 0.0, 1.0];
+
+impl SlopeColors {
+    // Band the normal's slope falls into, slopes past the last band use it
+    fn band(&self, normal: Vec3) -> &SlopeBand {
+        self.bands
+            .iter()
+            .find(|band| is_walkable(normal, band.max_angle))
+            .unwrap_or_else(|| self.bands.last().expect("at least one slope band"))
+    }
+
+    // `band` for each face normal, by the slope test of `classify_walkable`
+    fn bands_of(&self, normals: &[Vec3]) -> Vec<&SlopeBand> {
+        let mut bands = vec![None; normals.len()];
+        for band in &self.bands {
+            let walkable = classify_walkable(normals, band.max_angle);
+            for (found, walkable) in bands.iter_mut().zip(walkable) {
+                if walkable && found.is_none() {
+                    *found = Some(band);
+                }
+            }
+        }
+        let steepest = self.bands.last().expect("at least one slope band");
+        bands
+            .into_iter()
+            .map(|band| band.unwrap_or(steepest))
+            .collect()
+    }
+
+    // Color of a face with the given normal
+    fn shade(&self, normal: Vec3) -> [f32; 4] {
+        self.shade_in(normal, self.band(normal))
+    }
+
+    // Color of a face with the given normal, `band` being the one it is in
+    fn shade_in(&self, normal: Vec3, band: &SlopeBand) -> [f32; 4] {
+        let grey = self.base.map(|c| c * brightness(normal));
+
+        if let Some([r, g, b]) = self.flipped.filter(|_| normal.y < 0.0) {
+            return [r, g, b, 1.0];
+        }
+
+        // Lerp between grey and the band color
+        let t = band.blend;
+        [
+            grey[0] * (1.0 - t) + band.color[0] * t,
+            grey[1] * (1.0 - t) + band.color[1] * t,
+            grey[2] * (1.0 - t) + band.color[2] * t,
+            1.0,
+        ]
+    }
+
+    // Color of a corner at `height` on a face with the given normal. Only
+    // the elevation gradient looks at the height
+    fn shade_corner(&self, normal: Vec3, height: f32) -> [f32; 4] {
+        match &self.elevation {
+            Some(ramp) if self.flipped.is_none() || normal.y >= 0.0 => {
+                let [r, g, b] = ramp.color(height).map(|c| c * brightness(normal));
+                [r, g, b, 1.0]
+            }
+            _ => self.shade(normal),
+        }
+    }
+
+    // These colors with the elevation gradient spread over `bounds`
+    fn with_heights(&self, (min, max): (Vec3, Vec3)) -> Cow<'_, SlopeColors> {
+        match self.elevation {
+            Some(ramp) => Cow::Owned(SlopeColors {
+                elevation: Some(ElevationRamp {
+                    min: min.y,
+                    max: max.y,
+                    ..ramp
+                }),
+                ..self.clone()
+            }),
+            None => Cow::Borrowed(self),
+        }
+    }
+
+    // Set the upper edge of the first band, returns the angle it got
+    fn set_walkable_slope_angle(&mut self, angle: f32) -> f32 {
+        let angle = clamp_slope_angle(angle);
+        self.bands[0].max_angle = angle;
+        angle
+    }
+
+    fn angles_in_range(&self) -> bool {
+        self.bands
+            .iter()
+            .all(|band| (0.0..=MAX_SLOPE_ANGLE).contains(&band.max_angle))
+    }
+
+    fn clamp_angles(&mut self) {
+        for band in &mut self.bands {
+            band.max_angle = clamp_slope_angle(band.max_angle);
+        }
+    }
+
+    // Keep the bands in order after an edit
+    fn sort_bands(&mut self) {
+        self.bands
+            .sort_by(|a, b| a.max_angle.total_cmp(&b.max_angle));
+    }
+}
+
+// Brightness based on normal x and y components
+fn brightness(normal: Vec3) -> f32 {
+    (2.0 + normal.x + normal.y) / 4.0
+}
+
+// Add this function to calculate colors based on slope
+fn calculate_colors(
+    vertices: &[Vec3],
+    indices: &[u32],
+    normals: &[Vec3],
+    slope_colors: &SlopeColors,
+) -> Vec<[f32; 4]> {
+    // Colors not edited through the UI can be out of range
+    let slope_colors = if slope_colors.angles_in_range() {
+        Cow::Borrowed(slope_colors)
+    } else {
+        let mut clamped = slope_colors.clone();
+        clamped.clamp_angles();
+        Cow::Owned(clamped)
+    };
+    let mut colors = vec![[1.0, 1.0, 1.0, 1.0]; vertices.len()];
+
+    // Triangles are colored by the normal of their first corner
+    let face_normals: Vec<Vec3> = indices
+        .chunks_exact(3)
+        .map(|chunk| normals[chunk[0] as usize])
+        .collect();
+    if slope_colors.elevation.is_some() {
+        for (chunk, &normal) in indices.chunks_exact(3).zip(&face_normals) {
+            for &index in chunk {
+                colors[index as usize] =
+                    slope_colors.shade_corner(normal, vertices[index as usize].y);
+            }
+        }
+        return colors;
+    }
+
+    let bands = slope_colors.bands_of(&face_normals);
+    for ((chunk, &normal), band) in indices.chunks_exact(3).zip(&face_normals).zip(bands) {
+        let color = slope_colors.shade_in(normal, band);
+
+        // Apply the color to all vertices of the triangle
+        for &index in chunk {
+            colors[index as usize] = color;
+        }
+    }
+    colors
+}
+
+// Recompute the vertex colors of a mesh in place from its own attributes
+fn recolor_mesh(mesh: &mut Mesh, slope_colors: &SlopeColors) {
+    let attribute = |mesh: &Mesh, id| -> Option<Vec<Vec3>> {
+        let values = mesh.attribute(id)?.as_float3()?;
+        Some(values.iter().map(|&v| Vec3::from(v)).collect())
+    };
+    let (Some(vertices), Some(normals), Some(indices)) = (
+        attribute(mesh, Mesh::ATTRIBUTE_POSITION),
+        attribute(mesh, Mesh::ATTRIBUTE_NORMAL),
+        mesh.indices(),
+    ) else {
+        return;
+    };
+    let indices: Vec<u32> = indices.iter().map(|i| i as u32).collect();
+
+    let colors = calculate_colors(&vertices, &indices, &normals, slope_colors);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+}
+
+// Picking new colors repaints the spawned meshes without reloading them
+fn recolor_meshes(
+    slope_colors: Res<SlopeColors>,
+    elevation: Res<ElevationColors>,
+    mode: Res<RenderMode>,
+    mesh_data: Option<Res<MeshData>>,
+    layers: Res<layers::MeshLayers>,
+    handles: Query<(&Handle<Mesh>, Option<&layers::MeshLayerId>), ViewerMeshFilter>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    if !slope_colors.is_changed() && !elevation.is_changed() && !mode.is_changed() {
+        return;
+    }
+    let colors = mode.colors(&slope_colors, &elevation);
+    for (handle, layer) in handles.iter() {
+        // The gradient spans the whole mesh a tile belongs to
+        let bounds = match layer {
+            Some(&layers::MeshLayerId(layers::PRIMARY_LAYER)) => mesh_data.as_deref(),
+            Some(id) => layers.mesh_data(id.0),
+            None => None,
+        }
+        .map(|mesh_data| mesh_data.bounds);
+        let colors = match bounds {
+            Some(bounds) => colors.with_heights(bounds),
+            None => Cow::Borrowed(&*colors),
+        };
+        if let Some(mesh) = meshes.get_mut(handle) {
+            recolor_mesh(mesh, &colors);
+        }
+    }
+}
+
+// Near and far planes of the main camera, edited in the UI and saved
+#[derive(Resource, Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct ClipPlanes {
+    near: f32,
+    far: f32,
+}
+
+impl Default for ClipPlanes {
+    fn default() -> Self {
+        Self {
+            near: 0.01,
+            far: 100000.0,
+        }
+    }
+}
+
+impl ClipPlanes {
+    // Both planes in their ranges, with the far one past the near one
+    fn validated(self) -> Self {
+        let near = self.near.clamp(MIN_NEAR_CLIP, MAX_NEAR_CLIP);
+        Self {
+            near,
+            far: self.far.clamp(near * 2.0, MAX_FAR_CLIP),
+        }
+    }
+}
+
+// Where the directional light shines from, high up and to the side so
+// slopes facing it are lit brighter than those facing away
+fn sun_transform() -> Transform {
+    Transform::from_xyz(4.0, 8.0, 4.0).looking_at(Vec3::ZERO, Vec3::Y)
+}
+
+fn main_camera_projection() -> PerspectiveProjection {
+    let clip_planes = ClipPlanes::default();
+    PerspectiveProjection {
+        far: clip_planes.far,
+        near: clip_planes.near,
+        fov: 60.0_f32.to_radians(),
+        ..default()
+    }
+}
+
+fn apply_clip_planes(
+    clip_planes: Res<ClipPlanes>,
+    mut cameras: Query<&mut Projection, With<MainCamera>>,
+) {
+    for mut projection in cameras.iter_mut() {
+        match &mut *projection {
+            Projection::Perspective(perspective) => {
+                if perspective.near != clip_planes.near || perspective.far != clip_planes.far {
+                    perspective.near = clip_planes.near;
+                    perspective.far = clip_planes.far;
+                }
+            }
+            // The near plane stays at the camera, nothing is too close to see
+            Projection::Orthographic(ortho) => {
+                if ortho.far != clip_planes.far {
+                    ortho.far = clip_planes.far;
+                }
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    settings: Res<ViewerSettings>,
+    slope_colors: Res<SlopeColors>,
+    render_mode: Res<RenderMode>,
+    elevation_colors: Res<ElevationColors>,
+    tile_settings: Res<TileSettings>,
+    config: Res<plugin::NavViewerConfig>,
+    speed: Res<CameraSpeed>,
+) {
+    // Camera with adjusted settings
+    commands.spawn((
+        Camera3dBundle {
+            transform: Transform::from_xyz(0.0, 2.0, 5.0).looking_at(Vec3::ZERO, Vec3::Y),
+            projection: Projection::Perspective(main_camera_projection()),
+            ..default()
+        },
+        MainCamera {
+            move_speed: speed.base,
+            ..default()
+        },
+        CameraMouseState::default(),
+    ));
+
+    // Light
+    commands.spawn(DirectionalLightBundle {
+        directional_light: DirectionalLight {
+            shadows_enabled: true,
+            ..default()
+        },
+        transform: sun_transform(),
+        ..default()
+    });
+
+    // Create default mesh
+    let mut default_mesh = Mesh::new(PrimitiveTopology::TriangleList);
+
+    let vertices = vec![
+        Vec3::new(1.0, 0.0, 1.0),     // Bottom-left corner (0)
+        Vec3::new(987.0, 0.0, 1.0),   // Bottom-right corner (1)
+        Vec3::new(987.0, 0.0, 987.0), // Top-right corner (2)
+        Vec3::new(1.0, 0.0, 987.0),   // Top-left corner (3)
+    ];
+
+    let indices = vec![
+        0, 1, 2, // First triangle: bottom-left -> bottom-right -> top-right
+        0, 2, 3, // Second triangle: bottom-left -> top-right -> top-left
+    ];
+    // Both triangles lie in the same plane, so every corner shares its normal
+    let normal = face_normal(&vertices, &indices, 0);
+    let normals = vec![normal; vertices.len()];
+    let colors = calculate_colors(
+        &vertices,
+        &indices,
+        &normals,
+        &render_mode
+            .colors(&slope_colors, &elevation_colors)
+            .with_heights(point_bounds(&vertices)),
+    );
+
+    insert_vertex_attributes(
+        &mut default_mesh,
+        vertices.clone(),
+        normals.clone(),
+        colors,
+        Vec::new(),
+    );
+    default_mesh.set_indices(Some(Indices::U32(indices.clone())));
+
+    // Spawn mesh entity
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(default_mesh),
+            material: materials.add(StandardMaterial {
+                base_color: Color::WHITE,
+                unlit: true,
+                ..default()
+            }),
+            ..default()
+        },
+        DebugMesh,
+    ));
+
+    // Spawn mesh viewer
+    // A bad path shows up as a load error in the UI
+    commands.spawn(MeshViewer {
+        obj_path: config.obj_path.clone(),
+        walkable_slope_angle: slope_colors.bands[0].max_angle,
+        needs_update: config.obj_path.is_some(),
+        needs_rebuild: false,
+        load_error: None,
+        loading: None,
+        load_progress: 0.0,
+        export_message: None,
+        normal_weighting: NormalWeighting::default(),
+        mitm_info: Arc::new(Mutex::new(MitmInfo::new(
+            config
+                .mitm_address
+                .as_deref()
+                .unwrap_or(&settings.mitm_address),
+            config.max_frame_len(&settings),
+        ))),
+    });
+
+    // Insert initial mesh data
+    commands.insert_resource(world::TriangleGrid::build(&vertices, &indices));
+    commands.insert_resource(MeshData {
+        bounds: point_bounds(&vertices),
+        vertices,
+        indices,
+        normals,
+        uvs: Vec::new(),
+        tile_size: tile_settings.tile_size,
+        material_groups: Vec::new(),
+        file_vertices: Vec::new(),
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn camera_control(
+    time: Res<Time>,
+    keyboard: Res<Input<KeyCode>>,
+    mut mouse_motion: EventReader<MouseMotion>,
+    mut mouse_wheel: EventReader<MouseWheel>,
+    mouse_button: Res<Input<MouseButton>>,
+    key_mods: Res<Input<KeyCode>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mesh_data: Option<Res<MeshData>>, // Add MeshData as an optional resource
+    picking_grid: Option<Res<world::TriangleGrid>>,
+    layers: Res<layers::MeshLayers>,
+    section: Res<section::SectionPlane>,
+    pick_plane: Res<world::PickPlane>,
+    mesh_viewer: Query<&MeshViewer>,
+    speed: Res<CameraSpeed>,
+    mut query: Query<(
+        &mut Transform,
+        &mut MainCamera,
+        &mut CameraMouseState,
+        &Camera,
+        &mut Projection,
+    )>,
+) {
+    let (mut transform, mut camera, mut mouse_state, camera_comp, mut projection) =
+        query.single_mut();
+    let window = windows.single();
+
+    // Handle rotation - using CTRL + Left Click
+    let ctrl_pressed =
+        key_mods.pressed(KeyCode::ControlLeft) || key_mods.pressed(KeyCode::ControlRight);
+
+    // Track mouse button press/release
+    if ctrl_pressed && mouse_button.just_pressed(MouseButton::Left) {
+        if let Some(position) = window.cursor_position() {
+            mouse_state.initial_position = Some(position);
+            mouse_state.last_position = Some(position);
+        }
+    }
+
+    if mouse_button.just_released(MouseButton::Left) {
+        mouse_state.initial_position = None;
+        mouse_state.last_position = None;
+    }
+
+    // Handle mouse movement when dragging
+    if ctrl_pressed && mouse_button.pressed(MouseButton::Left) {
+        for _ in mouse_motion.read() {
+            if let Some(last_pos) = mouse_state.last_position {
+                // Update position
+                if let Some(current_pos) = window.cursor_position() {
+                    let delta = current_pos - last_pos;
+
+                    // Apply camera rotation
+                    camera.yaw += delta.x * 0.00125;
+                    let new_pitch = camera.pitch - delta.y * 0.00125;
+                    camera.pitch = new_pitch.clamp(-89.0_f32.to_radians(), 89.0_f32.to_radians());
+
+                    // Update last position
+                    mouse_state.last_position = Some(current_pos);
+                }
+            }
+        }
+    }
+
+    // Middle click picks the orbit focus on the mesh, or on the fallback plane
+    // off it, and turns the camera towards it
+    if mouse_button.just_pressed(MouseButton::Middle) {
+        if let Some(focus) = window.cursor_position().and_then(|cursor| {
+            let (origin, direction) = world::screen_to_ray(window, camera_comp, &transform, cursor);
+            let primary = mesh_data
+                .as_deref()
+                .map(|mesh_data| (mesh_data, picking_grid.as_deref()));
+            section
+                .clip_ray(origin, direction)
+                .and_then(|origin| layers.raycast(primary, origin, direction))
+                .map(|(_, point, ..)| point)
+                .or_else(|| world::ray_plane_intersection(origin, direction, pick_plane.height))
+        }) {
+            let offset = focus - transform.translation;
+            let direction = offset.normalize();
+
+            camera.focus = focus;
+            camera.orbit_radius = offset.length().clamp(MIN_ORBIT_RADIUS, MAX_ORBIT_RADIUS);
+            camera.yaw = direction.z.atan2(direction.x);
+            camera.pitch = direction
+                .y
+                .asin()
+                .clamp(-89.0_f32.to_radians(), 89.0_f32.to_radians());
+        }
+    }
+
+    // Scroll changes the move speed in fly mode and the orbit radius in orbit
+    // mode, with Alt held it changes the field of view instead
+    let mut scroll = 0.0;
+    for ev in mouse_wheel.read() {
+        scroll += match ev.unit {
+            MouseScrollUnit::Line => ev.y,
+            MouseScrollUnit::Pixel => ev.y / 100.0,
+        };
+    }
+
+    // In orthographic the camera distance changes nothing, so scroll zooms
+    if let Projection::Orthographic(ortho) = &mut *projection {
+        if scroll != 0.0 {
+            ortho.scale =
+                (ortho.scale * 0.9_f32.powf(scroll)).clamp(MIN_ORTHO_SCALE, MAX_ORTHO_SCALE);
+            scroll = 0.0;
+        }
+    }
+
+    let alt_pressed = key_mods.pressed(KeyCode::AltLeft) || key_mods.pressed(KeyCode::AltRight);
+    if alt_pressed && scroll != 0.0 {
+        // Only touch the projection when it actually changes
+        if let Projection::Perspective(perspective) = &mut *projection {
+            let fov = perspective.fov.to_degrees() - scroll * 2.0;
+            perspective.fov = fov.clamp(MIN_FOV_DEGREES, MAX_FOV_DEGREES).to_radians();
+        }
+        scroll = 0.0;
+    }
+
+    // Level the view: look at the horizon along the nearest axis. There is no
+    // roll to reset, the rotation is rebuilt upright from yaw and pitch below
+    if keyboard.just_pressed(LEVEL_VIEW_KEY) {
+        camera.pitch = 0.0;
+        camera.yaw = snap_yaw(camera.yaw);
+    }
+
+    // Calculate movement vectors
+    let mut forward = camera_forward(camera.yaw, camera.pitch);
+
+    let right = forward.cross(Vec3::Y).normalize();
+    let up = Vec3::Y;
+
+    // Handle movement
+    let mut movement = Vec3::ZERO;
+    if camera.mode == CameraMode::Fly {
+        camera.move_speed =
+            (camera.move_speed * 1.1_f32.powf(scroll)).clamp(MIN_MOVE_SPEED, MAX_MOVE_SPEED);
+    }
+    // Per frame, so the distance over time doesn't depend on the frame rate.
+    // Ctrl while dragging to look around slows movement down too
+    let shift_pressed = keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let move_speed =
+        camera.move_speed * speed.multiplier(shift_pressed, ctrl_pressed) * time.delta_seconds();
+
+    if keyboard.pressed(KeyCode::W) {
+        movement += forward;
+    }
+    if keyboard.pressed(KeyCode::S) {
+        movement -= forward;
+    }
+    if keyboard.pressed(KeyCode::A) {
+        movement -= right;
+    }
+    if keyboard.pressed(KeyCode::D) {
+        movement += right;
+    }
+    if keyboard.pressed(KeyCode::E) {
+        movement += up;
+    }
+    if keyboard.pressed(KeyCode::Q) {
+        movement -= up;
+    }
+
+    if keyboard.just_pressed(KeyCode::F) {
+        if let Some(mesh_data) = &mesh_data {
+            // Bounds of the mesh where it is drawn
+            let bounds =
+                transformed_bounds(mesh_data.bounds, &layers.transform(layers::PRIMARY_LAYER));
+            let aspect = window.width() / window.height().max(1.0);
+            transform.translation = fit_view(&mut camera, &mut projection, bounds, aspect);
+            forward = camera_forward(camera.yaw, camera.pitch);
+        }
+    }
+
+    match camera.mode {
+        CameraMode::Fly => transform.translation += movement * move_speed,
+        CameraMode::Orbit | CameraMode::Follow => {
+            // Movement keys pan the focus, the camera keeps its distance
+            camera.focus += movement * move_speed;
+            if camera.mode == CameraMode::Follow {
+                let target = mesh_viewer
+                    .get_single()
+                    .ok()
+                    .and_then(|viewer| viewer.mitm_info.lock().unwrap().followed_position());
+                if let Some(target) = target {
+                    let t = 1.0 - (-FOLLOW_SMOOTHING * time.delta_seconds()).exp();
+                    camera.focus = camera.focus.lerp(target, t);
+                }
+            }
+            camera.orbit_radius = (camera.orbit_radius * 0.9_f32.powf(scroll))
+                .clamp(MIN_ORBIT_RADIUS, MAX_ORBIT_RADIUS);
+            transform.translation = camera.focus - forward * camera.orbit_radius;
+        }
+    }
+    transform.look_to(forward, Vec3::Y);
+}
+
+// Double click on the mesh orbits the hit point, from a distance that goes
+// with the size of the mesh. Left alone while measuring, where clicks place
+// points
+#[allow(clippy::too_many_arguments)]
+fn double_click_focus(
+    time: Res<Time>,
+    mouse_button: Res<Input<MouseButton>>,
+    keyboard: Res<Input<KeyCode>>,
+    mut contexts: EguiContexts,
+    measure: Res<measure::MeasureTool>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mesh_data: Option<Res<MeshData>>,
+    picking_grid: Option<Res<world::TriangleGrid>>,
+    layers: Res<layers::MeshLayers>,
+    section: Res<section::SectionPlane>,
+    selection: Res<selections::TileSelection>,
+    mut camera_query: Query<(&mut Transform, &mut MainCamera, &Camera)>,
+    mut clicks: Local<ClickTracker>,
+) {
+    let ctrl_pressed = keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    // Clicks pick tiles while selecting
+    if measure.enabled
+        || selection.picking
+        || ctrl_pressed
+        || !mouse_button.just_pressed(MouseButton::Left)
+        || contexts.ctx_mut().wants_pointer_input()
+    {
+        return;
+    }
+    let (Ok(window), Ok((mut transform, mut camera, camera_comp))) =
+        (windows.get_single(), camera_query.get_single_mut())
+    else {
+        return;
+    };
+    let Some(cursor_pos) = window.cursor_position() else {
+        return;
+    };
+    if !clicks.click(time.elapsed_seconds_f64(), cursor_pos) {
+        return;
+    }
+
+    let (origin, direction) = world::screen_to_ray(window, camera_comp, &transform, cursor_pos);
+    let primary = mesh_data
+        .as_deref()
+        .map(|mesh_data| (mesh_data, picking_grid.as_deref()));
+    let Some((layer, point, ..)) = section
+        .clip_ray(origin, direction)
+        .and_then(|origin| layers.raycast(primary, origin, direction))
+    else {
+        return;
+    };
+    let bounds = match layer {
+        layers::PRIMARY_LAYER => mesh_data.as_deref().map(|mesh_data| mesh_data.bounds),
+        _ => layers.mesh_data(layer).map(|mesh_data| mesh_data.bounds),
+    };
+    let Some(bounds) = bounds else {
+        return;
+    };
+    let (min, max) = transformed_bounds(bounds, &layers.transform(layer));
+    transform.translation = orbit_point(
+        &mut camera,
+        point,
+        (max - min).length() * DOUBLE_CLICK_DISTANCE,
+    );
+}
+
+// Runs in `Last`, after the window close in `PostUpdate` has turned into an
+// `AppExit`, so the server hears from us before the app is gone
+fn close_mitm_on_exit(mut exits: EventReader<AppExit>, mesh_viewer: Query<&MeshViewer>) {
+    if exits.read().next().is_none() {
+        return;
+    }
+    for viewer in mesh_viewer.iter() {
+        viewer.mitm_info.lock().unwrap().shutdown();
+    }
+}
+
+// Finish connection attempts and retry dropped streams once the backoff
+// delay has passed
+fn maintain_mitm_connection(mesh_viewer: Query<&MeshViewer>) {
+    let viewer = mesh_viewer.single();
+    let mut mitm = viewer.mitm_info.lock().unwrap();
+
+    if let Some(result) = mitm.pending.as_ref().and_then(|pending| pending.poll()) {
+        mitm.pending = None;
+        match result {
+            Ok((socket, version)) => {
+                mitm.socket = Some(socket);
+                mitm.protocol_version = Some(version);
+                mitm.error = None;
+                mitm.reader.clear();
+                mitm.held.clear();
+                mitm.state = net::ConnectionState::Connected;
+                mitm.reconnect = true;
+                mitm.backoff.reset();
+            }
+            Err(e) => mitm.disconnect(e.to_string()),
+        }
+    }
+
+    if mitm
+        .retry_at
+        .is_some_and(|retry_at| Instant::now() >= retry_at)
+    {
+        mitm.start_connect();
+    }
+}
+
+// Send the messages due in the replay and those that arrived on the watch
+// socket as `ActorMessage` events
+fn receive_actor_messages(
+    mesh_viewer: Query<&MeshViewer>,
+    mut inbox: ResMut<actors::ActorInbox>,
+    mut replay: ResMut<replay::ReplayController>,
+    actor_map: Res<actors::ActorMap>,
+    time: Res<Time>,
+    mut events: EventWriter<net::ActorMessage>,
+) {
+    let viewer = mesh_viewer.single();
+    let mut mitm = viewer.mitm_info.lock().unwrap();
+
+    let mut messages = Vec::new();
+    // The replay starting over, or seeking back, begins from an empty scene
+    if replay.advance(time.delta_seconds(), &mut messages) {
+        inbox.0.clear();
+        inbox.0.extend(
+            actor_map
+                .0
+                .keys()
+                .map(|id| net::ActorMessage::Despawn(net::ActorDespawn::new(id.clone()))),
+        );
+        mitm.positions.clear();
+        mitm.latest = None;
+        mitm.held.clear();
+    }
+    let result = net::try_read_batch(&mut mitm, net::MAX_MESSAGES_PER_FRAME, &mut messages);
+    mitm.hold_messages(&mut messages);
+    events.send_batch(messages);
+    if let Err(e) = result {
+        warn!("Dropped the MITM connection to {}: {}", mitm.address, e);
+        mitm.disconnect(e.to_string());
+    }
+}
+
+// Every actor message goes through here on its way to `apply_actor_messages`:
+// the ones from the socket and the replay, and any other code sends with an
+// `EventWriter<ActorMessage>` or `World::send_event`, in game coordinates
+fn queue_actor_messages(
+    mut events: EventReader<net::ActorMessage>,
+    mesh_viewer: Query<&MeshViewer>,
+    mut inbox: ResMut<actors::ActorInbox>,
+    mut log: ResMut<message_log::MessageLog>,
+    coords: Res<coords::CoordinateTransform>,
+) {
+    if events.is_empty() {
+        return;
+    }
+    let viewer = mesh_viewer.single();
+    let mut mitm = viewer.mitm_info.lock().unwrap();
+
+    let received = std::time::SystemTime::now();
+    for message in events.read() {
+        let message = message.clone().map_positions(|v| coords.vector3(v));
+        if let net::ActorMessage::Position(msg) = &message {
+            mitm.update_position(&msg.id, Vec3::new(msg.pos.x, msg.pos.y, msg.pos.z));
+        }
+        log.push(message.clone(), received);
+        inbox.0.push(message);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_mesh(
+    mut commands: Commands,
+    mut mesh_viewer: Query<&mut MeshViewer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+    mesh_query: Query<(Entity, &layers::MeshLayerId), LoadedMeshFilter>,
+    mut mesh_data: Option<ResMut<MeshData>>,
+    mut layers: ResMut<layers::MeshLayers>,
+    tile_settings: Res<TileSettings>,
+    slope_colors: Res<SlopeColors>,
+    render_mode: Res<RenderMode>,
+    elevation_colors: Res<ElevationColors>,
+    render_settings: Res<RenderSettings>,
+    mut placement: MeshPlacement,
+    mut groups: ResMut<ObjGroups>,
+    mut tiler: ResMut<tiling::IncrementalTiler>,
+) {
+    let mut viewer = mesh_viewer.single_mut();
+    let slope_colors = render_mode.colors(&slope_colors, &elevation_colors);
+
+    // Start loading in the background, replacing the previous task cancels it
+    if viewer.needs_update {
+        viewer.needs_update = false;
+        viewer.load_progress = 0.0;
+        viewer.loading = viewer.obj_path.as_ref().map(obj_loader::load_obj_streaming);
+        tiler.cancel(layers::PRIMARY_LAYER);
+    }
+
+    // Only the primary layer's entities are replaced here, the other layers
+    // are `update_layers`' business
+    let primary_entities = mesh_query
+        .iter()
+        .filter(|(_, layer)| layer.0 == layers::PRIMARY_LAYER)
+        .map(|(entity, _)| entity)
+        .collect::<Vec<_>>();
+
+    // Re-tile the cached mesh, a load in progress will do it anyway
+    let rebuild = std::mem::take(&mut viewer.needs_rebuild);
+    if rebuild {
+        layers.needs_rebuild = true;
+    }
+    if rebuild && viewer.loading.is_none() {
+        if let Some(mesh_data) = mesh_data.as_deref_mut() {
+            for &entity in &primary_entities {
+                commands.entity(entity).despawn();
+            }
+            mesh_data.tile_size = tile_settings.tile_size.max(MIN_TILE_SIZE);
+            spawn_mesh_entities(
+                &mut commands,
+                &mut meshes,
+                &mut tiler,
+                mesh_data,
+                &tile_settings,
+                &render_settings,
+                &slope_colors,
+                layers::PRIMARY_LAYER,
+                placement
+                    .section
+                    .local_plane(&layers.transform(layers::PRIMARY_LAYER)),
+            );
+        }
+    }
+
+    // Groups shown or hidden only need the kept file rebuilt
+    let regroup = viewer.loading.is_none() && std::mem::take(&mut groups.changed);
+    if !regroup {
+        let Some(loading) = &viewer.loading else {
+            return;
+        };
+
+        let mut result = None;
+        let mut progress = viewer.load_progress;
+        for message in loading.poll() {
+            match message {
+                obj_loader::LoadProgress::Progress(p) => progress = p,
+                obj_loader::LoadProgress::Done(done) => result = Some(done),
+            }
+        }
+        viewer.load_progress = progress;
+
+        // Keep showing the previous mesh until the new one is ready
+        let Some(result) = result else {
+            return;
+        };
+        viewer.loading = None;
+
+        match *result {
+            Ok(obj_data) => {
+                viewer.load_error = None;
+                groups.set_obj_data(obj_data);
+            }
+            Err(e) => {
+                viewer.load_error = Some(e.to_string());
+                groups.obj_data = None;
+            }
+        }
+    }
+
+    // A file over the memory budget waits for the user to say how to build
+    // it, the previous mesh stays until then
+    let decision = match &groups.obj_data {
+        Some(obj_data) => {
+            let Some(decision) = placement
+                .budget
+                .decide(viewer.obj_path.as_deref(), obj_data)
+            else {
+                return;
+            };
+            decision
+        }
+        None => budget::BudgetDecision::Full,
+    };
+
+    // Clean up existing tiles
+    for entity in primary_entities {
+        commands.entity(entity).despawn();
+    }
+
+    if let Some(obj_data) = &groups.obj_data {
+        let shown;
+        let obj_data = if groups.visible.iter().all(|&visible| visible) {
+            obj_data
+        } else {
+            shown = obj_data.with_groups(&groups.visible);
+            &shown
+        };
+        let (mut vertices, mut indices, mut normals, mut uvs) =
+            convert_obj_to_mesh_data(obj_data, viewer.normal_weighting, *placement.import);
+        placement
+            .coords
+            .apply_to_mesh(&mut vertices, &mut indices, &mut normals);
+        let mut triangle_materials = obj_data.triangulate_materials();
+        let mut file_vertices = obj_data.triangulate_vertex_numbers();
+
+        // Decimated before tiling, which is where most of the memory goes,
+        // and nothing of the full mesh is kept, unlike with `Decimation`
+        if decision == budget::BudgetDecision::Decimate {
+            let decimated =
+                budget::decimate(&vertices, &indices, placement.budget.triangle_budget());
+            normals = decimated.vertex_values(&normals);
+            uvs = decimated.vertex_values(&uvs);
+            triangle_materials = decimated.triangle_values(&triangle_materials);
+            file_vertices = decimated.triangle_values(&file_vertices);
+            vertices = decimated.positions;
+            indices = decimated.indices;
+        }
+
+        // Group triangles by their material, each group gets its own
+        // StandardMaterial and is tiled on its own
+        let mut material_groups: std::collections::BTreeMap<Option<usize>, Vec<u32>> =
+            std::collections::BTreeMap::new();
+        for (triangle, material) in indices.chunks(3).zip(triangle_materials.iter()) {
+            material_groups
+                .entry(*material)
+                .or_default()
+                .extend_from_slice(triangle);
+        }
+        let material_groups = material_groups
+            .into_iter()
+            .map(|(material, group_indices)| {
+                let obj_material =
+                    material.and_then(|m| obj_data.materials.get(&obj_data.material_names[m]));
+                let material = match obj_material {
+                    Some(obj_material) => {
+                        materials.add(obj_material_to_standard(obj_material, &mut images))
+                    }
+                    None => materials.add(StandardMaterial {
+                        base_color: Color::WHITE,
+                        unlit: true,
+                        ..default()
+                    }),
+                };
+                (material, group_indices)
+            })
+            .collect();
+
+        let mesh_data = placement.decimation.apply(MeshData {
+            bounds: point_bounds(&vertices),
+            vertices,
+            indices,
+            normals,
+            uvs,
+            tile_size: tile_settings.tile_size.max(MIN_TILE_SIZE),
+            material_groups,
+            file_vertices,
+        });
+        spawn_mesh_entities(
+            &mut commands,
+            &mut meshes,
+            &mut tiler,
+            &mesh_data,
+            &tile_settings,
+            &render_settings,
+            &slope_colors,
+            layers::PRIMARY_LAYER,
+            placement
+                .section
+                .local_plane(&layers.transform(layers::PRIMARY_LAYER)),
+        );
+
+        commands.insert_resource(world::TriangleGrid::build(
+            &mesh_data.vertices,
+            &mesh_data.indices,
+        ));
+        commands.insert_resource(mesh_data);
+    } else {
+        // Nothing left to show, so nothing left to pick or walk on either
+        commands.remove_resource::<MeshData>();
+        commands.remove_resource::<world::TriangleGrid>();
+    }
+}
+
+// Set the per vertex attributes, which must all have one entry per vertex.
+// `uvs` may be empty when the mesh has no texture coordinates
+fn insert_vertex_attributes(
+    mesh: &mut Mesh,
+    vertices: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    colors: Vec<[f32; 4]>,
+    uvs: Vec<Vec2>,
+) {
+    debug_assert_eq!(normals.len(), vertices.len(), "one normal per vertex");
+    debug_assert_eq!(colors.len(), vertices.len(), "one color per vertex");
+    debug_assert!(
+        uvs.is_empty() || uvs.len() == vertices.len(),
+        "one texture coordinate per vertex"
+    );
+
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    if !uvs.is_empty() {
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    }
+}
+
+// Slope colored mesh of a piece of geometry
+fn build_colored_mesh(geometry: TileGeometry, slope_colors: &SlopeColors) -> Mesh {
+    let colors = calculate_colors(
+        &geometry.vertices,
+        &geometry.indices,
+        &geometry.normals,
+        slope_colors,
+    );
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    insert_vertex_attributes(
+        &mut mesh,
+        geometry.vertices,
+        geometry.normals,
+        colors,
+        geometry.uvs,
+    );
+    mesh.set_indices(Some(Indices::U32(geometry.indices)));
+    mesh
+}
+
+// Copy the vertices used by `indices` into one piece of geometry
+fn merge_triangles(
+    vertices: &[Vec3],
+    indices: &[u32],
+    normals: &[Vec3],
+    uvs: &[Vec2],
+) -> TileGeometry {
+    let mut geometry = TileGeometry::new(0, 0);
+    let mut remap = std::collections::HashMap::new();
+    for corners in indices.chunks_exact(3) {
+        // Shared only between triangles colored alike, like in `build_tile`
+        let shading_normal = quantize_vec3(normals[corners[0] as usize]);
+        for &index in corners {
+            let new_index = *remap.entry((index, shading_normal)).or_insert_with(|| {
+                let i = index as usize;
+                geometry.vertices.push(vertices[i]);
+                geometry.normals.push(normals[i]);
+                if !uvs.is_empty() {
+                    geometry.uvs.push(uvs[i]);
+                }
+                geometry.vertices.len() as u32 - 1
+            });
+            geometry.indices.push(new_index);
+        }
+    }
+    geometry
+}
+
+// Every triangle with corners of its own and the face normal on them, so it is
+// shaded and colored by its real slope instead of the smoothed one
+fn flat_shaded(
+    vertices: &[Vec3],
+    indices: &[u32],
+    uvs: &[Vec2],
+) -> (Vec<Vec3>, Vec<u32>, Vec<Vec3>, Vec<Vec2>) {
+    let mut flat_vertices = Vec::with_capacity(indices.len());
+    let mut flat_normals = Vec::with_capacity(indices.len());
+    let mut flat_uvs = Vec::new();
+    for triangle in 0..indices.len() / 3 {
+        let normal = face_normal(vertices, indices, triangle);
+        for &index in &indices[triangle * 3..triangle * 3 + 3] {
+            flat_vertices.push(vertices[index as usize]);
+            flat_normals.push(normal);
+            if !uvs.is_empty() {
+                flat_uvs.push(uvs[index as usize]);
+            }
+        }
+    }
+    let flat_indices = (0..flat_vertices.len() as u32).collect();
+    (flat_vertices, flat_indices, flat_normals, flat_uvs)
+}
+
+// Spawn the entities showing a loaded mesh, per tile or merged per material.
+// Tiling still going on for the layer is dropped, the new entities replace it
+#[allow(clippy::too_many_arguments)]
+fn spawn_mesh_entities(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    tiler: &mut tiling::IncrementalTiler,
+    mesh_data: &MeshData,
+    tile_settings: &TileSettings,
+    render_settings: &RenderSettings,
+    slope_colors: &SlopeColors,
+    layer: u32,
+    section: Option<(Vec3, f32)>, // Plane in the mesh's space, see `section::SectionPlane`
+) {
+    tiler.cancel(layer);
+    let slope_colors = &*slope_colors.with_heights(mesh_data.bounds);
+    for (material, group_indices) in &mesh_data.material_groups {
+        let clipped;
+        let group_indices = match section {
+            Some(plane) => {
+                clipped = section::clip_triangles(&mesh_data.vertices, group_indices, plane);
+                &clipped
+            }
+            None => group_indices,
+        };
+        let flat;
+        let (vertices, group_indices, normals, uvs) = if render_settings.flat_shading {
+            flat = flat_shaded(&mesh_data.vertices, group_indices, &mesh_data.uvs);
+            (&flat.0, &flat.1, &flat.2, &flat.3)
+        } else {
+            (
+                &mesh_data.vertices,
+                group_indices,
+                &mesh_data.normals,
+                &mesh_data.uvs,
+            )
+        };
+        if tile_settings.single_mesh {
+            let geometry = merge_triangles(vertices, group_indices, normals, uvs);
+            commands.spawn((
+                PbrBundle {
+                    mesh: meshes.add(build_colored_mesh(geometry, slope_colors)),
+                    material: material.clone(),
+                    ..default()
+                },
+                MergedMesh,
+                layers::MeshLayerId(layer),
+            ));
+            continue;
+        }
+
+        // Big groups are split over several frames by `advance_tiling`
+        if group_indices.len() / 3 > tiling::TRIANGLES_PER_FRAME {
+            tiler.push(tiling::TilingGroup::new(
+                layer,
+                material.clone(),
+                slope_colors.clone(),
+                (
+                    vertices.clone(),
+                    group_indices.clone(),
+                    normals.clone(),
+                    uvs.clone(),
+                ),
+                mesh_data.tile_size,
+            ));
+            continue;
+        }
+
+        // Split into tiles
+        let tiles =
+            split_mesh_into_tiles(vertices, group_indices, normals, uvs, mesh_data.tile_size);
+        for tile in tiles {
+            spawn_tile(commands, meshes, tile, material, slope_colors, layer);
+        }
+    }
+}
+
+fn spawn_tile(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    tile: TileGeometry,
+    material: &Handle<StandardMaterial>,
+    slope_colors: &SlopeColors,
+    layer: u32,
+) {
+    let (aabb_min, aabb_max) = point_bounds(&tile.vertices);
+    let tile_mesh = TileMesh {
+        tile_x: tile.tile_x,
+        tile_y: tile.tile_z,
+        aabb: Aabb::from_min_max(aabb_min, aabb_max),
+    };
+
+    // Spawn a new entity for this tile
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(build_colored_mesh(tile, slope_colors)),
+            material: material.clone(),
+            transform: Transform::from_xyz(0.0, 0.0, 0.0),
+            ..default()
+        },
+        tile_mesh,
+        layers::MeshLayerId(layer),
+    ));
+}
+
+// Save the next rendered frame to a timestamped PNG in the working directory.
+// Runs before the systems drawing UI so a screenshot without it can skip them
+fn take_screenshot(
+    keys: Res<Input<KeyCode>>,
+    mut state: ResMut<ScreenshotState>,
+    mut screenshots: ResMut<ScreenshotManager>,
+    window: Query<Entity, With<PrimaryWindow>>,
+) {
+    state.hide_ui = false;
+    let saved = state.saved.lock().unwrap().take();
+    if saved.is_some() {
+        state.message = saved;
+    }
+    if keys.just_pressed(KeyCode::F12) {
+        state.requested = true;
+    }
+    if !std::mem::take(&mut state.requested) {
+        return;
+    }
+    let Ok(window) = window.get_single() else {
+        return;
+    };
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let path = std::env::current_dir()
+        .unwrap_or_default()
+        .join(format!("screenshot_{}.png", timestamp));
+    // The frame is only read back after it is rendered, the message waits
+    // for the file
+    let saved = state.saved.clone();
+    let message = match screenshots.take_screenshot(window, move |image| {
+        *saved.lock().unwrap() = Some(save_screenshot(image, &path));
+    }) {
+        Ok(()) => {
+            state.hide_ui = !state.include_ui;
+            Ok("Taking screenshot...".to_string())
+        }
+        Err(_) => Err("A screenshot is already being taken".to_string()),
+    };
+    state.message = Some(message);
+}
+
+fn save_screenshot(image: Image, path: &Path) -> Result<String, String> {
+    image
+        .try_into_dynamic()
+        .map_err(|e| e.to_string())
+        .and_then(|image| image.to_rgb8().save(path).map_err(|e| e.to_string()))
+        .map(|()| format!("Saved screenshot to {}", path.display()))
+        .map_err(|e| format!("Failed to save {}: {}", path.display(), e))
+}
+
+// Entities showing a loaded OBJ
+type LoadedMeshFilter = Or<(With<TileMesh>, With<MergedMesh>)>;
+
+// The default mesh or the entities of a loaded one
+type ViewerMeshFilter = Or<(With<TileMesh>, With<MergedMesh>, With<DebugMesh>)>;
+
+// Add or remove wireframes to match the settings, also for freshly spawned
+// meshes, the material underneath is left alone
+fn apply_wireframe(
+    mut commands: Commands,
+    mode: Res<RenderMode>,
+    meshes: Query<(Entity, Has<Wireframe>), ViewerMeshFilter>,
+) {
+    let wireframe = mode.wireframe();
+    for (entity, has_wireframe) in meshes.iter() {
+        if wireframe && !has_wireframe {
+            commands.entity(entity).insert((
+                Wireframe,
+                WireframeColor {
+                    color: WIREFRAME_COLOR,
+                },
+            ));
+        } else if !wireframe && has_wireframe {
+            commands
+                .entity(entity)
+                .remove::<(Wireframe, WireframeColor)>();
+        }
+    }
+}
+
+// Turn backface culling off or on for the materials of the viewer meshes,
+// also for meshes spawned or given another material since
+fn apply_culling(
+    settings: Res<RenderSettings>,
+    meshes: Query<Ref<Handle<StandardMaterial>>, ViewerMeshFilter>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let cull_mode = (!settings.double_sided).then_some(Face::Back);
+    for handle in meshes.iter() {
+        if !settings.is_changed() && !handle.is_changed() {
+            continue;
+        }
+        // Only touch materials that differ, `get_mut` marks them modified
+        if materials
+            .get(&*handle)
+            .is_some_and(|material| material.cull_mode != cull_mode)
+        {
+            let material = materials.get_mut(&*handle).unwrap();
+            material.cull_mode = cull_mode;
+            material.double_sided = settings.double_sided;
+        }
+    }
+}
+
+// Switch the materials of the viewer meshes between unlit and lit by the
+// sun, also for meshes spawned or given another material since. The
+// highlight material stays unlit so highlighted tiles keep standing out
+fn apply_lighting(
+    settings: Res<RenderSettings>,
+    meshes: Query<Ref<Handle<StandardMaterial>>, (ViewerMeshFilter, Without<HighlightedTile>)>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let unlit = !settings.lit;
+    for handle in meshes.iter() {
+        if !settings.is_changed() && !handle.is_changed() {
+            continue;
+        }
+        // Only touch materials that differ, `get_mut` marks them modified
+        if materials
+            .get(&*handle)
+            .is_some_and(|material| material.unlit != unlit)
+        {
+            materials.get_mut(&*handle).unwrap().unlit = unlit;
+        }
+    }
+}
+
+// Tiles with their layer and material, swapped while highlighted
+type HighlightedTileQuery<'a> = (
+    Entity,
+    &'a TileMesh,
+    Option<&'a layers::MeshLayerId>,
+    &'a mut Handle<StandardMaterial>,
+    Option<&'a HighlightedTile>,
+);
+
+// Apply the tile highlight and the tile selection, also to tiles spawned
+// after they were picked
+#[allow(clippy::too_many_arguments)]
+fn highlight_tiles(
+    mut commands: Commands,
+    mut highlight: ResMut<TileHighlight>,
+    selection: Res<selections::TileSelection>,
+    mut tiles: Query<HighlightedTileQuery>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut highlight_material: Local<Option<Handle<StandardMaterial>>>,
+    mut camera_query: Query<(&mut Transform, &mut MainCamera)>,
+    tile_settings: Res<TileSettings>,
+    layers: Res<layers::MeshLayers>,
+) {
+    if highlight.requested {
+        highlight.requested = false;
+
+        // Tile coordinates are the primary mesh's, flown to where its layer
+        // is drawn
+        let coords = (highlight.tile_x, highlight.tile_y);
+        let transform = layers.transform(layers::PRIMARY_LAYER);
+        let bounds = tiles
+            .iter()
+            .filter(|(_, tile, layer, ..)| {
+                (tile.tile_x, tile.tile_y) == coords
+                    && layer.map_or(layers::PRIMARY_LAYER, |layer| layer.0) == layers::PRIMARY_LAYER
+            })
+            .map(|(_, tile, ..)| {
+                let bounds = (Vec3::from(tile.aabb.min()), Vec3::from(tile.aabb.max()));
+                transformed_bounds(bounds, &transform)
+            })
+            .reduce(|(min_a, max_a), (min_b, max_b)| (min_a.min(min_b), max_a.max(max_b)));
+
+        match bounds {
+            Some((min, max)) => {
+                highlight.selected = Some(coords);
+                highlight.message = None;
+
+                // Look down at the tile center from a tile and a half away
+                if highlight.fly_to {
+                    if let Ok((mut transform, mut camera)) = camera_query.get_single_mut() {
+                        let center = (min + max) * 0.5;
+                        camera.yaw = -90.0_f32.to_radians();
+                        camera.pitch = -45.0_f32.to_radians();
+                        camera.focus = center;
+                        camera.orbit_radius = tile_settings.tile_size * 1.5;
+
+                        let forward = camera_forward(camera.yaw, camera.pitch);
+                        transform.translation = center - forward * camera.orbit_radius;
+                        transform.look_to(forward, Vec3::Y);
+                    }
+                }
+            }
+            None => {
+                highlight.selected = None;
+                highlight.message = Some(format!("No tile at ({}, {})", coords.0, coords.1));
+            }
+        }
+    }
+
+    let highlight_material = highlight_material
+        .get_or_insert_with(|| {
+            materials.add(StandardMaterial {
+                base_color: Color::rgb(1.0, 0.0, 1.0),
+                unlit: true,
+                ..default()
+            })
+        })
+        .clone();
+
+    for (entity, tile, layer, mut material, highlighted) in tiles.iter_mut() {
+        let coords = (tile.tile_x, tile.tile_y);
+        let layer = layer.map_or(layers::PRIMARY_LAYER, |layer| layer.0);
+        let selected = highlight.selected == Some(coords)
+            || (layer == selection.layer && selection.tiles.contains(&coords));
+        match (selected, highlighted) {
+            (true, None) => {
+                commands.entity(entity).insert(HighlightedTile {
+                    original: material.clone(),
+                });
+                *material = highlight_material.clone();
+            }
+            (false, Some(highlighted)) => {
+                *material = highlighted.original.clone();
+                commands.entity(entity).remove::<HighlightedTile>();
+            }
+            _ => {}
+        }
+    }
+}
+
+// Range of tiles covering the mesh bounds, as (min_x, min_z, max_x, max_z)
+fn tile_range(bounds: (Vec3, Vec3), tile_size: f32) -> Option<(i32, i32, i32, i32)> {
+    let (min, max) = bounds;
+    if min.x > max.x || min.z > max.z {
+        return None;
+    }
+    Some((
+        (min.x / tile_size).floor() as i32,
+        (min.z / tile_size).floor() as i32,
+        (max.x / tile_size).floor() as i32,
+        (max.z / tile_size).floor() as i32,
+    ))
+}
+
+// Forwards lines to another `DebugDraw`, moved by a layer transform
+struct TransformedDraw<'a, D> {
+    inner: &'a mut D,
+    transform: Transform,
+}
+
+impl<D: debug_draw_b::DebugDraw> debug_draw_b::DebugDraw for TransformedDraw<'_, D> {
+    fn begin(&mut self, prim: i32, size: f32) {
+        self.inner.begin(prim, size);
+    }
+
+    fn end(&mut self) {
+        self.inner.end();
+    }
+
+    fn vertex(&mut self, pos: Vec3, color: Vec4) {
+        self.inner
+            .vertex(self.transform.transform_point(pos), color);
+    }
+
+    fn vertex_uv(&mut self, pos: Vec3, color: Vec4, uv: Vec2) {
+        self.inner
+            .vertex_uv(self.transform.transform_point(pos), color, uv);
+    }
+
+    fn texture(&mut self, state: bool) {
+        self.inner.texture(state);
+    }
+}
+
+// Tile boundary lines above the mesh, plus the tile coordinates as egui text.
+// The grid is laid out in the mesh's own space, where it was tiled, and drawn
+// with its layer transform
+fn draw_tile_grid(
+    mut gizmos: Gizmos,
+    mut contexts: EguiContexts,
+    tile_settings: Res<TileSettings>,
+    mesh_data: Option<Res<MeshData>>,
+    layers: Res<layers::MeshLayers>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    screenshot: Res<ScreenshotState>,
+) {
+    if !tile_settings.show_grid {
+        return;
+    }
+    let Some(mesh_data) = mesh_data else {
+        return;
+    };
+    let tile_size = tile_settings.tile_size.max(MIN_TILE_SIZE);
+    let Some((min_x, min_z, max_x, max_z)) = tile_range(mesh_data.bounds, tile_size) else {
+        return;
+    };
+
+    let height = mesh_data.bounds.1.y;
+    let origin = Vec3::new(min_x as f32 * tile_size, height, min_z as f32 * tile_size);
+    let transform = layers.transform(layers::PRIMARY_LAYER);
+    let mut dd = TransformedDraw {
+        inner: &mut debug_draw_b::GizmoDebugDraw::new(&mut gizmos),
+        transform,
+    };
+    debug_draw_b::du_debug_draw_grid_xz(
+        &mut dd,
+        origin,
+        max_x - min_x + 1,
+        max_z - min_z + 1,
+        tile_size,
+        Vec4::new(0.3, 0.8, 1.0, 0.8),
+        1.0,
+    );
+
+    let label_count = (max_x - min_x + 1) as i64 * (max_z - min_z + 1) as i64;
+    let Ok((camera, camera_transform)) = camera_query.get_single() else {
+        return;
+    };
+    // Labels are UI, left out of screenshots without it
+    if label_count > MAX_TILE_LABELS || screenshot.hide_ui {
+        return;
+    }
+
+    let painter = contexts
+        .ctx_mut()
+        .layer_painter(egui::LayerId::background());
+    for tile_z in min_z..=max_z {
+        for tile_x in min_x..=max_x {
+            let center = Vec3::new(
+                (tile_x as f32 + 0.5) * tile_size,
+                height,
+                (tile_z as f32 + 0.5) * tile_size,
+            );
+            let center = transform.transform_point(center);
+            if let Some(screen) = camera.world_to_viewport(camera_transform, center) {
+                painter.text(
+                    egui::pos2(screen.x, screen.y),
+                    egui::Align2::CENTER_CENTER,
+                    format!("({}, {})", tile_x, tile_z),
+                    egui::FontId::default(),
+                    egui::Color32::from_rgb(77, 204, 255),
+                );
+            }
+        }
+    }
+}
+
+// Edges of a single triangle in yellow, of more than two in red
+const BOUNDARY_EDGE_COLOR: Vec4 = Vec4::new(1.0, 0.85, 0.0, 1.0);
+const NON_MANIFOLD_EDGE_COLOR: Vec4 = Vec4::new(1.0, 0.1, 0.3, 1.0);
+
+// Mesh health check: where the primary mesh isn't a clean two-sided surface.
+// The edges are found again only when the mesh or its layer transform changes
+fn draw_open_edges(
+    mut gizmos: Gizmos,
+    settings: Res<RenderSettings>,
+    mesh_data: Option<Res<MeshData>>,
+    layers: Res<layers::MeshLayers>,
+    mut cache: Local<Option<(stats::EdgeHealth, Vec<Vec3>, Transform)>>,
+) {
+    let Some(mesh_data) = mesh_data.filter(|_| settings.show_open_edges) else {
+        *cache = None;
+        return;
+    };
+    let transform = layers.transform(layers::PRIMARY_LAYER);
+    if mesh_data.is_changed() || cache.as_ref().is_none_or(|(_, _, t)| *t != transform) {
+        let vertices = mesh_data
+            .vertices
+            .iter()
+            .map(|&v| transform.transform_point(v))
+            .collect();
+        *cache = Some((
+            stats::edge_health(&mesh_data.vertices, &mesh_data.indices),
+            vertices,
+            transform,
+        ));
+    }
+    let Some((edges, vertices, _)) = cache.as_ref() else {
+        return;
+    };
+
+    let mut dd = debug_draw_b::GizmoDebugDraw::new(&mut gizmos);
+    debug_draw_b::du_debug_draw_edges(&mut dd, vertices, &edges.boundary, BOUNDARY_EDGE_COLOR, 2.0);
+    debug_draw_b::du_debug_draw_edges(
+        &mut dd,
+        vertices,
+        &edges.non_manifold,
+        NON_MANIFOLD_EDGE_COLOR,
+        2.0,
+    );
+}
+
+// Screen direction of each world axis as seen by a camera with this rotation,
+// in egui coordinates (y down), with its depth towards the viewer
+fn screen_axes(camera_rotation: Quat) -> [(Vec2, f32); 3] {
+    let view = camera_rotation.inverse();
+    [Vec3::X, Vec3::Y, Vec3::Z].map(|axis| {
+        let v = view * axis;
+        (Vec2::new(v.x, -v.y), v.z)
+    })
+}
+
+// Axis lines at the world origin, sized to the mesh, plus a gizmo in the
+// bottom right corner that turns with the camera
+fn draw_axes(
+    mut gizmos: Gizmos,
+    mut contexts: EguiContexts,
+    settings: Res<RenderSettings>,
+    mesh_data: Option<Res<MeshData>>,
+    camera_query: Query<&GlobalTransform, With<MainCamera>>,
+    screenshot: Res<ScreenshotState>,
+) {
+    if !settings.show_axes {
+        return;
+    }
+
+    let length = mesh_data
+        .map(|mesh_data| {
+            let size = mesh_data.bounds.1 - mesh_data.bounds.0;
+            size.x.max(size.z) * 0.1
+        })
+        .filter(|length| length.is_finite() && *length > 0.0)
+        .unwrap_or(100.0);
+    let mut dd = debug_draw_b::GizmoDebugDraw::new(&mut gizmos);
+    debug_draw_b::du_debug_draw_axes(&mut dd, Vec3::ZERO, length, 2.0);
+
+    // The gizmo is UI, left out of screenshots without it
+    let Ok(camera_transform) = camera_query.get_single() else {
+        return;
+    };
+    if screenshot.hide_ui {
+        return;
+    }
+    let ctx = contexts.ctx_mut();
+    let screen = ctx.screen_rect();
+    let center = screen.right_bottom()
+        - egui::vec2(
+            AXIS_GIZMO_RADIUS + AXIS_GIZMO_MARGIN,
+            AXIS_GIZMO_RADIUS + AXIS_GIZMO_MARGIN,
+        );
+    let painter = ctx.layer_painter(egui::LayerId::background());
+
+    // Farthest axis first so the nearer ones are drawn over it
+    let mut axes: Vec<_> = screen_axes(camera_transform.compute_transform().rotation)
+        .into_iter()
+        .zip([
+            ("X", egui::Color32::RED),
+            ("Y", egui::Color32::GREEN),
+            ("Z", egui::Color32::from_rgb(64, 128, 255)),
+        ])
+        .collect();
+    axes.sort_by(|((_, a), _), ((_, b), _)| a.total_cmp(b));
+    for ((direction, _), (label, color)) in axes {
+        let end = center + egui::vec2(direction.x, direction.y) * AXIS_GIZMO_RADIUS;
+        painter.line_segment([center, end], egui::Stroke::new(2.0, color));
+        painter.text(
+            end,
+            egui::Align2::CENTER_CENTER,
+            label,
+            egui::FontId::default(),
+            color,
+        );
+    }
+}
+
+// Hide tiles whose bounds are outside the main camera frustum
+fn cull_tiles(
+    camera_query: Query<(&Projection, &GlobalTransform), With<MainCamera>>,
+    mut tiles: Query<(&TileMesh, Option<&layers::MeshLayerId>, &mut Visibility)>,
+    mut stats: ResMut<TileCullStats>,
+    layers: Res<layers::MeshLayers>,
+) {
+    let Ok((projection, camera_transform)) = camera_query.get_single() else {
+        return;
+    };
+
+    let view_projection =
+        projection.get_projection_matrix() * camera_transform.compute_matrix().inverse();
+    let frustum = Frustum::from_view_projection(&view_projection);
+
+    stats.visible = 0;
+    stats.total = 0;
+    for (tile, layer, mut visibility) in tiles.iter_mut() {
+        // Tiles of a hidden layer stay hidden
+        let layer = layer.map_or(layers::PRIMARY_LAYER, |layer| layer.0);
+        // The projection has an infinite far plane, so only test the near one
+        let visible = layers.is_visible(layer)
+            && frustum.intersects_obb(
+                &tile.aabb,
+                &layers.transform(layer).compute_affine(),
+                true,
+                false,
+            );
+        let wanted = if visible {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        if *visibility != wanted {
+            *visibility = wanted;
+        }
+
+        stats.total += 1;
+        stats.visible += visible as usize;
+    }
+}
+
+// Build the material for an .mtl entry. Like the default material it starts
+// unlit until `apply_lighting` says otherwise, the slope vertex colors tint the
+// diffuse color. StandardMaterial has no ambient term so Ka is not used
+fn obj_material_to_standard(
+    obj_material: &obj_loader::ObjMaterial,
+    images: &mut Assets<Image>,
+) -> StandardMaterial {
+    let [r, g, b] = obj_material.diffuse;
+    let specular = obj_material.specular;
+
+    let base_color_texture = obj_material.diffuse_map.as_ref().and_then(|path| {
+        let extension = path.extension()?.to_str()?.to_string();
+        let image = std::fs::read(path).ok().and_then(|bytes| {
+            Image::from_buffer(
+                &bytes,
+                bevy::render::texture::ImageType::Extension(&extension),
+                bevy::render::texture::CompressedImageFormats::NONE,
+                true,
+                bevy::render::texture::ImageSampler::Default,
+            )
+            .ok()
+        });
+
+        match image {
+            Some(image) => Some(images.add(image)),
+            None => {
+                warn!("Could not load texture {}", path.display());
+                None
+            }
+        }
+    });
+
+    StandardMaterial {
+        base_color: Color::rgb(r, g, b),
+        base_color_texture,
+        reflectance: specular[0].max(specular[1]).max(specular[2]),
+        unlit: true,
+        ..default()
+    }
+}
+
+// Nearest hit on the loaded mesh, through the picking grid when there is one
+fn raycast_mesh_data(
+    mesh_data: &MeshData,
+    picking_grid: Option<&world::TriangleGrid>,
+    origin: Vec3,
+    direction: Vec3,
+) -> Option<(Vec3, usize)> {
+    let (vertices, indices) = (&mesh_data.vertices, &mesh_data.indices);
+    match picking_grid {
+        Some(grid) => grid.raycast(origin, direction, vertices, indices),
+        None => world::raycast_mesh(origin, direction, vertices, indices),
+    }
+}
+
+// Which computed normal a triangle's corners take
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum SmoothingKey {
+    Group(u32),  // Averaged over the faces of the smoothing group
+    Flat(usize), // The triangle's own normal
+}
+
+// Returns vertices, indices, normals and texture coordinates. The texture
+// coordinates are empty unless some face references them
+fn convert_obj_to_mesh_data(
+    obj: &ObjData,
+    weighting: NormalWeighting,
+    import: ImportOptions,
+) -> (Vec<Vec3>, Vec<u32>, Vec<Vec3>, Vec<Vec2>) {
+    let mut vertices: Vec<Vec3> = obj
+        .vertices
+        .iter()
+        .map(|v| Vec3::new(v.x, v.y, v.z))
+        .collect();
+
+    let mut triangles = obj.triangulate();
+    let mut triangle_normals = obj.triangulate_normals();
+    let mut triangle_tex_coords = obj.triangulate_tex_coords();
+
+    // Corners in reverse order turn the faces around, and with them the
+    // computed normals. Normals from the file are kept as they are
+    if import.flip_winding {
+        for tri in triangles.iter_mut() {
+            tri.reverse();
+        }
+        for corners in triangle_normals
+            .iter_mut()
+            .chain(&mut triangle_tex_coords)
+            .flatten()
+        {
+            corners.reverse();
+        }
+    }
+    let triangle_smoothing = obj.triangulate_smoothing();
+    let degenerate: Vec<bool> = triangles
+        .iter()
+        .map(|tri| is_degenerate(vertices[tri[0]], vertices[tri[1]], vertices[tri[2]]))
+        .collect();
+
+    // Faces only share computed normals within a smoothing group, a face
+    // outside any group shades flat with a normal of its own
+    let smoothing_key = |triangle: usize| match triangle_smoothing.get(triangle) {
+        Some(None) => SmoothingKey::Flat(triangle),
+        Some(&Some(group)) => SmoothingKey::Group(group),
+        None => SmoothingKey::Group(0),
+    };
+
+    // Calculate normals per vertex by summing face normals, only used for
+    // faces that don't supply their own normals in the file. The first
+    // smoothing group to use a position sums into the shared vertex, later
+    // ones into `group_normals`
+    let mut normals = vec![Vec3::ZERO; vertices.len()];
+    let mut owners: Vec<Option<SmoothingKey>> = vec![None; vertices.len()];
+    let mut group_normals: std::collections::HashMap<(usize, SmoothingKey), Vec3> =
+        std::collections::HashMap::new();
+
+    // Calculate face normals and accumulate them for each vertex
+    for (i, (tri, file_normals)) in triangles.iter().zip(triangle_normals.iter()).enumerate() {
+        // Degenerate triangles have no direction to add, they stay in the
+        // index buffer and `MeshStats` counts them
+        if file_normals.is_some() || degenerate[i] {
+            continue;
+        }
+        let key = smoothing_key(i);
+
+        let v0 = vertices[tri[0]];
+        let v1 = vertices[tri[1]];
+        let v2 = vertices[tri[2]];
+        // The cross product is twice the triangle's area long
+        let cross = (v1 - v0).cross(v2 - v0);
+        let normal = match weighting {
+            NormalWeighting::Area => cross,
+            NormalWeighting::Equal => cross.normalize_or_zero(),
+        };
+
+        // Add the face normal to each vertex's accumulated normal
+        for &index in tri {
+            if *owners[index].get_or_insert(key) == key {
+                normals[index] += normal;
+            } else {
+                *group_normals.entry((index, key)).or_default() += normal;
+            }
+        }
+    }
+
+    // Only the direction of the sum matters
+    for normal in normals.iter_mut().chain(group_normals.values_mut()) {
+        *normal = normal.normalize_or_zero();
+    }
+
+    // Shared vertices without a texture coordinate fall back to zero, so the
+    // attribute stays aligned with the positions
+    let has_uvs = triangle_tex_coords.iter().any(Option::is_some);
+    let mut uvs = if has_uvs {
+        vec![Vec2::ZERO; vertices.len()]
+    } else {
+        Vec::new()
+    };
+
+    // Corners with a file normal, a texture coordinate or a smoothing group
+    // other than the shared vertex's get their own vertex per (position,
+    // normal, uv, group) combination so hard edges and UV seams survive, the
+    // rest index the shared vertices directly
+    type SplitKey = (usize, Option<usize>, Option<usize>, Option<SmoothingKey>);
+    let mut split_vertices: std::collections::HashMap<SplitKey, u32> =
+        std::collections::HashMap::new();
+    let mut indices = Vec::with_capacity(triangles.len() * 3);
+
+    for (i, tri) in triangles.iter().enumerate() {
+        for k in 0..3 {
+            let position = tri[k];
+            let normal = triangle_normals[i].map(|n| n[k]);
+            let tex_coord = triangle_tex_coords[i].map(|t| t[k]);
+            // A file normal makes the smoothing group irrelevant, degenerate
+            // triangles take whatever the shared vertex has
+            let key = (normal.is_none() && !degenerate[i]).then(|| smoothing_key(i));
+            let shared = key.is_none() || key == owners[position];
+
+            if normal.is_none() && tex_coord.is_none() && shared {
+                indices.push(position as u32);
+                continue;
+            }
+
+            let index = *split_vertices
+                .entry((position, normal, tex_coord, key))
+                .or_insert_with(|| {
+                    let n = match normal.and_then(|n| obj.normals.get(n)) {
+                        Some(n) => Vec3::new(n.x, n.y, n.z).normalize_or_zero(),
+                        None if shared => normals[position],
+                        None => group_normals[&(position, key.unwrap())],
+                    };
+                    vertices.push(vertices[position]);
+                    normals.push(n);
+
+                    if has_uvs {
+                        let uv = match tex_coord.and_then(|t| obj.tex_coords.get(t)) {
+                            Some(t) => Vec2::new(t.x, t.y),
+                            None => Vec2::ZERO,
+                        };
+                        uvs.push(uv);
+                    }
+
+                    (vertices.len() - 1) as u32
+                });
+
+            indices.push(index);
+        }
+    }
+
+    (vertices, indices, normals, uvs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy::render::mesh::VertexAttributeValues;
+    use bevy::tasks::TaskPoolBuilder;
+
+    #[test]
+    fn test_clip_planes_apply_and_validate() {
+        let mut app = App::new();
+        app.insert_resource(ClipPlanes {
+            near: 0.5,
+            far: 2000.0,
+        })
+        .add_systems(Update, apply_clip_planes);
+        let camera = app
+            .world
+            .spawn((
+                Projection::Perspective(main_camera_projection()),
+                MainCamera::default(),
+            ))
+            .id();
+
+        app.update();
+        match app.world.get::<Projection>(camera).unwrap() {
+            Projection::Perspective(perspective) => {
+                assert_eq!((perspective.near, perspective.far), (0.5, 2000.0));
+            }
+            _ => panic!("Main camera should stay perspective"),
+        }
+
+        // Out of range planes are pulled back, the far one past the near one
+        let clip_planes = ClipPlanes {
+            near: 5.0,
+            far: 0.1,
+        }
+        .validated();
+        assert_eq!(clip_planes.near, MAX_NEAR_CLIP);
+        assert!(clip_planes.far > clip_planes.near);
+        let clip_planes = ClipPlanes {
+            near: 0.0,
+            far: 1e9,
+        }
+        .validated();
+        assert_eq!(
+            (clip_planes.near, clip_planes.far),
+            (MIN_NEAR_CLIP, MAX_FAR_CLIP)
+        );
+    }
+
+    // An app running only `camera_control`, and the primary window it reads
+    fn camera_test_app() -> (App, Entity) {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, bevy::input::InputPlugin))
+            .init_resource::<layers::MeshLayers>()
+            .init_resource::<section::SectionPlane>()
+            .init_resource::<world::PickPlane>()
+            .init_resource::<CameraSpeed>()
+            .add_systems(Update, camera_control);
+        let window = app.world.spawn((Window::default(), PrimaryWindow)).id();
+        (app, window)
+    }
+
+    fn spawn_test_camera(app: &mut App, projection: Projection, main_camera: MainCamera) -> Entity {
+        app.world
+            .spawn((
+                Camera3dBundle {
+                    projection,
+                    ..default()
+                },
+                main_camera,
+                CameraMouseState::default(),
+            ))
+            .id()
+    }
+
+    #[test]
+    fn test_camera_control_keeps_far_plane() {
+        let (mut app, _) = camera_test_app();
+        let camera = spawn_test_camera(
+            &mut app,
+            Projection::Perspective(main_camera_projection()),
+            MainCamera::default(),
+        );
+
+        app.update();
+
+        match app.world.get::<Projection>(camera).unwrap() {
+            Projection::Perspective(perspective) => {
+                assert_eq!(perspective.far, main_camera_projection().far);
+                assert_eq!(perspective.near, main_camera_projection().near);
+            }
+            _ => panic!("Main camera should stay perspective"),
+        }
+    }
+
+    #[test]
+    fn test_scroll_adjusts_speed_and_fov() {
+        let (mut app, window) = camera_test_app();
+        let camera = spawn_test_camera(
+            &mut app,
+            Projection::Perspective(main_camera_projection()),
+            MainCamera::default(),
+        );
+
+        let scroll = MouseWheel {
+            unit: MouseScrollUnit::Line,
+            x: 0.0,
+            y: 100.0,
+            window,
+        };
+
+        // Plain scroll speeds up fly movement, clamped to the maximum
+        app.world.send_event(scroll);
+        app.update();
+        let main_camera = app.world.get::<MainCamera>(camera).unwrap();
+        assert_eq!(main_camera.move_speed, MAX_MOVE_SPEED);
+
+        // Alt + scroll narrows the field of view instead
+        app.world
+            .resource_mut::<Input<KeyCode>>()
+            .press(KeyCode::AltLeft);
+        app.world.send_event(scroll);
+        app.update();
+        match app.world.get::<Projection>(camera).unwrap() {
+            Projection::Perspective(perspective) => {
+                assert!((perspective.fov.to_degrees() - MIN_FOV_DEGREES).abs() < 1e-3);
+            }
+            _ => panic!("Main camera should stay perspective"),
+        }
+        let main_camera = app.world.get::<MainCamera>(camera).unwrap();
+        assert_eq!(main_camera.move_speed, MAX_MOVE_SPEED);
+    }
+
+    #[test]
+    fn test_orthographic_toggle_and_zoom() {
+        let (mut app, window) = camera_test_app();
+        let mut main_camera = MainCamera {
+            mode: CameraMode::Orbit,
+            orbit_radius: 100.0,
+            ..default()
+        };
+        let mut projection = Projection::Perspective(PerspectiveProjection {
+            fov: 90.0_f32.to_radians(),
+            ..main_camera_projection()
+        });
+
+        // At 90 degrees the view is as tall as twice its distance
+        toggle_orthographic(&mut projection, &mut main_camera, &ClipPlanes::default());
+        match &projection {
+            Projection::Orthographic(ortho) => assert!((ortho.scale - 100.0).abs() < 1e-3),
+            _ => panic!("Main camera should be orthographic"),
+        }
+        let camera = spawn_test_camera(&mut app, projection, main_camera);
+
+        // Scroll zooms in, the orbit radius and speed stay
+        app.world.send_event(MouseWheel {
+            unit: MouseScrollUnit::Line,
+            x: 0.0,
+            y: 1.0,
+            window,
+        });
+        app.update();
+        let mut projection = app.world.get::<Projection>(camera).unwrap().clone();
+        match &projection {
+            Projection::Orthographic(ortho) => assert!((ortho.scale - 90.0).abs() < 1e-3),
+            _ => panic!("Main camera should stay orthographic"),
+        }
+        let mut main_camera = app.world.entity_mut(camera).take::<MainCamera>().unwrap();
+        assert_eq!(main_camera.orbit_radius, 100.0);
+        assert_eq!(main_camera.move_speed, CameraSpeed::default().base);
+
+        // Back in perspective with the same field of view, as close as the
+        // zoom made it
+        toggle_orthographic(&mut projection, &mut main_camera, &ClipPlanes::default());
+        match &projection {
+            Projection::Perspective(perspective) => {
+                assert!((perspective.fov.to_degrees() - 90.0).abs() < 1e-3);
+            }
+            _ => panic!("Main camera should be perspective again"),
+        }
+        assert!((main_camera.orbit_radius - 90.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_movement_is_frame_rate_independent() {
+        // Distance flown in one simulated second at a frame rate, holding
+        // forward and the given modifiers
+        let fly_second = |fps: u32, modifiers: &[KeyCode]| {
+            let (mut app, _) = camera_test_app();
+            app.insert_resource(bevy::time::TimeUpdateStrategy::ManualDuration(
+                Duration::from_secs(1) / fps,
+            ));
+            let camera = spawn_test_camera(&mut app, Projection::default(), MainCamera::default());
+
+            // The first frame sets the camera up, the keys go down after it
+            app.update();
+            let start = app.world.get::<Transform>(camera).unwrap().translation;
+            let mut keyboard = app.world.resource_mut::<Input<KeyCode>>();
+            keyboard.press(KeyCode::W);
+            for &key in modifiers {
+                keyboard.press(key);
+            }
+            for _ in 0..fps {
+                app.update();
+            }
+            let end = app.world.get::<Transform>(camera).unwrap().translation;
+            end.distance(start)
+        };
+
+        let base = CameraSpeed::default().base;
+        for fps in [20, 60, 144] {
+            assert!(
+                (fly_second(fps, &[]) - base).abs() < base * 1e-3,
+                "{} fps",
+                fps
+            );
+        }
+        let sprint = fly_second(60, &[KeyCode::ShiftLeft]);
+        assert!((sprint - base * CameraSpeed::default().sprint).abs() < base * 1e-3);
+        let crawl = fly_second(30, &[KeyCode::ControlRight]);
+        assert!((crawl - base * CameraSpeed::default().crawl).abs() < base * 1e-3);
+    }
+
+    #[test]
+    fn test_parse_coordinates() {
+        let expected = Some(Vec3::new(1.5, -2.0, 30.0));
+        assert_eq!(parse_coordinates("1.5, -2, 30"), expected);
+        assert_eq!(parse_coordinates(" (1.5 -2 30) "), expected);
+        assert_eq!(parse_coordinates("[1.5,-2,30]"), expected);
+        assert_eq!(parse_coordinates("1.5, -2"), None);
+        assert_eq!(parse_coordinates("1.5, -2, 30, 4"), None);
+        assert_eq!(parse_coordinates("x, y, z"), None);
+    }
+
+    #[test]
+    fn test_fit_view_to_mesh() {
+        // A square window clips the sphere by the vertical angle, a tall one
+        // by the horizontal angle
+        let distance = fit_distance(100.0, 90.0_f32.to_radians(), 1.0);
+        assert!((distance - 100.0 * 2.0_f32.sqrt()).abs() < 1e-3);
+        let narrow = fit_distance(100.0, 90.0_f32.to_radians(), 0.5);
+        assert!((narrow - 100.0 * 5.0_f32.sqrt()).abs() < 1e-2);
+
+        let (mut app, window) = camera_test_app();
+        let camera = spawn_test_camera(
+            &mut app,
+            Projection::Perspective(main_camera_projection()),
+            MainCamera::default(),
+        );
+
+        // A tall thin mesh well away from the origin
+        let vertices = vec![Vec3::new(5000.0, 0.0, 0.0), Vec3::new(5010.0, 400.0, 10.0)];
+        app.world.insert_resource(MeshData {
+            bounds: point_bounds(&vertices),
+            normals: vec![Vec3::Y; 2],
+            vertices,
+            indices: Vec::new(),
+            uvs: Vec::new(),
+            tile_size: 988.0,
+            material_groups: Vec::new(),
+            file_vertices: Vec::new(),
+        });
+        app.world.send_event(bevy::input::keyboard::KeyboardInput {
+            scan_code: 0,
+            key_code: Some(KeyCode::F),
+            state: bevy::input::ButtonState::Pressed,
+            window,
+        });
+        app.update();
+
+        let center = Vec3::new(5005.0, 200.0, 5.0);
+        let main_camera = app.world.get::<MainCamera>(camera).unwrap();
+        assert_eq!(main_camera.focus, center);
+        let translation = app.world.get::<Transform>(camera).unwrap().translation;
+        let radius = Vec3::new(10.0, 400.0, 10.0).length() * 0.5;
+        assert!(translation.distance(center) > radius);
+        assert!((translation.distance(center) - main_camera.orbit_radius).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_level_view_and_no_roll() {
+        let (mut app, window) = camera_test_app();
+
+        // Looking almost straight up or down still keeps the horizon level
+        for pitch in [89.0_f32, -89.0] {
+            let camera = spawn_test_camera(
+                &mut app,
+                Projection::default(),
+                MainCamera {
+                    yaw: 130.0_f32.to_radians(),
+                    pitch: pitch.to_radians(),
+                    ..default()
+                },
+            );
+            app.update();
+
+            let transform = app.world.get::<Transform>(camera).unwrap();
+            assert!(transform.rotation.is_finite());
+            assert!(transform.right().y.abs() < 1e-5);
+            assert!(transform.up().y > 0.0);
+            let forward = camera_forward(130.0_f32.to_radians(), pitch.to_radians());
+            assert!(transform.forward().distance(forward) < 1e-4);
+            app.world.despawn(camera);
+        }
+
+        let camera = spawn_test_camera(
+            &mut app,
+            Projection::default(),
+            MainCamera {
+                yaw: 150.0_f32.to_radians(),
+                pitch: 60.0_f32.to_radians(),
+                ..default()
+            },
+        );
+        app.world.send_event(bevy::input::keyboard::KeyboardInput {
+            scan_code: 0,
+            key_code: Some(LEVEL_VIEW_KEY),
+            state: bevy::input::ButtonState::Pressed,
+            window,
+        });
+        app.update();
+        let main_camera = app.world.get::<MainCamera>(camera).unwrap();
+        assert_eq!(main_camera.pitch, 0.0);
+        assert!((main_camera.yaw - 180.0_f32.to_radians()).abs() < 1e-5);
+        let transform = app.world.get::<Transform>(camera).unwrap();
+        assert!(transform.forward().distance(Vec3::NEG_X) < 1e-5);
+
+        assert_eq!(snap_yaw(-100.0_f32.to_radians()), -90.0_f32.to_radians());
+    }
+
+    #[test]
+    fn test_double_click_orbits_point() {
+        let mut clicks = ClickTracker::default();
+        assert!(!clicks.click(1.0, Vec2::new(100.0, 100.0)));
+        assert!(clicks.click(1.2, Vec2::new(103.0, 100.0)));
+        // A third click is the first of the next double click
+        assert!(!clicks.click(1.3, Vec2::new(103.0, 100.0)));
+        // Too slow, then too far
+        assert!(!clicks.click(2.0, Vec2::new(103.0, 100.0)));
+        assert!(!clicks.click(2.1, Vec2::new(200.0, 100.0)));
+        assert!(clicks.click(2.2, Vec2::new(200.0, 100.0)));
+
+        // The look direction stays, the camera backs off along it
+        let mut camera = MainCamera {
+            pitch: -30.0_f32.to_radians(),
+            ..default()
+        };
+        let point = Vec3::new(10.0, 0.0, 10.0);
+        let position = orbit_point(&mut camera, point, 50.0);
+        assert_eq!(camera.mode, CameraMode::Orbit);
+        assert_eq!((camera.focus, camera.orbit_radius), (point, 50.0));
+        assert!((position.distance(point) - 50.0).abs() < 1e-4);
+        assert!(((point - position).normalize().y - (-0.5)).abs() < 1e-5);
+        orbit_point(&mut camera, point, 0.0);
+        assert_eq!(camera.orbit_radius, MIN_ORBIT_RADIUS);
+    }
+
+    // A viewer with nothing loaded, sharing `mitm_info` with the test
+    fn test_viewer(mitm_info: Arc<Mutex<MitmInfo>>) -> MeshViewer {
+        MeshViewer {
+            obj_path: None,
+            walkable_slope_angle: 45.0,
+            needs_update: false,
+            needs_rebuild: false,
+            load_error: None,
+            loading: None,
+            load_progress: 0.0,
+            export_message: None,
+            normal_weighting: NormalWeighting::default(),
+            mitm_info,
+        }
+    }
+
+    fn load_test_obj(name: &str, content: &str) -> ObjData {
+        let path =
+            std::env::temp_dir().join(format!("mesh_viewer_{}_{}.obj", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        obj_loader::load_obj(&path).unwrap()
+    }
+
+    #[test]
+    fn test_failed_load_clears_the_mesh() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .init_resource::<Assets<Mesh>>()
+            .init_resource::<Assets<StandardMaterial>>()
+            .init_resource::<Assets<Image>>()
+            .init_resource::<layers::MeshLayers>()
+            .init_resource::<TileSettings>()
+            .init_resource::<SlopeColors>()
+            .init_resource::<RenderMode>()
+            .init_resource::<ElevationColors>()
+            .init_resource::<RenderSettings>()
+            .init_resource::<coords::CoordinateTransform>()
+            .init_resource::<ImportOptions>()
+            .init_resource::<section::SectionPlane>()
+            .init_resource::<budget::MeshBudget>()
+            .init_resource::<decimation::Decimation>()
+            .init_resource::<ObjGroups>()
+            .init_resource::<tiling::IncrementalTiler>()
+            .add_systems(Update, update_mesh);
+        let viewer = app
+            .world
+            .spawn(test_viewer(Arc::new(Mutex::new(MitmInfo::new(
+                "127.0.0.1:9999",
+                net::MAX_FRAME_LEN,
+            )))))
+            .id();
+        // Loads in the background, polled until it's done
+        let load = |app: &mut App, name: &str, content: &str| {
+            let path = std::env::temp_dir().join(format!(
+                "mesh_viewer_{}_{}.obj",
+                std::process::id(),
+                name
+            ));
+            std::fs::write(&path, content).unwrap();
+            let mut entity = app.world.entity_mut(viewer);
+            let mut mesh_viewer = entity.get_mut::<MeshViewer>().unwrap();
+            mesh_viewer.obj_path = Some(path);
+            mesh_viewer.needs_update = true;
+            for _ in 0..1000 {
+                app.update();
+                if app
+                    .world
+                    .get::<MeshViewer>(viewer)
+                    .unwrap()
+                    .loading
+                    .is_none()
+                {
+                    return;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+            panic!("{} never finished loading", name);
+        };
+
+        load(&mut app, "load_good", "v 0 0 0\nv 1 0 0\nv 1 0 1\nf 1 3 2");
+        app.update();
+        assert!(app.world.contains_resource::<MeshData>());
+        assert!(app.world.contains_resource::<world::TriangleGrid>());
+
+        // The failed file's error is shown, without the good file's mesh
+        // still there to pick or walk on
+        load(&mut app, "load_bad", "v 0 0 0\nv 1 0 0\nv 1 0 1\nf a b c");
+        app.update();
+        assert!(app
+            .world
+            .get::<MeshViewer>(viewer)
+            .unwrap()
+            .load_error
+            .is_some());
+        assert!(!app.world.contains_resource::<MeshData>());
+        assert!(!app.world.contains_resource::<world::TriangleGrid>());
+    }
+
+    #[test]
+    fn test_hidden_groups_stay_hidden_on_reload() {
+        let content = "v 0 0 0\nv 1 0 0\nv 1 0 1\no a\nf 1 2 3\no b\nf 1 3 2";
+        let mut groups = ObjGroups::default();
+        groups.set_obj_data(load_test_obj("groups_reload", content));
+        assert_eq!(groups.visible, vec![true, true]);
+        assert_eq!(groups.face_counts, vec![1, 1]);
+        groups.visible[1] = false;
+
+        // A new group shows up, "b" is still hidden
+        let content = "v 0 0 0\nv 1 0 0\nv 1 0 1\no c\nf 1 2 3\no b\nf 1 3 2\no a\nf 3 2 1";
+        groups.set_obj_data(load_test_obj("groups_reload", content));
+        assert_eq!(groups.visible, vec![true, false, true]);
+    }
+
+    #[test]
+    fn test_smoothing_groups_keep_edges_hard() {
+        // A floor and a wall sharing the edge along X, so vertices 1 and 2 are
+        // used by both
+        let floor_and_wall = |floor: &str, wall: &str| {
+            format!(
+                "v 0 0 1\nv 0 0 0\nv 1 0 0\nv 0 1 0\n{}\nf 1 3 2\n{}\nf 2 3 4",
+                floor, wall
+            )
+        };
+        let edge_normals = |obj: &ObjData| {
+            let (vertices, indices, normals, _) =
+                convert_obj_to_mesh_data(obj, NormalWeighting::Area, ImportOptions::default());
+            let mut found: Vec<Vec3> = indices
+                .iter()
+                .filter(|&&index| vertices[index as usize] == Vec3::ZERO)
+                .map(|&index| normals[index as usize])
+                .collect();
+            found.dedup();
+            found
+        };
+
+        // The same group smooths across the edge
+        let smooth = load_test_obj("smooth_edge", &floor_and_wall("s 1", "s 1"));
+        let normals = edge_normals(&smooth);
+        assert_eq!(normals.len(), 1);
+        assert!((normals[0] - Vec3::new(0.0, 1.0, 1.0).normalize()).length() < 1e-5);
+
+        // Different groups, or smoothing off, keep each face's own normal
+        for (name, floor, wall) in [("split_edge", "s 1", "s 2"), ("flat_edge", "s off", "s 0")] {
+            let obj = load_test_obj(name, &floor_and_wall(floor, wall));
+            let normals = edge_normals(&obj);
+            assert_eq!(normals.len(), 2, "{}", name);
+            assert!((normals[0] - Vec3::Y).length() < 1e-5);
+            assert!((normals[1] - Vec3::Z).length() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_file_normals_are_preserved() {
+        // Unit cube with one flat normal per side
+        let obj = load_test_obj(
+            "cube_normals",
+            "\
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+v 0 0 1
+v 1 0 1
+v 1 1 1
+v 0 1 1
+vn 0 0 -1
+vn 0 0 1
+vn 0 -1 0
+vn 0 1 0
+vn -1 0 0
+vn 1 0 0
+f 1//1 4//1 3//1 2//1
+f 5//2 6//2 7//2 8//2
+f 1//3 2//3 6//3 5//3
+f 4//4 8//4 7//4 3//4
+f 1//5 5//5 8//5 4//5
+f 2//6 3//6 7//6 6//6",
+        );
+
+        let (vertices, indices, normals, uvs) =
+            convert_obj_to_mesh_data(&obj, NormalWeighting::Area, ImportOptions::default());
+        assert_eq!(indices.len(), 12 * 3);
+        assert!(uvs.is_empty());
+
+        // Every corner keeps the face normal from the file instead of the
+        // smoothed corner average
+        let expected = [
+            Vec3::NEG_Z,
+            Vec3::Z,
+            Vec3::NEG_Y,
+            Vec3::Y,
+            Vec3::NEG_X,
+            Vec3::X,
+        ];
+        for (i, tri) in indices.chunks(3).enumerate() {
+            for &index in tri {
+                let normal = normals[index as usize];
+                assert!((normal - expected[i / 2]).length() < 1e-6);
+                assert!(vertices[index as usize].x == 0.0 || vertices[index as usize].x == 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_missing_normals_are_averaged() {
+        let obj = load_test_obj(
+            "no_normals",
+            "\
+v 0 0 0
+v 1 0 0
+v 1 0 1
+f 1 3 2",
+        );
+
+        let (vertices, indices, normals, _) =
+            convert_obj_to_mesh_data(&obj, NormalWeighting::Area, ImportOptions::default());
+        assert_eq!(vertices.len(), 3);
+        assert_eq!(indices, vec![0, 2, 1]);
+        assert!((normals[0] - Vec3::Y).length() < 1e-6);
+    }
+
+    #[test]
+    fn test_degenerate_faces_are_skipped_for_normals() {
+        // A floor, a collinear face and a sliver, both sharing its corner
+        let obj = load_test_obj(
+            "degenerate",
+            "\
+v 0 0 0
+v 1 0 0
+v 1 0 1
+v 2 0 0
+v 3 0.0000001 0
+f 1 3 2
+f 1 2 4
+f 1 4 5",
+        );
+
+        // Counted equal, the sliver would pull the corner towards +Z
+        let (vertices, indices, normals, _) =
+            convert_obj_to_mesh_data(&obj, NormalWeighting::Equal, ImportOptions::default());
+        assert!(normals.iter().all(|normal| !normal.is_nan()));
+        assert!((normals[0] - Vec3::Y).length() < 1e-6);
+        assert_eq!(indices.len(), 9);
+
+        let stats = stats::MeshStats::new(&vertices, &indices, 100.0, 45.0);
+        assert_eq!(stats.degenerate_triangles, 2);
+    }
+
+    #[test]
+    fn test_import_options_flip_and_z_up() {
+        // Two floor quads' worth of triangles, one with its own file normals
+        let obj = load_test_obj(
+            "import_options",
+            "\
+v 0 0 0
+v 1 0 0
+v 1 0 1
+v 0 0 1
+vn 0 1 0
+f 1 3 2
+f 1 4 3
+f 1//1 2//1 3//1",
+        );
+        let (_, indices, normals, _) =
+            convert_obj_to_mesh_data(&obj, NormalWeighting::Area, ImportOptions::default());
+
+        let flip = ImportOptions { flip_winding: true };
+        let (_, flipped_indices, flipped_normals, _) =
+            convert_obj_to_mesh_data(&obj, NormalWeighting::Area, flip);
+        // Each computed triangle reversed, its normals pointing the other way
+        assert_eq!(indices[..6], [0, 2, 1, 0, 3, 2]);
+        assert_eq!(flipped_indices[..6], [1, 2, 0, 2, 3, 0]);
+        for i in 0..4 {
+            assert!((flipped_normals[i] + normals[i]).length() < 1e-6);
+        }
+        // The file's normals are left alone
+        assert_eq!(flipped_normals[flipped_indices[6] as usize], Vec3::Y);
+
+        // The Z-up preset turns the floor into a wall facing -Z, file normals
+        // included, rotated once by the preset and not again on import
+        let z_up = coords::CoordinateTransform {
+            preset: coords::CoordinatePreset::ZUp,
+            ..default()
+        };
+        let (mut vertices, mut indices, mut normals, _) =
+            convert_obj_to_mesh_data(&obj, NormalWeighting::Area, ImportOptions::default());
+        z_up.apply_to_mesh(&mut vertices, &mut indices, &mut normals);
+        assert_eq!(vertices[2], Vec3::new(1.0, 1.0, 0.0));
+        assert_eq!(indices[..6], [0, 2, 1, 0, 3, 2]);
+        assert!(normals
+            .iter()
+            .all(|normal| (*normal - Vec3::NEG_Z).length() < 1e-6));
+    }
+
+    #[test]
+    fn test_normals_weighted_by_area() {
+        // A large floor and a small wall sharing the corner at the origin
+        let obj = load_test_obj(
+            "area_weighted",
+            "\
+v 0 0 0
+v 10 0 0
+v 0 0 10
+v 0 1 0
+v 0 0 1
+f 1 3 2
+f 1 4 5",
+        );
+
+        let (_, _, normals, _) =
+            convert_obj_to_mesh_data(&obj, NormalWeighting::Area, ImportOptions::default());
+        assert!((normals[0] - (Vec3::Y * 100.0 + Vec3::X).normalize()).length() < 1e-6);
+
+        // Both faces count the same without area weighting
+        let (_, _, normals, _) =
+            convert_obj_to_mesh_data(&obj, NormalWeighting::Equal, ImportOptions::default());
+        assert!((normals[0] - (Vec3::Y + Vec3::X).normalize()).length() < 1e-6);
+    }
+
+    #[test]
+    fn test_split_mesh_dedups_tile_vertices() {
+        // 20 x 20 grid of quads spanning 2 x 2 tiles
+        let size = 20;
+        let mut vertices = Vec::new();
+        for z in 0..=size {
+            for x in 0..=size {
+                vertices.push(Vec3::new(x as f32 * 10.0, (x % 3) as f32, z as f32 * 10.0));
+            }
+        }
+        let row = size + 1;
+        let mut indices = Vec::new();
+        for z in 0..size {
+            for x in 0..size {
+                let i = z * row + x;
+                indices.extend_from_slice(&[i, i + row, i + 1, i + 1, i + row, i + row + 1]);
+            }
+        }
+        let normals = vec![Vec3::Y; vertices.len()];
+
+        let tiles = split_mesh_into_tiles(&vertices, &indices, &normals, &[], 100.0);
+        assert!(!tiles.is_empty());
+
+        let mut tile_triangles = 0;
+        let mut tile_vertices = 0;
+        for tile in &tiles {
+            tile_triangles += tile.indices.len() / 3;
+            tile_vertices += tile.vertices.len();
+            assert_eq!(tile.normals.len(), tile.vertices.len());
+            assert!(tile.uvs.is_empty());
+
+            // Every tile triangle is one of the source triangles
+            for tri in tile.indices.chunks(3) {
+                let corners = [0, 1, 2].map(|i| tile.vertices[tri[i] as usize]);
+                assert!(indices
+                    .chunks(3)
+                    .any(|src| { [0, 1, 2].map(|i| vertices[src[i] as usize]) == corners }));
+            }
+        }
+
+        // Triangles on tile borders land in every tile they touch
+        assert!(tile_triangles >= indices.len() / 3);
+
+        // A grid shares most corners, far fewer than 3 vertices per triangle
+        assert!(tile_vertices * 4 < tile_triangles * 3);
+    }
+
+    #[test]
+    fn test_cull_tiles_outside_frustum() {
+        let mut app = App::new();
+        app.init_resource::<TileCullStats>()
+            .init_resource::<layers::MeshLayers>()
+            .add_systems(Update, cull_tiles);
+
+        // Camera at the origin looking down -Z
+        app.world.spawn((
+            Projection::Perspective(main_camera_projection()),
+            GlobalTransform::from(Transform::from_xyz(0.0, 0.0, 0.0)),
+            MainCamera::default(),
+        ));
+
+        let spawn_tile = |app: &mut App, center: Vec3| {
+            app.world
+                .spawn((
+                    TileMesh {
+                        tile_x: 0,
+                        tile_y: 0,
+                        aabb: Aabb::from_min_max(center - Vec3::ONE, center + Vec3::ONE),
+                    },
+                    Visibility::Inherited,
+                ))
+                .id()
+        };
+        let ahead = spawn_tile(&mut app, Vec3::new(0.0, 0.0, -100.0));
+        let behind = spawn_tile(&mut app, Vec3::new(0.0, 0.0, 100.0));
+        let far_ahead = spawn_tile(&mut app, Vec3::new(0.0, 0.0, -50000.0));
+        let beside = spawn_tile(&mut app, Vec3::new(1000.0, 0.0, -10.0));
+
+        app.update();
+
+        let visibility = |app: &App, entity| *app.world.get::<Visibility>(entity).unwrap();
+        assert_eq!(visibility(&app, ahead), Visibility::Inherited);
+        assert_eq!(visibility(&app, far_ahead), Visibility::Inherited);
+        assert_eq!(visibility(&app, behind), Visibility::Hidden);
+        assert_eq!(visibility(&app, beside), Visibility::Hidden);
+
+        let stats = app.world.resource::<TileCullStats>();
+        assert_eq!((stats.visible, stats.total), (2, 4));
+    }
+
+    #[test]
+    fn test_highlight_tiles() {
+        let mut app = App::new();
+        app.init_resource::<Assets<StandardMaterial>>()
+            .init_resource::<TileHighlight>()
+            .init_resource::<selections::TileSelection>()
+            .init_resource::<TileSettings>()
+            .init_resource::<layers::MeshLayers>()
+            .add_systems(Update, highlight_tiles);
+
+        let original = app
+            .world
+            .resource_mut::<Assets<StandardMaterial>>()
+            .add(StandardMaterial::default());
+        let spawn_tile = |app: &mut App, tile_x: i32| {
+            app.world
+                .spawn((
+                    TileMesh {
+                        tile_x,
+                        tile_y: 0,
+                        aabb: Aabb::from_min_max(Vec3::ZERO, Vec3::ONE),
+                    },
+                    original.clone(),
+                ))
+                .id()
+        };
+        let first = spawn_tile(&mut app, 0);
+        let second = spawn_tile(&mut app, 1);
+        let material = |app: &App, entity| {
+            app.world
+                .get::<Handle<StandardMaterial>>(entity)
+                .unwrap()
+                .clone()
+        };
+
+        let mut highlight = app.world.resource_mut::<TileHighlight>();
+        highlight.requested = true;
+        app.update();
+        assert_ne!(material(&app, first), original);
+        assert_eq!(material(&app, second), original);
+
+        // Picking another tile moves the highlight
+        let mut highlight = app.world.resource_mut::<TileHighlight>();
+        highlight.tile_x = 1;
+        highlight.requested = true;
+        app.update();
+        assert_eq!(material(&app, first), original);
+        assert_ne!(material(&app, second), original);
+
+        // Coordinates without a tile clear it and say so
+        let mut highlight = app.world.resource_mut::<TileHighlight>();
+        highlight.tile_x = 7;
+        highlight.requested = true;
+        app.update();
+        assert_eq!(material(&app, second), original);
+        assert_eq!(
+            app.world.resource::<TileHighlight>().message.as_deref(),
+            Some("No tile at (7, 0)")
+        );
+
+        // Flying to a tile goes where the primary layer is drawn, another
+        // layer's tile at the same coordinates left out
+        app.world.spawn((
+            TileMesh {
+                tile_x: 0,
+                tile_y: 0,
+                aabb: Aabb::from_min_max(Vec3::splat(50.0), Vec3::splat(51.0)),
+            },
+            layers::MeshLayerId(1),
+            original.clone(),
+        ));
+        let camera = app
+            .world
+            .spawn((Transform::default(), MainCamera::default()))
+            .id();
+        app.world
+            .resource_mut::<layers::MeshLayers>()
+            .primary_transform
+            .translation = Vec3::new(10.0, 0.0, 0.0);
+        let mut highlight = app.world.resource_mut::<TileHighlight>();
+        highlight.tile_x = 0;
+        highlight.fly_to = true;
+        highlight.requested = true;
+        app.update();
+        assert_eq!(
+            app.world.get::<MainCamera>(camera).unwrap().focus,
+            Vec3::new(10.5, 0.5, 0.5)
+        );
+    }
+
+    #[test]
+    fn test_slope_colors() {
+        let colors = SlopeColors::default();
+        let wall = Vec3::new(1.0, 0.2, 0.0).normalize();
+
+        // Defaults match the original hardcoded grey and orange blend
+        let flat = 220.0 / 255.0 * 3.0 / 4.0;
+        assert_eq!(colors.shade(Vec3::Y), [flat, flat, flat, 1.0]);
+        let grey = 220.0 / 255.0 * (2.0 + wall.x + wall.y) / 4.0;
+        let t = 64.0 / 255.0;
+        let [r, g, b, _] = colors.shade(wall);
+        assert!((r - (grey * (1.0 - t) + 192.0 / 255.0 * t)).abs() < 1e-6);
+        assert!((g - (grey * (1.0 - t) + 128.0 / 255.0 * t)).abs() < 1e-6);
+        assert!((b - grey * (1.0 - t)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_slope_bands() {
+        let band = |max_angle, color| SlopeBand {
+            max_angle,
+            color,
+            blend: 1.0,
+        };
+        let mut colors = SlopeColors {
+            bands: vec![
+                band(60.0, [0.0, 0.0, 1.0]),
+                band(10.0, [0.0, 1.0, 0.0]),
+                band(30.0, [1.0, 1.0, 0.0]),
+            ],
+            ..default()
+        };
+        colors.sort_bands();
+
+        let slope = |degrees: f32| {
+            let radians = degrees.to_radians();
+            Vec3::new(radians.sin(), radians.cos(), 0.0)
+        };
+        assert_eq!(colors.shade(slope(5.0)), [0.0, 1.0, 0.0, 1.0]);
+        assert_eq!(colors.shade(slope(20.0)), [1.0, 1.0, 0.0, 1.0]);
+        assert_eq!(colors.shade(slope(45.0)), [0.0, 0.0, 1.0, 1.0]);
+        // Steeper than every band falls into the last one
+        assert_eq!(colors.shade(slope(80.0)), [0.0, 0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_recolor_mesh() {
+        // A floor and a wall, only the wall is unwalkable
+        let vertices = vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ];
+        let normals = vec![Vec3::Y, Vec3::Y, Vec3::Y, Vec3::X, Vec3::X, Vec3::X];
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.set_indices(Some(Indices::U32(vec![0, 1, 2, 3, 4, 5])));
+
+        let mut colors = SlopeColors::default();
+        colors.bands[1].color = [1.0, 0.0, 0.0];
+        colors.bands[1].blend = 1.0;
+        recolor_mesh(&mut mesh, &colors);
+
+        let Some(VertexAttributeValues::Float32x4(result)) = mesh.attribute(Mesh::ATTRIBUTE_COLOR)
+        else {
+            panic!("mesh has no colors");
+        };
+        assert_eq!(result[0], colors.shade(Vec3::Y));
+        assert_eq!(result[3], [1.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_slope_angle_is_clamped() {
+        let mut slope_colors = SlopeColors::default();
+        assert_eq!(slope_colors.set_walkable_slope_angle(120.0), 90.0);
+        assert_eq!(slope_colors.bands[0].max_angle, 90.0);
+        assert_eq!(slope_colors.set_walkable_slope_angle(-5.0), 0.0);
+        assert_eq!(slope_colors.set_walkable_slope_angle(f32::NAN), 0.0);
+
+        // A floor and a wall, both walkable at 90°
+        let vertices = vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ];
+        let indices = vec![0, 1, 2, 3, 4, 5];
+        let normals = vec![Vec3::Y, Vec3::Y, Vec3::Y, Vec3::X, Vec3::X, Vec3::X];
+        let mut at_90 = SlopeColors::default();
+        at_90.set_walkable_slope_angle(120.0);
+        assert_eq!(at_90.band(Vec3::X), &at_90.bands[0]);
+        let expected = calculate_colors(&vertices, &indices, &normals, &at_90);
+
+        // Set directly, as a settings file could, the colors come out the same
+        let mut past_vertical = SlopeColors::default();
+        past_vertical.bands[0].max_angle = 120.0;
+        assert_eq!(
+            calculate_colors(&vertices, &indices, &normals, &past_vertical),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_anti_aliasing_fallback() {
+        // Only 1x and 4x, like WebGL2
+        let webgl = |samples: u32| samples == 1 || samples == 4;
+        assert_eq!(AntiAliasing::X8.fallback(webgl), AntiAliasing::X4);
+        assert_eq!(AntiAliasing::X4.fallback(webgl), AntiAliasing::X4);
+        assert_eq!(AntiAliasing::X2.fallback(webgl), AntiAliasing::Off);
+        assert_eq!(AntiAliasing::X8.fallback(|_| true), AntiAliasing::X8);
+
+        // Without a GPU to ask the chosen level is used as is
+        let mut app = App::new();
+        app.insert_resource(AntiAliasing::X2)
+            .init_resource::<Msaa>()
+            .add_systems(Update, apply_anti_aliasing);
+        app.update();
+        assert_eq!(*app.world.resource::<Msaa>(), Msaa::Sample2);
+    }
+
+    #[test]
+    fn test_render_mode_cycle() {
+        let mut mode = RenderMode::default();
+        let mut seen = Vec::new();
+        for _ in 0..RenderMode::ALL.len() {
+            seen.push(mode);
+            mode = mode.next();
+        }
+        assert_eq!(mode, RenderMode::default());
+        assert_eq!(seen.len(), RenderMode::ALL.len());
+        assert!(RenderMode::ALL.iter().all(|mode| seen.contains(mode)));
+
+        // Without slope colors a wall only gets the shaded base color
+        let slope_colors = SlopeColors::default();
+        let wall = Vec3::new(1.0, 0.0, 0.0);
+        let elevation = ElevationColors::default();
+        let solid = RenderMode::Solid.colors(&slope_colors, &elevation);
+        let grey = slope_colors.base[0] * 0.75;
+        assert_eq!(solid.shade(wall), [grey, grey, grey, 1.0]);
+        assert_ne!(
+            solid.shade(wall),
+            RenderMode::SlopeColor
+                .colors(&slope_colors, &elevation)
+                .shade(wall)
+        );
+        assert_eq!(
+            *RenderMode::SlopeColorPlusWireframe.colors(&slope_colors, &elevation),
+            slope_colors
+        );
+    }
+
+    #[test]
+    fn test_elevation_colors() {
+        // A ramp from height 0 up to 10, one triangle per corner height
+        let vertices = vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(10.0, 5.0, 0.0),
+            Vec3::new(20.0, 10.0, 0.0),
+        ];
+        let indices = vec![0, 1, 2, 2, 1, 3];
+        let normals = vec![Vec3::Y; vertices.len()];
+        let gradient = ElevationColors::default();
+        let slope_colors = SlopeColors::default();
+        let colors = RenderMode::Elevation.colors(&slope_colors, &gradient);
+        let colors = colors.with_heights(point_bounds(&vertices));
+
+        // Flat faces get the gradient at three quarters brightness
+        let shaded = |[r, g, b]: [f32; 3]| [r * 0.75, g * 0.75, b * 0.75, 1.0];
+        let painted = calculate_colors(&vertices, &indices, &normals, &colors);
+        assert_eq!(painted[0], shaded(gradient.low));
+        assert_eq!(painted[2], shaded(gradient.mid));
+        assert_eq!(painted[3], shaded(gradient.high));
+        let between = Vec3::from(gradient.low).lerp(Vec3::from(gradient.mid), 0.5);
+        assert!(Vec3::from(gradient.at(0.25)).abs_diff_eq(between, 1e-6));
+
+        // Flipped faces still stand out, and other modes ignore the height
+        let mut flipped = colors.into_owned();
+        flipped.flipped = Some(FLIPPED_COLOR);
+        assert_eq!(
+            flipped.shade_corner(Vec3::NEG_Y, 10.0),
+            [1.0, 0.0, 1.0, 1.0]
+        );
+        let solid = RenderMode::Solid.colors(&slope_colors, &gradient);
+        assert!(solid
+            .with_heights(point_bounds(&vertices))
+            .elevation
+            .is_none());
+    }
+
+    #[test]
+    fn test_flipped_faces() {
+        let mut colors = SlopeColors::default();
+        let down = Vec3::NEG_Y;
+        // Off by default, a face pointing down counts as a steep one
+        assert_eq!(colors.band(down), colors.bands.last().unwrap());
+        assert_ne!(colors.shade(down), [1.0, 0.0, 1.0, 1.0]);
+        colors.flipped = Some(FLIPPED_COLOR);
+        assert_eq!(colors.shade(down), [1.0, 0.0, 1.0, 1.0]);
+        assert_eq!(colors.shade(Vec3::Y), SlopeColors::default().shade(Vec3::Y));
+        assert_eq!(colors.shade(Vec3::X), SlopeColors::default().shade(Vec3::X));
+    }
+
+    #[test]
+    fn test_injected_actor_messages() {
+        let mut app = App::new();
+        app.add_event::<net::ActorMessage>()
+            .init_resource::<actors::ActorMap>()
+            .init_resource::<actors::ActorInbox>()
+            .init_resource::<message_log::MessageLog>()
+            .insert_resource(coords::CoordinateTransform {
+                preset: coords::CoordinatePreset::ZUp,
+                scale: 1.0,
+            })
+            .insert_resource(actors::ActorAssets {
+                mesh: Handle::default(),
+                material: Handle::default(),
+            })
+            .add_systems(
+                Update,
+                (
+                    queue_actor_messages,
+                    actors::apply_actor_messages.after(queue_actor_messages),
+                ),
+            );
+        let mitm_info = Arc::new(Mutex::new(MitmInfo::new(
+            "127.0.0.1:9999",
+            net::MAX_FRAME_LEN,
+        )));
+        app.world.spawn(test_viewer(mitm_info.clone()));
+
+        // No socket, the messages take the same path as the network's
+        app.world.send_event_batch([
+            net::ActorMessage::Spawn(net::ActorSpawn::new(
+                "enemy1".to_string(),
+                "goblin".to_string(),
+                net::Vector3::new(1.0, 2.0, 3.0),
+            )),
+            net::ActorMessage::Position(net::ActorPosition::new(
+                "enemy1".to_string(),
+                net::Vector3::new(4.0, 5.0, 6.0),
+            )),
+        ]);
+        app.update();
+
+        let entity = app.world.resource::<actors::ActorMap>().0["enemy1"];
+        assert_eq!(
+            app.world.get::<Transform>(entity).unwrap().translation,
+            Vec3::new(1.0, 3.0, -2.0)
+        );
+        assert_eq!(app.world.resource::<message_log::MessageLog>().len(), 2);
+        assert_eq!(
+            mitm_info.lock().unwrap().followed_position(),
+            Some(Vec3::new(4.0, 6.0, -5.0))
+        );
+    }
+
+    #[test]
+    fn test_follow_watched_actor() {
+        let mut mitm = MitmInfo::new("127.0.0.1:9999", net::MAX_FRAME_LEN);
+        mitm.watch("player1".to_string());
+        mitm.watch("enemy2".to_string());
+        mitm.update_position("player1", Vec3::X);
+        mitm.update_position("enemy2", Vec3::Y);
+
+        // Unset follows whoever moved last
+        assert_eq!(mitm.followed_position(), Some(Vec3::Y));
+        mitm.follow = Some("player1".to_string());
+        assert_eq!(mitm.followed_position(), Some(Vec3::X));
+
+        mitm.unwatch("player1");
+        assert_eq!(mitm.follow, None);
+        assert!(!mitm.positions.contains_key("player1"));
+        assert_eq!(mitm.followed_position(), Some(Vec3::Y));
+        assert_eq!(mitm.watched.iter().collect::<Vec<_>>(), vec!["enemy2"]);
+    }
+
+    #[test]
+    fn test_watch_ids_replace_watching_everything() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        let mut mitm = MitmInfo::new("127.0.0.1:9999", net::MAX_FRAME_LEN);
+        mitm.socket = Some(client);
+        mitm.watch("player1".to_string());
+        mitm.watch("enemy2".to_string());
+        mitm.unwatch("player1");
+        mitm.unwatch("enemy2");
+
+        let expected: [&[u8]; 6] = [
+            b"\x03",
+            b"\x01player1",
+            b"\x01enemy2",
+            b"\x03player1",
+            b"\x03enemy2",
+            b"\x01",
+        ];
+        for payload in expected {
+            assert_eq!(net::read_frame(&mut server).unwrap(), payload);
+        }
+    }
+
+    #[test]
+    fn test_paused_updates_are_held_in_order() {
+        let despawn = |id: &str| net::ActorMessage::Despawn(net::ActorDespawn::new(id.to_string()));
+        let mut mitm = MitmInfo::new("127.0.0.1:9999", net::MAX_FRAME_LEN);
+        mitm.paused = true;
+        for id in ["a", "b"] {
+            let mut messages = vec![despawn(id)];
+            mitm.hold_messages(&mut messages);
+            assert!(messages.is_empty());
+        }
+        assert_eq!(mitm.held.len(), 2);
+
+        // Resuming sends the held batch ahead of the frame's own messages
+        mitm.paused = false;
+        let mut messages = vec![despawn("c")];
+        mitm.hold_messages(&mut messages);
+        let ids: Vec<&str> = messages.iter().map(|message| message.id()).collect();
+        assert_eq!(ids, vec!["a", "b", "c"]);
+        assert!(mitm.held.is_empty());
+    }
+
+    #[test]
+    fn test_held_moves_are_coalesced() {
+        let position = |id: &str, x: f32| {
+            net::ActorMessage::Position(net::ActorPosition::new(
+                id.to_string(),
+                net::Vector3::new(x, 0.0, 0.0),
+            ))
+        };
+        let despawn = |id: &str| net::ActorMessage::Despawn(net::ActorDespawn::new(id.to_string()));
+        let mut mitm = MitmInfo::new("127.0.0.1:9999", net::MAX_FRAME_LEN);
+        mitm.paused = true;
+        let mut messages = vec![
+            position("a", 1.0),
+            despawn("b"),
+            position("a", 2.0),
+            despawn("a"),
+            position("a", 3.0),
+            position("a", 4.0),
+        ];
+        mitm.hold_messages(&mut messages);
+        assert_eq!(
+            mitm.held,
+            vec![
+                position("a", 2.0),
+                despawn("b"),
+                despawn("a"),
+                position("a", 4.0)
+            ]
+        );
+
+        // A new connection starts without what the old one left behind
+        mitm.disconnect("connection closed".to_string());
+        assert!(mitm.held.is_empty());
+    }
+
+    #[test]
+    fn test_exit_closes_mitm_socket() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        let mut app = App::new();
+        app.add_event::<AppExit>()
+            .add_systems(Last, close_mitm_on_exit);
+        let mut mitm = MitmInfo::new("127.0.0.1:9999", net::MAX_FRAME_LEN);
+        mitm.socket = Some(client);
+        mitm.reconnect = true;
+        mitm.watched.insert("player1".to_string());
+        let mitm_info = Arc::new(Mutex::new(mitm));
+        app.world.spawn(test_viewer(mitm_info.clone()));
+
+        // Nothing happens until the app exits
+        app.update();
+        assert!(mitm_info.lock().unwrap().socket.is_some());
+        app.world.send_event(AppExit);
+        app.update();
+
+        let mitm = mitm_info.lock().unwrap();
+        assert!(mitm.socket.is_none());
+        assert!(!mitm.reconnect);
+        assert_eq!(net::read_frame(&mut server).unwrap(), b"\x03player1");
+        let mut rest = Vec::new();
+        std::io::Read::read_to_end(&mut server, &mut rest).unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn test_double_sided_toggle() {
+        let mut app = App::new();
+        app.init_resource::<Assets<StandardMaterial>>()
+            .init_resource::<RenderSettings>()
+            .add_systems(Update, apply_culling);
+
+        let material = app
+            .world
+            .resource_mut::<Assets<StandardMaterial>>()
+            .add(StandardMaterial::default());
+        app.world.spawn((
+            TileMesh {
+                tile_x: 0,
+                tile_y: 0,
+                aabb: Aabb::from_min_max(Vec3::ZERO, Vec3::ONE),
+            },
+            material.clone(),
+        ));
+        let cull_mode = |app: &App| {
+            let materials = app.world.resource::<Assets<StandardMaterial>>();
+            materials.get(&material).unwrap().cull_mode
+        };
+
+        app.update();
+        assert_eq!(cull_mode(&app), Some(Face::Back));
+
+        app.world.resource_mut::<RenderSettings>().double_sided = true;
+        app.update();
+        assert_eq!(cull_mode(&app), None);
+
+        app.world.resource_mut::<RenderSettings>().double_sided = false;
+        app.update();
+        assert_eq!(cull_mode(&app), Some(Face::Back));
+    }
+
+    #[test]
+    fn test_lit_toggle() {
+        let mut app = App::new();
+        app.init_resource::<Assets<StandardMaterial>>()
+            .init_resource::<RenderSettings>()
+            .add_systems(Update, apply_lighting);
+
+        let mut materials = app.world.resource_mut::<Assets<StandardMaterial>>();
+        let unlit = || StandardMaterial {
+            unlit: true,
+            ..default()
+        };
+        let material = materials.add(unlit());
+        let highlight = materials.add(unlit());
+        let tile = || TileMesh {
+            tile_x: 0,
+            tile_y: 0,
+            aabb: Aabb::from_min_max(Vec3::ZERO, Vec3::ONE),
+        };
+        app.world.spawn((tile(), material.clone()));
+        app.world.spawn((
+            tile(),
+            highlight.clone(),
+            HighlightedTile {
+                original: material.clone(),
+            },
+        ));
+        let is_unlit = |app: &App, handle| {
+            let materials = app.world.resource::<Assets<StandardMaterial>>();
+            materials.get(handle).unwrap().unlit
+        };
+
+        app.update();
+        assert!(is_unlit(&app, &material));
+
+        app.world.resource_mut::<RenderSettings>().lit = true;
+        app.update();
+        assert!(!is_unlit(&app, &material));
+        assert!(is_unlit(&app, &highlight));
+
+        // Lighting a tile needs its normals, it gets the ones it was shaded by
+        let vertices = [Vec3::ZERO, Vec3::Z, Vec3::X];
+        let geometry = build_tile(0, 0, &[0], &vertices, &[0, 1, 2], &[Vec3::Y; 3], &[]);
+        let mesh = build_colored_mesh(geometry, &SlopeColors::default());
+        let normals = mesh
+            .attribute(Mesh::ATTRIBUTE_NORMAL)
+            .and_then(|normals| normals.as_float3())
+            .unwrap();
+        assert_eq!(normals, [[0.0, 1.0, 0.0]; 3]);
+
+        app.world.resource_mut::<RenderSettings>().lit = false;
+        app.update();
+        assert!(is_unlit(&app, &material));
+    }
+
+    #[test]
+    fn test_save_screenshot() {
+        let path =
+            std::env::temp_dir().join(format!("mesh_viewer_{}_screenshot.png", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert!(save_screenshot(Image::default(), &path).is_ok());
+        assert!(path.exists());
+
+        // A failed write is reported, not taken for a saved file
+        let missing = std::env::temp_dir().join("mesh_viewer_no_such_dir/screenshot.png");
+        assert!(save_screenshot(Image::default(), &missing).is_err());
+    }
+
+    #[test]
+    fn test_wireframe_toggle() {
+        let mut app = App::new();
+        app.init_resource::<RenderMode>()
+            .add_systems(Update, apply_wireframe);
+
+        let tile = app
+            .world
+            .spawn(TileMesh {
+                tile_x: 0,
+                tile_y: 0,
+                aabb: Aabb::from_min_max(Vec3::ZERO, Vec3::ONE),
+            })
+            .id();
+        let debug_mesh = app.world.spawn(DebugMesh).id();
+        let other = app.world.spawn_empty().id();
+        let has_wireframe = |app: &App, entity| app.world.get::<Wireframe>(entity).is_some();
+
+        *app.world.resource_mut::<RenderMode>() = RenderMode::SlopeColorPlusWireframe;
+        app.update();
+        assert!(has_wireframe(&app, tile));
+        assert!(has_wireframe(&app, debug_mesh));
+        assert!(!has_wireframe(&app, other));
+
+        *app.world.resource_mut::<RenderMode>() = RenderMode::SlopeColor;
+        app.update();
+        assert!(!has_wireframe(&app, tile));
+        assert!(!has_wireframe(&app, debug_mesh));
+    }
+
+    #[test]
+    #[should_panic(expected = "one normal per vertex")]
+    fn test_vertex_attribute_lengths_are_checked() {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        insert_vertex_attributes(
+            &mut mesh,
+            vec![Vec3::ZERO; 4],
+            vec![Vec3::Y; 6],
+            vec![[1.0; 4]; 4],
+            Vec::new(),
+        );
+    }
+
+    #[test]
+    fn test_obj_watcher_debounces_writes() {
+        let start = Instant::now();
+        let time = |ms| start + Duration::from_millis(ms);
+        let modified = |secs| Some(UNIX_EPOCH + Duration::from_secs(secs));
+
+        let mut watcher = ObjWatcher::default();
+        watcher.watch(PathBuf::from("level.obj"), modified(1));
+        assert!(!watcher.update(modified(1), time(0)));
+
+        // Writes in quick succession reload once, after the last one settled
+        assert!(!watcher.update(modified(2), time(100)));
+        assert!(!watcher.update(modified(3), time(400)));
+        assert!(!watcher.update(modified(3), time(800)));
+        assert!(watcher.update(modified(3), time(900)));
+        assert!(!watcher.update(modified(3), time(2000)));
+    }
+
+    #[test]
+    fn test_mesh_stats_follow_slope_angle() {
+        let mut app = App::new();
+        app.init_resource::<MeshStatsPanel>()
+            .add_systems(Update, update_mesh_stats);
+
+        // A floor and a 60 degree ramp
+        let vertices = vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(-0.5, 3.0_f32.sqrt() / 2.0, 0.0),
+        ];
+        app.world.insert_resource(MeshData {
+            bounds: point_bounds(&vertices),
+            normals: vec![Vec3::Y; vertices.len()],
+            vertices,
+            indices: vec![0, 1, 2, 3, 5, 4],
+            uvs: Vec::new(),
+            tile_size: 988.0,
+            material_groups: Vec::new(),
+            file_vertices: Vec::new(),
+        });
+        let viewer = app
+            .world
+            .spawn(test_viewer(Arc::new(Mutex::new(MitmInfo::new(
+                "127.0.0.1:9999",
+                net::MAX_FRAME_LEN,
+            )))))
+            .id();
+        let walkable = |app: &App| {
+            let stats = app
+                .world
+                .resource::<MeshStatsPanel>()
+                .stats
+                .clone()
+                .unwrap();
+            (stats.triangle_count, stats.walkable_triangles)
+        };
+
+        app.update();
+        assert_eq!(walkable(&app), (2, 1));
+
+        app.world
+            .get_mut::<MeshViewer>(viewer)
+            .unwrap()
+            .walkable_slope_angle = 70.0;
+        app.update();
+        assert_eq!(walkable(&app), (2, 2));
+    }
+
+    #[test]
+    fn test_screen_axes() {
+        // Looking down -Z, X points right and Y up (down in egui)
+        let [x, y, z] = screen_axes(Quat::IDENTITY);
+        assert_eq!(x, (Vec2::X, 0.0));
+        assert_eq!(y, (Vec2::new(0.0, -1.0), 0.0));
+        assert_eq!(z, (Vec2::ZERO, 1.0));
+
+        // Turned to look down -X, the Z axis points left
+        let [x, _, z] = screen_axes(Quat::from_rotation_y(std::f32::consts::FRAC_PI_2));
+        assert!((z.0 + Vec2::X).length() < 1e-6);
+        assert!((x.1 - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_single_mesh_toggle() {
+        // Two triangles in different tiles
+        let vertices = vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(10.0, 0.0, 0.0),
+            Vec3::new(10.0, 0.0, 1.0),
+            Vec3::new(11.0, 0.0, 0.0),
+        ];
+        let mesh_data = MeshData {
+            bounds: point_bounds(&vertices),
+            normals: vec![Vec3::Y; vertices.len()],
+            vertices,
+            indices: vec![0, 1, 2, 3, 4, 5],
+            uvs: Vec::new(),
+            tile_size: 5.0,
+            material_groups: vec![(Handle::default(), vec![0, 1, 2, 3, 4, 5])],
+            file_vertices: Vec::new(),
+        };
+
+        let mut world = World::new();
+        world.init_resource::<Assets<Mesh>>();
+        world.init_resource::<tiling::IncrementalTiler>();
+        world.insert_resource(mesh_data);
+        let spawn = |world: &mut World, single_mesh| {
+            world.run_system_once(
+                move |mut commands: Commands,
+                      mut meshes: ResMut<Assets<Mesh>>,
+                      mut tiler: ResMut<tiling::IncrementalTiler>,
+                      mesh_data: Res<MeshData>| {
+                    let tile_settings = TileSettings {
+                        single_mesh,
+                        ..default()
+                    };
+                    spawn_mesh_entities(
+                        &mut commands,
+                        &mut meshes,
+                        &mut tiler,
+                        &mesh_data,
+                        &tile_settings,
+                        &RenderSettings::default(),
+                        &SlopeColors::default(),
+                        layers::PRIMARY_LAYER,
+                        None,
+                    );
+                },
+            );
+            let tiles = world.query::<&TileMesh>().iter(world).count();
+            let merged = world.query::<&MergedMesh>().iter(world).count();
+            world.clear_entities();
+            (tiles, merged)
+        };
+
+        assert_eq!(spawn(&mut world, false), (2, 0));
+        assert_eq!(spawn(&mut world, true), (0, 1));
+    }
+
+    #[test]
+    fn test_flat_shading_splits_corners() {
+        // A floor triangle and a wall triangle sharing an edge
+        let vertices = vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ];
+        let indices = vec![0, 1, 2, 0, 3, 1];
+        let uvs = vec![Vec2::ZERO, Vec2::X, Vec2::Y, Vec2::ONE];
+        let (flat_vertices, flat_indices, normals, flat_uvs) =
+            flat_shaded(&vertices, &indices, &uvs);
+
+        assert_eq!(flat_vertices.len(), 6);
+        assert_eq!(flat_indices, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(normals, [[Vec3::Y; 3], [Vec3::X; 3]].concat());
+        assert_eq!(flat_uvs[4], Vec2::ONE);
+
+        // The shared corners now color each face by its own slope
+        let colors = calculate_colors(
+            &flat_vertices,
+            &flat_indices,
+            &normals,
+            &SlopeColors::default(),
+        );
+        assert_ne!(colors[0], colors[3]);
+    }
+
+    #[test]
+    fn test_merge_triangles_keeps_used_vertices() {
+        let vertices = vec![Vec3::X, Vec3::Y, Vec3::Z, Vec3::ONE];
+        let normals = vec![Vec3::Y; 4];
+        let geometry = merge_triangles(&vertices, &[3, 1, 2], &normals, &[]);
+        assert_eq!(geometry.vertices, vec![Vec3::ONE, Vec3::Y, Vec3::Z]);
+        assert_eq!(geometry.indices, vec![0, 1, 2]);
+        assert!(geometry.uvs.is_empty());
+    }
+
+    #[test]
+    fn test_welding_keeps_slope_colors_apart() {
+        // Two triangles on a shared edge, the first colored from a floor
+        // normal and the second from a wall one
+        let vertices = vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 1.0),
+        ];
+        let normals = vec![Vec3::Y, Vec3::X, Vec3::Y, Vec3::Y];
+        let indices = vec![0, 2, 1, 1, 2, 3];
+        let slope_colors = SlopeColors::default();
+        let floor = slope_colors.shade(Vec3::Y);
+        let wall = slope_colors.shade(Vec3::X);
+        assert_ne!(floor, wall);
+
+        let check = |geometry: &TileGeometry| {
+            let colors = calculate_colors(
+                &geometry.vertices,
+                &geometry.indices,
+                &geometry.normals,
+                &slope_colors,
+            );
+            for (triangle, expected) in geometry.indices.chunks(3).zip([floor, wall]) {
+                for &corner in triangle {
+                    assert_eq!(colors[corner as usize], expected);
+                }
+            }
+        };
+        let tiles = split_mesh_into_tiles(&vertices, &indices, &normals, &[], 100.0);
+        assert_eq!(tiles.len(), 1);
+        check(&tiles[0]);
+        check(&merge_triangles(&vertices, &indices, &normals, &[]));
+    }
+
+    // A bumpy grid of `size` x `size` quads, one unit each
+    fn test_terrain(size: u32) -> (Vec<Vec3>, Vec<u32>, Vec<Vec3>) {
+        let mut vertices = Vec::new();
+        for z in 0..=size {
+            for x in 0..=size {
+                let height = ((x as f32) * 0.3).sin() * ((z as f32) * 0.2).cos();
+                vertices.push(Vec3::new(x as f32, height, z as f32));
+            }
+        }
+        let mut indices = Vec::new();
+        for z in 0..size {
+            for x in 0..size {
+                let i = z * (size + 1) + x;
+                indices.extend([i, i + size + 1, i + 1, i + 1, i + size + 1, i + size + 2]);
+            }
+        }
+        let normals = vec![Vec3::Y; vertices.len()];
+        (vertices, indices, normals)
+    }
+
+    fn assert_same_tiles(a: &[TileGeometry], b: &[TileGeometry]) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b) {
+            assert_eq!((a.tile_x, a.tile_z), (b.tile_x, b.tile_z));
+            assert_eq!(a.vertices, b.vertices);
+            assert_eq!(a.indices, b.indices);
+        }
+    }
+
+    #[test]
+    fn test_split_mesh_is_deterministic() {
+        let (vertices, indices, normals) = test_terrain(40);
+        let serial = TaskPoolBuilder::new().num_threads(1).build();
+        let parallel = TaskPoolBuilder::new().num_threads(4).build();
+
+        let a = split_mesh_into_tiles_on(&serial, &vertices, &indices, &normals, &[], 7.0);
+        let b = split_mesh_into_tiles_on(&parallel, &vertices, &indices, &normals, &[], 7.0);
+        assert_same_tiles(&a, &b);
+
+        // Sorted by tile coordinates
+        let keys: Vec<_> = a.iter().map(|t| (t.tile_x, t.tile_z)).collect();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+    }
+
+    // cargo test --release bench_split_mesh -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_split_mesh() {
+        // 512 x 512 quads is just over 500k triangles
+        let (vertices, indices, normals) = test_terrain(512);
+        let serial = TaskPoolBuilder::new().num_threads(1).build();
+        let parallel = TaskPool::new();
+
+        let start = std::time::Instant::now();
+        let a = split_mesh_into_tiles_on(&serial, &vertices, &indices, &normals, &[], 32.0);
+        let serial_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let b = split_mesh_into_tiles_on(&parallel, &vertices, &indices, &normals, &[], 32.0);
+        let parallel_time = start.elapsed();
+
+        assert_same_tiles(&a, &b);
+        println!(
+            "{} triangles into {} tiles: serial {:?}, parallel {:?} ({} threads)",
+            indices.len() / 3,
+            a.len(),
+            serial_time,
+            parallel_time,
+            parallel.thread_num()
+        );
+    }
+
+    #[test]
+    fn test_split_mesh_clamps_tile_size() {
+        let vertices = vec![
+            Vec3::ZERO,
+            Vec3::new(0.0, 0.0, 0.5),
+            Vec3::new(0.5, 0.0, 0.0),
+        ];
+        let normals = vec![Vec3::Y; 3];
+
+        // Zero and negative sizes fall back to the minimum instead of dividing by zero
+        for tile_size in [0.0, -10.0] {
+            let tiles = split_mesh_into_tiles(&vertices, &[0, 1, 2], &normals, &[], tile_size);
+            assert_eq!(tiles.len(), 1);
+            assert_eq!((tiles[0].tile_x, tiles[0].tile_z), (0, 0));
+        }
+    }
+
+    #[test]
+    fn test_face_normal_walkability() {
+        // Flat floor and a 60 degree ramp rising along X
+        let slope = 60.0_f32.to_radians();
+        let vertices = vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 1.0),
+            Vec3::new(2.0, slope.tan(), 0.0),
+        ];
+        let indices = vec![0, 1, 2, 2, 3, 4];
+
+        let floor = face_normal(&vertices, &indices, 0);
+        assert!((floor - Vec3::Y).length() < 1e-5);
+        assert!(is_walkable(floor, 45.0));
+
+        let ramp = face_normal(&vertices, &indices, 1);
+        assert!((ramp.y.acos().to_degrees() - 60.0).abs() < 1e-3);
+        assert!(!is_walkable(ramp, 45.0));
+        assert!(is_walkable(ramp, 61.0));
+    }
+
+    #[test]
+    fn test_extract_walkable_mesh() {
+        // A floor, a 45 degree ramp and a wall, each on its own vertices
+        let vertices = vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(2.0, 0.0, 1.0),
+            Vec3::new(3.0, 1.0, 0.0),
+            Vec3::new(5.0, 0.0, 0.0),
+            Vec3::new(5.0, 1.0, 0.0),
+            Vec3::new(5.0, 0.0, 1.0),
+        ];
+        let indices = vec![0, 1, 2, 3, 4, 5, 6, 7, 8];
+        let normals: Vec<Vec3> = (0..3)
+            .flat_map(|triangle| [face_normal(&vertices, &indices, triangle); 3])
+            .collect();
+
+        // Only perfectly flat ground at 0 degrees
+        assert_eq!(
+            classify_walkable(&normals, 0.0),
+            [[true; 3], [false; 3], [false; 3]].concat()
+        );
+        let (flat_vertices, flat_indices) =
+            extract_walkable_mesh(&vertices, &indices, &normals, 0.0);
+        assert_eq!(flat_vertices, vertices[..3]);
+        assert_eq!(flat_indices, vec![0, 1, 2]);
+
+        // The ramp just makes it at 45, and keeps its corners renumbered
+        let (ramp_vertices, ramp_indices) =
+            extract_walkable_mesh(&vertices, &indices, &normals, 45.0 + 1e-3);
+        assert_eq!(ramp_vertices, vertices[..6]);
+        assert_eq!(ramp_indices, vec![0, 1, 2, 3, 4, 5]);
+        let (_, steep_indices) = extract_walkable_mesh(&vertices, &indices, &normals, 44.0);
+        assert_eq!(steep_indices, vec![0, 1, 2]);
+
+        // The same triangles `calculate_colors` shades with the walkable band
+        let mut slope_colors = SlopeColors::default();
+        slope_colors.set_walkable_slope_angle(45.0 + 1e-3);
+        let colors = calculate_colors(&vertices, &indices, &normals, &slope_colors);
+        let walkable_band: Vec<bool> = (0..3)
+            .map(|triangle| {
+                let normal = normals[triangle * 3];
+                colors[triangle * 3] == slope_colors.shade_in(normal, &slope_colors.bands[0])
+            })
+            .collect();
+        assert_eq!(walkable_band, [true, true, false]);
+
+        // Walls too at 90
+        let (all_vertices, all_indices) =
+            extract_walkable_mesh(&vertices, &indices, &normals, 90.0);
+        assert_eq!(all_vertices, vertices);
+        assert_eq!(all_indices, indices);
+
+        // Nothing left, nothing returned
+        let (none_vertices, none_indices) =
+            extract_walkable_mesh(&vertices, &indices[3..], &normals, 0.0);
+        assert!(none_vertices.is_empty() && none_indices.is_empty());
+    }
+
+    #[test]
+    fn test_tex_coords_follow_positions() {
+        let obj = load_test_obj(
+            "tex_coords",
+            "\
+v 0 0 0
+v 1 0 0
+v 1 0 1
+v 0 0 1
+vt 0 0
+vt 1 0
+vt 1 1
+f 1/1 3/3 2/2
+f 1 4 3",
+        );
+
+        let (vertices, indices, normals, uvs) =
+            convert_obj_to_mesh_data(&obj, NormalWeighting::Area, ImportOptions::default());
+        assert_eq!(uvs.len(), vertices.len());
+        assert_eq!(normals.len(), vertices.len());
+
+        // Textured corners carry their coordinate, the untextured face falls back to zero
+        assert_eq!(uvs[indices[1] as usize], Vec2::new(1.0, 1.0));
+        assert_eq!(uvs[indices[2] as usize], Vec2::new(1.0, 0.0));
+        assert_eq!(uvs[indices[4] as usize], Vec2::ZERO);
+        assert_eq!(vertices[indices[1] as usize], vertices[indices[5] as usize]);
+    }
+}
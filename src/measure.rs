@@ -0,0 +1,178 @@
+use crate::debug_draw_b::{du_debug_draw_measure, GizmoDebugDraw};
+use crate::{layers, section, transformed_bounds, world, MainCamera, MeshData};
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use bevy_egui::EguiContexts;
+
+pub const CLEAR_KEY: KeyCode = KeyCode::Delete;
+
+const MEASURE_COLOR: Vec4 = Vec4::new(1.0, 0.9, 0.1, 1.0);
+
+// Distance between two points picked on the mesh
+#[derive(Resource)]
+pub struct MeasureTool {
+    pub enabled: bool,
+    pub snap: bool,     // Snap picked points to the XZ grid
+    pub grid_step: f32, // Grid spacing in world units
+    pub points: Vec<Vec3>,
+}
+
+impl Default for MeasureTool {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            snap: false,
+            grid_step: 1.0,
+            points: Vec::new(),
+        }
+    }
+}
+
+impl MeasureTool {
+    // The click after a finished measurement starts a new one
+    pub fn add_point(&mut self, point: Vec3) {
+        if self.points.len() >= 2 {
+            self.points.clear();
+        }
+        self.points.push(point);
+    }
+
+    // 3D and horizontal distance, once both points are picked
+    pub fn distances(&self) -> Option<(f32, f32)> {
+        let [a, b] = self.points[..] else {
+            return None;
+        };
+        let horizontal = Vec2::new(b.x - a.x, b.z - a.z).length();
+        Some((a.distance(b), horizontal))
+    }
+}
+
+// Round X and Z to the nearest multiple of `step`, Y is left alone
+pub fn snap_to_grid(point: Vec3, step: f32) -> Vec3 {
+    if step <= 0.0 {
+        return point;
+    }
+    Vec3::new(
+        (point.x / step).round() * step,
+        point.y,
+        (point.z / step).round() * step,
+    )
+}
+
+// Left click picks a measure point, Ctrl + Left Click still rotates the camera
+#[allow(clippy::too_many_arguments)]
+pub fn pick_measure_points(
+    mut tool: ResMut<MeasureTool>,
+    mut contexts: EguiContexts,
+    keyboard: Res<Input<KeyCode>>,
+    mouse_button: Res<Input<MouseButton>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Transform, &Camera), With<MainCamera>>,
+    mesh_data: Option<Res<MeshData>>,
+    picking_grid: Option<Res<world::TriangleGrid>>,
+    layers: Res<layers::MeshLayers>,
+    section: Res<section::SectionPlane>,
+) {
+    if keyboard.just_pressed(CLEAR_KEY) {
+        tool.points.clear();
+    }
+
+    let ctrl_pressed =
+        keyboard.pressed(KeyCode::ControlLeft) || keyboard.pressed(KeyCode::ControlRight);
+    if !tool.enabled
+        || ctrl_pressed
+        || !mouse_button.just_pressed(MouseButton::Left)
+        || contexts.ctx_mut().wants_pointer_input()
+    {
+        return;
+    }
+    let (Ok(window), Ok((transform, camera))) = (windows.get_single(), camera_query.get_single())
+    else {
+        return;
+    };
+    let Some(cursor_pos) = window.cursor_position() else {
+        return;
+    };
+
+    let (origin, direction) = world::screen_to_ray(window, camera, transform, cursor_pos);
+    let primary = mesh_data
+        .as_deref()
+        .map(|mesh_data| (mesh_data, picking_grid.as_deref()));
+    // Only what the section plane leaves standing can be picked
+    let Some((layer, mut point, ..)) = section
+        .clip_ray(origin, direction)
+        .and_then(|origin| layers.raycast(primary, origin, direction))
+    else {
+        return;
+    };
+
+    // Drop the snapped point back onto the surface below or above it, from
+    // over the top of the layer that was hit
+    if tool.snap {
+        let snapped = snap_to_grid(point, tool.grid_step);
+        let bounds = match layer {
+            layers::PRIMARY_LAYER => mesh_data.as_deref().map(|mesh_data| mesh_data.bounds),
+            _ => layers.mesh_data(layer).map(|mesh_data| mesh_data.bounds),
+        };
+        let top = bounds.map_or(point.y, |bounds| {
+            transformed_bounds(bounds, &layers.transform(layer)).1.y
+        });
+        let above = Vec3::new(snapped.x, top + 1.0, snapped.z);
+        point = section
+            .clip_ray(above, Vec3::NEG_Y)
+            .and_then(|above| layers.raycast(primary, above, Vec3::NEG_Y))
+            .map(|(_, hit, ..)| hit)
+            .unwrap_or(snapped);
+    }
+    tool.add_point(point);
+}
+
+pub fn draw_measurement(
+    mut gizmos: Gizmos,
+    tool: Res<MeasureTool>,
+    mesh_data: Option<Res<MeshData>>,
+) {
+    // Ticks scale with the mesh so they stay visible at any size
+    let tick = mesh_data
+        .map(|mesh_data| {
+            let size = mesh_data.bounds.1 - mesh_data.bounds.0;
+            size.max_element() * 0.02
+        })
+        .filter(|tick| tick.is_finite() && *tick > 0.0)
+        .unwrap_or(10.0);
+    let mut dd = GizmoDebugDraw::new(&mut gizmos);
+    du_debug_draw_measure(&mut dd, &tool.points, tick, MEASURE_COLOR, 2.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure_two_points() {
+        let mut tool = MeasureTool::default();
+        tool.add_point(Vec3::new(0.0, 0.0, 0.0));
+        assert_eq!(tool.distances(), None);
+
+        tool.add_point(Vec3::new(3.0, 12.0, 4.0));
+        assert_eq!(tool.distances(), Some((13.0, 5.0)));
+
+        // A third click starts over
+        tool.add_point(Vec3::ONE);
+        assert_eq!(tool.points, vec![Vec3::ONE]);
+        assert_eq!(tool.distances(), None);
+    }
+
+    #[test]
+    fn test_snap_to_grid() {
+        assert_eq!(
+            snap_to_grid(Vec3::new(1.4, 2.7, -3.6), 1.0),
+            Vec3::new(1.0, 2.7, -4.0)
+        );
+        assert_eq!(
+            snap_to_grid(Vec3::new(12.0, 1.0, 26.0), 5.0),
+            Vec3::new(10.0, 1.0, 25.0)
+        );
+        assert_eq!(snap_to_grid(Vec3::ONE * 0.3, 0.0), Vec3::ONE * 0.3);
+    }
+}
@@ -0,0 +1,192 @@
+use crate::net::{ActorMessage, Vector3};
+use crate::ScreenshotState;
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Older messages are dropped past this many
+pub const MESSAGE_LOG_CAPACITY: usize = 1000;
+
+pub struct LogEntry {
+    pub received: SystemTime,
+    pub message: ActorMessage,
+}
+
+// Recently decoded actor messages, shown in the network log window
+#[derive(Resource, Default)]
+pub struct MessageLog {
+    entries: VecDeque<LogEntry>,
+    pub filter: String, // Actor id or message type, empty shows everything
+}
+
+impl MessageLog {
+    pub fn push(&mut self, message: ActorMessage, received: SystemTime) {
+        if self.entries.len() >= MESSAGE_LOG_CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(LogEntry { received, message });
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    // Entries whose actor id is the filter, or whose type is, ignoring case
+    pub fn filtered(&self) -> impl Iterator<Item = &LogEntry> {
+        let filter = self.filter.trim();
+        self.entries.iter().filter(move |entry| {
+            filter.is_empty()
+                || entry.message.id() == filter
+                || kind(&entry.message).eq_ignore_ascii_case(filter)
+        })
+    }
+}
+
+pub fn kind(message: &ActorMessage) -> &'static str {
+    match message {
+        ActorMessage::Move(_) => "Move",
+        ActorMessage::Spawn(_) => "Spawn",
+        ActorMessage::Despawn(_) => "Despawn",
+        ActorMessage::Position(_) => "Position",
+    }
+}
+
+fn color(message: &ActorMessage) -> egui::Color32 {
+    match message {
+        ActorMessage::Move(_) => egui::Color32::LIGHT_BLUE,
+        ActorMessage::Spawn(_) => egui::Color32::LIGHT_GREEN,
+        ActorMessage::Despawn(_) => egui::Color32::LIGHT_RED,
+        ActorMessage::Position(_) => egui::Color32::LIGHT_GRAY,
+    }
+}
+
+fn summary(message: &ActorMessage) -> String {
+    let v = |v: &Vector3| format!("({:.1}, {:.1}, {:.1})", v.x, v.y, v.z);
+    match message {
+        ActorMessage::Move(msg) => format!("{} {} -> {}", msg.id, v(&msg.orig), v(&msg.dest)),
+        ActorMessage::Spawn(msg) => {
+            format!("{} ({}) at {}", msg.id, msg.actor_type, v(&msg.position))
+        }
+        ActorMessage::Despawn(msg) => msg.id.clone(),
+        ActorMessage::Position(msg) => format!("{} at {}", msg.id, v(&msg.pos)),
+    }
+}
+
+// Time of day the message arrived as HH:MM:SS.mmm, in UTC
+pub fn format_time_of_day(time: SystemTime) -> String {
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+        % 86_400_000;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+pub fn message_log_window(
+    mut contexts: EguiContexts,
+    mut log: ResMut<MessageLog>,
+    screenshot: Res<ScreenshotState>,
+) {
+    if screenshot.hide_ui {
+        return;
+    }
+
+    egui::Window::new("Network Log")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Filter:");
+                ui.add(
+                    egui::TextEdit::singleline(&mut log.filter)
+                        .hint_text("actor id or type")
+                        .desired_width(120.0),
+                );
+                if ui.button("Clear").clicked() {
+                    log.clear();
+                }
+            });
+            ui.label(format!("{} / {} messages", log.len(), MESSAGE_LOG_CAPACITY));
+            ui.separator();
+
+            egui::ScrollArea::vertical()
+                .max_height(300.0)
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    for entry in log.filtered() {
+                        ui.colored_label(
+                            color(&entry.message),
+                            format!(
+                                "{} {:<8} {}",
+                                format_time_of_day(entry.received),
+                                kind(&entry.message),
+                                summary(&entry.message)
+                            ),
+                        );
+                    }
+                });
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{ActorDespawn, ActorSpawn};
+    use std::time::Duration;
+
+    fn despawn(id: &str) -> ActorMessage {
+        ActorMessage::Despawn(ActorDespawn::new(id.to_string()))
+    }
+
+    #[test]
+    fn test_message_log_is_capped() {
+        let mut log = MessageLog::default();
+        for i in 0..MESSAGE_LOG_CAPACITY + 5 {
+            log.push(despawn(&format!("enemy{}", i)), UNIX_EPOCH);
+        }
+        assert_eq!(log.len(), MESSAGE_LOG_CAPACITY);
+        assert_eq!(log.filtered().next().unwrap().message.id(), "enemy5");
+    }
+
+    #[test]
+    fn test_message_log_filter() {
+        let mut log = MessageLog::default();
+        log.push(despawn("enemy1"), UNIX_EPOCH);
+        log.push(
+            ActorMessage::Spawn(ActorSpawn::new(
+                "enemy2".to_string(),
+                "goblin".to_string(),
+                Vector3::new(0.0, 0.0, 0.0),
+            )),
+            UNIX_EPOCH,
+        );
+        log.push(despawn("enemy10"), UNIX_EPOCH);
+        let ids = |log: &MessageLog| -> Vec<String> {
+            log.filtered()
+                .map(|entry| entry.message.id().to_string())
+                .collect()
+        };
+
+        assert_eq!(ids(&log).len(), 3);
+        log.filter = "enemy1".to_string();
+        assert_eq!(ids(&log), vec!["enemy1"]);
+        log.filter = " despawn ".to_string();
+        assert_eq!(ids(&log), vec!["enemy1", "enemy10"]);
+    }
+
+    #[test]
+    fn test_format_time_of_day() {
+        let time = UNIX_EPOCH + Duration::from_millis(3 * 86_400_000 + 13 * 3_600_000 + 5_007);
+        assert_eq!(format_time_of_day(time), "13:00:05.007");
+    }
+}
@@ -0,0 +1,339 @@
+use crate::{
+    build_colored_mesh, layers, merge_triangles, transformed_bounds, world, MainCamera, MeshData,
+    ScreenshotState, SlopeColors,
+};
+use bevy::core_pipeline::clear_color::ClearColorConfig;
+use bevy::prelude::*;
+use bevy::render::camera::{RenderTarget, ScalingMode};
+use bevy::render::render_resource::{
+    Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+};
+use bevy::render::view::RenderLayers;
+use bevy_egui::{egui, EguiContexts};
+
+pub const TOGGLE_KEY: KeyCode = KeyCode::M;
+
+// Only the minimap camera sees this layer, the main view keeps layer 0
+const MINIMAP_RENDER_LAYER: u8 = 1;
+const MIN_MINIMAP_SIZE: f32 = 64.0; // Points
+const CAMERA_MARKER_RADIUS: f32 = 4.0;
+
+// Top-down orthographic view of the primary mesh, rendered into an image the
+// minimap window shows
+#[derive(Resource, Default)]
+pub struct Minimap {
+    pub open: bool,
+    image: Handle<Image>,
+    view: Option<MinimapView>, // Area of the world in the image, None without a mesh
+}
+
+// The part of the XZ plane the minimap shows, the image top is towards -Z
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MinimapView {
+    pub center: Vec2, // World X and Z
+    pub size: Vec2,   // Width along X and height along Z
+}
+
+impl MinimapView {
+    // Smallest view centered on the bounds that covers them at the image's
+    // aspect ratio (width / height)
+    pub fn fit((min, max): (Vec3, Vec3), aspect: f32) -> Self {
+        let extent = Vec2::new(max.x - min.x, max.z - min.z).max(Vec2::ONE);
+        let size = if extent.x / extent.y > aspect {
+            Vec2::new(extent.x, extent.x / aspect)
+        } else {
+            Vec2::new(extent.y * aspect, extent.y)
+        };
+        Self {
+            center: Vec2::new(min.x + max.x, min.z + max.z) * 0.5,
+            size,
+        }
+    }
+
+    // Position in the image from 0 to 1, from the top left to the bottom right
+    pub fn to_uv(self, world: Vec3) -> Vec2 {
+        (Vec2::new(world.x, world.z) - self.center) / self.size + 0.5
+    }
+
+    // World X and Z at a position in the image
+    pub fn to_world(self, uv: Vec2) -> Vec2 {
+        self.center + (uv - 0.5) * self.size
+    }
+}
+
+#[derive(Component)]
+pub struct MinimapCamera;
+
+// The copy of the mesh the minimap camera renders. The main view culls the
+// tiles to its own frustum, so the minimap can't draw those
+#[derive(Component)]
+pub struct MinimapMesh;
+
+fn minimap_image(width: u32, height: u32) -> Image {
+    let size = Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: Some("minimap"),
+            size,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Bgra8UnormSrgb,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        },
+        ..default()
+    };
+    image.resize(size);
+    image
+}
+
+pub fn setup_minimap(
+    mut commands: Commands,
+    mut minimap: ResMut<Minimap>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let image = images.add(minimap_image(256, 256));
+    commands.spawn((
+        Camera3dBundle {
+            camera: Camera {
+                order: -1,
+                target: RenderTarget::Image(image.clone()),
+                is_active: false,
+                ..default()
+            },
+            camera_3d: Camera3d {
+                clear_color: ClearColorConfig::Custom(Color::rgb(0.05, 0.05, 0.05)),
+                ..default()
+            },
+            ..default()
+        },
+        RenderLayers::layer(MINIMAP_RENDER_LAYER),
+        MinimapCamera,
+    ));
+    minimap.image = image;
+}
+
+// Keep the minimap mesh in step with the primary mesh and its colors, and the
+// camera over the middle of it. Nothing is rendered while the minimap is closed
+#[allow(clippy::too_many_arguments)]
+pub fn update_minimap(
+    mut commands: Commands,
+    mut minimap: ResMut<Minimap>,
+    mesh_data: Option<Res<MeshData>>,
+    slope_colors: Res<SlopeColors>,
+    layers: Res<layers::MeshLayers>,
+    images: Res<Assets<Image>>,
+    minimap_meshes: Query<Entity, With<MinimapMesh>>,
+    mut cameras: Query<(&mut Camera, &mut Transform, &mut Projection), With<MinimapCamera>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut built_with: Local<Option<SlopeColors>>, // Colors of the minimap mesh shown
+) {
+    let Ok((mut camera, mut transform, mut projection)) = cameras.get_single_mut() else {
+        return;
+    };
+    let mesh_data = mesh_data.filter(|_| minimap.open);
+    camera.is_active = mesh_data.is_some();
+    let Some(mesh_data) = mesh_data else {
+        minimap.view = None;
+        return;
+    };
+
+    if mesh_data.is_changed() || built_with.as_ref() != Some(&*slope_colors) {
+        for entity in minimap_meshes.iter() {
+            commands.entity(entity).despawn();
+        }
+        let geometry = merge_triangles(
+            &mesh_data.vertices,
+            &mesh_data.indices,
+            &mesh_data.normals,
+            &[],
+        );
+        commands.spawn((
+            PbrBundle {
+                mesh: meshes.add(build_colored_mesh(geometry, &slope_colors)),
+                material: materials.add(StandardMaterial {
+                    base_color: Color::WHITE,
+                    unlit: true,
+                    cull_mode: None,
+                    ..default()
+                }),
+                ..default()
+            },
+            MinimapMesh,
+            RenderLayers::layer(MINIMAP_RENDER_LAYER),
+            layers::MeshLayerId(layers::PRIMARY_LAYER),
+        ));
+        *built_with = Some(slope_colors.clone());
+    }
+
+    let aspect = images
+        .get(&minimap.image)
+        .map_or(1.0, |image| image.aspect_ratio());
+    let bounds = transformed_bounds(mesh_data.bounds, &layers.transform(layers::PRIMARY_LAYER));
+    let view = MinimapView::fit(bounds, aspect);
+    minimap.view = Some(view);
+
+    // Straight down from above the highest point, with -Z at the top
+    let (min, max) = bounds;
+    let margin = 1.0 + (max.y - min.y) * 0.1;
+    *transform = Transform::from_xyz(view.center.x, max.y + margin, view.center.y)
+        .looking_to(Vec3::NEG_Y, Vec3::NEG_Z);
+    *projection = Projection::Orthographic(OrthographicProjection {
+        near: 0.0,
+        far: max.y - min.y + margin * 2.0,
+        scaling_mode: ScalingMode::Fixed {
+            width: view.size.x,
+            height: view.size.y,
+        },
+        ..default()
+    });
+}
+
+// Where the corners of the main view meet the ground at `height`, in the order
+// bottom left, bottom right, top right, top left. Corners looking above the
+// horizon are cut off `max_distance` away
+pub fn view_footprint(
+    camera: &Camera,
+    camera_transform: &Transform,
+    height: f32,
+    max_distance: f32,
+) -> [Vec3; 4] {
+    [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].map(|(x, y)| {
+        let (origin, direction) = world::ndc_to_ray(camera, camera_transform, Vec2::new(x, y));
+        world::ray_plane_intersection(origin, direction, height)
+            .filter(|point| point.distance(origin) <= max_distance)
+            .unwrap_or_else(|| {
+                let flat = Vec3::new(direction.x, 0.0, direction.z).normalize_or_zero();
+                Vec3::new(origin.x, height, origin.z) + flat * max_distance
+            })
+    })
+}
+
+pub fn minimap_window(
+    mut contexts: EguiContexts,
+    keyboard: Res<Input<KeyCode>>,
+    mut minimap: ResMut<Minimap>,
+    mut images: ResMut<Assets<Image>>,
+    pick_plane: Res<world::PickPlane>,
+    mut main_camera: Query<(&mut Transform, &mut MainCamera, &Camera)>,
+    screenshot: Res<ScreenshotState>,
+) {
+    if keyboard.just_pressed(TOGGLE_KEY) && !contexts.ctx_mut().wants_keyboard_input() {
+        minimap.open = !minimap.open;
+    }
+    if screenshot.hide_ui || !minimap.open {
+        return;
+    }
+
+    let texture = contexts.add_image(minimap.image.clone_weak());
+    let mut open = minimap.open;
+    egui::Window::new("Minimap")
+        .open(&mut open)
+        .resizable(true)
+        .default_size([256.0, 256.0])
+        .show(contexts.ctx_mut(), |ui| {
+            let Some(view) = minimap.view else {
+                ui.label("No mesh loaded");
+                return;
+            };
+
+            // Follow the window size, in pixels so the image stays sharp
+            let size = ui.available_size().max(egui::Vec2::splat(MIN_MINIMAP_SIZE));
+            let pixels = (size * ui.ctx().pixels_per_point()).round();
+            let (width, height) = (pixels.x as u32, pixels.y as u32);
+            if let Some(image) = images.get(&minimap.image) {
+                if image.width() != width || image.height() != height {
+                    if let Some(image) = images.get_mut(&minimap.image) {
+                        image.resize(Extent3d {
+                            width,
+                            height,
+                            depth_or_array_layers: 1,
+                        });
+                    }
+                }
+            }
+
+            let response = ui.add(
+                egui::Image::new(egui::load::SizedTexture::new(texture, size))
+                    .sense(egui::Sense::click()),
+            );
+            let rect = response.rect;
+            let to_screen = |world: Vec3| {
+                let uv = view.to_uv(world);
+                rect.min + egui::vec2(uv.x, uv.y) * rect.size()
+            };
+
+            let Ok((mut transform, mut camera, camera_comp)) = main_camera.get_single_mut() else {
+                return;
+            };
+
+            // Ground the main view covers, and where the camera is looking from
+            let painter = ui.painter_at(rect);
+            let footprint = view_footprint(
+                camera_comp,
+                &transform,
+                pick_plane.height,
+                view.size.length(),
+            );
+            painter.add(egui::Shape::closed_line(
+                footprint.iter().map(|&corner| to_screen(corner)).collect(),
+                egui::Stroke::new(1.5, egui::Color32::YELLOW),
+            ));
+            painter.circle_filled(
+                to_screen(transform.translation),
+                CAMERA_MARKER_RADIUS,
+                egui::Color32::RED,
+            );
+
+            // Move the camera over the clicked spot at the same height
+            if let Some(pointer) = response
+                .interact_pointer_pos()
+                .filter(|_| response.clicked())
+            {
+                let uv = (pointer - rect.min) / rect.size();
+                let target = view.to_world(Vec2::new(uv.x, uv.y));
+                let offset = Vec3::new(
+                    target.x - transform.translation.x,
+                    0.0,
+                    target.y - transform.translation.z,
+                );
+                transform.translation += offset;
+                camera.focus += offset;
+            }
+        });
+    minimap.open &= open;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minimap_view_mapping() {
+        let bounds = (Vec3::new(-10.0, 0.0, 0.0), Vec3::new(10.0, 5.0, 10.0));
+
+        // A wide mesh in a square image leaves room above and below
+        let view = MinimapView::fit(bounds, 1.0);
+        assert_eq!(view.center, Vec2::new(0.0, 5.0));
+        assert_eq!(view.size, Vec2::new(20.0, 20.0));
+
+        // In a tall image too, and the width still covers the mesh
+        let view = MinimapView::fit(bounds, 0.5);
+        assert_eq!(view.size, Vec2::new(20.0, 40.0));
+
+        // Top left is -X -Z
+        let view = MinimapView::fit(bounds, 2.0);
+        assert_eq!(view.size, Vec2::new(20.0, 10.0));
+        assert_eq!(view.to_uv(Vec3::new(-10.0, 3.0, 0.0)), Vec2::ZERO);
+        assert_eq!(view.to_uv(Vec3::new(10.0, 0.0, 10.0)), Vec2::ONE);
+        assert_eq!(view.to_world(Vec2::new(0.75, 0.5)), Vec2::new(5.0, 5.0));
+    }
+}
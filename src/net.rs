@@ -1,9 +1,55 @@
 use crate::MitmInfo;
+use bevy::ecs::event::Event;
+use bevy::log::warn;
+use bevy::tasks::IoTaskPool;
 use serde::{Deserialize, Serialize};
-use std::io::Read;
-use std::sync::Arc;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::{Duration, Instant};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// Protocol version spoken by this client, sent in the handshake frame
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// Single byte commands, sent as a length-prefixed frame. The watch command
+// also picks how the server encodes actor messages, see `WireFormat`
+pub const CMD_WATCH_MITM: u8 = 1;
+pub const CMD_WATCH_MITM_BINARY: u8 = 2;
+// Followed by an actor id, stops the updates a watch command started for it.
+// Without an id it stops the watch of every actor
+pub const CMD_STOP_WATCH: u8 = 3;
+
+// First payload byte of a binary actor message, JSON payloads start with `{`
+pub const BINARY_FORMAT_TAG: u8 = 0xB1;
+
+// Message type byte after `BINARY_FORMAT_TAG`
+const BINARY_MOVE: u8 = 1;
+const BINARY_SPAWN: u8 = 2;
+const BINARY_DESPAWN: u8 = 3;
+const BINARY_POSITION: u8 = 4;
+
+// How long to wait for the server to answer the handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// How long the stop commands may take to go out when the viewer closes
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(500);
+
+// Reconnect delays start here and double up to the maximum
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_millis(250);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+// Default limit on a frame's length, no actor message comes close to this.
+// A larger length means the stream is out of step, so the connection is
+// dropped rather than read on. Settable with `max_frame_len` in the settings
+pub const MAX_FRAME_LEN: u32 = 1 << 20;
+
+// Messages handled per frame, the rest wait in the socket buffer
+pub const MAX_MESSAGES_PER_FRAME: usize = 512;
+
+// Binary: x, y and z as big endian f32
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Vector3 {
     pub x: f32,
     pub y: f32,
@@ -16,7 +62,8 @@ impl Vector3 {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// Binary type 1: id, orig, dest
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ActorMove {
     pub id: String,
     pub orig: Vector3,
@@ -29,7 +76,8 @@ impl ActorMove {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// Binary type 2: id, type, position
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ActorSpawn {
     pub id: String,
     #[serde(rename = "type")]
@@ -47,7 +95,8 @@ impl ActorSpawn {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// Binary type 3: id
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ActorDespawn {
     pub id: String,
 }
@@ -58,13 +107,697 @@ impl ActorDespawn {
     }
 }
 
-// Define an enum to handle all possible message types
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// Position update for the watched actor. Binary type 4: id, pos
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActorPosition {
+    pub id: String,
+    pub pos: Vector3,
+}
+
+impl ActorPosition {
+    pub fn new(id: String, pos: Vector3) -> Self {
+        Self { id, pos }
+    }
+}
+
+// Define an enum to handle all possible message types. Each frame holds one
+// message, either as JSON tagged with `message_type` or, after
+// `CMD_WATCH_MITM_BINARY`, as `BINARY_FORMAT_TAG`, the type byte and the
+// fields in order. Binary strings are a big endian u16 byte count and UTF-8.
+// Also a Bevy event, see `queue_actor_messages`
+#[derive(Event, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "message_type")]
 pub enum ActorMessage {
     Move(ActorMove),
     Spawn(ActorSpawn),
     Despawn(ActorDespawn),
+    Position(ActorPosition),
+}
+
+impl ActorMessage {
+    // Actor the message is about
+    pub fn id(&self) -> &str {
+        match self {
+            ActorMessage::Move(msg) => &msg.id,
+            ActorMessage::Spawn(msg) => &msg.id,
+            ActorMessage::Despawn(msg) => &msg.id,
+            ActorMessage::Position(msg) => &msg.id,
+        }
+    }
+
+    // Apply `f` to every position the message carries
+    pub fn map_positions(self, f: impl Fn(Vector3) -> Vector3) -> Self {
+        match self {
+            ActorMessage::Move(msg) => ActorMessage::Move(ActorMove {
+                orig: f(msg.orig),
+                dest: f(msg.dest),
+                ..msg
+            }),
+            ActorMessage::Spawn(msg) => ActorMessage::Spawn(ActorSpawn {
+                position: f(msg.position),
+                ..msg
+            }),
+            ActorMessage::Despawn(msg) => ActorMessage::Despawn(msg),
+            ActorMessage::Position(msg) => ActorMessage::Position(ActorPosition {
+                pos: f(msg.pos),
+                ..msg
+            }),
+        }
+    }
+}
+
+// First frame sent by the client after connecting
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Handshake {
+    pub version: u32,
+    pub features: Vec<String>,
+}
+
+impl Handshake {
+    pub fn new(version: u32, features: Vec<String>) -> Self {
+        Self { version, features }
+    }
+}
+
+// Server answer to the handshake, `version` is the negotiated protocol version
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HandshakeReply {
+    pub accepted: bool,
+    pub version: u32,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+// Replies come from the server, only tests stand in for it
+#[cfg(test)]
+impl HandshakeReply {
+    pub fn new(accepted: bool, version: u32, reason: Option<String>) -> Self {
+        Self {
+            accepted,
+            version,
+            reason,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HandshakeError {
+    IoError(io::Error),
+    ParseError(String),
+    Rejected(String),
+    WatchFailed(io::Error), // Handshake done, but the watch commands didn't go out
+    SocketSetup(io::Error), // Couldn't switch the socket to non-blocking reads
+}
+
+impl From<io::Error> for HandshakeError {
+    fn from(error: io::Error) -> Self {
+        HandshakeError::IoError(error)
+    }
+}
+
+impl std::fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HandshakeError::IoError(e) => write!(f, "io error: {}", e),
+            HandshakeError::ParseError(e) => write!(f, "invalid handshake reply: {}", e),
+            HandshakeError::Rejected(reason) => write!(f, "server rejected handshake: {}", reason),
+            HandshakeError::WatchFailed(e) => write!(f, "failed to send watch command: {}", e),
+            HandshakeError::SocketSetup(e) => write!(f, "failed to set up socket: {}", e),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum NetError {
+    IoError(io::Error),
+    Disconnected,
+    FrameTooLarge { len: u32, max: u32 },
+    Utf8Error,
+    ParseError(String),
+}
+
+impl From<io::Error> for NetError {
+    fn from(error: io::Error) -> Self {
+        NetError::IoError(error)
+    }
+}
+
+impl std::fmt::Display for NetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetError::IoError(e) => write!(f, "io error: {}", e),
+            NetError::Disconnected => write!(f, "connection closed"),
+            NetError::FrameTooLarge { len, max } => {
+                write!(f, "frame of {} bytes exceeds the {} byte limit", len, max)
+            }
+            NetError::Utf8Error => write!(f, "frame is not valid utf-8, skipped"),
+            NetError::ParseError(e) => write!(f, "invalid actor message, skipped: {}", e),
+        }
+    }
+}
+
+// Incoming bytes from a non-blocking socket. Frames can arrive in pieces, so
+// bytes are kept across reads until a whole frame is available
+pub struct FrameReader {
+    buf: Vec<u8>,
+    max_len: u32,                        // Longer frames end the connection
+    pub recorder: Option<FrameRecorder>, // Gets a copy of every whole frame
+}
+
+impl Default for FrameReader {
+    fn default() -> Self {
+        Self::new(MAX_FRAME_LEN)
+    }
+}
+
+impl FrameReader {
+    pub fn new(max_len: u32) -> Self {
+        Self {
+            buf: Vec::new(),
+            max_len,
+            recorder: None,
+        }
+    }
+
+    // Forget buffered bytes from an old connection, recording carries on
+    pub fn clear(&mut self) {
+        self.buf.clear();
+    }
+
+    // Pull whatever the reader has without blocking, then decode the next
+    // frame if one is complete. Frames that don't decode are consumed and
+    // reported as errors, so the next call continues with the following
+    // frame. An oversized frame can't be stepped over, it stays an error
+    pub fn read_message<R: Read>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<Option<ActorMessage>, NetError> {
+        if let Some(frame) = self.next_frame()? {
+            return decode_message(&frame).map(Some);
+        }
+
+        // Frames that arrived just before the stream closed are still read
+        let mut chunk = [0u8; 4096];
+        let mut closed = false;
+        loop {
+            match reader.read(&mut chunk) {
+                Ok(0) => {
+                    closed = true;
+                    break;
+                }
+                Ok(n) => self.push(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        match self.next_frame()? {
+            Some(frame) => decode_message(&frame).map(Some),
+            None if closed => Err(NetError::Disconnected),
+            None => Ok(None),
+        }
+    }
+
+    // Decode up to `max` complete frames into `messages`, a partial frame at
+    // the end stays buffered. Frames that can't be decoded are skipped with a
+    // warning, a failed socket or an oversized frame ends the batch after what
+    // was decoded before it
+    pub fn read_messages<R: Read>(
+        &mut self,
+        reader: &mut R,
+        max: usize,
+        messages: &mut Vec<ActorMessage>,
+    ) -> Result<(), NetError> {
+        for _ in 0..max {
+            match self.read_message(reader) {
+                Ok(Some(message)) => messages.push(message),
+                Ok(None) => break,
+                Err(
+                    e @ (NetError::IoError(_)
+                    | NetError::Disconnected
+                    | NetError::FrameTooLarge { .. }),
+                ) => return Err(e),
+                Err(e) => warn!("{}", e),
+            }
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn next_frame(&mut self) -> Result<Option<Vec<u8>>, NetError> {
+        if self.buf.len() < 4 {
+            return Ok(None);
+        }
+
+        let len = u32::from_be_bytes([self.buf[0], self.buf[1], self.buf[2], self.buf[3]]);
+        if len > self.max_len {
+            return Err(NetError::FrameTooLarge {
+                len,
+                max: self.max_len,
+            });
+        }
+
+        let end = 4 + len as usize;
+        if self.buf.len() < end {
+            return Ok(None);
+        }
+
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(e) = recorder.record(&self.buf[4..end]) {
+                warn!("Stopped recording to {}: {}", recorder.path.display(), e);
+                self.recorder = None;
+            }
+        }
+        let frame = self.buf[4..end].to_vec();
+        self.buf.drain(..end);
+        Ok(Some(frame))
+    }
+}
+
+// Actor messages that have arrived on the watch socket, at most `max` so a
+// backlog can't stall a frame. The caller holds the `MitmInfo` lock
+pub fn try_read_batch(
+    mitm_info: &mut MitmInfo,
+    max: usize,
+    messages: &mut Vec<ActorMessage>,
+) -> Result<(), NetError> {
+    let Some(socket) = mitm_info.socket.as_mut() else {
+        return Ok(());
+    };
+
+    mitm_info.reader.read_messages(socket, max, messages)
+}
+
+// Encoding of actor messages, chosen by the client's watch command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WireFormat {
+    #[default]
+    Json,
+    Binary, // Smaller, for high-frequency moves
+}
+
+impl WireFormat {
+    pub fn watch_command(self) -> u8 {
+        match self {
+            WireFormat::Json => CMD_WATCH_MITM,
+            WireFormat::Binary => CMD_WATCH_MITM_BINARY,
+        }
+    }
+}
+
+// Watch a single actor: the watch command followed by the actor id
+pub fn watch_actor_command(format: WireFormat, id: &str) -> Vec<u8> {
+    let mut payload = vec![format.watch_command()];
+    payload.extend_from_slice(id.as_bytes());
+    payload
+}
+
+pub fn stop_watch_command(id: &str) -> Vec<u8> {
+    let mut payload = vec![CMD_STOP_WATCH];
+    payload.extend_from_slice(id.as_bytes());
+    payload
+}
+
+// One watch command per actor, or the bare command watching everything when
+// no actor is picked
+pub fn send_watch_commands<W: Write>(
+    writer: &mut W,
+    format: WireFormat,
+    watched: &[String],
+) -> io::Result<()> {
+    if watched.is_empty() {
+        return write_frame(writer, &[format.watch_command()]);
+    }
+    for id in watched {
+        write_frame(writer, &watch_actor_command(format, id))?;
+    }
+    Ok(())
+}
+
+// Undo `send_watch_commands`: a stop command per actor, or the bare one when
+// everything was watched
+pub fn send_stop_commands<W: Write>(writer: &mut W, watched: &[String]) -> io::Result<()> {
+    if watched.is_empty() {
+        return write_frame(writer, &[CMD_STOP_WATCH]);
+    }
+    for id in watched {
+        write_frame(writer, &stop_watch_command(id))?;
+    }
+    Ok(())
+}
+
+// Stop the watches and close the socket, so the server doesn't keep them
+// going for a viewer that is gone. The socket is shut down even when the stop
+// commands fail
+pub fn close_connection(mut socket: TcpStream, watched: &[String]) -> io::Result<()> {
+    let sent = socket
+        .set_nonblocking(false)
+        .and_then(|_| socket.set_write_timeout(Some(SHUTDOWN_TIMEOUT)))
+        .and_then(|_| send_stop_commands(&mut socket, watched));
+    let closed = socket.shutdown(std::net::Shutdown::Both);
+    sent.and(closed)
+}
+
+// Payload of a frame holding `message`. The viewer only decodes, this stands
+// in for the server in tests
+#[cfg(test)]
+pub fn encode_message(message: &ActorMessage, format: WireFormat) -> Vec<u8> {
+    match format {
+        WireFormat::Json => serde_json::to_vec(message).expect("actor messages serialize"),
+        WireFormat::Binary => encode_binary(message),
+    }
+}
+
+// The first byte tells the encoding apart, so both can be read without
+// knowing which one was asked for
+pub fn decode_message(frame: &[u8]) -> Result<ActorMessage, NetError> {
+    if let Some((&BINARY_FORMAT_TAG, body)) = frame.split_first() {
+        return decode_binary(body);
+    }
+    let text = std::str::from_utf8(frame).map_err(|_| NetError::Utf8Error)?;
+    serde_json::from_str(text).map_err(|e| NetError::ParseError(e.to_string()))
+}
+
+#[cfg(test)]
+fn encode_binary(message: &ActorMessage) -> Vec<u8> {
+    fn string(out: &mut Vec<u8>, s: &str) {
+        // Longer strings are cut on a char boundary, no id comes close
+        let mut len = s.len().min(u16::MAX as usize);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        let bytes = &s.as_bytes()[..len];
+        out.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+        out.extend_from_slice(bytes);
+    }
+    fn vector3(out: &mut Vec<u8>, v: &Vector3) {
+        for c in [v.x, v.y, v.z] {
+            out.extend_from_slice(&c.to_be_bytes());
+        }
+    }
+
+    let mut out = vec![BINARY_FORMAT_TAG];
+    match message {
+        ActorMessage::Move(msg) => {
+            out.push(BINARY_MOVE);
+            string(&mut out, &msg.id);
+            vector3(&mut out, &msg.orig);
+            vector3(&mut out, &msg.dest);
+        }
+        ActorMessage::Spawn(msg) => {
+            out.push(BINARY_SPAWN);
+            string(&mut out, &msg.id);
+            string(&mut out, &msg.actor_type);
+            vector3(&mut out, &msg.position);
+        }
+        ActorMessage::Despawn(msg) => {
+            out.push(BINARY_DESPAWN);
+            string(&mut out, &msg.id);
+        }
+        ActorMessage::Position(msg) => {
+            out.push(BINARY_POSITION);
+            string(&mut out, &msg.id);
+            vector3(&mut out, &msg.pos);
+        }
+    }
+    out
+}
+
+// Reads the fields of a binary message front to back
+struct BinaryReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> BinaryReader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], NetError> {
+        if self.bytes.len() < n {
+            return Err(NetError::ParseError(
+                "binary message ends early".to_string(),
+            ));
+        }
+        let (head, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, NetError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn f32(&mut self) -> Result<f32, NetError> {
+        let bytes = self.take(4)?;
+        Ok(f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn string(&mut self) -> Result<String, NetError> {
+        let len = self.take(2)?;
+        let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| NetError::Utf8Error)
+    }
+
+    fn vector3(&mut self) -> Result<Vector3, NetError> {
+        Ok(Vector3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+}
+
+fn decode_binary(body: &[u8]) -> Result<ActorMessage, NetError> {
+    let mut reader = BinaryReader { bytes: body };
+    let message = match reader.u8()? {
+        BINARY_MOVE => ActorMessage::Move(ActorMove::new(
+            reader.string()?,
+            reader.vector3()?,
+            reader.vector3()?,
+        )),
+        BINARY_SPAWN => ActorMessage::Spawn(ActorSpawn::new(
+            reader.string()?,
+            reader.string()?,
+            reader.vector3()?,
+        )),
+        BINARY_DESPAWN => ActorMessage::Despawn(ActorDespawn::new(reader.string()?)),
+        BINARY_POSITION => {
+            ActorMessage::Position(ActorPosition::new(reader.string()?, reader.vector3()?))
+        }
+        other => {
+            return Err(NetError::ParseError(format!(
+                "unknown binary message type {}",
+                other
+            )))
+        }
+    };
+    if !reader.bytes.is_empty() {
+        return Err(NetError::ParseError(format!(
+            "{} bytes after binary message",
+            reader.bytes.len()
+        )));
+    }
+    Ok(message)
+}
+
+// Log of received frames, each written as it came: milliseconds since the
+// recording started as a big endian u64, then the frame as on the wire
+pub struct FrameRecorder {
+    out: BufWriter<File>,
+    started: Instant,
+    pub path: PathBuf,
+}
+
+impl FrameRecorder {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(Self {
+            out: BufWriter::new(file),
+            started: Instant::now(),
+            path: path.to_path_buf(),
+        })
+    }
+
+    // Flushed per frame so a crash keeps everything up to it
+    fn record(&mut self, frame: &[u8]) -> io::Result<()> {
+        let millis = self.started.elapsed().as_millis() as u64;
+        self.out.write_all(&millis.to_be_bytes())?;
+        write_frame(&mut self.out, frame)?;
+        self.out.flush()
+    }
+}
+
+// Every frame of a recorded log with the time it arrived. A log cut off part
+// way through an entry, by a crash while recording, keeps the whole entries
+pub fn read_frame_log<R: Read>(reader: &mut R) -> io::Result<Vec<(Duration, Vec<u8>)>> {
+    let mut frames = Vec::new();
+    loop {
+        let mut millis = [0u8; 8];
+        let entry = reader
+            .read_exact(&mut millis)
+            .and_then(|_| read_frame(reader));
+        match entry {
+            Ok(frame) => {
+                frames.push((Duration::from_millis(u64::from_be_bytes(millis)), frame));
+            }
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(frames),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+pub fn load_frame_log(path: &Path) -> io::Result<Vec<(Duration, Vec<u8>)>> {
+    read_frame_log(&mut io::BufReader::new(File::open(path)?))
+}
+
+// Write a frame: 4 byte big endian length followed by the payload
+pub fn write_frame<W: Write>(writer: &mut W, payload: &[u8]) -> io::Result<()> {
+    writer.write_all(&(payload.len() as u32).to_be_bytes())?;
+    writer.write_all(payload)
+}
+
+// Blocking read of a single frame of at most `MAX_FRAME_LEN` bytes
+pub fn read_frame<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    read_frame_limited(reader, MAX_FRAME_LEN)
+}
+
+// A longer length is an `InvalidData` error before anything is allocated,
+// a corrupt or hostile prefix can claim up to 4 GB. There is no telling
+// where the next frame starts after it, so callers give up on the stream
+pub fn read_frame_limited<R: Read>(reader: &mut R, max_len: u32) -> io::Result<Vec<u8>> {
+    let mut lbuf = [0u8; 4];
+    reader.read_exact(&mut lbuf)?;
+
+    let len = u32::from_be_bytes(lbuf);
+    if len > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes exceeds {} bytes", len, max_len),
+        ));
+    }
+    let mut buf = vec![0u8; len as usize];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+// Send our version and features, and wait for the server to pick a version
+pub fn handshake<S: Read + Write>(socket: &mut S) -> Result<u32, HandshakeError> {
+    let hello = Handshake::new(PROTOCOL_VERSION, vec!["watch".to_string()]);
+    let payload =
+        serde_json::to_vec(&hello).map_err(|e| HandshakeError::ParseError(e.to_string()))?;
+    write_frame(socket, &payload)?;
+
+    let reply = read_frame(socket)?;
+    let reply: HandshakeReply =
+        serde_json::from_slice(&reply).map_err(|e| HandshakeError::ParseError(e.to_string()))?;
+
+    if !reply.accepted {
+        let reason = reply
+            .reason
+            .unwrap_or_else(|| format!("version {} not supported", PROTOCOL_VERSION));
+        return Err(HandshakeError::Rejected(reason));
+    }
+
+    Ok(reply.version)
+}
+
+// Connect, negotiate the protocol version and start watching the actors with
+// messages in `format`, returns the non-blocking socket and the negotiated
+// version
+pub fn connect(
+    addr: &str,
+    format: WireFormat,
+    watched: &[String],
+) -> Result<(TcpStream, u32), HandshakeError> {
+    let mut socket = TcpStream::connect(addr)?;
+    socket.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+
+    // A half-open socket is never handed out, it is closed on any failure
+    let result = handshake(&mut socket).and_then(|version| {
+        start_watching(&mut socket, format, watched)?;
+        socket
+            .set_read_timeout(None)
+            .and_then(|_| socket.set_nonblocking(true))
+            .map_err(HandshakeError::SocketSetup)?;
+        Ok(version)
+    });
+    match result {
+        Ok(version) => Ok((socket, version)),
+        Err(e) => {
+            let _ = socket.shutdown(std::net::Shutdown::Both);
+            Err(e)
+        }
+    }
+}
+
+// Send the watch commands after the handshake
+pub fn start_watching<W: Write>(
+    socket: &mut W,
+    format: WireFormat,
+    watched: &[String],
+) -> Result<(), HandshakeError> {
+    send_watch_commands(socket, format, watched)
+        .and_then(|_| socket.flush())
+        .map_err(HandshakeError::WatchFailed)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Disconnected,
+    Connecting,
+    Connected,
+}
+
+// `connect` running on the IO task pool so the UI keeps drawing meanwhile
+pub struct PendingConnect {
+    receiver: Receiver<Result<(TcpStream, u32), HandshakeError>>,
+}
+
+impl PendingConnect {
+    pub fn poll(&self) -> Option<Result<(TcpStream, u32), HandshakeError>> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+pub fn connect_in_background(
+    addr: String,
+    format: WireFormat,
+    watched: Vec<String>,
+) -> PendingConnect {
+    let (sender, receiver) = channel();
+    IoTaskPool::get()
+        .spawn(async move {
+            let _ = sender.send(connect(&addr, format, &watched));
+        })
+        .detach();
+    PendingConnect { receiver }
+}
+
+// Exponential backoff between reconnect attempts
+pub struct Backoff {
+    next: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            next: INITIAL_RECONNECT_DELAY,
+        }
+    }
+}
+
+impl Backoff {
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(MAX_RECONNECT_DELAY);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.next = INITIAL_RECONNECT_DELAY;
+    }
 }
 
 #[cfg(test)]
@@ -124,46 +857,486 @@ mod tests {
             panic!("Wrong message type after deserialization");
         }
     }
-}
 
-pub fn try_read(mitm_info: &mut Arc<MitmInfo>) {
-    let a = Arc::get_mut(mitm_info).unwrap();
-    if (*a).socket.is_none() {
-        return;
+    #[test]
+    fn test_position_message() {
+        let position_msg = ActorMessage::Position(ActorPosition::new(
+            "player1".to_string(),
+            Vector3::new(1.0, 2.0, 3.0),
+        ));
+
+        let json = serde_json::to_string(&position_msg).unwrap();
+        println!("Position message: {}", json);
+
+        let deserialized: ActorMessage = serde_json::from_str(&json).unwrap();
+        if let ActorMessage::Position(msg) = deserialized {
+            assert_eq!(msg.id, "player1");
+            assert_eq!((msg.pos.x, msg.pos.y, msg.pos.z), (1.0, 2.0, 3.0));
+        } else {
+            panic!("Wrong message type after deserialization");
+        }
     }
 
-    let socket = (*a).socket.as_mut().unwrap();
-    let mut lbuf = [0u8; 4];
-    let len = socket.peek(&mut lbuf);
+    #[test]
+    fn test_handshake_round_trip() {
+        let hello = Handshake::new(PROTOCOL_VERSION, vec!["watch".to_string()]);
+
+        let json = serde_json::to_string(&hello).unwrap();
+        let deserialized: Handshake = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.version, PROTOCOL_VERSION);
+        assert_eq!(deserialized.features, vec!["watch".to_string()]);
+
+        let reply = HandshakeReply::new(false, 0, Some("too old".to_string()));
+        let json = serde_json::to_string(&reply).unwrap();
+        let deserialized: HandshakeReply = serde_json::from_str(&json).unwrap();
+        assert!(!deserialized.accepted);
+        assert_eq!(deserialized.reason.as_deref(), Some("too old"));
+
+        // reason is optional on the wire
+        let deserialized: HandshakeReply =
+            serde_json::from_str(r#"{"accepted":true,"version":1}"#).unwrap();
+        assert!(deserialized.accepted);
+        assert!(deserialized.reason.is_none());
+    }
+
+    #[test]
+    fn test_frame_round_trip() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, &[CMD_WATCH_MITM]).unwrap();
+        assert_eq!(buf, vec![0, 0, 0, 1, 1]);
 
-    match len {
-        Ok(4) => {}
-        Ok(_) => return,
-        Err(_) => return,
+        let payload = read_frame(&mut buf.as_slice()).unwrap();
+        assert_eq!(payload, vec![CMD_WATCH_MITM]);
     }
 
-    let _res = socket.read_exact(&mut lbuf);
+    #[test]
+    fn test_watch_commands() {
+        // Nothing picked watches everything, as before
+        let mut buf = Vec::new();
+        send_watch_commands(&mut buf, WireFormat::Json, &[]).unwrap();
+        assert_eq!(buf, vec![0, 0, 0, 1, 1]);
 
-    //println!("res {:?} {:?}", res, lbuf);
+        let mut buf = Vec::new();
+        let watched = ["player1".to_string(), "enemy2".to_string()];
+        send_watch_commands(&mut buf, WireFormat::Binary, &watched).unwrap();
+        let mut reader = buf.as_slice();
+        assert_eq!(read_frame(&mut reader).unwrap(), b"\x02player1");
+        assert_eq!(read_frame(&mut reader).unwrap(), b"\x02enemy2");
+        assert!(reader.is_empty());
 
-    let count = u32::from_be_bytes(lbuf);
-    let mut buf = Vec::with_capacity(count as usize);
-    buf.resize(count as usize, 0);
-    //println!("reading {:?}", count);
+        assert_eq!(stop_watch_command("enemy2"), b"\x03enemy2");
 
-    let _res = socket.read_exact(&mut buf);
-    //println!("reading {:?}", buf);
+        let mut buf = Vec::new();
+        send_stop_commands(&mut buf, &[]).unwrap();
+        assert_eq!(buf, vec![0, 0, 0, 1, 3]);
+        let mut buf = Vec::new();
+        send_stop_commands(&mut buf, &watched).unwrap();
+        let mut reader = buf.as_slice();
+        assert_eq!(read_frame(&mut reader).unwrap(), b"\x03player1");
+        assert_eq!(read_frame(&mut reader).unwrap(), b"\x03enemy2");
+    }
 
-    let text = std::str::from_utf8(&buf).unwrap();
+    #[test]
+    fn test_close_connection_stops_watching() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.set_nonblocking(true).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
 
-    //println!("read something {}", text);
+        close_connection(client, &["player1".to_string()]).unwrap();
+        assert_eq!(read_frame(&mut server).unwrap(), b"\x03player1");
+        let mut rest = Vec::new();
+        server.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+    }
 
-    let message: ActorMessage = serde_json::from_str(&text).unwrap();
+    // In-memory stand-in for the socket: reads come from `input`, writes go to `output`
+    struct FakeSocket {
+        input: std::io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
 
-    // Handle different message types
-    match message {
-        ActorMessage::Move(msg) => println!("Actor {} is moving", msg.id),
-        ActorMessage::Spawn(msg) => println!("Spawning {} of type {}", msg.id, msg.actor_type),
-        ActorMessage::Despawn(msg) => println!("Despawning {}", msg.id),
+    impl Read for FakeSocket {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for FakeSocket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn fake_server(reply: &HandshakeReply) -> FakeSocket {
+        let mut input = Vec::new();
+        write_frame(&mut input, &serde_json::to_vec(reply).unwrap()).unwrap();
+        FakeSocket {
+            input: std::io::Cursor::new(input),
+            output: Vec::new(),
+        }
+    }
+
+    // Accepts the handshake, then fails every write
+    struct BrokenPipe;
+
+    impl Write for BrokenPipe {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::from(io::ErrorKind::BrokenPipe))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_failed_watch_command_is_an_error() {
+        let result = start_watching(&mut BrokenPipe, WireFormat::Json, &[]);
+        let Err(e @ HandshakeError::WatchFailed(_)) = result else {
+            panic!("expected WatchFailed, got {:?}", result);
+        };
+        assert!(e.to_string().starts_with("failed to send watch command"));
+
+        let mut socket = Vec::new();
+        start_watching(&mut socket, WireFormat::Json, &[]).unwrap();
+        assert_eq!(socket, vec![0, 0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn test_handshake_negotiation() {
+        let mut socket = fake_server(&HandshakeReply::new(true, 1, None));
+        assert_eq!(handshake(&mut socket).unwrap(), 1);
+
+        // the client sent its own handshake frame first
+        let sent = read_frame(&mut socket.output.as_slice()).unwrap();
+        let sent: Handshake = serde_json::from_slice(&sent).unwrap();
+        assert_eq!(sent.version, PROTOCOL_VERSION);
+
+        let mut socket = fake_server(&HandshakeReply::new(false, 0, None));
+        match handshake(&mut socket) {
+            Err(HandshakeError::Rejected(_)) => {}
+            other => panic!("Expected rejection, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_oversized_length_is_refused() {
+        // Only the header arrives, reading nothing more than it
+        let header = u32::MAX.to_be_bytes();
+        let error = read_frame(&mut header.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("4294967295"));
+
+        // Up to the limit is fine
+        let mut bytes = framed(b"abcd");
+        assert_eq!(
+            read_frame_limited(&mut bytes.as_slice(), 4).unwrap(),
+            b"abcd"
+        );
+        bytes.extend(framed(b"abcde"));
+        let mut reader = &bytes[8..];
+        assert!(read_frame_limited(&mut reader, 4).is_err());
+
+        // A server replying with one fails the handshake
+        let mut socket = fake_server(&HandshakeReply::new(true, 1, None));
+        socket.input = io::Cursor::new((MAX_FRAME_LEN + 1).to_be_bytes().to_vec());
+        match handshake(&mut socket) {
+            Err(HandshakeError::IoError(e)) if e.kind() == io::ErrorKind::InvalidData => {}
+            other => panic!("Expected an invalid frame, got {:?}", other),
+        }
+
+        // A recording cut at a bad entry fails to load rather than allocating
+        let mut log = 5u64.to_be_bytes().to_vec();
+        log.extend(u32::MAX.to_be_bytes());
+        assert!(read_frame_log(&mut log.as_slice()).is_err());
+    }
+
+    // Non-blocking socket stand-in, hands out at most one chunk per read and
+    // WouldBlock when nothing is queued
+    struct ChunkedReader {
+        chunks: std::collections::VecDeque<Vec<u8>>,
+        closed: bool,
+    }
+
+    impl ChunkedReader {
+        fn new(chunks: Vec<Vec<u8>>) -> Self {
+            Self {
+                chunks: chunks.into(),
+                closed: false,
+            }
+        }
+    }
+
+    impl Read for ChunkedReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.chunks.pop_front() {
+                Some(mut chunk) => {
+                    let n = chunk.len().min(buf.len());
+                    buf[..n].copy_from_slice(&chunk[..n]);
+                    if n < chunk.len() {
+                        self.chunks.push_front(chunk.split_off(n));
+                    }
+                    Ok(n)
+                }
+                None if self.closed => Ok(0),
+                None => Err(io::ErrorKind::WouldBlock.into()),
+            }
+        }
+    }
+
+    fn framed(payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::new();
+        write_frame(&mut frame, payload).unwrap();
+        frame
+    }
+
+    fn despawn_frame(id: &str) -> Vec<u8> {
+        let message = ActorMessage::Despawn(ActorDespawn::new(id.to_string()));
+        framed(&serde_json::to_vec(&message).unwrap())
+    }
+
+    fn expect_despawn(result: Result<Option<ActorMessage>, NetError>, id: &str) {
+        match result {
+            Ok(Some(ActorMessage::Despawn(msg))) => assert_eq!(msg.id, id),
+            other => panic!("Expected despawn of {}, got {:?}", id, other),
+        }
+    }
+
+    fn all_messages() -> Vec<ActorMessage> {
+        vec![
+            ActorMessage::Move(ActorMove::new(
+                "player1".to_string(),
+                Vector3::new(0.0, -1.5, 2.0),
+                Vector3::new(10.0, 0.0, 1e6),
+            )),
+            ActorMessage::Spawn(ActorSpawn::new(
+                "enemy1".to_string(),
+                "göblin".to_string(),
+                Vector3::new(5.0, 0.0, 5.0),
+            )),
+            ActorMessage::Despawn(ActorDespawn::new("enemy1".to_string())),
+            ActorMessage::Position(ActorPosition::new(
+                "player1".to_string(),
+                Vector3::new(1.0, 2.0, 3.0),
+            )),
+        ]
+    }
+
+    #[test]
+    fn test_wire_format_round_trip() {
+        for format in [WireFormat::Json, WireFormat::Binary] {
+            let frames: Vec<Vec<u8>> = all_messages()
+                .iter()
+                .map(|message| framed(&encode_message(message, format)))
+                .collect();
+            let mut socket = ChunkedReader::new(vec![frames.concat()]);
+            let mut reader = FrameReader::default();
+            for expected in all_messages() {
+                assert_eq!(reader.read_message(&mut socket).unwrap(), Some(expected));
+            }
+            assert!(reader.read_message(&mut socket).unwrap().is_none());
+        }
+
+        // Binary is the smaller one
+        let message = &all_messages()[0];
+        let binary = encode_message(message, WireFormat::Binary);
+        assert_eq!(binary[0], BINARY_FORMAT_TAG);
+        assert_eq!(binary.len(), 2 + 2 + "player1".len() + 2 * 12);
+        assert!(binary.len() < encode_message(message, WireFormat::Json).len());
+        assert_eq!(WireFormat::Binary.watch_command(), CMD_WATCH_MITM_BINARY);
+
+        // Ids too long for the length prefix are cut without splitting a char
+        let id = "é".repeat(u16::MAX as usize);
+        let message = ActorMessage::Despawn(ActorDespawn::new(id.clone()));
+        match decode_message(&encode_message(&message, WireFormat::Binary)) {
+            Ok(ActorMessage::Despawn(msg)) => assert_eq!(msg.id, id[..u16::MAX as usize - 1]),
+            other => panic!("Expected a despawn, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_bad_binary_messages_are_skipped() {
+        let despawn = encode_message(
+            &ActorMessage::Despawn(ActorDespawn::new("enemy1".to_string())),
+            WireFormat::Binary,
+        );
+        let mut trailing = despawn.clone();
+        trailing.push(0);
+        let chunks = [
+            framed(&despawn[..despawn.len() - 1]),
+            framed(&[BINARY_FORMAT_TAG, 99]),
+            framed(&trailing),
+            framed(&despawn),
+        ];
+
+        let mut socket = ChunkedReader::new(vec![chunks.concat()]);
+        let mut reader = FrameReader::default();
+        for _ in 0..3 {
+            assert!(matches!(
+                reader.read_message(&mut socket),
+                Err(NetError::ParseError(_))
+            ));
+        }
+        expect_despawn(reader.read_message(&mut socket), "enemy1");
+    }
+
+    #[test]
+    fn test_read_messages_in_batches() {
+        let frames: Vec<u8> = (0..5)
+            .flat_map(|i| despawn_frame(&format!("enemy{}", i)))
+            .collect();
+        let tail = despawn_frame("enemy5");
+        let mut socket = ChunkedReader::new(vec![frames, tail[..3].to_vec()]);
+        let mut reader = FrameReader::default();
+        let ids = |messages: &[ActorMessage]| -> Vec<String> {
+            messages
+                .iter()
+                .map(|message| match message {
+                    ActorMessage::Despawn(msg) => msg.id.clone(),
+                    other => panic!("Expected despawn, got {:?}", other),
+                })
+                .collect()
+        };
+
+        // At most `max` per call, the rest stay queued
+        let mut messages = Vec::new();
+        reader.read_messages(&mut socket, 3, &mut messages).unwrap();
+        assert_eq!(ids(&messages), vec!["enemy0", "enemy1", "enemy2"]);
+
+        messages.clear();
+        reader.read_messages(&mut socket, 3, &mut messages).unwrap();
+        assert_eq!(ids(&messages), vec!["enemy3", "enemy4"]);
+
+        // The partial frame is kept until the rest of it arrives
+        messages.clear();
+        socket.chunks.push_back(tail[3..].to_vec());
+        reader.read_messages(&mut socket, 3, &mut messages).unwrap();
+        assert_eq!(ids(&messages), vec!["enemy5"]);
+
+        // Messages before a disconnect are still handed out
+        messages.clear();
+        socket.chunks.push_back(despawn_frame("enemy6"));
+        socket.closed = true;
+        assert!(matches!(
+            reader.read_messages(&mut socket, 3, &mut messages),
+            Err(NetError::Disconnected)
+        ));
+        assert_eq!(ids(&messages), vec!["enemy6"]);
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let path =
+            std::env::temp_dir().join(format!("mesh_viewer_{}_frames.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        // Frames are recorded as they are read, whole or not when they arrived
+        let frames = [despawn_frame("enemy1"), despawn_frame("enemy2")].concat();
+        let (head, tail) = frames.split_at(7);
+        let mut socket = ChunkedReader::new(vec![head.to_vec(), tail.to_vec()]);
+        let mut reader = FrameReader {
+            recorder: Some(FrameRecorder::create(&path).unwrap()),
+            ..Default::default()
+        };
+        let mut live = Vec::new();
+        reader.read_messages(&mut socket, 10, &mut live).unwrap();
+        reader.read_messages(&mut socket, 10, &mut live).unwrap();
+        drop(reader);
+
+        let log = load_frame_log(&path).unwrap();
+        assert_eq!(log.len(), 2);
+        assert!(log[0].0 <= log[1].0);
+        let replayed: Vec<ActorMessage> = log
+            .iter()
+            .map(|(_, frame)| decode_message(frame).unwrap())
+            .collect();
+        assert_eq!(replayed, live);
+        assert_eq!(replayed.len(), 2);
+
+        // A half written last entry is dropped
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.truncate(bytes.len() - 3);
+        let log = read_frame_log(&mut bytes.as_slice()).unwrap();
+        assert_eq!(log.len(), 1);
+    }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let mut backoff = Backoff::default();
+        let delays: Vec<u64> = (0..7)
+            .map(|_| backoff.next_delay().as_millis() as u64)
+            .collect();
+        assert_eq!(delays, vec![250, 500, 1000, 2000, 4000, 5000, 5000]);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), INITIAL_RECONNECT_DELAY);
+    }
+
+    #[test]
+    fn test_truncated_frames_are_buffered() {
+        let frame = despawn_frame("enemy1");
+        let (head, tail) = frame.split_at(6);
+
+        // Length prefix split in two, then the payload in a later read
+        let mut socket = ChunkedReader::new(vec![head[..2].to_vec(), head[2..].to_vec()]);
+        let mut reader = FrameReader::default();
+        assert!(reader.read_message(&mut socket).unwrap().is_none());
+
+        socket.chunks.push_back(tail.to_vec());
+        expect_despawn(reader.read_message(&mut socket), "enemy1");
+        assert!(reader.read_message(&mut socket).unwrap().is_none());
+
+        // Closing mid frame is reported, not treated as data
+        socket.chunks.push_back(head.to_vec());
+        socket.closed = true;
+        assert!(matches!(
+            reader.read_message(&mut socket),
+            Err(NetError::Disconnected)
+        ));
+    }
+
+    #[test]
+    fn test_malformed_frames_are_skipped() {
+        let mut bytes = framed(&[0xff, 0xfe, 0x00]);
+        bytes.extend(framed(b"{\"message_type\":\"Teleport\"}"));
+        let mut socket = ChunkedReader::new(vec![bytes, despawn_frame("enemy1")]);
+
+        let mut reader = FrameReader::default();
+        assert!(matches!(
+            reader.read_message(&mut socket),
+            Err(NetError::Utf8Error)
+        ));
+        assert!(matches!(
+            reader.read_message(&mut socket),
+            Err(NetError::ParseError(_))
+        ));
+        expect_despawn(reader.read_message(&mut socket), "enemy1");
+    }
+
+    #[test]
+    fn test_oversized_frame_is_fatal() {
+        // Only the header has arrived, that is enough to give up on the stream
+        let mut bytes = despawn_frame("enemy1");
+        bytes.extend(257u32.to_be_bytes());
+        let mut socket = ChunkedReader::new(vec![bytes, despawn_frame("enemy2")]);
+
+        let mut reader = FrameReader::new(256);
+        let mut messages = Vec::new();
+        assert!(matches!(
+            reader.read_messages(&mut socket, 10, &mut messages),
+            Err(NetError::FrameTooLarge { len: 257, max: 256 })
+        ));
+        assert_eq!(messages.len(), 1);
+
+        // Nothing after it is read, the next call fails the same way
+        assert!(matches!(
+            reader.read_message(&mut socket),
+            Err(NetError::FrameTooLarge { .. })
+        ));
     }
 }
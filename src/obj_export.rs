@@ -0,0 +1,121 @@
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use glam::{Vec2, Vec3};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// Write an indexed triangle mesh as OBJ. Normals and texture coordinates are
+// per vertex and only written when there is one for every vertex. Colors are
+// kept as a `# color r g b` comment after each vertex, which `load_obj` skips
+pub fn write_obj<W: Write>(
+    mut out: W,
+    vertices: &[Vec3],
+    normals: &[Vec3],
+    uvs: &[Vec2],
+    indices: &[u32],
+    colors: &[[f32; 4]],
+) -> io::Result<()> {
+    let has_normals = !normals.is_empty() && normals.len() == vertices.len();
+    let has_uvs = !uvs.is_empty() && uvs.len() == vertices.len();
+
+    writeln!(out, "# Exported by mesh_viewer")?;
+    for (i, v) in vertices.iter().enumerate() {
+        writeln!(out, "v {} {} {}", v.x, v.y, v.z)?;
+        if let Some([r, g, b, _]) = colors.get(i) {
+            writeln!(out, "# color {} {} {}", r, g, b)?;
+        }
+    }
+    if has_normals {
+        for n in normals {
+            writeln!(out, "vn {} {} {}", n.x, n.y, n.z)?;
+        }
+    }
+    if has_uvs {
+        for uv in uvs {
+            writeln!(out, "vt {} {}", uv.x, uv.y)?;
+        }
+    }
+
+    // Same winding as `indices`, OBJ indices are 1-based
+    for triangle in indices.chunks_exact(3) {
+        write!(out, "f")?;
+        for &index in triangle {
+            let index = index + 1;
+            match (has_uvs, has_normals) {
+                (true, true) => write!(out, " {0}/{0}/{0}", index)?,
+                (true, false) => write!(out, " {0}/{0}", index)?,
+                (false, true) => write!(out, " {0}//{0}", index)?,
+                (false, false) => write!(out, " {}", index)?,
+            }
+        }
+        writeln!(out)?;
+    }
+    out.flush()
+}
+
+// A .gz name gzips the file, `load_obj` reads it back either way
+pub fn save_obj(
+    path: &Path,
+    vertices: &[Vec3],
+    normals: &[Vec3],
+    uvs: &[Vec2],
+    indices: &[u32],
+    colors: &[[f32; 4]],
+) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let gzip = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("gz"));
+    if !gzip {
+        return write_obj(file, vertices, normals, uvs, indices, colors);
+    }
+    let mut encoder = GzEncoder::new(file, Compression::default());
+    write_obj(&mut encoder, vertices, normals, uvs, indices, colors)?;
+    encoder.finish()?.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj_loader::load_obj;
+
+    #[test]
+    fn test_export_round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("mesh_viewer_{}_export.obj", std::process::id()));
+
+        let vertices = vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.5, 1.0),
+            Vec3::new(0.0, 0.5, 1.0),
+        ];
+        let normals = vec![Vec3::Y; 4];
+        let indices = vec![0, 2, 1, 0, 3, 2];
+        let colors = vec![[0.25, 0.5, 0.75, 1.0]; 4];
+        save_obj(&path, &vertices, &normals, &[], &indices, &colors).unwrap();
+
+        let obj = load_obj(&path).unwrap();
+        let loaded: Vec<Vec3> = obj
+            .vertices
+            .iter()
+            .map(|v| Vec3::new(v.x, v.y, v.z))
+            .collect();
+        assert_eq!(loaded, vertices);
+        assert_eq!(obj.faces, vec![vec![0, 2, 1], vec![0, 3, 2]]);
+        assert_eq!(obj.face_normals, obj.faces);
+        assert!(obj.face_tex_coords.iter().all(Vec::is_empty));
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.contains("# color 0.25 0.5 0.75"));
+
+        // Gzipped by the name, the same mesh loads back
+        let gz_path = path.with_extension("obj.gz");
+        save_obj(&gz_path, &vertices, &normals, &[], &indices, &colors).unwrap();
+        assert!(std::fs::read(&gz_path).unwrap().starts_with(&[0x1f, 0x8b]));
+        let gz_obj = load_obj(&gz_path).unwrap();
+        assert_eq!(gz_obj.vertices.len(), obj.vertices.len());
+        assert_eq!(gz_obj.faces, obj.faces);
+    }
+}
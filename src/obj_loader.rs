@@ -1,6 +1,16 @@
+use bevy::tasks::AsyncComputeTaskPool;
+use flate2::bufread::MultiGzDecoder;
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader};
-use std::path::Path;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+
+// Group of the faces before the first `o` or `g`, and of a nameless one
+pub const UNNAMED_GROUP: &str = "unnamed";
 
 #[derive(Debug, Clone)]
 pub struct Vec3 {
@@ -9,16 +19,69 @@ pub struct Vec3 {
     pub z: f32,
 }
 
+#[derive(Debug, Clone)]
+pub struct Vec2 {
+    pub x: f32,
+    pub y: f32,
+}
+
 #[derive(Debug)]
 pub struct ObjData {
     pub vertices: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub tex_coords: Vec<Vec2>,
     pub faces: Vec<Vec<usize>>,
+    // Normal index per face corner, empty when the face has no (complete) normal indices
+    pub face_normals: Vec<Vec<usize>>,
+    // Texture coordinate index per face corner, empty like `face_normals`
+    pub face_tex_coords: Vec<Vec<usize>>,
+    // Materials from the referenced .mtl files, by name
+    pub materials: HashMap<String, ObjMaterial>,
+    // Names used by `usemtl`, in order of first use
+    pub material_names: Vec<String>,
+    // Index into `material_names` per face, None before the first `usemtl`
+    pub face_materials: Vec<Option<usize>>,
+    // Names used by `o` and `g`, in order of first use
+    pub group_names: Vec<String>,
+    // Index into `group_names` per face
+    pub face_groups: Vec<usize>,
+    // Smoothing group per face from `s`, None for `s off` and `s 0`. Faces
+    // before the first `s` line are smoothed together as group 0
+    pub face_smoothing: Vec<Option<u32>>,
+    // One-based number of each vertex's `v` line among those in the file, as
+    // faces refer to them. Only differs from the position once groups are hidden
+    pub vertex_numbers: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjMaterial {
+    pub diffuse: [f32; 3],  // Kd
+    pub ambient: [f32; 3],  // Ka
+    pub specular: [f32; 3], // Ks
+    // map_Kd, resolved relative to the .mtl file
+    pub diffuse_map: Option<PathBuf>,
 }
 
+impl Default for ObjMaterial {
+    fn default() -> Self {
+        Self {
+            diffuse: [1.0, 1.0, 1.0],
+            ambient: [0.0, 0.0, 0.0],
+            specular: [0.0, 0.0, 0.0],
+            diffuse_map: None,
+        }
+    }
+}
+
+// Line numbers are 1-based, like in a text editor
 #[derive(Debug)]
 pub enum ObjLoadError {
     IoError(io::Error),
-    ParseError(String),
+    Utf8Error { line: usize },
+    MalformedVertex { line: usize, message: String },
+    MalformedFace { line: usize, message: String },
+    MalformedMaterial { line: usize, message: String },
+    Cancelled,
 }
 
 impl From<io::Error> for ObjLoadError {
@@ -27,70 +90,642 @@ impl From<io::Error> for ObjLoadError {
     }
 }
 
+impl std::fmt::Display for ObjLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObjLoadError::IoError(e) => write!(f, "io error: {}", e),
+            ObjLoadError::Utf8Error { line } => write!(f, "line {}: invalid UTF-8", line),
+            ObjLoadError::MalformedVertex { line, message }
+            | ObjLoadError::MalformedFace { line, message }
+            | ObjLoadError::MalformedMaterial { line, message } => {
+                write!(f, "line {}: {}", line, message)
+            }
+            ObjLoadError::Cancelled => write!(f, "loading was cancelled"),
+        }
+    }
+}
+
+// Split a reader into lines, reporting invalid UTF-8 with its line number
+// instead of a generic io error. The `\r` of CRLF endings and anything after a
+// `#` are cut off, so comments can follow the data on a line
+fn read_lines<R: BufRead>(
+    reader: R,
+) -> impl Iterator<Item = Result<(usize, String), ObjLoadError>> {
+    reader.split(b'\n').enumerate().map(|(i, bytes)| {
+        let line = i + 1;
+        let mut text = String::from_utf8(bytes?).map_err(|_| ObjLoadError::Utf8Error { line })?;
+        text.truncate(text.find('#').unwrap_or(text.len()));
+        if text.ends_with('\r') {
+            text.pop();
+        }
+        Ok((line, text))
+    })
+}
+
+// First bytes of every gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+// Counts the bytes read from the file itself, so progress follows the file
+// on disk even when it is decompressed on the way
+struct CountingReader<'a, R> {
+    inner: R,
+    count: &'a Cell<u64>,
+}
+
+impl<R: Read> Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+}
+
+// Reader over the text of a mesh file, decompressed when it is gzipped. A .gz
+// name or the gzip magic bytes mark it, files without either are read as they
+// are
+fn open_text<'a>(path: &Path, count: &'a Cell<u64>) -> io::Result<Box<dyn BufRead + 'a>> {
+    let mut reader = BufReader::new(CountingReader {
+        inner: File::open(path)?,
+        count,
+    });
+    let named_gz = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("gz"));
+    if named_gz || reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
 pub fn load_obj<P: AsRef<Path>>(path: P) -> Result<ObjData, ObjLoadError> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
+    parse_obj(path.as_ref(), &mut |_| true)
+}
+
+// Messages sent back by `load_obj_streaming`
+pub enum LoadProgress {
+    Progress(f32), // Fraction of the file read, 0 to 1
+    Done(Box<Result<ObjData, ObjLoadError>>),
+}
+
+// Handle to an OBJ being loaded in the background, dropping it cancels the load
+pub struct ObjLoadTask {
+    receiver: Mutex<Receiver<LoadProgress>>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl ObjLoadTask {
+    // Messages received since the last poll
+    pub fn poll(&self) -> Vec<LoadProgress> {
+        self.receiver.lock().unwrap().try_iter().collect()
+    }
+}
+
+impl Drop for ObjLoadTask {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+// Load an OBJ on the async compute task pool so big files don't stall the frame
+pub fn load_obj_streaming<P: AsRef<Path>>(path: P) -> ObjLoadTask {
+    let path = path.as_ref().to_path_buf();
+    let (sender, receiver) = channel();
+    let cancel = Arc::new(AtomicBool::new(false));
+
+    let task_cancel = cancel.clone();
+    AsyncComputeTaskPool::get()
+        .spawn(async move {
+            // Only report whole percent steps to keep the channel quiet
+            let mut last_percent = 0;
+            let result = parse_obj(&path, &mut |progress| {
+                let percent = (progress * 100.0) as u32;
+                if percent > last_percent {
+                    last_percent = percent;
+                    let _ = sender.send(LoadProgress::Progress(progress));
+                }
+                !task_cancel.load(Ordering::Relaxed)
+            });
+            let _ = sender.send(LoadProgress::Done(Box::new(result)));
+        })
+        .detach();
+
+    ObjLoadTask {
+        receiver: Mutex::new(receiver),
+        cancel,
+    }
+}
+
+// `on_progress` gets the fraction of the file read so far after each line,
+// returning false stops the load with `ObjLoadError::Cancelled`
+fn parse_obj(
+    path: &Path,
+    on_progress: &mut dyn FnMut(f32) -> bool,
+) -> Result<ObjData, ObjLoadError> {
+    let file_size = std::fs::metadata(path)?.len().max(1) as f32;
+    let bytes_read = Cell::new(0);
+    let reader = open_text(path, &bytes_read)?;
 
     let mut vertices = Vec::new();
+    let mut normals = Vec::new();
+    let mut tex_coords = Vec::new();
     let mut faces = Vec::new();
+    let mut face_normals = Vec::new();
+    let mut face_tex_coords = Vec::new();
+    let mut materials = HashMap::new();
+    let mut material_names: Vec<String> = Vec::new();
+    let mut face_materials = Vec::new();
+    let mut current_material = None;
+    let mut group_names: Vec<String> = Vec::new();
+    let mut face_groups = Vec::new();
+    let mut current_group = None;
+    let mut face_smoothing = Vec::new();
+    let mut current_smoothing = Some(0);
+    let mut face_lines = Vec::new();
 
-    // OBJ files are 1-indexed, so we'll push a dummy vertex at index 0
-    vertices.push(Vec3 {
-        x: 0.0,
-        y: 0.0,
-        z: 0.0,
-    });
-
-    for line in reader.lines() {
-        let line = line?;
+    for line in read_lines(reader) {
+        let (line_number, line) = line?;
         let mut tokens = line.split_whitespace();
 
+        if !on_progress((bytes_read.get() as f32 / file_size).min(1.0)) {
+            return Err(ObjLoadError::Cancelled);
+        }
+
         match tokens.next() {
             Some("v") => {
                 // Parse vertex
                 let x = tokens.next().and_then(|s| s.parse().ok()).ok_or_else(|| {
-                    ObjLoadError::ParseError("Invalid vertex x coordinate".to_string())
+                    ObjLoadError::MalformedVertex {
+                        line: line_number,
+                        message: "Invalid vertex x coordinate".to_string(),
+                    }
                 })?;
                 let y = tokens.next().and_then(|s| s.parse().ok()).ok_or_else(|| {
-                    ObjLoadError::ParseError("Invalid vertex y coordinate".to_string())
+                    ObjLoadError::MalformedVertex {
+                        line: line_number,
+                        message: "Invalid vertex y coordinate".to_string(),
+                    }
                 })?;
                 let z = tokens.next().and_then(|s| s.parse().ok()).ok_or_else(|| {
-                    ObjLoadError::ParseError("Invalid vertex z coordinate".to_string())
+                    ObjLoadError::MalformedVertex {
+                        line: line_number,
+                        message: "Invalid vertex z coordinate".to_string(),
+                    }
                 })?;
 
                 vertices.push(Vec3 { x, y, z });
             }
+            Some("vn") => {
+                // Parse vertex normal
+                let x = tokens.next().and_then(|s| s.parse().ok()).ok_or_else(|| {
+                    ObjLoadError::MalformedVertex {
+                        line: line_number,
+                        message: "Invalid vertex normal x coordinate".to_string(),
+                    }
+                })?;
+                let y = tokens.next().and_then(|s| s.parse().ok()).ok_or_else(|| {
+                    ObjLoadError::MalformedVertex {
+                        line: line_number,
+                        message: "Invalid vertex normal y coordinate".to_string(),
+                    }
+                })?;
+                let z = tokens.next().and_then(|s| s.parse().ok()).ok_or_else(|| {
+                    ObjLoadError::MalformedVertex {
+                        line: line_number,
+                        message: "Invalid vertex normal z coordinate".to_string(),
+                    }
+                })?;
+
+                normals.push(Vec3 { x, y, z });
+            }
+            Some("vt") => {
+                // Parse texture coordinate, v is optional and w is ignored
+                let x = tokens.next().and_then(|s| s.parse().ok()).ok_or_else(|| {
+                    ObjLoadError::MalformedVertex {
+                        line: line_number,
+                        message: "Invalid texture u coordinate".to_string(),
+                    }
+                })?;
+                let y = match tokens.next() {
+                    Some(s) => s.parse().map_err(|_| ObjLoadError::MalformedVertex {
+                        line: line_number,
+                        message: "Invalid texture v coordinate".to_string(),
+                    })?,
+                    None => 0.0,
+                };
+
+                tex_coords.push(Vec2 { x, y });
+            }
             Some("f") => {
-                // Parse face: collect vertex indices
-                let indices: Result<Vec<usize>, _> = tokens
-                    .map(|token| {
-                        // Handle vertex/texture/normal format by taking first number
-                        token
-                            .split('/')
-                            .next()
-                            .and_then(|idx| idx.parse().ok())
-                            .ok_or_else(|| {
-                                ObjLoadError::ParseError(format!("Invalid face index: {}", token))
-                            })
-                    })
-                    .collect();
+                // Parse face: collect vertex, texture and normal indices
+                let mut indices = Vec::new();
+                let mut tex_indices = Vec::new();
+                let mut normal_indices = Vec::new();
+
+                for token in tokens {
+                    // Handle v, v/vt, v//vn and v/vt/vn formats
+                    let mut parts = token.split('/');
+                    let index = parts
+                        .next()
+                        .and_then(|idx| resolve_index(idx, vertices.len()))
+                        .ok_or_else(|| ObjLoadError::MalformedFace {
+                            line: line_number,
+                            message: format!("Invalid face index: {}", token),
+                        })?;
+                    indices.push(index);
+
+                    if let Some(tex) = parts.next().filter(|idx| !idx.is_empty()) {
+                        let tex = resolve_index(tex, tex_coords.len()).ok_or_else(|| {
+                            ObjLoadError::MalformedFace {
+                                line: line_number,
+                                message: format!("Invalid face texture: {}", token),
+                            }
+                        })?;
+                        tex_indices.push(tex);
+                    }
+
+                    if let Some(normal) = parts.next().filter(|idx| !idx.is_empty()) {
+                        let normal = resolve_index(normal, normals.len()).ok_or_else(|| {
+                            ObjLoadError::MalformedFace {
+                                line: line_number,
+                                message: format!("Invalid face normal: {}", token),
+                            }
+                        })?;
+                        normal_indices.push(normal);
+                    }
+                }
+
+                // Only keep normals and texture coordinates when every
+                // corner of the face has one
+                if normal_indices.len() != indices.len() {
+                    normal_indices.clear();
+                }
+                if tex_indices.len() != indices.len() {
+                    tex_indices.clear();
+                }
+
+                faces.push(indices);
+                face_normals.push(normal_indices);
+                face_tex_coords.push(tex_indices);
+                face_materials.push(current_material);
+                let group = *current_group
+                    .get_or_insert_with(|| name_index(&mut group_names, UNNAMED_GROUP));
+                face_groups.push(group);
+                face_smoothing.push(current_smoothing);
+                face_lines.push(line_number);
+            }
+            Some("mtllib") => {
+                // Material libraries are relative to the OBJ file, a missing
+                // one only costs us the colors
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                let mtl_path = path.parent().unwrap_or(Path::new("")).join(&name);
+                match load_mtl(&mtl_path) {
+                    Ok(library) => materials.extend(library),
+                    Err(e) => bevy::log::warn!(
+                        "Could not load material library {}: {}",
+                        mtl_path.display(),
+                        e
+                    ),
+                }
+            }
+            Some("usemtl") => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                current_material = Some(name_index(&mut material_names, &name));
+            }
+            Some("o" | "g") => {
+                // Objects and groups are treated alike, a face belongs to the
+                // last one named before it
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                let name = if name.is_empty() {
+                    UNNAMED_GROUP
+                } else {
+                    &name
+                };
+                current_group = Some(name_index(&mut group_names, name));
+            }
+            Some("s") => match tokens.next() {
+                Some("off" | "0") => current_smoothing = None,
+                Some(token) => match token.parse() {
+                    Ok(group) => current_smoothing = Some(group),
+                    Err(_) => bevy::log::warn!(
+                        "Ignoring smoothing group {:?} on line {}",
+                        token,
+                        line_number
+                    ),
+                },
+                None => continue,
+            },
+            // Ignore other lines
+            _ => continue,
+        }
+    }
+
+    // Positive indices may point past what was read so far, so they can only
+    // be checked once the whole file is in
+    check_indices(&faces, &face_lines, vertices.len(), "vertex")?;
+    check_indices(&face_tex_coords, &face_lines, tex_coords.len(), "texture")?;
+    check_indices(&face_normals, &face_lines, normals.len(), "normal")?;
+
+    Ok(ObjData {
+        vertex_numbers: (1..=vertices.len()).collect(),
+        vertices,
+        normals,
+        tex_coords,
+        faces,
+        face_normals,
+        face_tex_coords,
+        materials,
+        material_names,
+        face_materials,
+        group_names,
+        face_groups,
+        face_smoothing,
+    })
+}
+
+// Every index of every face below `count`, the face's line otherwise
+fn check_indices(
+    faces: &[Vec<usize>],
+    face_lines: &[usize],
+    count: usize,
+    kind: &str,
+) -> Result<(), ObjLoadError> {
+    for (face, &line) in faces.iter().zip(face_lines) {
+        if let Some(&index) = face.iter().find(|&&index| index >= count) {
+            return Err(ObjLoadError::MalformedFace {
+                line,
+                message: format!(
+                    "Face {} index {} out of range, the file has {}",
+                    kind,
+                    index + 1,
+                    count
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Position of `name` in `names`, added at the end the first time it is seen
+fn name_index(names: &mut Vec<String>, name: &str) -> usize {
+    match names.iter().position(|n| n == name) {
+        Some(index) => index,
+        None => {
+            names.push(name.to_string());
+            names.len() - 1
+        }
+    }
+}
+
+pub fn load_mtl<P: AsRef<Path>>(path: P) -> Result<HashMap<String, ObjMaterial>, ObjLoadError> {
+    let path = path.as_ref();
+    let file = File::open(path)?;
+    let reader = BufReader::new(file);
 
-                faces.push(indices?);
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, ObjMaterial)> = None;
+
+    for line in read_lines(reader) {
+        let (line_number, line) = line?;
+        let mut tokens = line.split_whitespace();
+
+        match tokens.next() {
+            Some("newmtl") => {
+                if let Some((name, material)) = current.take() {
+                    materials.insert(name, material);
+                }
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                current = Some((name, ObjMaterial::default()));
+            }
+            Some(key @ ("Kd" | "Ka" | "Ks")) => {
+                let (_, material) =
+                    current
+                        .as_mut()
+                        .ok_or_else(|| ObjLoadError::MalformedMaterial {
+                            line: line_number,
+                            message: format!("{} before newmtl", key),
+                        })?;
+                let color =
+                    parse_color(&mut tokens).ok_or_else(|| ObjLoadError::MalformedMaterial {
+                        line: line_number,
+                        message: format!("Invalid {} color", key),
+                    })?;
+
+                match key {
+                    "Kd" => material.diffuse = color,
+                    "Ka" => material.ambient = color,
+                    _ => material.specular = color,
+                }
+            }
+            Some("map_Kd") => {
+                let (_, material) =
+                    current
+                        .as_mut()
+                        .ok_or_else(|| ObjLoadError::MalformedMaterial {
+                            line: line_number,
+                            message: "map_Kd before newmtl".to_string(),
+                        })?;
+                // Options like `-s 1 1 1` come first, the file name is last
+                let name = tokens
+                    .last()
+                    .ok_or_else(|| ObjLoadError::MalformedMaterial {
+                        line: line_number,
+                        message: "Missing map_Kd file name".to_string(),
+                    })?;
+                material.diffuse_map = Some(path.parent().unwrap_or(Path::new("")).join(name));
             }
             // Ignore other lines
             _ => continue,
         }
     }
 
-    Ok(ObjData { vertices, faces })
+    if let Some((name, material)) = current {
+        materials.insert(name, material);
+    }
+
+    Ok(materials)
+}
+
+fn parse_color<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Option<[f32; 3]> {
+    let r = tokens.next()?.parse().ok()?;
+    let g = tokens.next()?.parse().ok()?;
+    let b = tokens.next()?.parse().ok()?;
+    Some([r, g, b])
+}
+
+// OBJ indices are 1-based, or negative to count back from the last element
+// read so far. Returns the absolute 0-based index
+fn resolve_index(token: &str, count: usize) -> Option<usize> {
+    let index: i64 = token.parse().ok()?;
+
+    if index > 0 {
+        Some(index as usize - 1)
+    } else if index < 0 && index.unsigned_abs() as usize <= count {
+        Some(count - index.unsigned_abs() as usize)
+    } else {
+        None
+    }
 }
 
 // Example usage and testing
+// Corner positions of the fan triangles for a face, keeping the face winding.
+// Faces with fewer than three vertices give nothing, and triangles that reuse
+// a vertex index are degenerate and dropped
+fn fan(face: &[usize]) -> Vec<[usize; 3]> {
+    // Triangulate as a fan from the first vertex: v0-v1-v2, v0-v2-v3, ...
+    (1..face.len().saturating_sub(1))
+        .map(|i| [0, i, i + 1])
+        .filter(|&[a, b, c]| face[a] != face[b] && face[b] != face[c] && face[a] != face[c])
+        .collect()
+}
+
+// Utility functions for working with the loaded data
+impl ObjData {
+    // Number of faces per entry of `group_names`
+    pub fn group_face_counts(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.group_names.len()];
+        for &group in &self.face_groups {
+            counts[group] += 1;
+        }
+        counts
+    }
+
+    // Copy with only the faces of the groups marked in `visible_groups`, by
+    // index into `group_names`. Vertices no kept face uses are dropped so the
+    // bounds are those of what is shown; groups, materials, normals and
+    // texture coordinates keep their indices
+    pub fn with_groups(&self, visible_groups: &[bool]) -> ObjData {
+        let keep: Vec<usize> = (0..self.faces.len())
+            .filter(|&face| visible_groups.get(self.face_groups[face]) == Some(&true))
+            .collect();
+
+        let mut remap = vec![None; self.vertices.len()];
+        let mut vertices = Vec::new();
+        let mut vertex_numbers = Vec::new();
+        let faces = keep
+            .iter()
+            .map(|&face| {
+                self.faces[face]
+                    .iter()
+                    .map(|&index| {
+                        *remap[index].get_or_insert_with(|| {
+                            vertices.push(self.vertices[index].clone());
+                            vertex_numbers.push(self.vertex_numbers[index]);
+                            vertices.len() - 1
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+        let pick = |attribute: &Vec<Vec<usize>>| -> Vec<Vec<usize>> {
+            keep.iter().map(|&face| attribute[face].clone()).collect()
+        };
+
+        ObjData {
+            vertices,
+            normals: self.normals.clone(),
+            tex_coords: self.tex_coords.clone(),
+            faces,
+            face_normals: pick(&self.face_normals),
+            face_tex_coords: pick(&self.face_tex_coords),
+            materials: self.materials.clone(),
+            material_names: self.material_names.clone(),
+            face_materials: keep.iter().map(|&face| self.face_materials[face]).collect(),
+            group_names: self.group_names.clone(),
+            face_groups: keep.iter().map(|&face| self.face_groups[face]).collect(),
+            face_smoothing: keep.iter().map(|&face| self.face_smoothing[face]).collect(),
+            vertex_numbers,
+        }
+    }
+
+    // Convert all faces to triangles (using simple fan triangulation)
+    pub fn triangulate(&self) -> Vec<[usize; 3]> {
+        let mut triangles = Vec::new();
+
+        for face in &self.faces {
+            for [a, b, c] in fan(face) {
+                triangles.push([face[a], face[b], face[c]]);
+            }
+        }
+
+        triangles
+    }
+
+    // The file's vertex numbers for each triangle returned by `triangulate`
+    pub fn triangulate_vertex_numbers(&self) -> Vec<[usize; 3]> {
+        self.triangulate()
+            .into_iter()
+            .map(|triangle| triangle.map(|index| self.vertex_numbers[index]))
+            .collect()
+    }
+
+    // Normal indices for each triangle returned by `triangulate`, None when
+    // the face the triangle came from has no normals
+    pub fn triangulate_normals(&self) -> Vec<Option<[usize; 3]>> {
+        self.triangulate_attribute(&self.face_normals)
+    }
+
+    // Texture coordinate indices for each triangle returned by `triangulate`
+    pub fn triangulate_tex_coords(&self) -> Vec<Option<[usize; 3]>> {
+        self.triangulate_attribute(&self.face_tex_coords)
+    }
+
+    // Material index (into `material_names`) for each triangle returned by `triangulate`
+    pub fn triangulate_materials(&self) -> Vec<Option<usize>> {
+        let mut triangles = Vec::new();
+
+        for (face, material) in self.faces.iter().zip(self.face_materials.iter()) {
+            for _ in fan(face) {
+                triangles.push(*material);
+            }
+        }
+
+        triangles
+    }
+
+    // Smoothing group for each triangle returned by `triangulate`
+    pub fn triangulate_smoothing(&self) -> Vec<Option<u32>> {
+        self.faces
+            .iter()
+            .zip(self.face_smoothing.iter())
+            .flat_map(|(face, &smoothing)| fan(face).into_iter().map(move |_| smoothing))
+            .collect()
+    }
+
+    fn triangulate_attribute(&self, face_attribute: &[Vec<usize>]) -> Vec<Option<[usize; 3]>> {
+        let mut triangles = Vec::new();
+
+        for (face, attribute) in self.faces.iter().zip(face_attribute.iter()) {
+            for [a, b, c] in fan(face) {
+                if attribute.is_empty() {
+                    triangles.push(None);
+                } else {
+                    triangles.push(Some([attribute[a], attribute[b], attribute[c]]));
+                }
+            }
+        }
+
+        triangles
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::fs::write;
-    use tempfile::NamedTempFile;
+    use std::path::PathBuf;
+
+    // Write `content` to a file in the temp dir, unique per test name
+    fn write_temp_obj(name: &str, content: &str) -> PathBuf {
+        write_temp_file(&format!("{}.obj", name), content)
+    }
+
+    fn write_temp_file(name: &str, content: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("mesh_viewer_{}_{}", std::process::id(), name));
+        write(&path, content).unwrap();
+        path
+    }
 
     #[test]
     fn test_load_obj() {
@@ -105,79 +740,444 @@ v -15.585992 -2.492895 20.986834
 f 1 2 3 4 5
 f 1 5 6";
 
-        let temp_file = NamedTempFile::new().unwrap();
-        write(temp_file.path(), obj_content).unwrap();
+        let path = write_temp_obj("load_obj", obj_content);
 
-        let obj_data = load_obj(temp_file.path()).unwrap();
+        let obj_data = load_obj(&path).unwrap();
 
-        // Check vertices (remember we added a dummy vertex at index 0)
-        assert_eq!(obj_data.vertices.len(), 7); // 6 + 1 dummy
+        // Check vertices
+        assert_eq!(obj_data.vertices.len(), 6);
         assert_eq!(obj_data.faces.len(), 2);
 
         // Check first vertex
-        let first_vertex = &obj_data.vertices[1]; // Index 1 due to dummy vertex
+        let first_vertex = &obj_data.vertices[0];
         assert!((first_vertex.x - -21.847065).abs() < 1e-6);
         assert!((first_vertex.y - -2.492895).abs() < 1e-6);
-        assert!((first_vertex.z - 19.569759).abs() < 1e-6);
+        assert!((first_vertex.z - 19.569_76).abs() < 1e-6);
+
+        // Check faces, indices are 0-based after loading
+        assert_eq!(obj_data.faces[0], vec![0, 1, 2, 3, 4]);
+        assert_eq!(obj_data.faces[1], vec![0, 4, 5]);
 
-        // Check faces
-        assert_eq!(obj_data.faces[0], vec![1, 2, 3, 4, 5]);
-        assert_eq!(obj_data.faces[1], vec![1, 5, 6]);
+        // No normals in the file
+        assert!(obj_data.normals.is_empty());
+        assert!(obj_data.face_normals.iter().all(|n| n.is_empty()));
     }
-}
 
-// Utility functions for working with the loaded data
-impl ObjData {
-    // Get total number of vertices (excluding dummy vertex)
-    pub fn vertex_count(&self) -> usize {
-        self.vertices.len() - 1 // Subtract dummy vertex
+    #[test]
+    fn test_load_obj_normals() {
+        let obj_content = "\
+v 0 0 0
+v 1 0 0
+v 1 0 1
+vn 0 1 0
+vn 1 0 0
+vt 0 0
+vt 1 0
+vt 1 1
+f 1//1 2//1 3//1
+f 1/1/2 2/2/2 3/3/2
+f 1 2/1 3";
+
+        let path = write_temp_obj("load_obj_normals", obj_content);
+        let obj_data = load_obj(&path).unwrap();
+
+        assert_eq!(obj_data.normals.len(), 2);
+        assert!((obj_data.normals[1].x - 1.0).abs() < 1e-6);
+
+        assert_eq!(obj_data.face_normals[0], vec![0, 0, 0]);
+        assert_eq!(obj_data.face_normals[1], vec![1, 1, 1]);
+        assert!(obj_data.face_normals[2].is_empty());
+
+        assert_eq!(
+            obj_data.triangulate_normals(),
+            vec![Some([0, 0, 0]), Some([1, 1, 1]), None]
+        );
     }
 
-    // Get total number of faces
-    pub fn face_count(&self) -> usize {
-        self.faces.len()
+    #[test]
+    fn test_triangulate_quad() {
+        let obj_content = "\
+v 0 0 0
+v 1 0 0
+v 1 0 1
+v 0 0 1
+f 1 2 3 4";
+
+        let path = write_temp_obj("triangulate_quad", obj_content);
+        let obj_data = load_obj(&path).unwrap();
+
+        // Two triangles sharing the 1-3 diagonal, same winding as the quad
+        assert_eq!(obj_data.triangulate(), vec![[0, 1, 2], [0, 2, 3]]);
     }
 
-    // Convert all faces to triangles (using simple fan triangulation)
-    pub fn triangulate(&self) -> Vec<[usize; 3]> {
-        let mut triangles = Vec::new();
+    #[test]
+    fn test_triangulate_degenerate_faces() {
+        let obj_content = "\
+v 0 0 0
+v 1 0 0
+v 1 0 1
+v 0 0 1
+f 1 2
+f 1 2 2
+f 1 2 3 3 4";
 
-        for face in &self.faces {
-            if face.len() >= 3 {
-                // Triangulate as a fan from the first vertex
-                for i in 1..(face.len() - 1) {
-                    triangles.push([face[0], face[i], face[i + 1]]);
+        let path = write_temp_obj("triangulate_degenerate", obj_content);
+        let obj_data = load_obj(&path).unwrap();
+
+        // The line and the collapsed triangle vanish, the pentagon keeps its
+        // two real triangles
+        assert_eq!(obj_data.triangulate(), vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(obj_data.triangulate_normals(), vec![None, None]);
+    }
+
+    #[test]
+    fn test_negative_indices() {
+        let obj_content = "\
+v 0 0 0
+v 1 0 0
+v 1 0 1
+vn 0 1 0
+f -3//-1 -2//-1 -1//-1
+v 0 0 1
+f 1 -2 -1";
+
+        let path = write_temp_obj("negative_indices", obj_content);
+        let obj_data = load_obj(&path).unwrap();
+
+        // Relative to the vertices read before each face line
+        assert_eq!(obj_data.faces[0], vec![0, 1, 2]);
+        assert_eq!(obj_data.face_normals[0], vec![0, 0, 0]);
+        assert_eq!(obj_data.faces[1], vec![0, 2, 3]);
+
+        // Counting back past the first vertex is an error, not garbage
+        assert!(load_obj(write_temp_obj("index_underflow", "v 0 0 0\nf -2 1 1")).is_err());
+        assert!(load_obj(write_temp_obj("index_zero", "v 0 0 0\nf 0 1 1")).is_err());
+    }
+
+    #[test]
+    fn test_load_obj_tex_coords() {
+        let obj_content = "\
+v 0 0 0
+v 1 0 0
+v 1 0 1
+vt 0 0
+vt 1 0
+vt 1 1 0
+vt 0.5
+vn 0 1 0
+f 1/1 2/2 3/3
+f 1/1/1 2/4/1 3/3/1
+f 1//1 2 3";
+
+        let path = write_temp_obj("load_obj_tex_coords", obj_content);
+        let obj_data = load_obj(&path).unwrap();
+
+        assert_eq!(obj_data.tex_coords.len(), 4);
+        assert!((obj_data.tex_coords[2].y - 1.0).abs() < 1e-6);
+        assert!((obj_data.tex_coords[3].x - 0.5).abs() < 1e-6);
+        assert!(obj_data.tex_coords[3].y.abs() < 1e-6);
+
+        assert_eq!(
+            obj_data.triangulate_tex_coords(),
+            vec![Some([0, 1, 2]), Some([0, 3, 2]), None]
+        );
+    }
+
+    #[test]
+    fn test_load_obj_materials() {
+        let mtl_path = write_temp_file(
+            "materials.mtl",
+            "\
+# two materials
+newmtl floor
+Ka 0.1 0.1 0.1
+Kd 0.5 0.25 1.0
+Ks 0.2 0.2 0.2
+map_Kd -s 1 1 1 floor.png
+
+newmtl wall
+Kd 1 0 0",
+        );
+        let mtl_name = mtl_path.file_name().unwrap().to_str().unwrap();
+
+        let obj_content = format!(
+            "\
+mtllib {}
+v 0 0 0
+v 1 0 0
+v 1 0 1
+v 0 0 1
+f 1 2 3
+usemtl wall
+f 1 3 4
+usemtl floor
+f 1 2 3 4
+usemtl wall
+f 1 2 4",
+            mtl_name
+        );
+        let path = write_temp_obj("load_obj_materials", &obj_content);
+        let obj_data = load_obj(&path).unwrap();
+
+        let floor = &obj_data.materials["floor"];
+        assert_eq!(floor.diffuse, [0.5, 0.25, 1.0]);
+        assert_eq!(floor.ambient, [0.1, 0.1, 0.1]);
+        assert_eq!(floor.specular, [0.2, 0.2, 0.2]);
+        assert_eq!(
+            floor.diffuse_map.as_deref(),
+            Some(mtl_path.parent().unwrap().join("floor.png").as_path())
+        );
+        assert_eq!(obj_data.materials["wall"].diffuse, [1.0, 0.0, 0.0]);
+        assert!(obj_data.materials["wall"].diffuse_map.is_none());
+
+        assert_eq!(obj_data.material_names, vec!["wall", "floor"]);
+        assert_eq!(
+            obj_data.triangulate_materials(),
+            vec![None, Some(0), Some(1), Some(1), Some(0)]
+        );
+    }
+
+    #[test]
+    fn test_malformed_lines() {
+        let path = write_temp_obj("malformed_face", "v 0 0 0\nv 1 0 0\nv 1 0 1\n\nf a b c");
+        match load_obj(&path) {
+            Err(ObjLoadError::MalformedFace { line, .. }) => assert_eq!(line, 5),
+            other => panic!("Expected MalformedFace, got {:?}", other),
+        }
+
+        let path = write_temp_obj("malformed_vertex", "v 0 0 0\nv 1 x 0");
+        match load_obj(&path) {
+            Err(ObjLoadError::MalformedVertex { line, .. }) => assert_eq!(line, 2),
+            other => panic!("Expected MalformedVertex, got {:?}", other),
+        }
+
+        let path = std::env::temp_dir().join(format!(
+            "mesh_viewer_{}_invalid_utf8.obj",
+            std::process::id()
+        ));
+        write(&path, b"v 0 0 0\n# \xff\xfe\n").unwrap();
+        match load_obj(&path) {
+            Err(ObjLoadError::Utf8Error { line }) => assert_eq!(line, 2),
+            other => panic!("Expected Utf8Error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_out_of_range_indices() {
+        let path = write_temp_obj("index_past_end", "v 0 0 0\nv 1 0 0\nv 1 0 1\nf 1 2 99\n");
+        match load_obj(&path) {
+            Err(ObjLoadError::MalformedFace { line, .. }) => assert_eq!(line, 4),
+            other => panic!("Expected MalformedFace, got {:?}", other),
+        }
+
+        let path = write_temp_obj(
+            "normal_past_end",
+            "v 0 0 0\nv 1 0 0\nv 1 0 1\nvn 0 1 0\nf 1//1 2//1 3//2\n",
+        );
+        match load_obj(&path) {
+            Err(ObjLoadError::MalformedFace { line, .. }) => assert_eq!(line, 5),
+            other => panic!("Expected MalformedFace, got {:?}", other),
+        }
+
+        // Faces may come before the vertices they use
+        let path = write_temp_obj("index_ahead", "f 1 2 3\nv 0 0 0\nv 1 0 0\nv 1 0 1\n");
+        assert_eq!(load_obj(&path).unwrap().faces, vec![vec![0, 1, 2]]);
+    }
+
+    #[test]
+    fn test_load_obj_streaming() {
+        bevy::tasks::AsyncComputeTaskPool::get_or_init(bevy::tasks::TaskPool::default);
+
+        let content = "v 0 0 0\nv 1 0 0\nv 1 0 1\nf 1 2 3\n".repeat(100);
+        let path = write_temp_obj("streaming", &content);
+        let task = load_obj_streaming(&path);
+
+        let mut last_progress = 0.0;
+        let obj_data = loop {
+            match task.poll().into_iter().last() {
+                Some(LoadProgress::Progress(progress)) => {
+                    assert!(progress >= last_progress);
+                    last_progress = progress;
                 }
+                Some(LoadProgress::Done(result)) => break result.unwrap(),
+                None => std::thread::sleep(std::time::Duration::from_millis(1)),
             }
+        };
+        assert_eq!(obj_data.vertices.len(), 300);
+        assert_eq!(obj_data.faces.len(), 100);
+    }
+
+    #[test]
+    fn test_load_gzipped_obj() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        let content = "v 0 0 0\nv 1 0 0\nv 1 0 1\nvn 0 1 0\nf 1//1 2//1 3//1\n";
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(content.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        // By its name, and by its magic bytes under a plain .obj name
+        for name in ["gzipped.obj.gz", "gzipped_unnamed.obj"] {
+            let path =
+                std::env::temp_dir().join(format!("mesh_viewer_{}_{}", std::process::id(), name));
+            write(&path, &compressed).unwrap();
+            let mut progress = Vec::new();
+            let obj_data = parse_obj(&path, &mut |p| {
+                progress.push(p);
+                true
+            })
+            .unwrap();
+            assert_eq!(obj_data.vertices.len(), 3);
+            assert_eq!(obj_data.faces, vec![vec![0, 1, 2]]);
+            assert_eq!(obj_data.face_normals, vec![vec![0, 0, 0]]);
+            assert!(progress.iter().all(|p| (0.0..=1.0).contains(p)));
         }
 
-        triangles
+        // A .gz that isn't gzip is an error, not garbage
+        let path = write_temp_file("not_gzipped.obj.gz", content);
+        assert!(matches!(load_obj(&path), Err(ObjLoadError::IoError(_))));
     }
 
-    // Get bounds of the model
-    pub fn get_bounds(&self) -> (Vec3, Vec3) {
-        let mut min = Vec3 {
-            x: f32::INFINITY,
-            y: f32::INFINITY,
-            z: f32::INFINITY,
-        };
-        let mut max = Vec3 {
-            x: f32::NEG_INFINITY,
-            y: f32::NEG_INFINITY,
-            z: f32::NEG_INFINITY,
-        };
+    #[test]
+    fn test_parse_obj_cancel() {
+        let path = write_temp_obj("cancel", "v 0 0 0\nv 1 0 0\n");
+        match parse_obj(&path, &mut |_| false) {
+            Err(ObjLoadError::Cancelled) => {}
+            other => panic!("Expected Cancelled, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_missing_mtl_is_not_fatal() {
+        let path = write_temp_obj(
+            "missing_mtl",
+            "mtllib does_not_exist.mtl\nv 0 0 0\nv 1 0 0\nv 1 0 1\nusemtl foo\nf 1 2 3",
+        );
+        let obj_data = load_obj(&path).unwrap();
 
-        // Skip dummy vertex at index 0
-        for vertex in self.vertices.iter().skip(1) {
-            min.x = min.x.min(vertex.x);
-            min.y = min.y.min(vertex.y);
-            min.z = min.z.min(vertex.z);
+        assert!(obj_data.materials.is_empty());
+        assert_eq!(obj_data.triangulate_materials(), vec![Some(0)]);
+    }
 
-            max.x = max.x.max(vertex.x);
-            max.y = max.y.max(vertex.y);
-            max.z = max.z.max(vertex.z);
+    #[test]
+    fn test_load_obj_crlf_and_comments() {
+        let lines = [
+            "# Exported by hand",
+            "",
+            "mtllib  missing.mtl  # no such file",
+            "v 0 0 0",
+            "v\t1   0\t0 # tabs and runs of spaces",
+            "   ",
+            "v 1 0 1",
+            "#f 9 9 9",
+            "o  big   room # the name",
+            "usemtl stone # comment after the name",
+            "f 1 2 3 # inline comment",
+            "  f 3 2 1",
+        ];
+        let path = write_temp_obj("crlf", &lines.join("\r\n"));
+        let obj_data = load_obj(&path).unwrap();
+
+        assert_eq!(obj_data.vertices.len(), 3);
+        assert!((obj_data.vertices[1].x - 1.0).abs() < 1e-6);
+        assert_eq!(obj_data.faces, vec![vec![0, 1, 2], vec![2, 1, 0]]);
+        assert_eq!(obj_data.group_names, vec!["big room"]);
+        assert_eq!(obj_data.material_names, vec!["stone"]);
+
+        // Same result with plain LF endings
+        let path = write_temp_obj("lf", &lines.join("\n"));
+        assert_eq!(load_obj(&path).unwrap().faces, obj_data.faces);
+
+        // Errors still point at the right line
+        let path = write_temp_obj("crlf_error", "v 0 0 0\r\n# comment\r\nv 1 x 0\r\n");
+        match load_obj(&path) {
+            Err(ObjLoadError::MalformedVertex { line, .. }) => assert_eq!(line, 3),
+            other => panic!("expected a malformed vertex, got {:?}", other),
         }
+    }
+
+    #[test]
+    fn test_load_mtl_crlf() {
+        let path = write_temp_file(
+            "crlf.mtl",
+            "# Materials\r\nnewmtl stone # grey\r\nKd 0.5 0.5 0.5\r\n\r\n",
+        );
+        let materials = load_mtl(&path).unwrap();
+        assert_eq!(materials["stone"].diffuse, [0.5, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn test_load_obj_groups() {
+        let path = write_temp_obj(
+            "groups",
+            "\
+v 0 0 0
+v 1 0 0
+v 1 0 1
+v 5 0 0
+v 6 0 0
+v 6 0 1
+f 1 2 3
+o building
+f 4 5 6
+g
+f 1 3 2
+g building
+f 6 5 4",
+        );
+        let obj_data = load_obj(&path).unwrap();
+
+        // Faces before any name and after a bare `g` share the unnamed group
+        assert_eq!(obj_data.group_names, vec![UNNAMED_GROUP, "building"]);
+        assert_eq!(obj_data.face_groups, vec![0, 1, 0, 1]);
+        assert_eq!(obj_data.group_face_counts(), vec![2, 2]);
+
+        let building = obj_data.with_groups(&[false, true]);
+        assert_eq!(building.vertices.len(), 3);
+        assert!((building.vertices[0].x - 5.0).abs() < 1e-6);
+        assert_eq!(building.faces, vec![vec![0, 1, 2], vec![2, 1, 0]]);
+        assert_eq!(building.face_groups, vec![1, 1]);
+        assert_eq!(building.face_materials, vec![None, None]);
+        assert_eq!(
+            building.triangulate_vertex_numbers(),
+            vec![[4, 5, 6], [6, 5, 4]]
+        );
+
+        assert_eq!(obj_data.with_groups(&[false, false]).faces.len(), 0);
+    }
+
+    #[test]
+    fn test_load_obj_smoothing_groups() {
+        let path = write_temp_obj(
+            "smoothing",
+            "\
+v 0 0 0
+v 1 0 0
+v 1 0 1
+f 1 2 3
+s 1
+f 1 2 3
+s off
+f 1 2 3
+s 2
+f 1 2 3 2
+s 0
+f 1 2 3
+s bad
+f 1 2 3",
+        );
+        let obj_data = load_obj(&path).unwrap();
 
-        (min, max)
+        // Faces before any `s` are group 0, a bad value keeps the last group
+        assert_eq!(
+            obj_data.face_smoothing,
+            vec![Some(0), Some(1), None, Some(2), None, None]
+        );
+        assert_eq!(
+            obj_data.triangulate_smoothing().len(),
+            obj_data.triangulate().len()
+        );
     }
 }
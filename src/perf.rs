@@ -0,0 +1,137 @@
+use crate::{ScreenshotState, TileCullStats, TileMesh};
+use bevy::diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin};
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use std::collections::VecDeque;
+
+pub const TOGGLE_KEY: KeyCode = KeyCode::F3;
+
+// Frames in the graph, the diagnostic itself only keeps the last 20
+const FRAME_HISTORY_LENGTH: usize = 240;
+const GRAPH_SIZE: egui::Vec2 = egui::vec2(240.0, 60.0);
+
+// Frame times the graph always has room for, in milliseconds: 60 and 30 FPS
+const GRAPH_REFERENCE_TIMES: [f32; 2] = [1000.0 / 60.0, 1000.0 / 30.0];
+
+// FPS, frame times and tile counts in a corner of the screen
+#[derive(Resource, Default)]
+pub struct PerfOverlay {
+    pub open: bool,
+    frame_times: VecDeque<f32>, // Milliseconds, oldest first
+}
+
+impl PerfOverlay {
+    pub fn record(&mut self, frame_time: f32) {
+        self.frame_times.push_back(frame_time);
+        while self.frame_times.len() > FRAME_HISTORY_LENGTH {
+            self.frame_times.pop_front();
+        }
+    }
+}
+
+// Frame times are recorded while the overlay is closed too, so it opens with
+// a full graph
+pub fn record_frame_time(mut overlay: ResMut<PerfOverlay>, diagnostics: Res<DiagnosticsStore>) {
+    if let Some(frame_time) = diagnostics
+        .get(FrameTimeDiagnosticsPlugin::FRAME_TIME)
+        .and_then(|diagnostic| diagnostic.value())
+    {
+        overlay.record(frame_time as f32);
+    }
+}
+
+fn frame_time_graph(ui: &mut egui::Ui, frame_times: &VecDeque<f32>) {
+    let (rect, _) = ui.allocate_exact_size(GRAPH_SIZE, egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+
+    let longest = frame_times
+        .iter()
+        .copied()
+        .fold(GRAPH_REFERENCE_TIMES[1], f32::max);
+    let y_at = |frame_time: f32| rect.bottom() - frame_time / longest * rect.height();
+    for frame_time in GRAPH_REFERENCE_TIMES {
+        painter.hline(
+            rect.x_range(),
+            y_at(frame_time),
+            egui::Stroke::new(1.0, egui::Color32::DARK_GRAY),
+        );
+    }
+
+    // Newest frame on the right
+    let step = rect.width() / (FRAME_HISTORY_LENGTH - 1) as f32;
+    let start = rect.right() - (frame_times.len().max(1) - 1) as f32 * step;
+    let points = frame_times
+        .iter()
+        .enumerate()
+        .map(|(i, &frame_time)| egui::pos2(start + i as f32 * step, y_at(frame_time)))
+        .collect();
+    painter.add(egui::Shape::line(
+        points,
+        egui::Stroke::new(1.0, egui::Color32::LIGHT_GREEN),
+    ));
+    painter.text(
+        rect.left_top(),
+        egui::Align2::LEFT_TOP,
+        format!("{:.1} ms", longest),
+        egui::FontId::monospace(10.0),
+        ui.visuals().text_color(),
+    );
+}
+
+pub fn perf_overlay_window(
+    mut contexts: EguiContexts,
+    keyboard: Res<Input<KeyCode>>,
+    mut overlay: ResMut<PerfOverlay>,
+    diagnostics: Res<DiagnosticsStore>,
+    cull_stats: Res<TileCullStats>,
+    tiles: Query<(), With<TileMesh>>,
+    screenshot: Res<ScreenshotState>,
+) {
+    if keyboard.just_pressed(TOGGLE_KEY) && !contexts.ctx_mut().wants_keyboard_input() {
+        overlay.open = !overlay.open;
+    }
+    if screenshot.hide_ui || !overlay.open {
+        return;
+    }
+
+    let fps = diagnostics
+        .get(FrameTimeDiagnosticsPlugin::FPS)
+        .and_then(|diagnostic| diagnostic.smoothed());
+    egui::Area::new("perf_overlay")
+        .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-10.0, 10.0))
+        .interactable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                match fps {
+                    Some(fps) => ui.label(format!("{:.0} FPS", fps)),
+                    None => ui.label("- FPS"),
+                };
+                frame_time_graph(ui, &overlay.frame_times);
+                ui.label(format!("Tile entities: {}", tiles.iter().count()));
+                ui.label(format!(
+                    "Visible tiles: {} / {}",
+                    cull_stats.visible, cull_stats.total
+                ));
+            });
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_history_keeps_latest() {
+        let mut overlay = PerfOverlay::default();
+        for frame in 0..FRAME_HISTORY_LENGTH + 10 {
+            overlay.record(frame as f32);
+        }
+        assert_eq!(overlay.frame_times.len(), FRAME_HISTORY_LENGTH);
+        assert_eq!(overlay.frame_times.front(), Some(&10.0));
+        assert_eq!(
+            overlay.frame_times.back(),
+            Some(&((FRAME_HISTORY_LENGTH + 9) as f32))
+        );
+    }
+}
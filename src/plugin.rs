@@ -0,0 +1,254 @@
+use crate::*;
+use bevy::diagnostic::FrameTimeDiagnosticsPlugin;
+use bevy::pbr::wireframe::WireframePlugin;
+use bevy_egui::EguiPlugin;
+use std::path::PathBuf;
+
+// Values the viewer starts with, unset ones come from the saved settings.
+// They are only saved once the matching setting is edited
+#[derive(Resource, Clone, Debug, Default)]
+pub struct NavViewerConfig {
+    pub obj_path: Option<PathBuf>, // Loaded on startup
+    pub tile_size: Option<f32>,
+    pub walkable_slope_angle: Option<f32>,
+    pub mitm_address: Option<String>,
+    pub max_frame_len: Option<u32>,
+}
+
+impl NavViewerConfig {
+    pub(crate) fn tile_size(&self, settings: &ViewerSettings) -> f32 {
+        self.tile_size
+            .unwrap_or(settings.tile_size)
+            .max(MIN_TILE_SIZE)
+    }
+
+    pub(crate) fn max_frame_len(&self, settings: &ViewerSettings) -> u32 {
+        self.max_frame_len.unwrap_or(settings.max_frame_len)
+    }
+
+    pub(crate) fn slope_colors(&self, settings: &ViewerSettings) -> SlopeColors {
+        let mut slope_colors = settings.slope_colors.clone();
+        if let Some(angle) = self.walkable_slope_angle {
+            slope_colors.set_walkable_slope_angle(angle);
+        }
+        slope_colors
+    }
+}
+
+// The whole viewer: mesh loading and tiling, camera, UI and the MITM actor
+// feed. The app needs the default plugins, with `WgpuFeatures::POLYGON_MODE_LINE`
+// for the wireframe render modes
+pub struct NavViewerPlugin {
+    pub config: NavViewerConfig,
+}
+
+impl Plugin for NavViewerPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<WireframePlugin>() {
+            app.add_plugins(WireframePlugin);
+        }
+        if !app.is_plugin_added::<EguiPlugin>() {
+            app.add_plugins(EguiPlugin);
+        }
+        if !app.is_plugin_added::<FrameTimeDiagnosticsPlugin>() {
+            app.add_plugins(FrameTimeDiagnosticsPlugin);
+        }
+
+        let settings = ViewerSettings::load_or_default(&settings::settings_path());
+        let tile_settings = TileSettings {
+            tile_size: self.config.tile_size(&settings),
+            ..default()
+        };
+        let slope_colors = self.config.slope_colors(&settings);
+        let render_mode = settings.render_mode;
+        let actor_styles = settings.actor_styles.clone();
+        let clip_planes = settings.clip_planes;
+        let anti_aliasing = settings.anti_aliasing;
+        let import_options = settings.import_options;
+        let camera_speed = settings.camera_speed;
+        let budget = budget::MeshBudget::new(settings.memory_budget_mb);
+        let elevation_colors = settings.elevation_colors;
+
+        app.insert_resource(CameraBookmarks::load_or_default(
+            &bookmarks::bookmarks_path(),
+        ))
+        .insert_resource(selections::SelectionSets::load_or_default(
+            &selections::selections_path(),
+        ))
+        .insert_resource(settings)
+        .insert_resource(tile_settings)
+        .insert_resource(self.config.clone())
+        .init_resource::<ObjWatcher>()
+        .init_resource::<coords::CoordinateTransform>()
+        .init_resource::<MeshStatsPanel>()
+        .init_resource::<ObjGroups>()
+        .init_resource::<TileCullStats>()
+        .init_resource::<TileHighlight>()
+        .init_resource::<RenderSettings>()
+        .insert_resource(render_mode)
+        .insert_resource(slope_colors)
+        .init_resource::<ScreenshotState>()
+        .init_resource::<actors::ActorMap>()
+        .init_resource::<actors::ActorInbox>()
+        .add_event::<net::ActorMessage>()
+        .init_resource::<message_log::MessageLog>()
+        .init_resource::<actors::TrailSettings>()
+        .init_resource::<actors::ActorLabels>()
+        .init_resource::<actors::OutOfBounds>()
+        .init_resource::<actors::GroundSnap>()
+        .init_resource::<actors::SelectedActor>()
+        .init_resource::<replay::ReplayController>()
+        .init_resource::<layers::MeshLayers>()
+        .init_resource::<world::PickPlane>()
+        .insert_resource(actor_styles)
+        .insert_resource(clip_planes)
+        .insert_resource(anti_aliasing)
+        .insert_resource(import_options)
+        .insert_resource(camera_speed)
+        .insert_resource(budget)
+        .insert_resource(elevation_colors)
+        .init_resource::<Msaa>()
+        .init_resource::<measure::MeasureTool>()
+        .init_resource::<minimap::Minimap>()
+        .init_resource::<tiling::IncrementalTiler>()
+        .init_resource::<perf::PerfOverlay>()
+        .init_resource::<ground::GroundPlane>()
+        .init_resource::<section::SectionPlane>()
+        .init_resource::<decimation::Decimation>()
+        .init_resource::<selections::TileSelection>()
+        .add_systems(Last, close_mitm_on_exit)
+        .add_systems(
+            Startup,
+            (
+                setup,
+                actors::setup_actor_assets,
+                minimap::setup_minimap,
+                ground::setup_ground,
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                camera_control,
+                take_screenshot
+                    .before(ui_system)
+                    .before(draw_tile_grid)
+                    .before(draw_axes),
+                ui_system,
+                cycle_render_mode.after(ui_system),
+                watch_obj_file,
+                update_mesh,
+                update_mesh_stats,
+                cull_tiles,
+                highlight_tiles,
+                apply_wireframe,
+                apply_culling.after(highlight_tiles),
+                recolor_meshes,
+                draw_tile_grid,
+                draw_axes,
+                maintain_mitm_connection,
+                receive_actor_messages,
+                queue_actor_messages.after(receive_actor_messages),
+                actors::apply_actor_messages.after(queue_actor_messages),
+                actors::animate_actors,
+                actors::draw_actor_trails,
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                actors::style_actors.after(actors::apply_actor_messages),
+                actors::check_actor_bounds
+                    .after(queue_actor_messages)
+                    .before(actors::apply_actor_messages),
+                actors::draw_out_of_bounds,
+                actors::snap_actors_to_ground.after(actors::animate_actors),
+                actors::actor_styles_window.after(take_screenshot),
+                actors::draw_actor_labels.after(take_screenshot),
+                measure::pick_measure_points,
+                message_log::message_log_window.after(take_screenshot),
+                replay::replay_window.after(take_screenshot),
+                layers::layers_window.after(take_screenshot),
+                layers::update_layers.after(update_mesh),
+                layers::apply_layer_visibility,
+                follow_mesh_height,
+                islands::update_island_overlay,
+                draw_open_edges,
+                layers::apply_layer_transforms
+                    .after(update_mesh)
+                    .after(layers::update_layers),
+                measure::draw_measurement,
+                settings::save_settings,
+                settings::undo_settings.after(settings::save_settings),
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                minimap::update_minimap.after(update_mesh),
+                tiling::advance_tiling
+                    .after(update_mesh)
+                    .after(layers::update_layers),
+                minimap::minimap_window
+                    .after(take_screenshot)
+                    .after(minimap::update_minimap),
+                apply_clip_planes,
+                apply_anti_aliasing,
+                ground::update_ground_plane
+                    .after(update_mesh)
+                    .after(layers::apply_layer_transforms),
+                ground::draw_ground_grid.after(ground::update_ground_plane),
+                perf::record_frame_time,
+                perf::perf_overlay_window
+                    .after(take_screenshot)
+                    .after(cull_tiles),
+                actors::select_actor,
+                actors::draw_selected_actor.after(actors::select_actor),
+                actors::actor_inspector_window
+                    .after(take_screenshot)
+                    .after(actors::select_actor),
+                section::rebuild_on_section_change
+                    .after(ui_system)
+                    .after(layers::layers_window)
+                    .before(update_mesh),
+                decimation::update_decimation
+                    .after(ui_system)
+                    .before(update_mesh),
+                double_click_focus.before(camera_control),
+                selections::pick_tiles.before(highlight_tiles),
+                selections::frame_selection.after(ui_system),
+                // Before, so a tile being highlighted already has `HighlightedTile`
+                apply_lighting.before(highlight_tiles),
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_overrides_settings() {
+        let settings = ViewerSettings {
+            tile_size: 500.0,
+            ..default()
+        };
+        let saved_angle = settings.slope_colors.bands[0].max_angle;
+
+        let config = NavViewerConfig::default();
+        assert_eq!(config.tile_size(&settings), 500.0);
+        assert_eq!(
+            config.slope_colors(&settings).bands[0].max_angle,
+            saved_angle
+        );
+
+        let config = NavViewerConfig {
+            tile_size: Some(0.0),
+            walkable_slope_angle: Some(30.0),
+            ..default()
+        };
+        assert_eq!(config.tile_size(&settings), MIN_TILE_SIZE);
+        assert_eq!(config.slope_colors(&settings).bands[0].max_angle, 30.0);
+    }
+}
@@ -0,0 +1,258 @@
+use crate::net::{self, ActorMessage};
+use crate::ScreenshotState;
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const MIN_REPLAY_SPEED: f32 = 0.25;
+pub const MAX_REPLAY_SPEED: f32 = 4.0;
+
+// Plays a recorded frame log back on its own timeline, with pause, speed and
+// seeking
+#[derive(Resource)]
+pub struct ReplayController {
+    pub path: Option<PathBuf>,
+    frames: Vec<(f32, ActorMessage)>, // Seconds into the recording, in order
+    pub playing: bool,
+    pub speed: f32,
+    time: f32,
+    next: usize,               // First frame not sent yet
+    restart: bool,             // Actors must be cleared before the frames are sent again
+    pub error: Option<String>, // Why the last log didn't open
+}
+
+impl Default for ReplayController {
+    fn default() -> Self {
+        Self {
+            path: None,
+            frames: Vec::new(),
+            playing: false,
+            speed: 1.0,
+            time: 0.0,
+            next: 0,
+            restart: false,
+            error: None,
+        }
+    }
+}
+
+impl ReplayController {
+    // Start playing a recording, frames that don't decode are skipped
+    pub fn load(&mut self, path: PathBuf, log: Vec<(Duration, Vec<u8>)>) {
+        self.frames = log
+            .into_iter()
+            .filter_map(|(time, frame)| match net::decode_message(&frame) {
+                Ok(message) => Some((time.as_secs_f32(), message)),
+                Err(e) => {
+                    warn!("Skipping frame at {:?} in {}: {}", time, path.display(), e);
+                    None
+                }
+            })
+            .collect();
+        self.path = Some(path);
+        self.error = None;
+        self.playing = true;
+        self.time = 0.0;
+        self.next = 0;
+        self.restart = true;
+    }
+
+    pub fn close(&mut self) {
+        *self = Self {
+            speed: self.speed,
+            ..Self::default()
+        };
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.path.is_some()
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.frames.last().map_or(0.0, |(time, _)| *time)
+    }
+
+    // Jumping back replays every frame from the start to get the actors to
+    // where they were at `time`
+    pub fn seek(&mut self, time: f32) {
+        let time = time.clamp(0.0, self.duration());
+        if time < self.time {
+            self.next = 0;
+            self.restart = true;
+        }
+        self.time = time;
+    }
+
+    // How fast actor moves play out, relative to real time
+    pub fn time_scale(&self) -> f32 {
+        match (self.is_active(), self.playing) {
+            (false, _) => 1.0,
+            (true, true) => self.speed,
+            (true, false) => 0.0,
+        }
+    }
+
+    // Move the timeline on and collect the frames that are now due. True when
+    // the actors have to be cleared before the frames are applied
+    pub fn advance(&mut self, delta_seconds: f32, messages: &mut Vec<ActorMessage>) -> bool {
+        if !self.is_active() {
+            return false;
+        }
+        if self.playing {
+            self.time = (self.time + delta_seconds * self.speed).min(self.duration());
+        }
+        let due = self.frames[self.next..].partition_point(|(time, _)| *time <= self.time);
+        messages.extend(
+            self.frames[self.next..self.next + due]
+                .iter()
+                .map(|(_, message)| message.clone()),
+        );
+        self.next += due;
+        std::mem::take(&mut self.restart)
+    }
+}
+
+pub fn replay_window(
+    mut contexts: EguiContexts,
+    mut replay: ResMut<ReplayController>,
+    screenshot: Res<ScreenshotState>,
+) {
+    if screenshot.hide_ui {
+        return;
+    }
+
+    egui::Window::new("Replay")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Open log").clicked() {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("Frame logs", &["bin"])
+                        .pick_file()
+                    {
+                        match net::load_frame_log(&path) {
+                            Ok(log) => replay.load(path, log),
+                            Err(e) => {
+                                let error = format!("Failed to open {}: {}", path.display(), e);
+                                warn!("{}", error);
+                                replay.error = Some(error);
+                            }
+                        }
+                    }
+                }
+                if replay.is_active() && ui.button("Close").clicked() {
+                    replay.close();
+                }
+            });
+            if let Some(error) = &replay.error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+            let Some(path) = replay.path.clone() else {
+                ui.label("No recording loaded");
+                return;
+            };
+            ui.label(format!("Replaying {}", path.display()));
+
+            ui.horizontal(|ui| {
+                let label = if replay.playing { "Pause" } else { "Play" };
+                if ui.button(label).clicked() {
+                    // Play at the end starts over
+                    if !replay.playing && replay.time() >= replay.duration() {
+                        replay.seek(0.0);
+                    }
+                    replay.playing = !replay.playing;
+                }
+                ui.add(
+                    egui::Slider::new(&mut replay.speed, MIN_REPLAY_SPEED..=MAX_REPLAY_SPEED)
+                        .logarithmic(true)
+                        .suffix("x")
+                        .text("Speed"),
+                );
+            });
+
+            let mut time = replay.time();
+            let duration = replay.duration();
+            if ui
+                .add(
+                    egui::Slider::new(&mut time, 0.0..=duration)
+                        .suffix(" s")
+                        .text(format!("of {:.1} s", duration)),
+                )
+                .changed()
+            {
+                replay.seek(time);
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{ActorDespawn, WireFormat};
+
+    fn log(times: &[u64]) -> Vec<(Duration, Vec<u8>)> {
+        times
+            .iter()
+            .map(|&millis| {
+                let message = ActorMessage::Despawn(ActorDespawn::new(format!("enemy{}", millis)));
+                (
+                    Duration::from_millis(millis),
+                    net::encode_message(&message, WireFormat::Json),
+                )
+            })
+            .collect()
+    }
+
+    fn ids(messages: &[ActorMessage]) -> Vec<&str> {
+        messages.iter().map(|message| message.id()).collect()
+    }
+
+    #[test]
+    fn test_replay_timeline() {
+        let mut replay = ReplayController::default();
+        let mut log = log(&[0, 1000, 2000, 4000]);
+        log.insert(1, (Duration::from_millis(500), b"not a message".to_vec()));
+        replay.load(PathBuf::from("frames.bin"), log);
+        assert_eq!(replay.duration(), 4.0);
+
+        // The first advance clears whatever was shown before
+        let mut messages = Vec::new();
+        assert!(replay.advance(0.5, &mut messages));
+        assert_eq!(ids(&messages), vec!["enemy0"]);
+
+        // Twice as fast
+        replay.speed = 2.0;
+        messages.clear();
+        assert!(!replay.advance(0.75, &mut messages));
+        assert_eq!(ids(&messages), vec!["enemy1000", "enemy2000"]);
+        assert_eq!(replay.time(), 2.0);
+
+        // Paused, nothing moves
+        replay.playing = false;
+        assert_eq!(replay.time_scale(), 0.0);
+        messages.clear();
+        replay.advance(10.0, &mut messages);
+        assert!(messages.is_empty());
+
+        // Forward skips straight to the frames in between
+        replay.seek(10.0);
+        assert!(!replay.advance(0.0, &mut messages));
+        assert_eq!(ids(&messages), vec!["enemy4000"]);
+
+        // Back rebuilds from the start
+        replay.seek(1.5);
+        messages.clear();
+        assert!(replay.advance(0.0, &mut messages));
+        assert_eq!(ids(&messages), vec!["enemy0", "enemy1000"]);
+
+        replay.close();
+        assert!(!replay.is_active());
+        assert_eq!(replay.time_scale(), 1.0);
+        assert_eq!(replay.speed, 2.0);
+    }
+}
@@ -0,0 +1,199 @@
+use crate::{layers, MeshViewer};
+use bevy::math::Vec3A;
+use bevy::prelude::*;
+use bevy_egui::egui;
+
+// Cuts the mesh open to see stacked floors, e.g. the ground under a bridge.
+// Geometry in front of the plane, where the normal points, is left out when
+// the tiles are built
+#[derive(Resource, Clone, Copy, PartialEq, Debug)]
+pub struct SectionPlane {
+    pub enabled: bool,
+    pub normal: Vec3,
+    pub offset: f32, // Along the normal from the world origin
+}
+
+impl Default for SectionPlane {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            normal: Vec3::Y,
+            offset: 0.0,
+        }
+    }
+}
+
+impl SectionPlane {
+    // The plane in the space of a mesh drawn with `transform`, as normal and
+    // offset. None while it is off or has no normal
+    pub fn local_plane(&self, transform: &Transform) -> Option<(Vec3, f32)> {
+        let normal = self.normal.normalize_or_zero();
+        if !self.enabled || normal == Vec3::ZERO {
+            return None;
+        }
+        let affine = transform.compute_affine();
+        let local = Vec3::from(affine.matrix3.transpose() * Vec3A::from(normal));
+        Some((
+            local,
+            self.offset - normal.dot(Vec3::from(affine.translation)),
+        ))
+    }
+
+    // Where a ray comes out of the cut away half, so picking finds what the
+    // section shows. None when it never does
+    pub fn clip_ray(&self, origin: Vec3, direction: Vec3) -> Option<Vec3> {
+        let normal = self.normal.normalize_or_zero();
+        let height = origin.dot(normal) - self.offset;
+        if !self.enabled || height <= 0.0 {
+            return Some(origin);
+        }
+        let speed = direction.dot(normal);
+        (speed < 0.0).then(|| origin - direction * (height / speed))
+    }
+
+    // Offsets that sweep the plane through the whole of `bounds`
+    pub fn offset_range(&self, (min, max): (Vec3, Vec3)) -> std::ops::RangeInclusive<f32> {
+        let normal = self.normal.normalize_or_zero();
+        let (low, high) = (0..8)
+            .map(|i| {
+                Vec3::new(
+                    if i & 1 == 0 { min.x } else { max.x },
+                    if i & 2 == 0 { min.y } else { max.y },
+                    if i & 4 == 0 { min.z } else { max.z },
+                )
+                .dot(normal)
+            })
+            .fold((f32::MAX, f32::MIN), |(low, high), d| {
+                (low.min(d), high.max(d))
+            });
+        low..=high
+    }
+}
+
+// Triangles with a corner behind the plane, those it cuts through stay whole
+// so the section has no gaps
+pub fn clip_triangles(
+    vertices: &[Vec3],
+    indices: &[u32],
+    (normal, offset): (Vec3, f32),
+) -> Vec<u32> {
+    indices
+        .chunks_exact(3)
+        .filter(|tri| {
+            tri.iter()
+                .any(|&index| vertices[index as usize].dot(normal) <= offset)
+        })
+        .flatten()
+        .copied()
+        .collect()
+}
+
+// Plane controls, `bounds` is the world box the offset slider covers
+pub fn section_controls(ui: &mut egui::Ui, section: &mut SectionPlane, bounds: (Vec3, Vec3)) {
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut section.enabled, "Section Plane");
+        for (label, axis) in [("X", Vec3::X), ("Y", Vec3::Y), ("Z", Vec3::Z)] {
+            if ui.small_button(label).clicked() {
+                section.normal = axis;
+            }
+        }
+        if ui.small_button("Flip").clicked() {
+            section.normal = -section.normal;
+            section.offset = -section.offset;
+        }
+    });
+    if !section.enabled {
+        return;
+    }
+    ui.horizontal(|ui| {
+        ui.label("Normal:");
+        for axis in 0..3 {
+            ui.add(
+                egui::DragValue::new(&mut section.normal[axis])
+                    .speed(0.01)
+                    .clamp_range(-1.0..=1.0),
+            );
+        }
+    });
+    let range = section.offset_range(bounds);
+    ui.add(egui::Slider::new(&mut section.offset, range).text("Section Offset"));
+}
+
+// Re-tile when the plane moves, or a layer moves while it is on
+pub fn rebuild_on_section_change(
+    section: Res<SectionPlane>,
+    layers: Res<layers::MeshLayers>,
+    mut mesh_viewer: Query<&mut MeshViewer>,
+    mut last: Local<Option<(SectionPlane, Vec<Transform>)>>,
+) {
+    let transforms = if section.enabled {
+        let ids = std::iter::once(layers::PRIMARY_LAYER)
+            .chain(layers.layers.iter().map(|layer| layer.id));
+        ids.map(|id| layers.transform(id)).collect()
+    } else {
+        Vec::new()
+    };
+    let current = (*section, transforms);
+    let Some(previous) = last.replace(current.clone()) else {
+        return;
+    };
+    // Moving a plane that is off changes nothing
+    let cuts = |(section, _): &(SectionPlane, Vec<Transform>)| section.enabled;
+    if previous != current && (cuts(&previous) || cuts(&current)) {
+        for mut viewer in mesh_viewer.iter_mut() {
+            viewer.needs_rebuild = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clip_triangles() {
+        // A floor at 0 and a bridge deck at 10, with a ramp between them
+        let vertices = vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 10.0, 0.0),
+            Vec3::new(1.0, 10.0, 0.0),
+            Vec3::new(0.0, 10.0, 1.0),
+        ];
+        let indices = vec![0, 2, 1, 3, 5, 4, 0, 2, 4];
+        let mut section = SectionPlane {
+            enabled: true,
+            offset: 5.0,
+            ..default()
+        };
+
+        // The deck is cut away, the ramp stays whole
+        let plane = section.local_plane(&Transform::IDENTITY).unwrap();
+        assert_eq!(
+            clip_triangles(&vertices, &indices, plane),
+            vec![0, 2, 1, 0, 2, 4]
+        );
+
+        // Seen from a layer raised by 10 and drawn twice as big
+        let raised = Transform::from_xyz(0.0, 10.0, 0.0).with_scale(Vec3::splat(2.0));
+        let (normal, offset) = section.local_plane(&raised).unwrap();
+        assert_eq!((normal, offset), (Vec3::Y * 2.0, -5.0));
+
+        assert_eq!(
+            section.offset_range((Vec3::ZERO, Vec3::splat(10.0))),
+            0.0..=10.0
+        );
+
+        // Rays from above start at the plane, those going up never reach it
+        let above = Vec3::new(1.0, 20.0, 0.0);
+        assert_eq!(
+            section.clip_ray(above, Vec3::NEG_Y),
+            Some(Vec3::new(1.0, 5.0, 0.0))
+        );
+        assert_eq!(section.clip_ray(above, Vec3::Y), None);
+        assert_eq!(section.clip_ray(Vec3::ZERO, Vec3::Y), Some(Vec3::ZERO));
+        section.enabled = false;
+        assert_eq!(section.local_plane(&Transform::IDENTITY), None);
+    }
+}
@@ -0,0 +1,376 @@
+use crate::{
+    camera_forward, fit_view, layers, measure, section, transformed_bounds, world, MainCamera,
+    MeshData, TileMesh, TileSettings, MIN_TILE_SIZE,
+};
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use bevy_egui::{egui, EguiContexts};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+// Tiles picked for review, highlighted by `highlight_tiles`. While `picking`
+// is on a click on the mesh adds or removes the tile under the cursor
+#[derive(Resource, Default)]
+pub struct TileSelection {
+    pub picking: bool,
+    pub tiles: BTreeSet<(i32, i32)>,
+    pub layer: u32,   // Layer the tiles are in, picking another one starts over
+    pub name: String, // Name the selection is saved under
+    pub frame_requested: bool, // Set by the UI, handled by `frame_selection`
+    pub message: Option<String>,
+}
+
+impl TileSelection {
+    pub fn toggle(&mut self, tile: (i32, i32)) {
+        if !self.tiles.remove(&tile) {
+            self.tiles.insert(tile);
+        }
+    }
+
+    // Tiles of a set picked at another tile size or on a layer that isn't
+    // loaded aren't the ones it was saved with, that gets a warning
+    pub fn load(&mut self, set: &SelectionSet, tile_size: f32, layers: &layers::MeshLayers) {
+        self.tiles = set.tiles.iter().copied().collect();
+        self.layer = set.layer;
+        self.name = set.name.clone();
+        self.message = if set.tile_size != tile_size {
+            Some(format!(
+                "{} was saved with {} unit tiles, the mesh uses {}",
+                set.name, set.tile_size, tile_size
+            ))
+        } else if set.layer != layers::PRIMARY_LAYER && layers.mesh_data(set.layer).is_none() {
+            Some(format!(
+                "{} was saved on layer {}, which isn't loaded",
+                set.name, set.layer
+            ))
+        } else {
+            None
+        };
+        if let Some(message) = &self.message {
+            warn!("{}", message);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SelectionSet {
+    pub name: String,
+    pub tiles: Vec<(i32, i32)>,
+    pub tile_size: f32, // Tiles only line up again at the same size
+    pub layer: u32,
+}
+
+// Saved selections, one per name
+#[derive(Resource, Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct SelectionSets(pub Vec<SelectionSet>);
+
+#[derive(Debug)]
+pub enum SelectionError {
+    IoError(io::Error),
+    ParseError(serde_json::Error),
+}
+
+impl From<io::Error> for SelectionError {
+    fn from(error: io::Error) -> Self {
+        SelectionError::IoError(error)
+    }
+}
+
+impl From<serde_json::Error> for SelectionError {
+    fn from(error: serde_json::Error) -> Self {
+        SelectionError::ParseError(error)
+    }
+}
+
+impl std::fmt::Display for SelectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SelectionError::IoError(e) => write!(f, "{}", e),
+            SelectionError::ParseError(e) => write!(f, "invalid selections file: {}", e),
+        }
+    }
+}
+
+// tile_selections.json lives next to the executable, like the bookmarks
+pub fn selections_path() -> PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+        .unwrap_or_default()
+        .join("tile_selections.json")
+}
+
+impl SelectionSets {
+    pub fn load(path: &Path) -> Result<Self, SelectionError> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SelectionError> {
+        let file = File::create(path)?;
+        Ok(serde_json::to_writer_pretty(BufWriter::new(file), self)?)
+    }
+
+    // Missing file means nothing saved yet, anything else is worth a warning
+    pub fn load_or_default(path: &Path) -> Self {
+        match Self::load(path) {
+            Ok(sets) => sets,
+            Err(SelectionError::IoError(e)) if e.kind() == io::ErrorKind::NotFound => {
+                Self::default()
+            }
+            Err(e) => {
+                warn!("Failed to load {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+
+    // Store `selection` under its name, replacing a set saved with that name
+    pub fn store(&mut self, selection: &TileSelection, tile_size: f32) {
+        let set = SelectionSet {
+            name: selection.name.trim().to_string(),
+            tiles: selection.tiles.iter().copied().collect(),
+            tile_size,
+            layer: selection.layer,
+        };
+        match self.0.iter_mut().find(|saved| saved.name == set.name) {
+            Some(saved) => *saved = set,
+            None => self.0.push(set),
+        }
+    }
+}
+
+// Tile a point in the mesh's space falls into, binned like the triangles
+pub fn tile_at(point: Vec3, tile_size: f32) -> (i32, i32) {
+    let tile_size = tile_size.max(MIN_TILE_SIZE);
+    (
+        (point.x / tile_size).floor() as i32,
+        (point.z / tile_size).floor() as i32,
+    )
+}
+
+pub fn selection_window(
+    ctx: &egui::Context,
+    selection: &mut TileSelection,
+    sets: &mut SelectionSets,
+    tile_size: f32,
+    layers: &layers::MeshLayers,
+) {
+    egui::Window::new("Tile Selection")
+        .default_open(false)
+        .show(ctx, |ui| {
+            ui.checkbox(&mut selection.picking, "Click tiles to select");
+            ui.horizontal(|ui| {
+                ui.label(format!("{} tiles", selection.tiles.len()));
+                if ui.button("Frame").clicked() {
+                    selection.frame_requested = true;
+                }
+                if ui.button("Clear").clicked() {
+                    selection.tiles.clear();
+                }
+            });
+            if let Some(message) = &selection.message {
+                ui.colored_label(egui::Color32::YELLOW, message);
+            }
+
+            let mut changed = false;
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut selection.name);
+                let can_save = !selection.name.trim().is_empty() && !selection.tiles.is_empty();
+                if ui
+                    .add_enabled(can_save, egui::Button::new("Save"))
+                    .clicked()
+                {
+                    sets.store(selection, tile_size);
+                    changed = true;
+                }
+            });
+
+            let mut remove = None;
+            for (index, set) in sets.0.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    if ui
+                        .button(format!("{} ({} tiles)", set.name, set.tiles.len()))
+                        .clicked()
+                    {
+                        selection.load(set, tile_size, layers);
+                        selection.frame_requested = true;
+                    }
+                    if ui.small_button("x").clicked() {
+                        remove = Some(index);
+                    }
+                });
+            }
+            if let Some(index) = remove {
+                sets.0.remove(index);
+                changed = true;
+            }
+
+            if changed {
+                let path = selections_path();
+                if let Err(e) = sets.save(&path) {
+                    warn!("Failed to save {}: {}", path.display(), e);
+                }
+            }
+        });
+}
+
+// Add or remove the tile under a click while picking
+#[allow(clippy::too_many_arguments)]
+pub fn pick_tiles(
+    mut selection: ResMut<TileSelection>,
+    mouse_button: Res<Input<MouseButton>>,
+    mut contexts: EguiContexts,
+    measure: Res<measure::MeasureTool>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Transform, &Camera), With<MainCamera>>,
+    mesh_data: Option<Res<MeshData>>,
+    picking_grid: Option<Res<world::TriangleGrid>>,
+    layers: Res<layers::MeshLayers>,
+    section: Res<section::SectionPlane>,
+    tile_settings: Res<TileSettings>,
+) {
+    if !selection.picking
+        || measure.enabled
+        || !mouse_button.just_pressed(MouseButton::Left)
+        || contexts.ctx_mut().wants_pointer_input()
+    {
+        return;
+    }
+    let (Ok(window), Ok((transform, camera))) = (windows.get_single(), camera_query.get_single())
+    else {
+        return;
+    };
+    let Some(cursor_pos) = window.cursor_position() else {
+        return;
+    };
+    let (origin, direction) = world::screen_to_ray(window, camera, transform, cursor_pos);
+    let primary = mesh_data
+        .as_deref()
+        .map(|mesh_data| (mesh_data, picking_grid.as_deref()));
+    let Some((layer, point, ..)) = section
+        .clip_ray(origin, direction)
+        .and_then(|origin| layers.raycast(primary, origin, direction))
+    else {
+        return;
+    };
+    // Tiles are binned in the space of their mesh
+    let local = layers
+        .transform(layer)
+        .compute_affine()
+        .inverse()
+        .transform_point3(point);
+    if layer != selection.layer {
+        selection.tiles.clear();
+        selection.layer = layer;
+    }
+    selection.toggle(tile_at(local, tile_settings.tile_size));
+    selection.message = None;
+}
+
+// Point the camera at the combined bounds of the selected tiles, moved to
+// where their layer is drawn
+pub fn frame_selection(
+    mut selection: ResMut<TileSelection>,
+    tiles: Query<(&TileMesh, Option<&layers::MeshLayerId>)>,
+    layers: Res<layers::MeshLayers>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut camera_query: Query<(&mut Transform, &mut MainCamera, &mut Projection)>,
+) {
+    if !std::mem::take(&mut selection.frame_requested) {
+        return;
+    }
+    let transform = layers.transform(selection.layer);
+    let bounds = tiles
+        .iter()
+        .filter(|(tile, layer)| {
+            layer.map_or(layers::PRIMARY_LAYER, |layer| layer.0) == selection.layer
+                && selection.tiles.contains(&(tile.tile_x, tile.tile_y))
+        })
+        .map(|(tile, _)| {
+            let bounds = (Vec3::from(tile.aabb.min()), Vec3::from(tile.aabb.max()));
+            transformed_bounds(bounds, &transform)
+        })
+        .reduce(|(min_a, max_a), (min_b, max_b)| (min_a.min(min_b), max_a.max(max_b)));
+    let Some(bounds) = bounds else {
+        selection.message = Some("None of the selected tiles are loaded".to_string());
+        return;
+    };
+    selection.message = None;
+    let Ok((mut transform, mut camera, mut projection)) = camera_query.get_single_mut() else {
+        return;
+    };
+    let aspect = windows
+        .get_single()
+        .map_or(1.0, |window| window.width() / window.height().max(1.0));
+    transform.translation = fit_view(&mut camera, &mut projection, bounds, aspect);
+    transform.look_to(camera_forward(camera.yaw, camera.pitch), Vec3::Y);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selection_sets_round_trip() {
+        let path = std::env::temp_dir().join(format!(
+            "mesh_viewer_{}_selections.json",
+            std::process::id()
+        ));
+
+        let mut selection = TileSelection {
+            name: " bridge ".to_string(),
+            ..default()
+        };
+        selection.toggle(tile_at(Vec3::new(25.0, 3.0, -5.0), 10.0));
+        selection.toggle((4, 4));
+        selection.toggle((0, 0));
+        selection.toggle((0, 0));
+        assert_eq!(selection.tiles, BTreeSet::from([(2, -1), (4, 4)]));
+
+        // Saving under a name already used replaces that set
+        let mut sets = SelectionSets::default();
+        sets.store(&selection, 10.0);
+        selection.tiles.remove(&(4, 4));
+        sets.store(&selection, 10.0);
+        assert_eq!(
+            sets.0,
+            vec![SelectionSet {
+                name: "bridge".to_string(),
+                tiles: vec![(2, -1)],
+                tile_size: 10.0,
+                layer: layers::PRIMARY_LAYER,
+            }]
+        );
+        sets.save(&path).unwrap();
+        assert_eq!(SelectionSets::load(&path).unwrap(), sets);
+
+        let layers = layers::MeshLayers::default();
+        let mut loaded = TileSelection::default();
+        loaded.load(&sets.0[0], 10.0, &layers);
+        assert_eq!(loaded.tiles, selection.tiles);
+        assert_eq!(loaded.name, "bridge");
+        assert_eq!(loaded.message, None);
+
+        // The tiles are still loaded when they no longer line up, with a warning
+        loaded.load(&sets.0[0], 20.0, &layers);
+        assert_eq!(loaded.tiles, selection.tiles);
+        assert!(loaded.message.is_some());
+        sets.0[0].layer = 3;
+        loaded.load(&sets.0[0], 10.0, &layers);
+        assert_eq!(loaded.layer, 3);
+        assert!(loaded.message.is_some());
+    }
+
+    #[test]
+    fn test_missing_selections_file() {
+        let path = std::env::temp_dir().join("mesh_viewer_no_such_selections.json");
+        assert_eq!(
+            SelectionSets::load_or_default(&path),
+            SelectionSets::default()
+        );
+    }
+}
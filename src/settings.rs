@@ -0,0 +1,459 @@
+use crate::actors::ActorStyles;
+use crate::budget::{self, MeshBudget};
+use crate::net;
+use crate::{
+    AntiAliasing, CameraSpeed, ClipPlanes, ElevationColors, ImportOptions, MeshViewer, RenderMode,
+    SlopeColors, TileSettings,
+};
+use bevy::prelude::*;
+use bevy_egui::EguiContexts;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// Settings are written once they have stopped changing for this long, so
+// dragging a slider doesn't save every frame
+pub const SAVE_DELAY: Duration = Duration::from_secs(1);
+
+// Edits that settle for this long become an undo step, so a slider drag is
+// one step rather than one per frame
+pub const HISTORY_DELAY: Duration = Duration::from_millis(500);
+
+// Oldest undo steps are dropped past this many
+pub const HISTORY_LIMIT: usize = 50;
+
+// Viewer options remembered across runs, missing keys take the default
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ViewerSettings {
+    pub mitm_address: String,
+    pub max_frame_len: u32, // Longer actor frames drop the MITM connection
+    pub tile_size: f32,
+    pub slope_colors: SlopeColors, // The walkable slope angle is the first band's limit
+    pub render_mode: RenderMode,
+    pub actor_styles: ActorStyles,
+    pub clip_planes: ClipPlanes,
+    pub anti_aliasing: AntiAliasing,
+    pub import_options: ImportOptions,
+    pub camera_speed: CameraSpeed,
+    pub memory_budget_mb: f32,
+    pub elevation_colors: ElevationColors,
+}
+
+impl Default for ViewerSettings {
+    fn default() -> Self {
+        Self {
+            mitm_address: "127.0.0.1:9999".to_string(),
+            max_frame_len: net::MAX_FRAME_LEN,
+            tile_size: TileSettings::default().tile_size,
+            slope_colors: SlopeColors::default(),
+            render_mode: RenderMode::default(),
+            actor_styles: ActorStyles::default(),
+            clip_planes: ClipPlanes::default(),
+            anti_aliasing: AntiAliasing::default(),
+            import_options: ImportOptions::default(),
+            camera_speed: CameraSpeed::default(),
+            memory_budget_mb: budget::DEFAULT_BUDGET_MB,
+            elevation_colors: ElevationColors::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    IoError(io::Error),
+    ParseError(serde_json::Error),
+}
+
+impl From<io::Error> for SettingsError {
+    fn from(error: io::Error) -> Self {
+        SettingsError::IoError(error)
+    }
+}
+
+impl From<serde_json::Error> for SettingsError {
+    fn from(error: serde_json::Error) -> Self {
+        SettingsError::ParseError(error)
+    }
+}
+
+impl std::fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingsError::IoError(e) => write!(f, "{}", e),
+            SettingsError::ParseError(e) => write!(f, "invalid settings file: {}", e),
+        }
+    }
+}
+
+// The user's config directory on this platform, None when the environment
+// doesn't name one
+fn config_dir() -> Option<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+    if cfg!(windows) {
+        var("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        var("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| var("HOME").map(|home| PathBuf::from(home).join(".config")))
+    }
+}
+
+// settings.json lives in a mesh_viewer folder of the config directory, next
+// to the executable when there is none
+pub fn settings_path() -> PathBuf {
+    match config_dir() {
+        Some(dir) => dir.join("mesh_viewer").join("settings.json"),
+        None => std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(Path::to_path_buf))
+            .unwrap_or_default()
+            .join("settings.json"),
+    }
+}
+
+impl ViewerSettings {
+    pub fn load(path: &Path) -> Result<Self, SettingsError> {
+        let file = File::open(path)?;
+        let mut settings: Self = serde_json::from_reader(BufReader::new(file))?;
+
+        // Hand edited files can break what the viewer relies on
+        if settings.slope_colors.bands.is_empty() {
+            settings.slope_colors = SlopeColors::default();
+        }
+        settings.slope_colors.clamp_angles();
+        settings.slope_colors.sort_bands();
+        settings.clip_planes = settings.clip_planes.validated();
+        settings.camera_speed = settings.camera_speed.validated();
+        Ok(settings)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SettingsError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = File::create(path)?;
+        Ok(serde_json::to_writer_pretty(BufWriter::new(file), self)?)
+    }
+
+    pub fn load_or_default(path: &Path) -> Self {
+        match Self::load(path) {
+            Ok(settings) => settings,
+            Err(SettingsError::IoError(e)) if e.kind() == io::ErrorKind::NotFound => {
+                Self::default()
+            }
+            Err(e) => {
+                warn!("Failed to load {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+}
+
+// Waits for edits to settle before `save_settings` writes the file
+#[derive(Default)]
+pub struct SettingsSaver {
+    changed_at: Option<Instant>,
+}
+
+impl SettingsSaver {
+    pub fn mark_changed(&mut self, now: Instant) {
+        self.changed_at = Some(now);
+    }
+
+    // True once per burst of changes, `SAVE_DELAY` after the last one
+    pub fn update(&mut self, now: Instant) -> bool {
+        match self.changed_at {
+            Some(changed_at) if now.duration_since(changed_at) >= SAVE_DELAY => {
+                self.changed_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// Undo and redo of the slope classification, as settings snapshots. Only
+// changes to the slope colors count, everything else in a snapshot is ignored
+#[derive(Default)]
+pub struct SettingsHistory {
+    current: Option<ViewerSettings>, // What the viewer shows, top of the undo stack
+    undo: Vec<ViewerSettings>,
+    redo: Vec<ViewerSettings>,
+    pending: Option<(ViewerSettings, Instant)>, // An edit that hasn't settled yet
+}
+
+impl SettingsHistory {
+    // Note the settings after an edit, they become a step once they settle
+    pub fn record(&mut self, settings: &ViewerSettings, now: Instant) {
+        match &self.current {
+            None => self.current = Some(settings.clone()),
+            // Dragged back to where it started, nothing to undo
+            Some(current) if current.slope_colors == settings.slope_colors => self.pending = None,
+            Some(_) => self.pending = Some((settings.clone(), now)),
+        }
+    }
+
+    pub fn update(&mut self, now: Instant) {
+        if let Some((_, changed_at)) = self.pending {
+            if now.duration_since(changed_at) >= HISTORY_DELAY {
+                self.commit();
+            }
+        }
+    }
+
+    fn commit(&mut self) {
+        let Some((settings, _)) = self.pending.take() else {
+            return;
+        };
+        if let Some(previous) = self.current.replace(settings) {
+            self.undo.push(previous);
+            if self.undo.len() > HISTORY_LIMIT {
+                self.undo.remove(0);
+            }
+        }
+        self.redo.clear();
+    }
+
+    // The settings to go back to, an unsettled edit is undone first
+    pub fn undo(&mut self) -> Option<&ViewerSettings> {
+        self.commit();
+        let previous = self.undo.pop()?;
+        self.redo.extend(self.current.replace(previous));
+        self.current.as_ref()
+    }
+
+    pub fn redo(&mut self) -> Option<&ViewerSettings> {
+        let next = self.redo.pop()?;
+        self.undo.extend(self.current.replace(next));
+        self.current.as_ref()
+    }
+}
+
+// Ctrl+Z steps back through the slope color history, Ctrl+Shift+Z forward.
+// Restored colors go through `recolor_meshes` like any other edit
+pub fn undo_settings(
+    mut history: Local<SettingsHistory>,
+    mut contexts: EguiContexts,
+    keyboard: Res<Input<KeyCode>>,
+    settings: Res<ViewerSettings>,
+    mut slope_colors: ResMut<SlopeColors>,
+    mut mesh_viewer: Query<&mut MeshViewer>,
+) {
+    let now = Instant::now();
+    if settings.is_changed() {
+        history.record(&settings, now);
+    }
+    history.update(now);
+
+    let ctrl = keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    let shift = keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    if !ctrl || !keyboard.just_pressed(KeyCode::Z) || contexts.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+    let restored = if shift {
+        history.redo()
+    } else {
+        history.undo()
+    };
+    if let Some(restored) = restored {
+        *slope_colors = restored.slope_colors.clone();
+        for mut viewer in mesh_viewer.iter_mut() {
+            viewer.walkable_slope_angle = slope_colors.bands[0].max_angle;
+        }
+    }
+}
+
+// Copy edited values into the settings and save them once they settle.
+// Values from the command line are only kept once something is edited
+#[allow(clippy::too_many_arguments)]
+pub fn save_settings(
+    mut settings: ResMut<ViewerSettings>,
+    mut saver: Local<SettingsSaver>,
+    slope_colors: Res<SlopeColors>,
+    tile_settings: Res<TileSettings>,
+    render_mode: Res<RenderMode>,
+    actor_styles: Res<ActorStyles>,
+    clip_planes: Res<ClipPlanes>,
+    anti_aliasing: Res<AntiAliasing>,
+    import_options: Res<ImportOptions>,
+    camera_speed: Res<CameraSpeed>,
+    budget: Res<MeshBudget>,
+    elevation_colors: Res<ElevationColors>,
+) {
+    if slope_colors.is_changed()
+        && !slope_colors.is_added()
+        && settings.slope_colors != *slope_colors
+    {
+        settings.slope_colors = slope_colors.clone();
+    }
+    if tile_settings.is_changed()
+        && !tile_settings.is_added()
+        && settings.tile_size != tile_settings.tile_size
+    {
+        settings.tile_size = tile_settings.tile_size;
+    }
+    if render_mode.is_changed() && !render_mode.is_added() && settings.render_mode != *render_mode {
+        settings.render_mode = *render_mode;
+    }
+    if actor_styles.is_changed()
+        && !actor_styles.is_added()
+        && settings.actor_styles != *actor_styles
+    {
+        settings.actor_styles = actor_styles.clone();
+    }
+    if clip_planes.is_changed() && !clip_planes.is_added() && settings.clip_planes != *clip_planes {
+        settings.clip_planes = *clip_planes;
+    }
+    if anti_aliasing.is_changed()
+        && !anti_aliasing.is_added()
+        && settings.anti_aliasing != *anti_aliasing
+    {
+        settings.anti_aliasing = *anti_aliasing;
+    }
+    if import_options.is_changed()
+        && !import_options.is_added()
+        && settings.import_options != *import_options
+    {
+        settings.import_options = *import_options;
+    }
+    if camera_speed.is_changed()
+        && !camera_speed.is_added()
+        && settings.camera_speed != *camera_speed
+    {
+        settings.camera_speed = *camera_speed;
+    }
+    if budget.is_changed() && !budget.is_added() && settings.memory_budget_mb != budget.limit_mb {
+        settings.memory_budget_mb = budget.limit_mb;
+    }
+    if elevation_colors.is_changed()
+        && !elevation_colors.is_added()
+        && settings.elevation_colors != *elevation_colors
+    {
+        settings.elevation_colors = *elevation_colors;
+    }
+
+    let now = Instant::now();
+    if settings.is_changed() && !settings.is_added() {
+        saver.mark_changed(now);
+    }
+    if saver.update(now) {
+        let path = settings_path();
+        if let Err(e) = settings.save(&path) {
+            warn!("Failed to save {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("mesh_viewer_{}_settings.json", std::process::id()));
+
+        let mut settings = ViewerSettings {
+            mitm_address: "10.0.0.2:4000".to_string(),
+            max_frame_len: 4096,
+            tile_size: 250.0,
+            render_mode: RenderMode::Wireframe,
+            ..Default::default()
+        };
+        settings.slope_colors.bands[0].max_angle = 30.0;
+        settings.slope_colors.base = [0.5, 0.6, 0.7];
+        settings.save(&path).unwrap();
+        assert_eq!(ViewerSettings::load(&path).unwrap(), settings);
+
+        // Older files without the key still load
+        std::fs::write(&path, "{}").unwrap();
+        assert_eq!(
+            ViewerSettings::load(&path).unwrap(),
+            ViewerSettings::default()
+        );
+
+        // Broken files fall back to the defaults
+        std::fs::write(&path, "{\"tile_size\": \"big\"").unwrap();
+        assert!(ViewerSettings::load(&path).is_err());
+        assert_eq!(
+            ViewerSettings::load_or_default(&path),
+            ViewerSettings::default()
+        );
+        std::fs::write(
+            &path,
+            "{\"slope_colors\": {\"base\": [1, 1, 1], \"bands\": []}}",
+        )
+        .unwrap();
+        assert!(!ViewerSettings::load(&path)
+            .unwrap()
+            .slope_colors
+            .bands
+            .is_empty());
+    }
+
+    #[test]
+    fn test_settings_history() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let with_angle = |angle| {
+            let mut settings = ViewerSettings::default();
+            settings.slope_colors.bands[0].max_angle = angle;
+            settings
+        };
+        let angle =
+            |settings: Option<&ViewerSettings>| settings.map(|s| s.slope_colors.bands[0].max_angle);
+
+        let mut history = SettingsHistory::default();
+        history.record(&with_angle(45.0), at(0));
+        assert!(history.undo().is_none());
+
+        // A drag through several values is a single step
+        history.record(&with_angle(40.0), at(0));
+        history.record(&with_angle(35.0), at(100));
+        history.record(&with_angle(30.0), at(200));
+        history.update(at(200) + HISTORY_DELAY);
+        history.record(&with_angle(20.0), at(2000));
+
+        // The unsettled edit is undone first
+        assert_eq!(angle(history.undo()), Some(30.0));
+        assert_eq!(angle(history.undo()), Some(45.0));
+        assert!(history.undo().is_none());
+        assert_eq!(angle(history.redo()), Some(30.0));
+
+        // Another edit drops what could be redone
+        history.record(&with_angle(60.0), at(3000));
+        history.update(at(3000) + HISTORY_DELAY);
+        assert!(history.redo().is_none());
+        assert_eq!(angle(history.undo()), Some(30.0));
+
+        // Only the slope colors count
+        let mut history = SettingsHistory::default();
+        history.record(&ViewerSettings::default(), at(0));
+        let moved = ViewerSettings {
+            tile_size: 10.0,
+            ..Default::default()
+        };
+        history.record(&moved, at(0));
+        history.update(at(0) + HISTORY_DELAY);
+        assert!(history.undo().is_none());
+    }
+
+    #[test]
+    fn test_settings_saver_debounce() {
+        let start = Instant::now();
+        let mut saver = SettingsSaver::default();
+        assert!(!saver.update(start + SAVE_DELAY));
+
+        // Every change pushes the save back
+        saver.mark_changed(start);
+        saver.mark_changed(start + Duration::from_millis(600));
+        assert!(!saver.update(start + SAVE_DELAY));
+        assert!(saver.update(start + Duration::from_millis(600) + SAVE_DELAY));
+        assert!(!saver.update(start + SAVE_DELAY * 3));
+    }
+}
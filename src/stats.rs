@@ -0,0 +1,492 @@
+use crate::analysis::MeshAnalysis;
+use crate::obj_loader::load_obj;
+use crate::{
+    convert_obj_to_mesh_data, face_normal, is_degenerate, is_walkable, point_bounds, quantize_vec3,
+    triangle_tiles, ImportOptions, NormalWeighting, MIN_TILE_SIZE,
+};
+use glam::Vec3;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::process::ExitCode;
+
+// Degrees covered by each bar of the slope histogram, from flat to vertical
+pub const SLOPE_HISTOGRAM_BINS: usize = 90;
+
+// Summary of a mesh, for the headless report and the stats panel
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshStats {
+    pub vertex_count: usize,
+    pub triangle_count: usize,
+    pub bounds: (Vec3, Vec3),
+    pub tile_count: usize,
+    pub walkable_triangles: usize,
+    pub walkable_components: usize, // Islands of walkable triangles joined by edges
+    pub largest_component: usize,   // Triangles in the biggest island
+    pub boundary_edges: usize,      // Edges of a single triangle, around holes and borders
+    pub non_manifold_edges: usize,  // Edges shared by more than two triangles
+    pub degenerate_triangles: usize, // Zero-area, left out of the computed normals
+    pub slope_histogram: SlopeHistogram,
+}
+
+impl MeshStats {
+    pub fn new(
+        vertices: &[Vec3],
+        indices: &[u32],
+        tile_size: f32,
+        walkable_slope_angle: f32,
+    ) -> Self {
+        let edges = edge_health(vertices, indices);
+        let mut stats = Self {
+            vertex_count: vertices.len(),
+            triangle_count: indices.len() / 3,
+            bounds: point_bounds(vertices),
+            tile_count: count_tiles(vertices, indices, tile_size),
+            walkable_triangles: 0,
+            walkable_components: 0,
+            largest_component: 0,
+            boundary_edges: edges.boundary.len(),
+            non_manifold_edges: edges.non_manifold.len(),
+            degenerate_triangles: count_degenerate(vertices, indices),
+            slope_histogram: slope_histogram(vertices, indices),
+        };
+        stats.set_walkable_slope_angle(vertices, indices, walkable_slope_angle);
+        stats
+    }
+
+    // Recount what depends on the slope angle
+    pub fn set_walkable_slope_angle(
+        &mut self,
+        vertices: &[Vec3],
+        indices: &[u32],
+        walkable_slope_angle: f32,
+    ) {
+        let components = walkable_components(vertices, indices, walkable_slope_angle);
+        self.walkable_triangles = count_walkable(vertices, indices, walkable_slope_angle);
+        self.walkable_components = components.sizes.len();
+        self.largest_component = components.sizes.iter().copied().max().unwrap_or(0);
+    }
+
+    // Fraction of walkable triangles, 0 for an empty mesh
+    pub fn walkable_ratio(&self) -> f32 {
+        if self.triangle_count == 0 {
+            0.0
+        } else {
+            self.walkable_triangles as f32 / self.triangle_count as f32
+        }
+    }
+
+    // Fraction of the walkable triangles in the biggest island, 0 without any
+    pub fn largest_component_share(&self) -> f32 {
+        if self.walkable_triangles == 0 {
+            0.0
+        } else {
+            self.largest_component as f32 / self.walkable_triangles as f32
+        }
+    }
+}
+
+// Triangles by slope in one degree bins, the first is 0° up to 1°. Vertical
+// faces go in the last bin, those leaning past it face down and are counted
+// apart, degenerate triangles are left out
+#[derive(Serialize, Debug, Clone, PartialEq, Default)]
+pub struct SlopeHistogram {
+    pub bins: Vec<usize>,
+    pub facing_down: usize,
+}
+
+pub fn count_degenerate(vertices: &[Vec3], indices: &[u32]) -> usize {
+    indices
+        .chunks_exact(3)
+        .filter(|tri| {
+            is_degenerate(
+                vertices[tri[0] as usize],
+                vertices[tri[1] as usize],
+                vertices[tri[2] as usize],
+            )
+        })
+        .count()
+}
+
+pub fn slope_histogram(vertices: &[Vec3], indices: &[u32]) -> SlopeHistogram {
+    let mut histogram = SlopeHistogram {
+        bins: vec![0; SLOPE_HISTOGRAM_BINS],
+        facing_down: 0,
+    };
+    for triangle in 0..indices.len() / 3 {
+        let normal = face_normal(vertices, indices, triangle);
+        if normal == Vec3::ZERO {
+            continue;
+        }
+        let angle = normal.y.clamp(-1.0, 1.0).acos().to_degrees();
+        if angle > 90.0 {
+            histogram.facing_down += 1;
+        } else {
+            histogram.bins[(angle as usize).min(SLOPE_HISTOGRAM_BINS - 1)] += 1;
+        }
+    }
+    histogram
+}
+
+// The first vertex at the position of each vertex. `convert_obj_to_mesh_data`
+// splits a file vertex for every normal, uv and smoothing group it has, and
+// edges have to join the pieces again to connect the way the surface does
+pub fn position_ids(vertices: &[Vec3]) -> Vec<u32> {
+    let mut first: HashMap<[i64; 3], u32> = HashMap::new();
+    vertices
+        .iter()
+        .enumerate()
+        .map(|(vertex, &position)| {
+            *first
+                .entry(quantize_vec3(position))
+                .or_insert(vertex as u32)
+        })
+        .collect()
+}
+
+fn edge_key(ids: &[u32], a: u32, b: u32) -> (u32, u32) {
+    let (a, b) = (ids[a as usize], ids[b as usize]);
+    (a.min(b), a.max(b))
+}
+
+// Walkable triangles grouped into islands, two triangles are connected when
+// they share an edge, i.e. both corner positions of it
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct WalkableComponents {
+    pub triangle_component: Vec<Option<usize>>, // Per triangle, None when too steep
+    pub sizes: Vec<usize>,                      // Triangles in each component
+}
+
+pub fn walkable_components(
+    vertices: &[Vec3],
+    indices: &[u32],
+    walkable_slope_angle: f32,
+) -> WalkableComponents {
+    let triangle_count = indices.len() / 3;
+    let walkable: Vec<bool> = (0..triangle_count)
+        .map(|triangle| {
+            is_walkable(
+                face_normal(vertices, indices, triangle),
+                walkable_slope_angle,
+            )
+        })
+        .collect();
+
+    // Union-find over the triangles, joined through the first triangle seen
+    // on each edge
+    let mut parent: Vec<usize> = (0..triangle_count).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    let ids = position_ids(vertices);
+    let mut edges: HashMap<(u32, u32), usize> = HashMap::new();
+    for triangle in (0..triangle_count).filter(|&triangle| walkable[triangle]) {
+        let corners = &indices[triangle * 3..triangle * 3 + 3];
+        for (a, b) in [(0, 1), (1, 2), (2, 0)] {
+            let edge = edge_key(&ids, corners[a], corners[b]);
+            let other = *edges.entry(edge).or_insert(triangle);
+            let (root_a, root_b) = (root(&mut parent, triangle), root(&mut parent, other));
+            parent[root_a.max(root_b)] = root_a.min(root_b);
+        }
+    }
+
+    // Number the components in order of their first triangle
+    let mut components = WalkableComponents::default();
+    let mut numbers: HashMap<usize, usize> = HashMap::new();
+    for (triangle, &walkable) in walkable.iter().enumerate() {
+        if !walkable {
+            components.triangle_component.push(None);
+            continue;
+        }
+        let root = root(&mut parent, triangle);
+        let next = numbers.len();
+        let number = *numbers.entry(root).or_insert(next);
+        if number == components.sizes.len() {
+            components.sizes.push(0);
+        }
+        components.sizes[number] += 1;
+        components.triangle_component.push(Some(number));
+    }
+    components
+}
+
+// Edges that don't join exactly two triangles, as pairs of vertex indices with
+// the lower one first, sorted
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct EdgeHealth {
+    pub boundary: Vec<(u32, u32)>,
+    pub non_manifold: Vec<(u32, u32)>,
+}
+
+// Edges are between positions, each named by the first vertex there
+pub fn edge_health(vertices: &[Vec3], indices: &[u32]) -> EdgeHealth {
+    let ids = position_ids(vertices);
+    let mut triangles_per_edge: HashMap<(u32, u32), usize> = HashMap::new();
+    for corners in indices.chunks_exact(3) {
+        for (a, b) in [(0, 1), (1, 2), (2, 0)] {
+            let edge = edge_key(&ids, corners[a], corners[b]);
+            *triangles_per_edge.entry(edge).or_default() += 1;
+        }
+    }
+
+    let mut health = EdgeHealth::default();
+    for (edge, count) in triangles_per_edge {
+        match count {
+            1 => health.boundary.push(edge),
+            2 => {}
+            _ => health.non_manifold.push(edge),
+        }
+    }
+    health.boundary.sort_unstable();
+    health.non_manifold.sort_unstable();
+    health
+}
+
+// Tiles `split_mesh_into_tiles` would make, without building them
+pub fn count_tiles(vertices: &[Vec3], indices: &[u32], tile_size: f32) -> usize {
+    let tile_size = tile_size.max(MIN_TILE_SIZE);
+    (0..indices.len() / 3)
+        .flat_map(|triangle| triangle_tiles(vertices, indices, triangle, tile_size))
+        .collect::<HashSet<_>>()
+        .len()
+}
+
+// Triangles whose face is not steeper than the angle
+pub fn count_walkable(vertices: &[Vec3], indices: &[u32], walkable_slope_angle: f32) -> usize {
+    (0..indices.len() / 3)
+        .filter(|&triangle| {
+            is_walkable(
+                face_normal(vertices, indices, triangle),
+                walkable_slope_angle,
+            )
+        })
+        .count()
+}
+
+// Load an OBJ without opening a window and print its stats, and write its
+// analysis when given a path for it. Fails when either file can't be used
+pub fn run_headless(
+    path: &Path,
+    tile_size: f32,
+    walkable_slope_angle: f32,
+    import: ImportOptions,
+    analysis_path: Option<&Path>,
+) -> ExitCode {
+    let obj = match load_obj(path) {
+        Ok(obj) => obj,
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", path.display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let (vertices, indices, _, _) = convert_obj_to_mesh_data(&obj, NormalWeighting::Area, import);
+    let stats = MeshStats::new(&vertices, &indices, tile_size, walkable_slope_angle);
+
+    let (min, max) = stats.bounds;
+    println!("File: {}", path.display());
+    println!("Vertices: {}", stats.vertex_count);
+    println!("Triangles: {}", stats.triangle_count);
+    println!(
+        "Bounds: ({:.2}, {:.2}, {:.2}) - ({:.2}, {:.2}, {:.2})",
+        min.x, min.y, min.z, max.x, max.y, max.z
+    );
+    println!("Tiles: {} (tile size {})", stats.tile_count, tile_size);
+    println!(
+        "Walkable: {} / {} triangles ({:.1}%) at {}°",
+        stats.walkable_triangles,
+        stats.triangle_count,
+        stats.walkable_ratio() * 100.0,
+        walkable_slope_angle
+    );
+    println!(
+        "Unwalkable: {} triangles",
+        stats.triangle_count - stats.walkable_triangles
+    );
+    println!(
+        "Boundary edges: {}, non-manifold edges: {}",
+        stats.boundary_edges, stats.non_manifold_edges
+    );
+    println!("Degenerate triangles: {}", stats.degenerate_triangles);
+    println!(
+        "Walkable islands: {} (largest {:.1}% of walkable)",
+        stats.walkable_components,
+        stats.largest_component_share() * 100.0
+    );
+
+    if let Some(analysis_path) = analysis_path {
+        let analysis = MeshAnalysis::new(&vertices, &indices, tile_size, walkable_slope_angle);
+        if let Err(e) = analysis.save(analysis_path) {
+            eprintln!("Failed to write {}: {}", analysis_path.display(), e);
+            return ExitCode::FAILURE;
+        }
+        println!("Analysis: {}", analysis_path.display());
+    }
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mesh_stats() {
+        // A floor triangle and a wall triangle
+        let vertices = vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ];
+        let indices = vec![0, 1, 2, 0, 3, 1];
+
+        let stats = MeshStats::new(&vertices, &indices, 100.0, 45.0);
+        assert_eq!(stats.vertex_count, 4);
+        assert_eq!(stats.triangle_count, 2);
+        assert_eq!(stats.bounds, (Vec3::ZERO, Vec3::ONE));
+        assert_eq!(stats.tile_count, 1);
+        assert_eq!(stats.walkable_triangles, 1);
+        assert_eq!(stats.walkable_ratio(), 0.5);
+        assert_eq!(stats.degenerate_triangles, 0);
+
+        // Everything is walkable when any slope is
+        assert_eq!(count_walkable(&vertices, &indices, 90.0), 2);
+        assert_eq!(MeshStats::new(&[], &[], 100.0, 45.0).walkable_ratio(), 0.0);
+
+        // Corners on a tile edge also touch the tiles past it
+        assert_eq!(count_tiles(&vertices, &indices, 1.0), 4);
+    }
+
+    #[test]
+    fn test_walkable_components() {
+        // Two floor quads joined at an edge, a third one apart that only
+        // touches them at a corner, and a wall between
+        let vertices = vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(2.0, 0.0, 1.0),
+            Vec3::new(3.0, 0.0, 1.0),
+            Vec3::new(3.0, 0.0, 2.0),
+            Vec3::new(2.0, 0.0, 2.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ];
+        let indices = vec![
+            0, 2, 1, 0, 3, 2, // First quad
+            1, 5, 4, 1, 2, 5, // Second quad, sharing the 1-2 edge
+            5, 7, 6, 5, 8, 7, // Third quad, touching at vertex 5 only
+            0, 1, 9, // Wall
+        ];
+
+        let components = walkable_components(&vertices, &indices, 45.0);
+        assert_eq!(components.sizes, vec![4, 2]);
+        assert_eq!(
+            components.triangle_component,
+            vec![Some(0), Some(0), Some(0), Some(0), Some(1), Some(1), None]
+        );
+
+        let stats = MeshStats::new(&vertices, &indices, 100.0, 45.0);
+        assert_eq!(stats.walkable_triangles, 6);
+        assert_eq!(stats.walkable_components, 2);
+        assert_eq!(stats.largest_component_share(), 4.0 / 6.0);
+
+        // Nothing walkable, no islands
+        let stats = MeshStats::new(&vertices, &indices[18..], 100.0, 45.0);
+        assert_eq!(stats.walkable_components, 0);
+        assert_eq!(stats.largest_component_share(), 0.0);
+    }
+
+    #[test]
+    fn test_slope_histogram() {
+        // Flat, 30° and 60° ramps, a wall, one facing down and a degenerate one
+        let ramp = |angle: f32| {
+            let (sin, cos) = angle.to_radians().sin_cos();
+            [Vec3::ZERO, Vec3::new(0.0, sin, cos), Vec3::X]
+        };
+        let vertices: Vec<Vec3> = [
+            ramp(0.0),
+            ramp(30.5),
+            ramp(60.5),
+            ramp(90.0),
+            [Vec3::ZERO, Vec3::X, Vec3::Z],
+            [Vec3::ZERO, Vec3::X, Vec3::X],
+        ]
+        .concat();
+        let indices: Vec<u32> = (0..vertices.len() as u32).collect();
+
+        let histogram = slope_histogram(&vertices, &indices);
+        assert_eq!(histogram.bins.len(), SLOPE_HISTOGRAM_BINS);
+        assert_eq!(histogram.bins.iter().sum::<usize>(), 4);
+        assert_eq!(histogram.bins[0], 1);
+        assert_eq!(histogram.bins[30], 1);
+        assert_eq!(histogram.bins[60], 1);
+        assert_eq!(histogram.bins[89], 1);
+        assert_eq!(histogram.facing_down, 1);
+    }
+
+    #[test]
+    fn test_edge_health() {
+        // A closed tetrahedron has every edge on two faces
+        let vertices = [Vec3::ZERO, Vec3::X, Vec3::Z, Vec3::Y, Vec3::NEG_Y];
+        let tetrahedron = [0, 1, 2, 0, 3, 1, 1, 3, 2, 2, 3, 0];
+        assert_eq!(edge_health(&vertices, &tetrahedron), EdgeHealth::default());
+
+        // Without its last face there is a triangular hole
+        let open = &tetrahedron[..9];
+        assert_eq!(
+            edge_health(&vertices, open).boundary,
+            vec![(0, 2), (0, 3), (2, 3)]
+        );
+
+        // A fin on the closed one makes the 0-1 edge non-manifold
+        let mut fin = tetrahedron.to_vec();
+        fin.extend([0, 1, 4]);
+        let health = edge_health(&vertices, &fin);
+        assert_eq!(health.non_manifold, vec![(0, 1)]);
+        assert_eq!(health.boundary, vec![(0, 4), (1, 4)]);
+    }
+
+    #[test]
+    fn test_split_vertices_still_connect() {
+        // Two quads on a shared edge, every corner with a texture coordinate
+        // and normal of its own and smoothing off, so no vertex is shared
+        // once the mesh is built
+        let path = std::env::temp_dir().join(format!(
+            "mesh_viewer_{}_split_quads.obj",
+            std::process::id()
+        ));
+        let mut content = String::new();
+        for (x, z) in [(0, 0), (1, 0), (1, 1), (0, 1), (2, 0), (2, 1)] {
+            content += &format!("v {} 0 {}\n", x, z);
+        }
+        for i in 0..8 {
+            content += &format!("vt {} 0\nvn 0 1 0.{}\n", i, i);
+        }
+        content += "s off\nf 1/1/1 4/2/2 3/3/3 2/4/4\nf 2/5/5 3/6/6 6/7/7 5/8/8\n";
+        std::fs::write(&path, content).unwrap();
+
+        let obj = load_obj(&path).unwrap();
+        let (vertices, indices, ..) =
+            convert_obj_to_mesh_data(&obj, NormalWeighting::Area, ImportOptions::default());
+        assert!(vertices.len() >= 8);
+
+        let stats = MeshStats::new(&vertices, &indices, 100.0, 45.0);
+        assert_eq!(stats.boundary_edges, 6);
+        assert_eq!(stats.non_manifold_edges, 0);
+        assert_eq!(stats.walkable_components, 1);
+    }
+
+    #[test]
+    fn test_headless_missing_file() {
+        let path = std::env::temp_dir().join("mesh_viewer_no_such_mesh.obj");
+        assert_eq!(
+            run_headless(&path, 988.0, 45.0, ImportOptions::default(), None),
+            ExitCode::FAILURE
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use crate::{bin_triangles, build_tiles, spawn_tile, SlopeColors, TileGeometry, TileTriangles};
+use bevy::prelude::*;
+use bevy::tasks::{ComputeTaskPool, TaskPool};
+use glam::{Vec2, Vec3};
+use std::collections::VecDeque;
+
+// Triangles binned, or copied into tiles, per frame. Groups up to this size
+// are still tiled at once
+pub const TRIANGLES_PER_FRAME: usize = 250_000;
+
+// Geometry of one material group: vertices, indices, normals and uvs
+type GroupGeometry = (Vec<Vec3>, Vec<u32>, Vec<Vec3>, Vec<Vec2>);
+
+// A material group of a layer being split into tiles. Triangles are binned
+// first, then the tiles are built in order
+pub struct TilingGroup {
+    layer: u32,
+    material: Handle<StandardMaterial>,
+    slope_colors: SlopeColors,
+    geometry: GroupGeometry,
+    tile_size: f32,
+    next_triangle: u32,                 // First triangle not binned yet
+    binned: TileTriangles,              // Filled while binning
+    tiles: Vec<((i32, i32), Vec<u32>)>, // Taken from `binned` once all are in
+    next_tile: usize,                   // First of `tiles` not built yet
+    built: Vec<TileGeometry>,
+}
+
+impl TilingGroup {
+    pub fn new(
+        layer: u32,
+        material: Handle<StandardMaterial>,
+        slope_colors: SlopeColors,
+        geometry: GroupGeometry,
+        tile_size: f32,
+    ) -> Self {
+        Self {
+            layer,
+            material,
+            slope_colors,
+            geometry,
+            tile_size,
+            next_triangle: 0,
+            binned: TileTriangles::new(),
+            tiles: Vec::new(),
+            next_tile: 0,
+            built: Vec::new(),
+        }
+    }
+
+    fn triangle_count(&self) -> u32 {
+        (self.geometry.1.len() / 3) as u32
+    }
+
+    fn is_done(&self) -> bool {
+        self.next_triangle == self.triangle_count() && self.next_tile == self.tiles.len()
+    }
+
+    // Binning is the first half of the work and building the second
+    fn progress(&self) -> f32 {
+        let binned = self.next_triangle as f32 / self.triangle_count().max(1) as f32;
+        let built = if self.tiles.is_empty() {
+            0.0
+        } else {
+            self.next_tile as f32 / self.tiles.len() as f32
+        };
+        (binned + built) * 0.5
+    }
+
+    // Do up to `budget` triangles of work, returns what is left of it
+    fn advance(&mut self, pool: &TaskPool, budget: usize) -> usize {
+        let (vertices, indices, normals, uvs) = &self.geometry;
+        let triangle_count = self.triangle_count();
+        if self.next_triangle < triangle_count {
+            let end = (self.next_triangle as usize + budget).min(triangle_count as usize) as u32;
+            bin_triangles(
+                pool,
+                vertices,
+                indices,
+                self.next_triangle..end,
+                self.tile_size,
+                &mut self.binned,
+            );
+            let used = (end - self.next_triangle) as usize;
+            self.next_triangle = end;
+            if end == triangle_count {
+                self.tiles = std::mem::take(&mut self.binned).into_iter().collect();
+            }
+            return budget - used;
+        }
+
+        // Whole tiles, at least one so a tile bigger than the budget gets done
+        let mut used = 0;
+        let mut end = self.next_tile;
+        while end < self.tiles.len() && (end == self.next_tile || used < budget) {
+            used += self.tiles[end].1.len();
+            end += 1;
+        }
+        self.built.extend(build_tiles(
+            pool,
+            &self.tiles[self.next_tile..end],
+            vertices,
+            indices,
+            normals,
+            uvs,
+        ));
+        self.next_tile = end;
+        budget.saturating_sub(used)
+    }
+}
+
+// Tiling spread over frames so big meshes don't stall the window. A layer's
+// tiles are spawned together once all its groups are done
+#[derive(Resource, Default)]
+pub struct IncrementalTiler {
+    groups: VecDeque<TilingGroup>, // The first one is worked on
+    done: Vec<TilingGroup>,        // Waiting for the rest of their layer
+    finished_triangles: usize,     // Of the groups done since the tiler was last idle
+}
+
+impl IncrementalTiler {
+    pub fn push(&mut self, group: TilingGroup) {
+        self.groups.push_back(group);
+    }
+
+    // Drop the work queued for a layer, e.g. when a new mesh replaces it
+    pub fn cancel(&mut self, layer: u32) {
+        self.groups.retain(|group| group.layer != layer);
+        self.done.retain(|group| group.layer != layer);
+        if self.is_idle() {
+            self.finished_triangles = 0;
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.groups.is_empty() && self.done.is_empty()
+    }
+
+    // Fraction of the queued work done, None when there is none
+    pub fn progress(&self) -> Option<f32> {
+        if self.is_idle() {
+            return None;
+        }
+        let pending = self
+            .groups
+            .iter()
+            .map(|group| group.triangle_count() as f32);
+        let pending_done = self
+            .groups
+            .iter()
+            .map(|group| group.triangle_count() as f32 * group.progress());
+        let finished = self.finished_triangles as f32;
+        Some((finished + pending_done.sum::<f32>()) / (finished + pending.sum::<f32>()).max(1.0))
+    }
+
+    // Work through up to `budget` triangles, returning the groups whose layer
+    // is now fully tiled
+    fn advance(&mut self, pool: &TaskPool, mut budget: usize) -> Vec<TilingGroup> {
+        while budget > 0 {
+            let Some(group) = self.groups.front_mut() else {
+                break;
+            };
+            budget = group.advance(pool, budget);
+            if group.is_done() {
+                let group = self.groups.pop_front().unwrap();
+                self.finished_triangles += group.triangle_count() as usize;
+                self.done.push(group);
+            }
+        }
+
+        let (ready, waiting) =
+            std::mem::take(&mut self.done)
+                .into_iter()
+                .partition(|done: &TilingGroup| {
+                    self.groups.iter().all(|group| group.layer != done.layer)
+                });
+        self.done = waiting;
+        if self.is_idle() {
+            self.finished_triangles = 0;
+        }
+        ready
+    }
+}
+
+pub fn advance_tiling(
+    mut commands: Commands,
+    mut tiler: ResMut<IncrementalTiler>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    if tiler.is_idle() {
+        return;
+    }
+    let pool = ComputeTaskPool::get_or_init(TaskPool::default);
+    for group in tiler.advance(pool, TRIANGLES_PER_FRAME) {
+        for tile in group.built {
+            spawn_tile(
+                &mut commands,
+                &mut meshes,
+                tile,
+                &group.material,
+                &group.slope_colors,
+                group.layer,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::split_mesh_into_tiles_on;
+    use bevy::tasks::TaskPoolBuilder;
+
+    #[test]
+    fn test_incremental_tiling_matches_one_go() {
+        // A strip of quads across several tiles
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for i in 0..20u32 {
+            let x = i as f32;
+            vertices.extend([
+                Vec3::new(x, 0.0, 0.0),
+                Vec3::new(x + 1.0, 0.0, 0.0),
+                Vec3::new(x + 1.0, 0.0, 1.0),
+                Vec3::new(x, 0.0, 1.0),
+            ]);
+            indices.extend([0, 2, 1, 0, 3, 2].map(|corner| i * 4 + corner));
+        }
+        let normals = vec![Vec3::Y; vertices.len()];
+        let pool = TaskPoolBuilder::new().num_threads(1).build();
+        let expected = split_mesh_into_tiles_on(&pool, &vertices, &indices, &normals, &[], 3.0);
+
+        let group = |layer| {
+            TilingGroup::new(
+                layer,
+                Handle::default(),
+                SlopeColors::default(),
+                (
+                    vertices.clone(),
+                    indices.clone(),
+                    normals.clone(),
+                    Vec::new(),
+                ),
+                3.0,
+            )
+        };
+        let mut tiler = IncrementalTiler::default();
+        assert_eq!(tiler.progress(), None);
+        tiler.push(group(0));
+        tiler.push(group(1));
+
+        // A few triangles a frame, the second layer is cancelled halfway
+        let mut frames = 0;
+        let mut ready = Vec::new();
+        let mut last_progress = 0.0;
+        while !tiler.is_idle() {
+            ready.extend(tiler.advance(&pool, 7));
+            if let Some(progress) = tiler.progress() {
+                assert!(progress >= last_progress && progress <= 1.0);
+                last_progress = progress;
+            }
+            frames += 1;
+            if frames == 10 {
+                tiler.cancel(1);
+            }
+        }
+        assert!(frames > 2);
+
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].layer, 0);
+        let built = &ready[0].built;
+        assert_eq!(built.len(), expected.len());
+        for (tile, expected) in built.iter().zip(&expected) {
+            assert_eq!(
+                (tile.tile_x, tile.tile_z),
+                (expected.tile_x, expected.tile_z)
+            );
+            assert_eq!(tile.vertices, expected.vertices);
+            assert_eq!(tile.indices, expected.indices);
+        }
+    }
+}
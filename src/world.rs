@@ -1,16 +1,18 @@
-use bevy::math::Vec2;
+use bevy::ecs::system::Resource;
 use bevy::math::Vec3;
-use bevy::math::Vec4;
+use bevy::math::{Mat4, Vec2};
 use bevy::render::camera::Camera;
 use bevy::transform::components::Transform;
 use bevy::window::Window;
 
-pub fn screen_to_world(
+// Ray through the cursor, returns the origin on the near plane and the
+// normalized direction in world space
+pub fn screen_to_ray(
     window: &Window,
     camera: &Camera,
     camera_transform: &Transform,
     cursor_pos: Vec2,
-) -> Option<Vec3> {
+) -> (Vec3, Vec3) {
     // Get the viewport size
     let viewport_size = Vec2::new(window.width(), window.height());
 
@@ -19,44 +21,541 @@ pub fn screen_to_world(
         (cursor_pos.x / viewport_size.x) * 2.0 - 1.0,
         -(cursor_pos.y / viewport_size.y) * 2.0 + 1.0,
     );
+    ndc_to_ray(camera, camera_transform, ndc)
+}
 
-    // Get the view-projection matrix and its inverse
-    let projection = camera.projection_matrix();
-    let view_matrix = camera_transform.compute_matrix();
-    let view_proj = projection * view_matrix;
-
-    let inverse_view_proj = view_proj.inverse();
-    // Create points in NDC space
-    let near_ndc = Vec4::new(ndc.x, ndc.y, -1.0, 1.0);
-    let far_ndc = Vec4::new(ndc.x, ndc.y, 1.0, 1.0);
-
-    // Transform to world space
-    let near_world = inverse_view_proj * near_ndc;
-    let far_world = inverse_view_proj * far_ndc;
-
-    // Perspective divide
-    let near_point = Vec3::new(
-        near_world.x / near_world.w,
-        near_world.y / near_world.w,
-        near_world.z / near_world.w,
-    );
-    let far_point = Vec3::new(
-        far_world.x / far_world.w,
-        far_world.y / far_world.w,
-        far_world.z / far_world.w,
-    );
+// Ray through a point of the view in normalized device coordinates, -1 to 1
+// from the bottom left corner
+pub fn ndc_to_ray(camera: &Camera, camera_transform: &Transform, ndc: Vec2) -> (Vec3, Vec3) {
+    unproject_ray(camera.projection_matrix(), camera_transform, ndc)
+}
+
+// Works for either projection. Orthographic rays all go the same way and
+// start on the near plane across the view
+pub fn unproject_ray(projection: Mat4, camera_transform: &Transform, ndc: Vec2) -> (Vec3, Vec3) {
+    // Unproject into view space. Bevy uses reverse Z, so depth 1 is the near
+    // plane and smaller depths are further away
+    let inverse_projection = projection.inverse();
+    let near_view = inverse_projection.project_point3(Vec3::new(ndc.x, ndc.y, 1.0));
+    let far_view = inverse_projection.project_point3(Vec3::new(ndc.x, ndc.y, 0.5));
+
+    // The camera transform takes view space to world space
+    let origin = camera_transform.transform_point(near_view);
+    let direction = (camera_transform.rotation * (far_view - near_view)).normalize();
+
+    (origin, direction)
+}
+
+// Horizontal plane picked against when the ray misses the mesh
+#[derive(Resource)]
+pub struct PickPlane {
+    pub height: f32,
+    pub auto: bool, // Follow the average height of the loaded mesh
+}
+
+impl Default for PickPlane {
+    fn default() -> Self {
+        Self {
+            height: 0.0,
+            auto: true,
+        }
+    }
+}
+
+// Mean Y of the vertices, None for an empty mesh
+pub fn average_height(vertices: &[Vec3]) -> Option<f32> {
+    if vertices.is_empty() {
+        return None;
+    }
+    let sum: f64 = vertices.iter().map(|v| v.y as f64).sum();
+    Some((sum / vertices.len() as f64) as f32)
+}
+
+// Intersection of the ray with the plane y = `height`. None when the ray runs
+// parallel to it or the plane is behind the origin
+pub fn ray_plane_intersection(origin: Vec3, direction: Vec3, height: f32) -> Option<Vec3> {
+    if direction.y.abs() < 1e-6 {
+        return None;
+    }
+    let t = (height - origin.y) / direction.y;
+    (t >= 0.0).then(|| origin + direction * t)
+}
+
+// Distance along the ray to where it first enters the sphere, zero when the
+// origin is inside. None when it misses or the sphere is behind the origin
+pub fn ray_sphere_intersection(
+    origin: Vec3,
+    direction: Vec3,
+    center: Vec3,
+    radius: f32,
+) -> Option<f32> {
+    let to_center = center - origin;
+    let along = to_center.dot(direction);
+    let miss_squared = to_center.length_squared() - along * along;
+    if miss_squared > radius * radius {
+        return None;
+    }
+    let half_chord = (radius * radius - miss_squared).sqrt();
+    let (near, far) = (along - half_chord, along + half_chord);
+    if far < 0.0 {
+        return None;
+    }
+    Some(near.max(0.0))
+}
+
+// Nearest intersection of the ray with the triangle mesh, returns the hit
+// point and the triangle index (into `indices` / 3)
+pub fn raycast_mesh(
+    origin: Vec3,
+    direction: Vec3,
+    vertices: &[Vec3],
+    indices: &[u32],
+) -> Option<(Vec3, usize)> {
+    let mut nearest: Option<(f32, usize)> = None;
+
+    for (triangle, tri) in indices.chunks_exact(3).enumerate() {
+        let v0 = vertices[tri[0] as usize];
+        let v1 = vertices[tri[1] as usize];
+        let v2 = vertices[tri[2] as usize];
+
+        if let Some(t) = ray_triangle(origin, direction, v0, v1, v2) {
+            if nearest.is_none_or(|(best, _)| t < best) {
+                nearest = Some((t, triangle));
+            }
+        }
+    }
+
+    nearest.map(|(t, triangle)| (origin + direction * t, triangle))
+}
+
+// Möller–Trumbore, two sided. Returns the distance along the ray
+fn ray_triangle(origin: Vec3, direction: Vec3, v0: Vec3, v1: Vec3, v2: Vec3) -> Option<f32> {
+    const EPSILON: f32 = 1e-7;
+
+    let edge1 = v1 - v0;
+    let edge2 = v2 - v0;
+    let p = direction.cross(edge2);
+    let det = edge1.dot(p);
+
+    // Ray parallel to the triangle, or degenerate triangle
+    if det.abs() < EPSILON {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let s = origin - v0;
+    let u = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
 
-    // Calculate ray direction
-    let ray_direction = (far_point - near_point).normalize();
+    let q = s.cross(edge1);
+    let v = direction.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = edge2.dot(q) * inv_det;
+    if t > EPSILON {
+        Some(t)
+    } else {
+        None
+    }
+}
 
-    // For simplicity, we'll intersect with the Y=0 plane
-    if ray_direction.y.abs() > 0.0001 {
-        let t = -near_point.y / ray_direction.y;
-        if t >= 0.0 {
-            let intersection = near_point + ray_direction * t;
-            return Some(intersection);
+// Uniform XZ grid over the mesh triangles, cells are keyed by
+// floor(x / cell_size) like the render tiles. Cells hold the triangle indices
+// whose bounds overlap them, so a raycast only tests the cells the ray crosses
+#[derive(Resource, Default)]
+pub struct TriangleGrid {
+    origin: Vec2, // XZ of the minimum corner
+    cell_size: f32,
+    width: usize,
+    depth: usize,
+    cells: Vec<Vec<u32>>,
+}
+
+// Keeps the cell count bounded for huge or very sparse meshes
+const MAX_GRID_CELLS_PER_AXIS: usize = 1024;
+
+impl TriangleGrid {
+    pub fn build(vertices: &[Vec3], indices: &[u32]) -> Self {
+        let triangle_count = indices.len() / 3;
+        if triangle_count == 0 {
+            return Self::default();
         }
+
+        let mut min = Vec2::splat(f32::MAX);
+        let mut max = Vec2::splat(f32::MIN);
+        for &index in indices {
+            let v = vertices[index as usize];
+            min = min.min(Vec2::new(v.x, v.z));
+            max = max.max(Vec2::new(v.x, v.z));
+        }
+
+        // Aim for a handful of triangles per cell
+        let extent = (max - min).max(Vec2::splat(1e-3));
+        let cell_size = ((extent.x * extent.y) / triangle_count as f32)
+            .sqrt()
+            .max(extent.max_element() / MAX_GRID_CELLS_PER_AXIS as f32)
+            * 2.0;
+        let width = ((extent.x / cell_size).floor() as usize + 1).min(MAX_GRID_CELLS_PER_AXIS);
+        let depth = ((extent.y / cell_size).floor() as usize + 1).min(MAX_GRID_CELLS_PER_AXIS);
+
+        let mut grid = Self {
+            origin: min,
+            cell_size,
+            width,
+            depth,
+            cells: vec![Vec::new(); width * depth],
+        };
+
+        for (triangle, tri) in indices.chunks_exact(3).enumerate() {
+            let points = tri.iter().map(|&i| vertices[i as usize]);
+            let (lo, hi) = points.fold(
+                (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)),
+                |(lo, hi), v| (lo.min(Vec2::new(v.x, v.z)), hi.max(Vec2::new(v.x, v.z))),
+            );
+
+            let (x0, z0) = grid.cell_of(lo);
+            let (x1, z1) = grid.cell_of(hi);
+            for z in z0..=z1 {
+                for x in x0..=x1 {
+                    grid.cells[z * width + x].push(triangle as u32);
+                }
+            }
+        }
+
+        grid
+    }
+
+    // Cell containing the XZ point, clamped to the grid
+    fn cell_of(&self, point: Vec2) -> (usize, usize) {
+        let cell = ((point - self.origin) / self.cell_size).floor();
+        (
+            (cell.x.max(0.0) as usize).min(self.width - 1),
+            (cell.y.max(0.0) as usize).min(self.depth - 1),
+        )
     }
 
-    return None;
+    // Same result as `raycast_mesh`, walking the grid cells along the ray
+    pub fn raycast(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        vertices: &[Vec3],
+        indices: &[u32],
+    ) -> Option<(Vec3, usize)> {
+        if self.cells.is_empty() {
+            return None;
+        }
+
+        // Clip the XZ projection of the ray to the grid bounds
+        let ray_origin = Vec2::new(origin.x, origin.z);
+        let ray_direction = Vec2::new(direction.x, direction.z);
+        let grid_max =
+            self.origin + Vec2::new(self.width as f32, self.depth as f32) * self.cell_size;
+
+        let mut t_enter = 0.0_f32;
+        let mut t_exit = f32::MAX;
+        for axis in 0..2 {
+            if ray_direction[axis].abs() < 1e-12 {
+                if ray_origin[axis] < self.origin[axis] || ray_origin[axis] > grid_max[axis] {
+                    return None;
+                }
+            } else {
+                let t0 = (self.origin[axis] - ray_origin[axis]) / ray_direction[axis];
+                let t1 = (grid_max[axis] - ray_origin[axis]) / ray_direction[axis];
+                t_enter = t_enter.max(t0.min(t1));
+                t_exit = t_exit.min(t0.max(t1));
+            }
+        }
+        if t_enter > t_exit {
+            return None;
+        }
+
+        // 2D DDA from the entry cell
+        let (mut x, mut z) = self.cell_of(ray_origin + ray_direction * t_enter);
+        let step_x = if ray_direction.x >= 0.0 { 1 } else { -1 };
+        let step_z = if ray_direction.y >= 0.0 { 1 } else { -1 };
+        let next_boundary = |cell: usize, step: i32, axis: usize| {
+            if ray_direction[axis].abs() < 1e-12 {
+                return f32::MAX;
+            }
+            let edge = if step > 0 { cell + 1 } else { cell } as f32;
+            (self.origin[axis] + edge * self.cell_size - ray_origin[axis]) / ray_direction[axis]
+        };
+        let delta_x = (self.cell_size / ray_direction.x).abs();
+        let delta_z = (self.cell_size / ray_direction.y).abs();
+        let mut t_max_x = next_boundary(x, step_x, 0);
+        let mut t_max_z = next_boundary(z, step_z, 1);
+
+        let mut nearest: Option<(f32, usize)> = None;
+        loop {
+            for &triangle in &self.cells[z * self.width + x] {
+                let tri = &indices[triangle as usize * 3..triangle as usize * 3 + 3];
+                let hit = ray_triangle(
+                    origin,
+                    direction,
+                    vertices[tri[0] as usize],
+                    vertices[tri[1] as usize],
+                    vertices[tri[2] as usize],
+                );
+                if let Some(t) = hit {
+                    if nearest.is_none_or(|(best, _)| t < best) {
+                        nearest = Some((t, triangle as usize));
+                    }
+                }
+            }
+
+            // Anything nearer would lie in a cell we have already visited
+            let cell_exit = t_max_x.min(t_max_z);
+            if nearest.is_some_and(|(best, _)| best <= cell_exit) || cell_exit > t_exit {
+                break;
+            }
+
+            if t_max_x < t_max_z {
+                if (step_x < 0 && x == 0) || (step_x > 0 && x + 1 == self.width) {
+                    break;
+                }
+                x = (x as i32 + step_x) as usize;
+                t_max_x += delta_x;
+            } else {
+                if (step_z < 0 && z == 0) || (step_z > 0 && z + 1 == self.depth) {
+                    break;
+                }
+                z = (z as i32 + step_z) as usize;
+                t_max_z += delta_z;
+            }
+        }
+
+        nearest.map(|(t, triangle)| (origin + direction * t, triangle))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two unit quads stacked at y = 0 and y = 1
+    fn stacked_quads() -> (Vec<Vec3>, Vec<u32>) {
+        let vertices = vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(1.0, 1.0, 0.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            Vec3::new(0.0, 1.0, 1.0),
+        ];
+        let indices = vec![0, 2, 1, 0, 3, 2, 4, 6, 5, 4, 7, 6];
+        (vertices, indices)
+    }
+
+    #[test]
+    fn test_unproject_ray_orthographic() {
+        use bevy::render::camera::{
+            CameraProjection, OrthographicProjection, PerspectiveProjection, ScalingMode,
+        };
+
+        let camera_transform = Transform::from_xyz(0.0, 10.0, 0.0).looking_to(Vec3::NEG_Y, Vec3::Z);
+        let mut ortho = OrthographicProjection {
+            scaling_mode: ScalingMode::FixedVertical(2.0),
+            scale: 5.0,
+            ..Default::default()
+        };
+        ortho.update(200.0, 100.0);
+        let projection = ortho.get_projection_matrix();
+
+        // Straight down from wherever on the view, the corner 10 by 5 off
+        let (center, center_direction) = unproject_ray(projection, &camera_transform, Vec2::ZERO);
+        let (corner, corner_direction) = unproject_ray(projection, &camera_transform, Vec2::ONE);
+        assert!(center.distance(Vec3::new(0.0, 10.0, 0.0)) < 1e-4);
+        assert!(center_direction.distance(Vec3::NEG_Y) < 1e-5);
+        assert!(corner_direction.distance(Vec3::NEG_Y) < 1e-5);
+        assert!((corner - center).distance(Vec3::new(-10.0, 0.0, 5.0)) < 1e-3);
+
+        // Perspective rays spread out
+        let mut perspective = PerspectiveProjection::default();
+        perspective.update(200.0, 100.0);
+        let projection = perspective.get_projection_matrix();
+        let (_, center_direction) = unproject_ray(projection, &camera_transform, Vec2::ZERO);
+        let (_, corner_direction) = unproject_ray(projection, &camera_transform, Vec2::ONE);
+        assert!(center_direction.dot(corner_direction) < 0.99);
+    }
+
+    #[test]
+    fn test_raycast_mesh_nearest_hit() {
+        let (vertices, indices) = stacked_quads();
+
+        // From above, the upper quad is hit first
+        let (point, triangle) =
+            raycast_mesh(Vec3::new(0.25, 5.0, 0.75), Vec3::NEG_Y, &vertices, &indices).unwrap();
+        assert!((point - Vec3::new(0.25, 1.0, 0.75)).length() < 1e-5);
+        assert_eq!(triangle, 3);
+
+        // From below, the lower quad is hit first
+        let (point, triangle) =
+            raycast_mesh(Vec3::new(0.75, -5.0, 0.25), Vec3::Y, &vertices, &indices).unwrap();
+        assert!((point - Vec3::new(0.75, 0.0, 0.25)).length() < 1e-5);
+        assert_eq!(triangle, 0);
+    }
+
+    #[test]
+    fn test_ray_plane_intersection() {
+        let origin = Vec3::new(1.0, 10.0, 2.0);
+        let hit = ray_plane_intersection(origin, Vec3::new(0.0, -1.0, 1.0).normalize(), 4.0);
+        assert!((hit.unwrap() - Vec3::new(1.0, 4.0, 8.0)).length() < 1e-5);
+
+        // Parallel, and pointing away from the plane
+        assert_eq!(ray_plane_intersection(origin, Vec3::X, 4.0), None);
+        assert_eq!(ray_plane_intersection(origin, Vec3::Y, 4.0), None);
+
+        assert_eq!(
+            average_height(&[Vec3::ZERO, Vec3::new(5.0, 3.0, 1.0)]),
+            Some(1.5)
+        );
+        assert_eq!(average_height(&[]), None);
+    }
+
+    #[test]
+    fn test_ray_sphere_intersection() {
+        let center = Vec3::new(0.0, 0.0, -10.0);
+        assert_eq!(
+            ray_sphere_intersection(Vec3::ZERO, Vec3::NEG_Z, center, 2.0),
+            Some(8.0)
+        );
+        // Passing beside it, pointing away, and starting inside
+        assert_eq!(
+            ray_sphere_intersection(Vec3::new(3.0, 0.0, 0.0), Vec3::NEG_Z, center, 2.0),
+            None
+        );
+        assert_eq!(
+            ray_sphere_intersection(Vec3::ZERO, Vec3::Z, center, 2.0),
+            None
+        );
+        assert_eq!(
+            ray_sphere_intersection(center, Vec3::Z, center, 2.0),
+            Some(0.0)
+        );
+    }
+
+    #[test]
+    fn test_raycast_mesh_miss() {
+        let (vertices, indices) = stacked_quads();
+
+        // Outside the quads, pointing away and parallel to them
+        assert!(raycast_mesh(Vec3::new(2.0, 5.0, 2.0), Vec3::NEG_Y, &vertices, &indices).is_none());
+        assert!(raycast_mesh(Vec3::new(0.5, 5.0, 0.5), Vec3::Y, &vertices, &indices).is_none());
+        assert!(raycast_mesh(Vec3::new(-1.0, 0.5, 0.5), Vec3::X, &vertices, &indices).is_none());
+        assert!(raycast_mesh(Vec3::ZERO, Vec3::NEG_Y, &[], &[]).is_none());
+    }
+
+    // Bumpy height field with `size` x `size` quads
+    fn terrain(size: usize) -> (Vec<Vec3>, Vec<u32>) {
+        let mut vertices = Vec::new();
+        for z in 0..=size {
+            for x in 0..=size {
+                let (fx, fz) = (x as f32, z as f32);
+                let height = (fx * 0.3).sin() * 4.0 + (fz * 0.17).cos() * 3.0;
+                vertices.push(Vec3::new(fx * 10.0, height, fz * 10.0));
+            }
+        }
+
+        let mut indices = Vec::new();
+        let row = size as u32 + 1;
+        for z in 0..size as u32 {
+            for x in 0..size as u32 {
+                let i = z * row + x;
+                indices.extend_from_slice(&[i, i + row, i + 1, i + 1, i + row, i + row + 1]);
+            }
+        }
+        (vertices, indices)
+    }
+
+    // Deterministic rays from above the terrain in all directions
+    fn test_rays(count: usize, size: usize) -> Vec<(Vec3, Vec3)> {
+        let mut seed = 12345_u32;
+        let mut next = move || {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            (seed >> 8) as f32 / (1 << 24) as f32
+        };
+        let extent = size as f32 * 10.0;
+        (0..count)
+            .map(|_| {
+                let origin = Vec3::new(
+                    next() * extent * 1.5 - extent * 0.25,
+                    20.0 + next() * 50.0,
+                    next() * extent * 1.5 - extent * 0.25,
+                );
+                let direction = Vec3::new(next() - 0.5, -next(), next() - 0.5).normalize();
+                (origin, direction)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_grid_matches_brute_force() {
+        let (vertices, indices) = terrain(40);
+        let grid = TriangleGrid::build(&vertices, &indices);
+
+        let mut rays = test_rays(500, 40);
+        rays.push((Vec3::new(55.0, 30.0, 123.0), Vec3::NEG_Y)); // Straight down
+        rays.push((Vec3::new(-10.0, 0.0, 50.0), Vec3::X)); // Through the side
+        rays.push((Vec3::new(55.0, 30.0, 123.0), Vec3::Y)); // Away from the terrain
+
+        for (origin, direction) in rays {
+            let expected = raycast_mesh(origin, direction, &vertices, &indices);
+            let actual = grid.raycast(origin, direction, &vertices, &indices);
+            match (expected, actual) {
+                (Some((expected_point, _)), Some((actual_point, _))) => {
+                    assert!((expected_point - actual_point).length() < 1e-3)
+                }
+                (None, None) => {}
+                _ => panic!("grid disagrees for ray {:?} {:?}", origin, direction),
+            }
+        }
+
+        assert!(TriangleGrid::build(&[], &[])
+            .raycast(Vec3::ZERO, Vec3::NEG_Y, &[], &[])
+            .is_none());
+    }
+
+    // cargo test --release bench_picking -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_picking() {
+        // 224 x 224 quads is just over 100k triangles
+        let (vertices, indices) = terrain(224);
+        let rays = test_rays(1000, 224);
+
+        let start = std::time::Instant::now();
+        let grid = TriangleGrid::build(&vertices, &indices);
+        let build_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let brute_hits = rays
+            .iter()
+            .filter(|(o, d)| raycast_mesh(*o, *d, &vertices, &indices).is_some())
+            .count();
+        let brute_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let grid_hits = rays
+            .iter()
+            .filter(|(o, d)| grid.raycast(*o, *d, &vertices, &indices).is_some())
+            .count();
+        let grid_time = start.elapsed();
+
+        assert_eq!(brute_hits, grid_hits);
+        println!(
+            "{} triangles, {} rays: brute force {:?}, grid {:?} (built in {:?})",
+            indices.len() / 3,
+            rays.len(),
+            brute_time,
+            grid_time,
+            build_time
+        );
+    }
 }